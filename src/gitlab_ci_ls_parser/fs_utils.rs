@@ -1,8 +1,10 @@
-use mockall::{automock, predicate::str};
+use mockall::predicate::str;
 
-#[cfg_attr(test, automock)]
-pub trait FSUtils {
+#[cfg_attr(test, mockall::automock)]
+pub trait FSUtils: Send + Sync {
     fn create_dir_all(&self, path: &str) -> anyhow::Result<()>;
+    // Entry names of directory, paired with whether entry is a directory.
+    fn read_dir(&self, path: &str) -> anyhow::Result<Vec<(String, bool)>>;
 }
 
 pub struct FSUtilsImpl {
@@ -44,6 +46,29 @@ impl FSUtils for FSUtilsImpl {
         std::fs::create_dir_all(path)?;
         Ok(())
     }
+
+    fn read_dir(&self, path: &str) -> anyhow::Result<Vec<(String, bool)>> {
+        Ok(std::fs::read_dir(self.get_path(path))?
+            .flatten()
+            .map(|entry| {
+                (
+                    entry.file_name().to_string_lossy().to_string(),
+                    entry.path().is_dir(),
+                )
+            })
+            .collect())
+    }
+}
+
+// Lets same implementation be shared by handlers and parser.
+impl<T: FSUtils + ?Sized> FSUtils for std::sync::Arc<T> {
+    fn create_dir_all(&self, path: &str) -> anyhow::Result<()> {
+        (**self).create_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &str) -> anyhow::Result<Vec<(String, bool)>> {
+        (**self).read_dir(path)
+    }
 }
 
 #[cfg(test)]
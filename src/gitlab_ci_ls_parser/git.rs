@@ -4,13 +4,13 @@ use std::{
     io::Write,
     path::{self, Path},
     process::Command,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 use super::{
     fs_utils::{self, FSUtils},
     parser_utils::{self, ComponentInfo, ParserUtils},
-    GitlabElement, GitlabFile, ProjectFile, DEFAULT_BRANCH_SUBFOLDER,
+    FetchMetadata, GitlabElement, GitlabFile, ProjectFile, DEFAULT_BRANCH_SUBFOLDER,
 };
 use log::{debug, error, info};
 use reqwest::{blocking::Client, header::IF_NONE_MATCH, StatusCode, Url};
@@ -28,13 +28,22 @@ pub trait Git {
         &self,
         component_info: ComponentInfo,
     ) -> anyhow::Result<GitlabElement>;
+    fn fetch_metadata(&self, remote_pkg: &str, remote_tag: Option<&str>) -> Option<FetchMetadata>;
+    fn project_root(&self, remote_pkg: &str, remote_tag: Option<&str>) -> anyhow::Result<String>;
+    fn refs(&self, repo_dir: &str) -> Vec<String>;
 }
 
+// Kept inside `.git` so it isn't part of the checkout and is removed with it.
+const FETCH_METADATA_FILE: &str = ".git/gitlab-ci-ls-fetch.json";
+
 #[allow(clippy::module_name_repetitions)]
 pub struct GitImpl {
     package_map: HashMap<String, String>,
+    // remote host -> local directory containing its projects
+    mirrors: HashMap<String, String>,
     remote_urls: Vec<String>,
     cache_path: String,
+    default_ref: Option<String>,
     fs_utils: Box<dyn FSUtils>,
 }
 
@@ -42,13 +51,17 @@ impl GitImpl {
     pub fn new(
         remote_urls: Vec<String>,
         package_map: HashMap<String, String>,
+        mirrors: HashMap<String, String>,
         cache_path: String,
+        default_ref: Option<String>,
         fs_utils: Box<dyn fs_utils::FSUtils>,
     ) -> Self {
         Self {
             package_map,
+            mirrors,
             remote_urls,
             cache_path,
+            default_ref,
             fs_utils,
         }
     }
@@ -127,7 +140,112 @@ impl GitImpl {
                     fs::remove_dir_all(dest).expect("should be able to remove");
                 }
             }
+        }
+    }
+
+    // Instance relative components are fetched from the same host as the
+    // project which is resolved from configured remotes.
+    fn resolve_component_host(&self, mut component_info: ComponentInfo) -> ComponentInfo {
+        if !ParserUtils::is_instance_host(&component_info.host) {
+            return component_info;
+        }
+
+        let host = self
+            .package_map
+            .get(&component_info.project)
+            .into_iter()
+            .chain(self.remote_urls.iter())
+            .find_map(|origin| ParserUtils::remote_host(origin));
+
+        if let Some(host) = host {
+            info!("resolved instance host: {} to: {host}", component_info.host);
+            component_info.host = host.to_string();
+        } else {
+            error!(
+                "could not resolve instance host: {}; no remotes configured",
+                component_info.host
+            );
+        }
+
+        component_info
+    }
+
+    // Project repository inside local mirror of its host.
+    fn mirror_dir(&self, host: &str, project: &str) -> Option<String> {
+        let dir = Path::new(self.mirrors.get(host)?).join(project);
+
+        dir.is_dir().then(|| dir.to_string_lossy().to_string())
+    }
+
+    // Same remotes as used for cloning decide which mirror is used.
+    fn project_mirror_dir(&self, remote_pkg: &str) -> Option<String> {
+        self.package_map
+            .get(remote_pkg)
+            .into_iter()
+            .chain(self.remote_urls.iter())
+            .filter_map(|origin| ParserUtils::remote_host(origin))
+            .find_map(|host| self.mirror_dir(host, remote_pkg))
+    }
+
+    // Mirror is checked out at the ref into a worktree placed where a clone
+    // would be, so files keep the `project/ref/file` layout and the mirror's
+    // own checkout isn't switched.
+    fn checkout_mirror(
+        mirror: &str,
+        repo_dest: &str,
+        remote_tag: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let rev = remote_tag.unwrap_or("HEAD");
+        let commit = git_stdout(&[
+            "-C",
+            mirror,
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{rev}^{{commit}}"),
+        ])
+        .ok_or_else(|| anyhow::anyhow!("ref: {rev} doesn't exist in mirror: {mirror}"))?;
+
+        if Path::new(repo_dest).join(".git").exists() {
+            if git_stdout(&["-C", repo_dest, "rev-parse", "HEAD"]).as_ref() == Some(&commit) {
+                return Ok(());
+            }
+
+            git_stdout(&["-C", repo_dest, "checkout", "--detach", &commit])
+                .ok_or_else(|| anyhow::anyhow!("error checking out: {repo_dest} @ {rev}"))?;
+        } else {
+            git_stdout(&[
+                "-C", mirror, "worktree", "add", "--detach", repo_dest, &commit,
+            ])
+            .ok_or_else(|| anyhow::anyhow!("error adding worktree: {repo_dest} @ {rev}"))?;
+        }
+
+        GitImpl::write_fetch_metadata(repo_dest, remote_tag);
+
+        Ok(())
+    }
+
+    fn write_fetch_metadata(repo_dest: &str, remote_tag: Option<&str>) {
+        let commit = git_stdout(&["-C", repo_dest, "rev-parse", "HEAD"]);
+
+        let metadata = FetchMetadata {
+            reference: remote_tag.map(ToString::to_string),
+            commit,
+            fetched_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
         };
+
+        let path = Path::new(repo_dest).join(FETCH_METADATA_FILE);
+        match serde_json::to_string(&metadata) {
+            Ok(json) => {
+                if let Err(err) = fs::write(&path, json) {
+                    error!("error writing fetch metadata to: {path:?}; got err: {err}");
+                }
+            }
+            Err(err) => error!("error serializing fetch metadata; got err: {err}"),
+        }
     }
 
     fn get_clone_repo_destination(
@@ -174,7 +292,10 @@ impl Git for GitImpl {
                     || GitImpl::is_not_semver_or_commit_hash(remote_tag.unwrap())
                 {
                     match Command::new("git").args(["-C", repo_dest, "pull"]).output() {
-                        Ok(_) => info!("{repo_dest}: successfully updated using git clone"),
+                        Ok(_) => {
+                            info!("{repo_dest}: successfully updated using git clone");
+                            GitImpl::write_fetch_metadata(repo_dest, remote_tag);
+                        }
                         Err(err) => {
                             error!("error using git clone inside: {repo_dest}; got: {err:?}");
                         }
@@ -188,7 +309,7 @@ impl Git for GitImpl {
         }
 
         let remotes = match self.package_map.get(remote_pkg) {
-            Some(host) => vec![host.to_string()],
+            Some(host) => vec![host.clone()],
             None => self.remote_urls.clone(),
         };
 
@@ -247,6 +368,10 @@ impl Git for GitImpl {
                             }
                         }
                     }
+
+                    if path::Path::new(repo_dest).exists() {
+                        GitImpl::write_fetch_metadata(repo_dest, remote_tag);
+                    }
                     break;
                 }
                 Err(err) => {
@@ -256,9 +381,8 @@ impl Git for GitImpl {
                     if dest.exists() {
                         fs::remove_dir_all(dest).expect("should be able to remove");
                     }
-                    continue;
                 }
-            };
+            }
         }
     }
 
@@ -277,12 +401,20 @@ impl Git for GitImpl {
             return Ok(vec![]);
         }
 
+        // includes without `ref` follow configured default ref if set,
+        // otherwise project's default branch is used
+        let remote_tag = remote_tag.or(self.default_ref.as_deref());
+
         self.fs_utils.create_dir_all(&self.cache_path)?;
 
-        let repo_dest =
-            GitImpl::get_clone_repo_destination(&self.cache_path, remote_pkg, remote_tag)?;
+        let repo_dest = self.project_root(remote_pkg, remote_tag)?;
 
-        self.clone_repo(repo_dest.as_str(), remote_tag, remote_pkg);
+        if let Some(mirror) = self.project_mirror_dir(remote_pkg) {
+            info!("reading {remote_pkg} from mirror: {mirror}");
+            GitImpl::checkout_mirror(&mirror, &repo_dest, remote_tag)?;
+        } else {
+            self.clone_repo(repo_dest.as_str(), remote_tag, remote_pkg);
+        }
 
         let files = files
             .iter()
@@ -392,18 +524,87 @@ impl Git for GitImpl {
         }
     }
 
+    fn fetch_metadata(&self, remote_pkg: &str, remote_tag: Option<&str>) -> Option<FetchMetadata> {
+        let repo_dest = self.project_root(remote_pkg, remote_tag).ok()?;
+
+        let content = fs::read_to_string(Path::new(&repo_dest).join(FETCH_METADATA_FILE)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
     fn fetch_remote_component(
         &self,
         component_info: ComponentInfo,
     ) -> anyhow::Result<GitlabElement> {
-        // TODO: handle slashes correctly..
+        let component_info = self.resolve_component_host(component_info);
 
         let repo_dest = ParserUtils::get_component_dest_dir(&self.cache_path, &component_info);
-        self.fs_utils.create_dir_all(&repo_dest)?;
 
-        GitImpl::clone_component_repo(repo_dest.as_str(), &component_info);
+        if let Some(mirror) = self.mirror_dir(&component_info.host, &component_info.project) {
+            info!("reading component from mirror: {mirror}");
+            GitImpl::checkout_mirror(&mirror, &repo_dest, Some(&component_info.version))?;
+        } else {
+            self.fs_utils.create_dir_all(&repo_dest)?;
+            GitImpl::clone_component_repo(repo_dest.as_str(), &component_info);
+        }
+
         ParserUtils::get_component(&repo_dest, &component_info.component)
     }
+
+    // Checkout directory of the project in cache; local includes inside its
+    // files are resolved against it.
+    fn project_root(&self, remote_pkg: &str, remote_tag: Option<&str>) -> anyhow::Result<String> {
+        let remote_tag = remote_tag.or(self.default_ref.as_deref());
+
+        GitImpl::get_clone_repo_destination(&self.cache_path, remote_pkg, remote_tag)
+    }
+
+    // Branches and tags of repository, both loose and packed ones.
+    fn refs(&self, repo_dir: &str) -> Vec<String> {
+        git_stdout(&[
+            "-C",
+            repo_dir,
+            "for-each-ref",
+            "--format=%(refname)",
+            "refs/heads",
+            "refs/tags",
+        ])
+        .map(|refs| refs.lines().map(ToString::to_string).collect())
+        .unwrap_or_default()
+    }
+}
+
+// Commits everything inside `dir`, creating the repository if needed, so
+// tests can use it as a mirror.
+#[cfg(test)]
+pub fn commit_all(dir: &Path, tag: Option<&str>) {
+    let git = |args: &[&str]| {
+        let output = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed: {output:?}");
+    };
+
+    if !dir.join(".git").exists() {
+        git(&["init", "--quiet", "--initial-branch", "main"]);
+    }
+    git(&["add", "."]);
+    git(&["commit", "--quiet", "-m", tag.unwrap_or("update")]);
+    if let Some(tag) = tag {
+        git(&["tag", tag]);
+    }
+}
+
+// Runs git and returns its trimmed output if it succeeded.
+fn git_stdout(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
 
 fn prepend_if_needed(input: &str, character: char) -> String {
@@ -443,4 +644,222 @@ mod tests {
             "/home/test/.cache/gitlab-ci-ls/repo/project/1.0.0"
         );
     }
+
+    #[test]
+    fn test_resolve_instance_relative_component_host() {
+        let git = GitImpl::new(
+            vec!["git@gitlab.example.com:".to_string()],
+            HashMap::from([(
+                "mirrored/project".to_string(),
+                "ssh://git@mirror.example.com:4242/".to_string(),
+            )]),
+            HashMap::new(),
+            "/home/test/.cache/gitlab-ci-ls/".to_string(),
+            None,
+            Box::new(fs_utils::MockFSUtils::new()),
+        );
+
+        let component =
+            ParserUtils::extract_component_from_uri("$CI_SERVER_FQDN/group/project/build@1.0.0")
+                .unwrap();
+        assert_eq!(
+            git.resolve_component_host(component).host,
+            "gitlab.example.com"
+        );
+
+        let component = ParserUtils::extract_component_from_uri(
+            "${CI_SERVER_FQDN}/mirrored/project/build@1.0.0",
+        )
+        .unwrap();
+        assert_eq!(
+            git.resolve_component_host(component).host,
+            "mirror.example.com"
+        );
+
+        let component =
+            ParserUtils::extract_component_from_uri("gitlab.com/group/project/build@1.0.0")
+                .unwrap();
+        assert_eq!(git.resolve_component_host(component).host, "gitlab.com");
+    }
+
+    #[test]
+    fn test_resolve_component_host_from_project_remotes() {
+        for remote in [
+            "git@gitlab.example.com:group/project.git",
+            "https://gitlab.example.com:8443/group/project.git",
+        ] {
+            let git = GitImpl::new(
+                vec![remote.to_string()],
+                HashMap::new(),
+                HashMap::new(),
+                "/home/test/.cache/gitlab-ci-ls/".to_string(),
+                None,
+                Box::new(fs_utils::MockFSUtils::new()),
+            );
+
+            let component = ParserUtils::extract_component_from_uri(
+                "$CI_SERVER_FQDN/group/components/build@1.0.0",
+            )
+            .unwrap();
+            let component = git.resolve_component_host(component);
+            assert_eq!(
+                component.host, "gitlab.example.com",
+                "invalid host of {remote}"
+            );
+            assert_eq!(component.project, "group/components");
+        }
+    }
+
+    #[test]
+    fn test_fetch_remote_repository_uses_default_ref() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = format!("{}/", cache_dir.path().to_string_lossy());
+        let repo_dest = cache_dir.path().join("group/project/1.0.0");
+        std::fs::create_dir_all(&repo_dest).unwrap();
+        std::fs::write(repo_dest.join("ci.yml"), "job:\n  script: echo\n").unwrap();
+
+        let mut fs_utils = fs_utils::MockFSUtils::new();
+        fs_utils.expect_create_dir_all().returning(|_| Ok(()));
+
+        let git = GitImpl::new(
+            vec![],
+            HashMap::new(),
+            HashMap::new(),
+            cache_path,
+            Some("1.0.0".to_string()),
+            Box::new(fs_utils),
+        );
+
+        let files = git
+            .fetch_remote_repository(
+                "group/project",
+                None,
+                ProjectFile::Single("ci.yml".to_string()),
+            )
+            .unwrap();
+
+        assert_eq!(files.len(), 1);
+        assert!(files[0].path.ends_with("/group/project/1.0.0/ci.yml"));
+    }
+
+    #[test]
+    fn test_fetch_remote_component_in_nested_subgroups() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = format!("{}/", cache_dir.path().to_string_lossy());
+        let templates = cache_dir
+            .path()
+            .join("components/group/subgroup/nested/project/2.1.0/templates");
+        std::fs::create_dir_all(&templates).unwrap();
+        std::fs::write(templates.join("build.yml"), "spec:\n  inputs:\n").unwrap();
+
+        let mut fs_utils = fs_utils::MockFSUtils::new();
+        fs_utils.expect_create_dir_all().returning(|_| Ok(()));
+
+        let git = GitImpl::new(
+            vec![],
+            HashMap::new(),
+            HashMap::new(),
+            cache_path,
+            None,
+            Box::new(fs_utils),
+        );
+
+        let component = git
+            .fetch_remote_component(
+                ParserUtils::extract_component_from_uri(
+                    "gitlab.com/group/subgroup/nested/project/build@2.1.0",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+
+        assert!(component
+            .uri
+            .ends_with("/components/group/subgroup/nested/project/2.1.0/templates/build.yml"));
+    }
+
+    #[test]
+    fn test_mirrored_host_reads_from_local_directory() {
+        let mirror_dir = tempfile::tempdir().unwrap();
+        let project_dir = mirror_dir.path().join("group/project");
+        std::fs::create_dir_all(project_dir.join("templates")).unwrap();
+        std::fs::write(project_dir.join("ci.yml"), "tagged:\n  script: echo\n").unwrap();
+        std::fs::write(project_dir.join("templates/build.yml"), "spec:\n").unwrap();
+        commit_all(&project_dir, Some("1.0.0"));
+        std::fs::write(project_dir.join("ci.yml"), "latest:\n  script: echo\n").unwrap();
+        commit_all(&project_dir, None);
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = format!("{}/", cache_dir.path().to_string_lossy());
+
+        let mut fs_utils = fs_utils::MockFSUtils::new();
+        fs_utils.expect_create_dir_all().returning(|_| Ok(()));
+
+        let git = GitImpl::new(
+            vec!["git@gitlab.example.com:".to_string()],
+            HashMap::new(),
+            HashMap::from([(
+                "gitlab.example.com".to_string(),
+                mirror_dir.path().to_string_lossy().to_string(),
+            )]),
+            cache_path.clone(),
+            None,
+            Box::new(fs_utils),
+        );
+
+        // pinned ref is checked out even though mirror is ahead of it
+        let files = git
+            .fetch_remote_repository(
+                "group/project",
+                Some("1.0.0"),
+                ProjectFile::Single("ci.yml".to_string()),
+            )
+            .unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(
+            files[0].path,
+            format!("file://{cache_path}group/project/1.0.0/ci.yml")
+        );
+        assert_eq!(files[0].content, "tagged:\n  script: echo\n");
+
+        let files = git
+            .fetch_remote_repository(
+                "group/project",
+                None,
+                ProjectFile::Single("ci.yml".to_string()),
+            )
+            .unwrap();
+        assert_eq!(
+            files[0].path,
+            format!("file://{cache_path}group/project/default/ci.yml")
+        );
+        assert_eq!(files[0].content, "latest:\n  script: echo\n");
+
+        assert!(git
+            .fetch_remote_repository(
+                "group/project",
+                Some("2.0.0"),
+                ProjectFile::Single("ci.yml".to_string()),
+            )
+            .is_err());
+
+        let component = git
+            .fetch_remote_component(
+                ParserUtils::extract_component_from_uri(
+                    "gitlab.example.com/group/project/build@1.0.0",
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(
+            component.uri,
+            format!("file://{cache_path}components/group/project/1.0.0/templates/build.yml")
+        );
+
+        // mirror's own checkout isn't switched
+        assert_eq!(
+            std::fs::read_to_string(project_dir.join("ci.yml")).unwrap(),
+            "latest:\n  script: echo\n"
+        );
+    }
 }
@@ -1,33 +1,70 @@
-use std::{collections::HashMap, fs, path::PathBuf, sync::Mutex, time::Instant};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex, MutexGuard, OnceLock},
+    time::Instant,
+};
 
 use anyhow::anyhow;
 use log::{debug, error, info, warn};
 use lsp_server::{Notification, Request};
 use lsp_types::{
-    request::GotoTypeDefinitionParams, CompletionParams, Diagnostic, DidChangeTextDocumentParams,
-    DidOpenTextDocumentParams, DidSaveTextDocumentParams, HoverParams, Position, RenameParams,
-    TextDocumentPositionParams, TextEdit, Url,
+    request::GotoTypeDefinitionParams, CallHierarchyIncomingCall, CallHierarchyIncomingCallsParams,
+    CallHierarchyItem, CallHierarchyOutgoingCall, CallHierarchyOutgoingCallsParams,
+    CallHierarchyPrepareParams, CodeAction, CodeActionKind, CodeActionParams, CompletionParams,
+    Diagnostic, DiagnosticSeverity, DidChangeConfigurationParams, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentLink, DocumentLinkParams,
+    DocumentSymbol, DocumentSymbolParams, ExecuteCommandParams, FoldingRange, FoldingRangeKind,
+    FoldingRangeParams, HoverParams, NumberOrString, Position, RenameParams, SymbolKind,
+    TextDocumentPositionParams, TextEdit, Url, WorkspaceEdit,
 };
 use regex::Regex;
 
 use crate::gitlab_ci_ls_parser::{
-    parser_utils::ParserUtils, DiagnosticsNotification, NodeDefinition, PrepareRenameResult,
-    RenameResult, DEFAULT_BRANCH_SUBFOLDER, MAX_CACHE_ITEMS,
+    parser_utils::ParserUtils, CodeActionResult, DiagnosticData, DiagnosticsNotification,
+    DocumentLinkResult, DocumentSymbolResult, ExecuteCommandResult, ExportResolvedParams,
+    ExportResolvedResult, FoldingRangeResult, IncomingCallsResult, NodeDefinition,
+    OutgoingCallsResult, PrepareCallHierarchyResult, PrepareRenameResult, RenameResult,
+    RevealCachedFileResult, Trigger, TriggerJob, UpdateIncludePathsParams, VariableDefinition,
+    DEFAULT_BRANCH_SUBFOLDER, DEPLOYMENT_TIERS, DOCKER_PLATFORMS, DOCKER_USERS,
+    GITLAB_PREDEFINED_VARIABLES, MAX_CACHES_MESSAGE, MAX_CACHE_ITEMS, PREDEFINED_VARIABLE_VALUES,
+    REVEAL_CACHED_FILE_COMMAND, SECRET_PROVIDERS,
 };
 
 use super::{
-    fs_utils,
+    fs_utils, keywords,
     parser::{self, PositionType},
     parser_utils, treesitter, CompletionResult, Component, ComponentInput, DefinitionResult,
     GitlabElement, GitlabFileElements, GitlabInputElement, HoverResult, IncludeInformation,
-    LSPCompletion, LSPConfig, LSPLocation, LSPPosition, LSPResult, Range, ReferencesResult,
-    RemoteInclude, RuleReference,
+    IncludeItem, LSPCompletion, LSPConfig, LSPExperimental, LSPLocation, LSPPosition, LSPResult,
+    ParseResults, PartialReferencesResult, Range, ReferencesResult, RemoteInclude, RuleReference,
 };
 
+// Predefined variables don't depend on workspace so they are parsed once and
+// shared by all handlers.
+static PREDEFINED_VARIABLES: OnceLock<HashSet<String>> = OnceLock::new();
+
+// Comparison of a variable being typed in `rules:if`, e.g. `$VAR == "pu`.
+static RULE_COMPARISON_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\$\{?(\w+)\}?\s*(?:==|!=)\s*("|')?(\w*)$"#).unwrap());
+
+// Searches single file content for references.
+type ReferencesFinder<'a> = Box<dyn Fn(&String, &String) -> Vec<GitlabElement> + 'a>;
+
 #[allow(clippy::module_name_repetitions)]
 pub struct LSPHandlers {
     cfg: LSPConfig,
+    // Experimental toggles can be changed at runtime through
+    // `workspace/didChangeConfiguration`.
+    experimental: Mutex<LSPExperimental>,
     store: Mutex<HashMap<String, String>>,
+    // Used to drop idle documents from store when `store_idle_timeout` is set.
+    // Documents opened in the editor are never dropped since they can contain
+    // unsaved changes.
+    store_access: Mutex<HashMap<String, Instant>>,
+    evicted: Mutex<HashSet<String>>,
+    open_documents: Mutex<HashSet<String>>,
     nodes: Mutex<HashMap<String, HashMap<String, GitlabElement>>>,
     // ordered list by imports -> meaning it starts at root element and parses from top down as
     // parser would do
@@ -42,37 +79,86 @@ pub struct LSPHandlers {
     // becomes invalid
     stages_ordered_list: Mutex<Vec<String>>,
     variables: Mutex<HashMap<String, GitlabElement>>,
+    // Variables defined by each file. `variables` holds only one definition
+    // per name so these are used to fall back to another file's definition
+    // once a file stops defining it.
+    variable_origins: Mutex<HashMap<String, HashMap<String, GitlabElement>>>,
     components: Mutex<HashMap<String, Component>>,
     indexing_in_progress: Mutex<bool>,
+    // Base files are static so they are parsed only once and reused on
+    // every following indexing.
+    base_results: Mutex<Option<Vec<ParseResults>>>,
     parser: Box<dyn parser::Parser>,
+    fs_utils: Arc<dyn fs_utils::FSUtils>,
+    exclude: Vec<glob::Pattern>,
+    // Files reachable from root files, used by `strict_indexing`. It's
+    // recomputed from files included by each file whenever they are parsed,
+    // so files stop being reachable once their includes are removed.
+    reachable: Mutex<HashSet<String>>,
+    root_files: Mutex<HashSet<String>>,
+    includes: Mutex<HashMap<String, HashSet<String>>>,
+    predefined_variables: &'static HashSet<String>,
 }
 
 impl LSPHandlers {
-    pub fn new(cfg: LSPConfig, fs_utils: Box<dyn fs_utils::FSUtils>) -> LSPHandlers {
+    pub fn new(
+        cfg: LSPConfig,
+        experimental: LSPExperimental,
+        fs_utils: Box<dyn fs_utils::FSUtils>,
+    ) -> LSPHandlers {
         let store = Mutex::new(HashMap::new());
         let nodes = Mutex::new(HashMap::new());
         let stages = Mutex::new(HashMap::new());
         let variables = Mutex::new(HashMap::new());
         let components = Mutex::new(HashMap::new());
         let indexing_in_progress = Mutex::new(false);
+        let fs_utils: Arc<dyn fs_utils::FSUtils> = Arc::from(fs_utils);
+
+        let exclude = cfg
+            .configuration
+            .exclude
+            .iter()
+            .filter_map(|e| match glob::Pattern::new(e) {
+                Ok(p) => Some(p),
+                Err(err) => {
+                    error!("invalid exclude glob: {e}; got err: {err}");
+                    None
+                }
+            })
+            .collect();
 
         let events = LSPHandlers {
             cfg: cfg.clone(),
+            experimental: Mutex::new(experimental),
             store,
+            store_access: Mutex::new(HashMap::new()),
+            evicted: Mutex::new(HashSet::new()),
+            open_documents: Mutex::new(HashSet::new()),
             nodes,
             nodes_ordered_list: vec![].into(),
             stages_ordered_list: vec![].into(),
             stages,
             variables,
+            variable_origins: Mutex::new(HashMap::new()),
             components,
             indexing_in_progress,
+            base_results: Mutex::new(None),
             parser: Box::new(parser::ParserImpl::new(
                 cfg.remote_urls,
                 cfg.package_map,
+                cfg.configuration.mirrors,
                 cfg.cache_path,
+                cfg.default_include_ref,
                 Box::new(treesitter::TreesitterImpl::new()),
-                fs_utils,
+                Box::new(fs_utils.clone()),
             )),
+            fs_utils,
+            exclude,
+            reachable: Mutex::new(HashSet::new()),
+            root_files: Mutex::new(HashSet::new()),
+            includes: Mutex::new(HashMap::new()),
+            predefined_variables: PREDEFINED_VARIABLES
+                .get_or_init(LSPHandlers::parse_predefined_variables),
         };
 
         if let Err(err) = events.index_workspace(events.cfg.root_dir.as_str()) {
@@ -86,6 +172,24 @@ impl LSPHandlers {
         events
     }
 
+    fn parse_predefined_variables() -> HashSet<String> {
+        let parsed = serde_yaml::from_str::<HashMap<String, HashMap<String, serde_yaml::Value>>>(
+            GITLAB_PREDEFINED_VARIABLES,
+        );
+
+        match parsed {
+            Ok(mut parsed) => parsed
+                .remove("variables")
+                .unwrap_or_default()
+                .into_keys()
+                .collect(),
+            Err(err) => {
+                error!("error parsing predefined variables; got err: {err}");
+                HashSet::new()
+            }
+        }
+    }
+
     fn default_stages() -> Vec<String> {
         vec![
             ".pre".to_string(),
@@ -96,6 +200,212 @@ impl LSPHandlers {
         ]
     }
 
+    // Exclude globs are relative to root dir, but absolute ones are supported as well.
+    fn is_excluded(&self, uri: &str) -> bool {
+        if self.exclude.is_empty() {
+            return false;
+        }
+
+        let path = uri.strip_prefix("file://").unwrap_or(uri);
+        let relative = path
+            .strip_prefix(self.cfg.root_dir.trim_end_matches('/'))
+            .map_or(path, |p| p.trim_start_matches('/'));
+
+        self.exclude
+            .iter()
+            .any(|p| p.matches(relative) || p.matches(path))
+    }
+
+    // Excluded files are still kept in store so they can be navigated to,
+    // only their definitions aren't indexed.
+    fn remove_excluded(&self, results: &mut ParseResults) {
+        results.nodes.retain(|n| self.is_indexed(&n.uri));
+        results.stages.retain(|s| self.is_indexed(&s.uri));
+        results.variables.retain(|v| self.is_indexed(&v.uri));
+    }
+
+    fn is_indexed(&self, uri: &str) -> bool {
+        !self.is_excluded(uri) && self.is_reachable(uri)
+    }
+
+    fn is_reachable(&self, uri: &str) -> bool {
+        !self.cfg.configuration.strict_indexing || self.reachable.lock().unwrap().contains(uri)
+    }
+
+    // Files parsed again replace their includes, while includes which
+    // weren't followed keep the ones from their last parse.
+    fn update_reachable(&self, results: &ParseResults) {
+        if !self.cfg.configuration.strict_indexing {
+            return;
+        }
+
+        let mut includes = self.includes.lock().unwrap();
+        for file in &results.files {
+            includes.insert(file.path.clone(), HashSet::new());
+        }
+        for (parent, included) in &results.includes {
+            includes
+                .entry(parent.clone())
+                .or_default()
+                .insert(included.clone());
+        }
+
+        let mut reachable = HashSet::new();
+        let mut queue: Vec<String> = self.root_files.lock().unwrap().iter().cloned().collect();
+        while let Some(uri) = queue.pop() {
+            if let Some(included) = includes.get(&uri) {
+                queue.extend(included.iter().filter(|i| !reachable.contains(*i)).cloned());
+            }
+            reachable.insert(uri);
+        }
+
+        *self.reachable.lock().unwrap() = reachable;
+    }
+
+    // First root file which isn't excluded; `.gitlab-ci.yaml` is preferred
+    // over `.gitlab-ci.yml` when both exist.
+    fn resolve_root_file(&self, root_dir: &str) -> anyhow::Result<Option<PathBuf>> {
+        let mut root_files: Vec<PathBuf> = std::fs::read_dir(root_dir)?
+            .flatten()
+            .filter(|item| {
+                item.file_name() == ".gitlab-ci.yaml" || item.file_name() == ".gitlab-ci.yml"
+            })
+            .map(|item| item.path())
+            .filter(|path| !self.is_excluded(&path.to_string_lossy()))
+            .collect();
+
+        root_files.sort();
+
+        Ok(root_files.into_iter().next())
+    }
+
+    // Locks store making sure given document is loaded.
+    fn document_store(&self, uri: &str) -> MutexGuard<'_, HashMap<String, String>> {
+        let mut store = self.store.lock().unwrap();
+        self.reload_documents(&mut store, |evicted| evicted == uri);
+
+        self.store_access
+            .lock()
+            .unwrap()
+            .insert(uri.to_string(), Instant::now());
+
+        store
+    }
+
+    // Locks store making sure all documents are loaded. Used by features that
+    // search through all files.
+    fn full_store(&self) -> MutexGuard<'_, HashMap<String, String>> {
+        let mut store = self.store.lock().unwrap();
+        self.reload_documents(&mut store, |_| true);
+
+        store
+    }
+
+    fn reload_documents(&self, store: &mut HashMap<String, String>, filter: impl Fn(&str) -> bool) {
+        let mut evicted = self.evicted.lock().unwrap();
+        if evicted.is_empty() {
+            return;
+        }
+
+        let mut store_access = self.store_access.lock().unwrap();
+        evicted.retain(|uri| {
+            if !filter(uri) {
+                return true;
+            }
+
+            let content = Url::parse(uri)
+                .ok()
+                .and_then(|url| url.to_file_path().ok())
+                .and_then(|path| fs::read_to_string(path).ok());
+
+            if let Some(content) = content {
+                info!("reloaded evicted document: {uri}");
+                store.entry(uri.clone()).or_insert(content);
+                store_access.insert(uri.clone(), Instant::now());
+            } else {
+                error!("could not reload evicted document: {uri}");
+            }
+
+            false
+        });
+    }
+
+    // Marks document as freshly loaded into store.
+    fn touch_document(&self, uri: &str) {
+        self.store_access
+            .lock()
+            .unwrap()
+            .insert(uri.to_string(), Instant::now());
+        self.evicted.lock().unwrap().remove(uri);
+    }
+
+    // Drops documents that weren't accessed for `store_idle_timeout` from
+    // store. Parsed nodes are kept so only file contents are read again.
+    pub fn evict_idle_documents(&self) {
+        let Some(timeout) = self.cfg.store_idle_timeout else {
+            return;
+        };
+
+        let mut store = self.store.lock().unwrap();
+        let open_documents = self.open_documents.lock().unwrap();
+        let mut store_access = self.store_access.lock().unwrap();
+        let mut evicted = self.evicted.lock().unwrap();
+
+        let now = Instant::now();
+        store.retain(|uri, _| {
+            let last_access = *store_access.entry(uri.clone()).or_insert(now);
+            if open_documents.contains(uri) || now.duration_since(last_access) < timeout {
+                return true;
+            }
+
+            info!("evicting idle document: {uri}");
+            self.parser.drop_tree(uri);
+            store_access.remove(uri);
+            evicted.insert(uri.clone());
+
+            false
+        });
+    }
+
+    fn insert_variable(
+        &self,
+        all_variables: &mut HashMap<String, GitlabElement>,
+        variable: GitlabElement,
+    ) {
+        self.variable_origins
+            .lock()
+            .unwrap()
+            .entry(variable.uri.clone())
+            .or_default()
+            .insert(variable.key.clone(), variable.clone());
+
+        all_variables.insert(variable.key.clone(), variable);
+    }
+
+    // Forgets variables defined by the file, restoring definitions from other
+    // files for names which are still defined elsewhere.
+    fn remove_file_variables(&self, all_variables: &mut HashMap<String, GitlabElement>, uri: &str) {
+        let mut origins = self.variable_origins.lock().unwrap();
+        let Some(removed) = origins.remove(uri) else {
+            return;
+        };
+
+        for key in removed.keys() {
+            if all_variables.get(key).is_none_or(|v| v.uri != uri) {
+                continue;
+            }
+
+            match origins.values().find_map(|variables| variables.get(key)) {
+                Some(variable) => {
+                    all_variables.insert(key.clone(), variable.clone());
+                }
+                None => {
+                    all_variables.remove(key);
+                }
+            }
+        }
+    }
+
     // When renaming or some other action that will be handled later on we need
     // to prevent modifications on cached/downloaded files.
     fn can_path_be_modified(&self, path: &str) -> bool {
@@ -108,17 +418,30 @@ impl LSPHandlers {
     pub fn on_hover(&self, request: Request) -> Option<LSPResult> {
         let params = serde_json::from_value::<HoverParams>(request.params).ok()?;
 
-        let store = self.store.lock().unwrap();
+        let uri = &params.text_document_position_params.text_document.uri;
+        let store = self.document_store(uri.as_str());
         let node_list = self.nodes_ordered_list.lock().unwrap();
         let nodes = self.nodes.lock().unwrap();
 
-        let uri = &params.text_document_position_params.text_document.uri;
         let document = store.get::<String>(&uri.to_string())?;
 
         let position = params.text_document_position_params.position;
         let line = document.lines().nth(position.line as usize)?;
 
-        let word = parser_utils::ParserUtils::extract_word(line, position.character as usize)?
+        if let Some(keyword) = self
+            .parser
+            .get_all_default_keywords(uri.as_str(), document)
+            .into_iter()
+            .filter(|k| keywords::is_default_keyword(&k.key))
+            .find(|k| k.range.start.line <= position.line && k.range.end.line >= position.line)
+        {
+            return Some(LSPResult::Hover(HoverResult {
+                id: request.id,
+                content: self.default_keyword_hover(&keyword, &nodes, &node_list),
+            }));
+        }
+
+        let word = parser_utils::ParserUtils::extract_key(line, position.character as usize)?
             .trim_end_matches(':');
 
         match self.parser.get_position_type(document, position) {
@@ -130,7 +453,7 @@ impl LSPHandlers {
                                 GitlabElement {
                                     key: key.clone(),
                                     content: element.content.clone(),
-                                    uri: document_uri.to_string(),
+                                    uri: document_uri.clone(),
                                     ..Default::default()
                                 },
                                 &node_list,
@@ -170,14 +493,14 @@ impl LSPHandlers {
 
                         return Some(LSPResult::Hover(HoverResult {
                             id: request.id,
-                            content: format!("```yaml\n{cnt}\n```"),
+                            content: LSPHandlers::job_hover(&cnt),
                         }));
                     }
                 }
 
                 None
             }
-            parser::PositionType::RuleReference(RuleReference { node }) => {
+            parser::PositionType::RuleReference(RuleReference { node, path }) => {
                 for (document_uri, n) in nodes.iter() {
                     for (key, element) in n {
                         if key.eq(&node) {
@@ -185,7 +508,7 @@ impl LSPHandlers {
                                 GitlabElement {
                                     key: key.clone(),
                                     content: element.content.clone(),
-                                    uri: document_uri.to_string(),
+                                    uri: document_uri.clone(),
                                     ..Default::default()
                                 },
                                 &node_list,
@@ -194,6 +517,13 @@ impl LSPHandlers {
                                 Err(err) => return Some(LSPResult::Error(err)),
                             };
 
+                            // only referenced key is shown, e.g. `script` of `.setup`
+                            let cnt = if path.is_empty() {
+                                cnt
+                            } else {
+                                ParserUtils::reference_content(&cnt, &path)?
+                            };
+
                             return Some(LSPResult::Hover(HoverResult {
                                 id: request.id,
                                 content: format!("```yaml\n{cnt}\n```"),
@@ -206,10 +536,8 @@ impl LSPHandlers {
             }
 
             parser::PositionType::Needs(NodeDefinition { name }) => {
-                let need_split = ParserUtils::strip_quotes(&name)
-                    .split(' ')
-                    .collect::<Vec<&str>>();
-                let node_name = need_split.first()?;
+                let name = ParserUtils::strip_quotes(&name);
+                let node_name = ParserUtils::matrix_job_name(name).unwrap_or(name);
 
                 for (document_uri, n) in nodes.iter() {
                     for (key, element) in n {
@@ -218,7 +546,7 @@ impl LSPHandlers {
                                 GitlabElement {
                                     key: key.clone(),
                                     content: element.content.clone(),
-                                    uri: document_uri.to_string(),
+                                    uri: document_uri.clone(),
                                     ..Default::default()
                                 },
                                 &node_list,
@@ -229,7 +557,7 @@ impl LSPHandlers {
 
                             return Some(LSPResult::Hover(HoverResult {
                                 id: request.id,
-                                content: format!("```yaml\n{cnt}\n```"),
+                                content: LSPHandlers::job_hover(&cnt),
                             }));
                         }
                     }
@@ -237,25 +565,222 @@ impl LSPHandlers {
 
                 None
             }
+            parser::PositionType::Variable | parser::PositionType::Script(_) => {
+                let name =
+                    parser_utils::ParserUtils::extract_variable(line, position.character as usize)?;
+
+                // job level definitions, including ones from extends, take
+                // precedence over root level variables
+                let mut definitions = self
+                    .parser
+                    .get_variable_definitions(name, uri.as_str(), position, &store, &node_list)
+                    .unwrap_or_default();
+
+                if let Some(root) = self.variables.lock().unwrap().get(name) {
+                    definitions.push(root.clone());
+                }
+
+                let definitions: Vec<(String, VariableDefinition)> = definitions
+                    .into_iter()
+                    .filter_map(|d| {
+                        let definition = VariableDefinition::from_content(d.content.as_deref()?)?;
+                        Some((self.relative_include_path(&d.uri).to_string(), definition))
+                    })
+                    .collect();
+
+                let predefined = self.predefined_variables.contains(name);
+                if definitions.is_empty() && !predefined {
+                    return None;
+                }
+
+                let mut sections = vec![format!("**${name}**")];
+                if predefined {
+                    sections.push("Predefined GitLab CI/CD variable.".to_string());
+                }
+                for (idx, (path, definition)) in definitions.into_iter().enumerate() {
+                    if idx > 0 {
+                        sections.push("---".to_string());
+                    }
+
+                    sections.append(&mut definition.details());
+                    sections.push(format!("defined in `{path}`"));
+                }
+
+                Some(LSPResult::Hover(HoverResult {
+                    id: request.id,
+                    content: sections.join("\n\n"),
+                }))
+            }
+            parser::PositionType::Service(service) => Some(LSPResult::Hover(HoverResult {
+                id: request.id,
+                content: service.hover(),
+            })),
+            parser::PositionType::Include(IncludeInformation {
+                remote: Some(remote),
+                ..
+            }) => {
+                let project = ParserUtils::strip_quotes(remote.project.as_deref()?);
+                let reference = remote.reference.as_deref().map(ParserUtils::strip_quotes);
+                let metadata = self.parser.fetch_metadata(project, reference)?;
+
+                Some(LSPResult::Hover(HoverResult {
+                    id: request.id,
+                    content: metadata.hover(),
+                }))
+            }
+            parser::PositionType::Include(IncludeInformation {
+                component: Some(component),
+                ..
+            }) => {
+                let hovered = component.inputs.iter().find(|i| i.hovered)?;
+                let components = self.components.lock().unwrap();
+                let input = components
+                    .get(&component.uri)?
+                    .inputs
+                    .iter()
+                    .find(|i| i.key == hovered.key)?;
+
+                Some(LSPResult::Hover(HoverResult {
+                    id: request.id,
+                    content: input.autocomplete_details(),
+                }))
+            }
+            parser::PositionType::Trigger => {
+                let trigger = self.job_trigger(document, position)?;
+
+                Some(LSPResult::Hover(HoverResult {
+                    id: request.id,
+                    content: trigger.hover(),
+                }))
+            }
             _ => None,
         }
     }
 
+    // Lists jobs which set their own value for keyword defined under `default:`.
+    fn default_keyword_hover(
+        &self,
+        keyword: &GitlabElement,
+        nodes: &HashMap<String, HashMap<String, GitlabElement>>,
+        node_list: &[GitlabFileElements],
+    ) -> String {
+        // full definitions are resolved without defaults so only values
+        // set by jobs themselves or their extends remain
+        let node_list: Vec<GitlabFileElements> = node_list
+            .iter()
+            .map(|file| GitlabFileElements {
+                uri: file.uri.clone(),
+                elements: file
+                    .elements
+                    .iter()
+                    .filter(|e| e.key != "default")
+                    .cloned()
+                    .collect(),
+            })
+            .collect();
+
+        let mut jobs: Vec<&str> = nodes
+            .iter()
+            .flat_map(|(uri, n)| n.iter().map(move |(key, element)| (uri, key, element)))
+            .filter(|(_, key, _)| !key.starts_with('.') && !keywords::is_root_keyword(key))
+            .filter(|(uri, key, element)| {
+                let Ok(definition) = self.parser.get_full_definition(
+                    GitlabElement {
+                        key: (*key).clone(),
+                        content: element.content.clone(),
+                        uri: (*uri).clone(),
+                        ..Default::default()
+                    },
+                    &node_list,
+                ) else {
+                    return false;
+                };
+
+                serde_yaml::from_str::<serde_yaml::Value>(&definition)
+                    .ok()
+                    .and_then(|root| root.get(key.as_str()).cloned())
+                    .is_some_and(|job| job.get(keyword.key.as_str()).is_some())
+            })
+            .map(|(_, key, _)| key.as_str())
+            .collect();
+        jobs.sort_unstable();
+        jobs.dedup();
+
+        let definition = keyword.content.as_deref().unwrap_or_default();
+        if jobs.is_empty() {
+            return format!(
+                "```yaml\n{definition}\n```\n\nNo jobs override `{}`.",
+                keyword.key
+            );
+        }
+
+        format!(
+            "```yaml\n{definition}\n```\n\nOverridden by jobs: {}",
+            jobs.iter()
+                .map(|job| format!("`{job}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+
+    fn job_hover(definition: &str) -> String {
+        let mut content = format!("```yaml\n{definition}\n```");
+        if ParserUtils::is_never_run(definition) {
+            content.push_str("\n\nJob never runs: its only rule is `when: never`.");
+        }
+
+        content
+    }
+
     pub fn on_change(&self, notification: Notification) -> Option<LSPResult> {
         let start = Instant::now();
         let params =
             serde_json::from_value::<DidChangeTextDocumentParams>(notification.params).ok()?;
 
-        if params.content_changes.len() != 1 {
-            return None;
+        let document_uri = params.text_document.uri.to_string();
+        let mut store = self.document_store(&document_uri);
+
+        // changes are applied in order, each one to the result of previous
+        let old_content = store.get(&document_uri).cloned().unwrap_or_default();
+        let mut content = old_content.clone();
+        // edits are applied to cached tree as well so document is reparsed
+        // incrementally; replaced document is parsed from scratch
+        let mut edits = Some(vec![]);
+        for change in &params.content_changes {
+            let Some(range) = change.range else {
+                content.clone_from(&change.text);
+                edits = None;
+                continue;
+            };
+
+            let (Some(start), Some(end)) = (
+                ParserUtils::position_offset(&content, range.start),
+                ParserUtils::position_offset(&content, range.end),
+            ) else {
+                error!("invalid change range: {range:?} for: {document_uri}");
+                return None;
+            };
+
+            if let Some(edits) = edits.as_mut() {
+                edits.push(ParserUtils::input_edit(&content, start, end, &change.text));
+            }
+            content.replace_range(start..end, &change.text);
         }
 
-        // TODO: nodes
+        if let Some(edits) = edits {
+            self.parser
+                .edit_tree(&document_uri, &old_content, &edits, &content);
+        }
 
-        let mut store = self.store.lock().unwrap();
         let mut all_nodes = self.nodes.lock().unwrap();
         let mut all_nodes_ordered_list = self.nodes_ordered_list.lock().unwrap();
         let mut all_stages_ordered_list = self.stages_ordered_list.lock().unwrap();
+
+        self.open_documents
+            .lock()
+            .unwrap()
+            .insert(params.text_document.uri.to_string());
+
         // reset previous
         all_nodes.insert(params.text_document.uri.to_string(), HashMap::new());
 
@@ -263,12 +788,15 @@ impl LSPHandlers {
 
         let mut all_components = self.components.lock().unwrap();
 
-        if let Some(results) = self.parser.parse_contents(
-            &params.text_document.uri,
-            &params.content_changes.first()?.text,
-            false,
-        ) {
+        if let Some(mut results) =
+            self.parser
+                .parse_contents(&params.text_document.uri, &content, false)
+        {
+            self.update_reachable(&results);
+            self.remove_excluded(&mut results);
+
             for file in results.files {
+                self.touch_document(&file.path);
                 store.insert(file.path, file.content);
             }
 
@@ -310,11 +838,11 @@ impl LSPHandlers {
                 );
             }
 
-            // should be per file...
-            // TODO: clear correct variables
+            // variables removed from the document shouldn't linger
+            self.remove_file_variables(&mut all_variables, &document_uri);
             for variable in results.variables {
                 info!("found variable: {:?}", &variable);
-                all_variables.insert(variable.key.clone(), variable);
+                self.insert_variable(&mut all_variables, variable);
             }
 
             for component in results.components {
@@ -328,7 +856,8 @@ impl LSPHandlers {
         None
     }
 
-    pub fn on_open(&self, notification: Notification) -> Option<LSPResult> {
+    // Diagnostics aren't generated here so they can be debounced by caller.
+    pub fn on_open(&self, notification: Notification) -> Option<Url> {
         let in_progress = self.indexing_in_progress.lock().unwrap();
         drop(in_progress);
 
@@ -339,14 +868,47 @@ impl LSPHandlers {
         let mut all_nodes = self.nodes.lock().unwrap();
         let mut all_stages = self.stages.lock().unwrap();
 
-        if let Some(results) =
+        self.open_documents
+            .lock()
+            .unwrap()
+            .insert(params.text_document.uri.to_string());
+
+        if let Some(mut results) =
             self.parser
                 .parse_contents(&params.text_document.uri, &params.text_document.text, true)
         {
+            self.update_reachable(&results);
+            self.remove_excluded(&mut results);
+
             for file in results.files {
+                self.touch_document(&file.path);
                 store.insert(file.path, file.content);
             }
 
+            // opened document isn't necessarily reachable from root files, so
+            // it's indexed here as well, replacing elements of previous parse
+            let mut all_nodes_ordered_list = self.nodes_ordered_list.lock().unwrap();
+            let mut indexed: HashSet<&str> = HashSet::new();
+            for node in &results.nodes {
+                let idx = all_nodes_ordered_list
+                    .iter()
+                    .position(|e| e.uri == node.uri)
+                    .unwrap_or_else(|| {
+                        all_nodes_ordered_list.push(GitlabFileElements {
+                            uri: node.uri.clone(),
+                            elements: vec![],
+                        });
+                        all_nodes_ordered_list.len() - 1
+                    });
+
+                let file = &mut all_nodes_ordered_list[idx];
+                if indexed.insert(&node.uri) {
+                    file.elements.clear();
+                }
+                file.elements.push(node.clone());
+            }
+            drop(all_nodes_ordered_list);
+
             for node in results.nodes {
                 info!("found node: {:?}", &node);
 
@@ -360,24 +922,37 @@ impl LSPHandlers {
                 info!("found stage: {:?}", &stage);
                 all_stages.insert(stage.key.clone(), stage);
             }
+
+            let mut all_variables = self.variables.lock().unwrap();
+            for variable in results.variables {
+                info!("found variable: {:?}", &variable);
+                self.insert_variable(&mut all_variables, variable);
+            }
         }
 
         info!("finished searching");
 
-        // releasing lock because generate diagnostics grabs it
-        // and is used in two places
-        drop(store);
-        drop(all_nodes);
-        drop(all_stages);
+        Some(params.text_document.uri)
+    }
+
+    // Closed documents stay in store, as other files can include them, until
+    // they are evicted for being idle.
+    pub fn on_close(&self, notification: Notification) -> Option<LSPResult> {
+        let params =
+            serde_json::from_value::<DidCloseTextDocumentParams>(notification.params).ok()?;
+        let uri = params.text_document.uri.to_string();
+
+        self.open_documents.lock().unwrap().remove(&uri);
+        self.parser.drop_tree(&uri);
 
-        self.generate_diagnostics(params.text_document.uri)
+        None
     }
 
     #[allow(clippy::too_many_lines)]
     pub fn on_definition(&self, request: Request) -> Option<LSPResult> {
         let params = serde_json::from_value::<GotoTypeDefinitionParams>(request.params).ok()?;
 
-        let store = self.store.lock().unwrap();
+        let store = self.full_store();
         let store = &*store;
         let node_list = self.nodes_ordered_list.lock().unwrap();
         let document_uri = params.text_document_position_params.text_document.uri;
@@ -387,19 +962,41 @@ impl LSPHandlers {
 
         let mut locations: Vec<LSPLocation> = vec![];
 
+        // `$[[ inputs.name ]]` can be used anywhere in component template,
+        // including values of other inputs, and resolves to spec input
+        let line = document.lines().nth(position.line as usize)?;
+        if let Some(input) = ParserUtils::input_interpolation(line, position.character as usize) {
+            locations.extend(
+                self.parser
+                    .get_all_spec_inputs(document_uri.as_str(), document)
+                    .into_iter()
+                    .filter(|i| i.key == input)
+                    .map(|i| LSPLocation {
+                        uri: i.uri,
+                        range: i.range,
+                    }),
+            );
+
+            return Some(LSPResult::Definition(DefinitionResult {
+                id: request.id,
+                locations,
+            }));
+        }
+
         match self.parser.get_position_type(document, position) {
-            parser::PositionType::RootNode
+            position_type @ (parser::PositionType::RootNode
             | parser::PositionType::Extend
-            | parser::PositionType::Dependency => {
+            | parser::PositionType::Dependency) => {
                 let line = document.lines().nth(position.line as usize)?;
                 let word =
-                    parser_utils::ParserUtils::extract_word(line, position.character as usize)?
+                    parser_utils::ParserUtils::extract_key(line, position.character as usize)?
                         .trim_end_matches(':');
 
                 for (uri, content) in store {
                     if let Some(element) = self.parser.get_root_node(uri, content, word) {
                         if document_uri.as_str().ends_with(uri)
-                            && line.eq(&format!("{}:", element.key.as_str()))
+                            && parser_utils::ParserUtils::strip_quotes(line.trim_end_matches(':'))
+                                .eq(element.key.as_str())
                         {
                             continue;
                         }
@@ -410,51 +1007,62 @@ impl LSPHandlers {
                         });
                     }
                 }
+
+                // `extends: base` is usually meant as `.base` template so it's
+                // offered when there is no exact match
+                if locations.is_empty()
+                    && matches!(position_type, parser::PositionType::Extend)
+                    && !word.starts_with('.')
+                {
+                    let template = format!(".{}", ParserUtils::strip_quotes(word));
+                    for (uri, content) in store {
+                        if let Some(element) = self.parser.get_root_node(uri, content, &template) {
+                            locations.push(LSPLocation {
+                                uri: uri.clone(),
+                                range: element.range,
+                            });
+                        }
+                    }
+                }
             }
             parser::PositionType::Include(info) => {
-                if let Some(include) = self.on_definition_include(info, store) {
+                if let Some(include) = self.on_definition_include(&document_uri, info, store) {
                     locations.push(include);
                 }
             }
+            parser::PositionType::Trigger => {
+                let trigger = self.job_trigger(document, position)?;
+
+                locations.extend(
+                    trigger
+                        .includes()
+                        .into_iter()
+                        .filter_map(|info| self.on_definition_include(&document_uri, info, store)),
+                );
+            }
             parser::PositionType::Needs(node) => {
-                for (uri, content) in store {
-                    let need_split = node.name.split(' ').collect::<Vec<&str>>();
-
-                    // need can be: needs: "wow [matrix1, matrix2]
-                    // currently just ignore matrixes
-                    match need_split.len() {
-                        1 => {
-                            if let Some(element) = self.parser.get_root_node(
-                                uri,
-                                content,
-                                parser_utils::ParserUtils::strip_quotes(node.name.as_str()),
-                            ) {
-                                locations.push(LSPLocation {
-                                    uri: uri.clone(),
-                                    range: element.range,
-                                });
-                            }
-                        }
+                let name = parser_utils::ParserUtils::strip_quotes(node.name.as_str());
 
-                        2 => {
-                            if let Some(element) = self.parser.get_root_node(
-                                uri,
-                                content,
-                                parser_utils::ParserUtils::strip_quotes(need_split[0]),
-                            ) {
-                                locations.push(LSPLocation {
-                                    uri: uri.clone(),
-                                    range: element.range,
-                                });
-                            }
-                        }
+                // need can reference a job generated by `parallel:matrix`:
+                // needs: "build: [linux, arm64]"
+                let matrix_job = parser_utils::ParserUtils::matrix_job_name(name);
 
-                        invalid => {
-                            warn!(
-                                "gotoref: invalid split len. got: {invalid}; needs: {need_split:?}"
-                            );
-                        }
+                for (uri, content) in store {
+                    let Some(element) =
+                        self.parser
+                            .get_root_node(uri, content, matrix_job.unwrap_or(name))
+                    else {
+                        continue;
                     };
+
+                    let range = matrix_job
+                        .and_then(|job| self.parser.job_parallel_matrix(uri, content, job))
+                        .map_or(element.range, |matrix| matrix.range);
+
+                    locations.push(LSPLocation {
+                        uri: uri.clone(),
+                        range,
+                    });
                 }
             }
             parser::PositionType::Stage => {
@@ -469,7 +1077,7 @@ impl LSPHandlers {
                     });
                 }
             }
-            parser::PositionType::Variable => {
+            parser::PositionType::Variable | parser::PositionType::Script(_) => {
                 let line = document.lines().nth(position.line as usize)?;
                 let word =
                     parser_utils::ParserUtils::extract_variable(line, position.character as usize)?;
@@ -502,21 +1110,35 @@ impl LSPHandlers {
 
                 locations.append(&mut root);
             }
-            parser::PositionType::RuleReference(RuleReference { node }) => {
-                for (uri, content) in store {
-                    if let Some(element) = self.parser.get_root_node(uri, content, &node) {
-                        locations.push(LSPLocation {
-                            uri: uri.clone(),
-                            range: element.range,
-                        });
-                    }
-                }
+            parser::PositionType::RuleReference(RuleReference { node, path }) => {
+                locations.extend(
+                    store
+                        .iter()
+                        .filter_map(|(uri, content)| self.key_location(uri, content, &node, &path)),
+                );
             }
             parser::PositionType::None => {
+                locations.extend(self.on_definition_inherited_key(
+                    document_uri.as_str(),
+                    position,
+                    store,
+                    &node_list,
+                )?);
+            }
+            parser::PositionType::Service(_)
+            | parser::PositionType::AllowFailure
+            | parser::PositionType::DeploymentTier
+            | parser::PositionType::DockerPlatform
+            | parser::PositionType::DockerUser
+            | parser::PositionType::Image
+            | parser::PositionType::ChangesPath
+            | parser::PositionType::ChangesCompareTo
+            | parser::PositionType::EmptyNeeds
+            | parser::PositionType::ComponentInputs(_) => {
                 error!("invalid position type for goto def");
                 return None;
             }
-        };
+        }
 
         Some(LSPResult::Definition(DefinitionResult {
             id: request.id,
@@ -524,10 +1146,72 @@ impl LSPHandlers {
         }))
     }
 
-    #[allow(clippy::too_many_lines)]
-    fn on_definition_include(
-        &self,
-        info: IncludeInformation,
+    // Trigger of the job at position.
+    fn job_trigger(&self, document: &str, position: Position) -> Option<Trigger> {
+        let job = self.parser.get_root_node_at_position(document, position)?;
+
+        serde_yaml::from_str::<HashMap<String, TriggerJob>>(&job.content?)
+            .ok()?
+            .into_values()
+            .next()?
+            .trigger
+    }
+
+    // Key inside a job resolves to templates from its extends chain which
+    // define the same key, closest one first.
+    fn on_definition_inherited_key(
+        &self,
+        uri: &str,
+        position: Position,
+        store: &HashMap<String, String>,
+        node_list: &[GitlabFileElements],
+    ) -> Option<Vec<LSPLocation>> {
+        let document = store.get(uri)?;
+        let (job, path) = self.parser.get_key_path(document, position)?;
+        let element = self.nodes.lock().unwrap().get(uri)?.get(&job)?.clone();
+
+        Some(
+            self.parser
+                .get_inherited_key_definitions(element, node_list, &path)
+                .into_iter()
+                .filter_map(|template| {
+                    let content = store.get(&template.uri)?;
+                    self.key_location(&template.uri, content, &template.key, &path)
+                })
+                .collect(),
+        )
+    }
+
+    // Location of key at `path` inside root node. Keys which aren't in the
+    // node itself, e.g. inherited through extends, resolve to the node.
+    fn key_location(
+        &self,
+        uri: &str,
+        content: &str,
+        node: &str,
+        path: &[String],
+    ) -> Option<LSPLocation> {
+        let element = self.parser.get_root_node(uri, content, node)?;
+
+        let range = if path.is_empty() {
+            element.range
+        } else {
+            self.parser
+                .job_key_definition(uri, content, node, path)
+                .map_or(element.range, |key| key.range)
+        };
+
+        Some(LSPLocation {
+            uri: uri.to_string(),
+            range,
+        })
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn on_definition_include(
+        &self,
+        document_uri: &Url,
+        info: IncludeInformation,
         store: &HashMap<String, String>,
     ) -> Option<LSPLocation> {
         match info {
@@ -540,7 +1224,8 @@ impl LSPHandlers {
             } => {
                 let local = parser_utils::ParserUtils::strip_quotes(&local.path);
 
-                LSPHandlers::on_definition_local(local, store)
+                LSPHandlers::on_definition_local(document_uri, local, store)
+                    .or_else(|| LSPHandlers::on_definition_unindexed_local(document_uri, local))
             }
             IncludeInformation {
                 local: None,
@@ -552,17 +1237,26 @@ impl LSPHandlers {
                 let file = remote.file?;
                 let file = parser_utils::ParserUtils::strip_quotes(&file).trim_start_matches('/');
 
-                let path = if let Some(reference) = remote.reference {
-                    format!("{}/{}/{}", remote.project?, reference, file)
-                } else {
-                    format!("{}/{}/{}", remote.project?, DEFAULT_BRANCH_SUBFOLDER, file)
-                };
+                let reference = remote
+                    .reference
+                    .clone()
+                    .or_else(|| self.cfg.default_include_ref.clone())
+                    .unwrap_or_else(|| DEFAULT_BRANCH_SUBFOLDER.to_string());
+                let project = remote.project?;
+                let path = format!("{project}/{reference}/{file}");
 
+                // child pipeline includes aren't fetched while parsing
                 store
                     .keys()
                     .find(|uri| uri.ends_with(&path))
+                    .cloned()
+                    .or_else(|| {
+                        self.parser
+                            .fetch_project_file(&project, remote.reference.as_deref(), file)
+                            .map(|file| file.path)
+                    })
                     .map(|uri| LSPLocation {
-                        uri: uri.clone(),
+                        uri,
                         range: Range {
                             start: LSPPosition {
                                 line: 0,
@@ -628,22 +1322,52 @@ impl LSPHandlers {
                 if let Ok(url) = Url::parse(url) {
                     LSPHandlers::on_definition_remote(url.as_str(), store)
                 } else {
-                    LSPHandlers::on_definition_local(url, store)
+                    LSPHandlers::on_definition_local(document_uri, url, store)
+                        .or_else(|| LSPHandlers::on_definition_unindexed_local(document_uri, url))
                 }
             }
             _ => None,
         }
     }
 
+    // Child pipeline files aren't indexed so they are resolved from disk.
+    fn on_definition_unindexed_local(document_uri: &Url, local_url: &str) -> Option<LSPLocation> {
+        let uri = ParserUtils::canonical_uri(&document_uri.join(local_url).ok()?);
+        if !uri.to_file_path().ok()?.is_file() {
+            return None;
+        }
+
+        Some(LSPLocation {
+            uri: uri.to_string(),
+            range: Range {
+                start: LSPPosition {
+                    line: 0,
+                    character: 0,
+                },
+                end: LSPPosition {
+                    line: 0,
+                    character: 0,
+                },
+            },
+        })
+    }
+
     pub fn on_definition_local(
+        document_uri: &Url,
         local_url: &str,
         store: &HashMap<String, String>,
     ) -> Option<LSPLocation> {
+        // symlinked includes are stored under their canonical path
+        let canonical = document_uri
+            .join(local_url)
+            .map(|uri| ParserUtils::canonical_uri(&uri).to_string())
+            .ok();
         let local_url = local_url.trim_start_matches('.');
 
         store
             .keys()
-            .find(|uri| uri.ends_with(local_url))
+            .find(|uri| canonical.as_ref() == Some(*uri))
+            .or_else(|| store.keys().find(|uri| uri.ends_with(local_url)))
             .map(|uri| LSPLocation {
                 uri: uri.clone(),
                 range: Range {
@@ -687,21 +1411,59 @@ impl LSPHandlers {
         let start = Instant::now();
         let params: CompletionParams = serde_json::from_value(request.params).ok()?;
 
-        let store = self.store.lock().unwrap();
         let document_uri = params.text_document_position.text_document.uri;
+        let store = self.document_store(document_uri.as_str());
         let document = store.get::<String>(&document_uri.clone().into())?;
 
         let position = params.text_document_position.position;
         let line = document.lines().nth(position.line as usize)?;
 
-        let items = match self.parser.get_position_type(document, position) {
+        let mut items = match self.parser.get_position_type(document, position) {
             parser::PositionType::Stage => self.on_completion_stages(line, position).ok()?,
             parser::PositionType::Dependency => self
                 .on_completion_dependencies(document_uri.as_ref(), document, line, position)
                 .ok()?,
-            parser::PositionType::Extend => self.on_completion_extends(line, position).ok()?,
-            parser::PositionType::Variable => self.on_completion_variables(line, position).ok()?,
+            parser::PositionType::Extend => self
+                .on_completion_extends(document_uri.as_str(), line, position)
+                .ok()?,
+            parser::PositionType::Variable => {
+                // `rules:if` comparisons against variables with known values
+                let values = LSPHandlers::on_completion_rule_condition(line, position).ok()?;
+                if values.is_empty() {
+                    self.on_completion_variables(document_uri.as_str(), document, line, position)
+                        .ok()?
+                } else {
+                    values
+                }
+            }
+            parser::PositionType::Script(script) => {
+                info!("completing variables inside: {}", script.key);
+                self.on_completion_variables(document_uri.as_str(), document, line, position)
+                    .ok()?
+            }
             parser::PositionType::Needs(_) => self.on_completion_needs(line, position).ok()?,
+            parser::PositionType::AllowFailure => {
+                LSPHandlers::on_completion_allow_failure(line, position).ok()?
+            }
+            parser::PositionType::DeploymentTier => {
+                LSPHandlers::on_completion_deployment_tier(line, position).ok()?
+            }
+            parser::PositionType::DockerPlatform => {
+                LSPHandlers::on_completion_docker_platform(line, position).ok()?
+            }
+            parser::PositionType::DockerUser => {
+                LSPHandlers::on_completion_docker_user(line, position).ok()?
+            }
+            parser::PositionType::Image => self
+                .on_completion_image(&store, document_uri.as_str(), line, position)
+                .ok()?,
+            // only service image is completed, not its alias or other attributes
+            parser::PositionType::Service(_)
+                if LSPHandlers::service_attribute(line).is_none_or(|key| key == "name") =>
+            {
+                self.on_completion_image(&store, document_uri.as_str(), line, position)
+                    .ok()?
+            }
             parser::PositionType::Include(IncludeInformation {
                 remote: None,
                 remote_url: None,
@@ -721,17 +1483,51 @@ impl LSPHandlers {
             parser::PositionType::RuleReference(_) => {
                 self.on_completion_rule_reference(line, position).ok()?
             }
+            parser::PositionType::ChangesPath => {
+                self.on_completion_changes_path(line, position).ok()?
+            }
+            parser::PositionType::ChangesCompareTo => {
+                self.on_completion_compare_to(line, position).ok()?
+            }
+            parser::PositionType::ComponentInputs(component) => self
+                .on_completion_component_inputs(line, position, &component)
+                .ok()?,
+            parser::PositionType::EmptyNeeds => {
+                self.on_completion_empty_needs(line, position).ok()?
+            }
             _ => return None,
         };
 
+        let is_incomplete = self.limit_completions(&mut items);
+
         info!("AUTOCOMPLETE ELAPSED: {:?}", start.elapsed());
 
         Some(LSPResult::Completion(CompletionResult {
             id: request.id,
             list: items,
+            is_incomplete,
         }))
     }
 
+    // Ranks completion items and caps them to the configured limit.
+    // Returns true when items were dropped so client knows it needs to refetch
+    // once user narrows down the search.
+    fn limit_completions(&self, items: &mut Vec<LSPCompletion>) -> bool {
+        items.sort_by(|a, b| {
+            let a_key = a.sort_text.as_ref().unwrap_or(&a.label);
+            let b_key = b.sort_text.as_ref().unwrap_or(&b.label);
+            a_key.cmp(b_key).then_with(|| a.label.cmp(&b.label))
+        });
+
+        match self.cfg.completion_limit {
+            Some(limit) if items.len() > limit => {
+                items.truncate(limit);
+                true
+            }
+            _ => false,
+        }
+    }
+
     fn on_completion_stages(
         &self,
         line: &str,
@@ -767,8 +1563,10 @@ impl LSPHandlers {
             .filter(|stage| stage.contains(word))
             .flat_map(|stage| -> anyhow::Result<LSPCompletion> {
                 Ok(LSPCompletion {
-                    label: stage.to_string(),
+                    label: stage.clone(),
                     details: None,
+                    sort_text: None,
+                    snippet: None,
                     location: LSPLocation {
                         range: Range {
                             start: LSPPosition {
@@ -819,11 +1617,13 @@ impl LSPHandlers {
         let all_stages_ordered_list = self.stages_ordered_list.lock().unwrap();
         let mut previous_stages = HashMap::new();
 
-        if self
-            .cfg
+        let stage_filtering = self
             .experimental
-            .dependencies_autocomplete_stage_filtering
-        {
+            .lock()
+            .map_err(|err| anyhow!("failed to lock experimental: {}", err))?
+            .dependencies_autocomplete_stage_filtering;
+
+        if stage_filtering {
             if let Some(root_node) = self.parser.get_root_node_at_position(document, position) {
                 if let Ok(full_definition) = self
                     .parser
@@ -849,11 +1649,7 @@ impl LSPHandlers {
             .flat_map(|needs| needs.iter())
             .filter(|(node_key, _)| !node_key.starts_with('.') && node_key.contains(word))
             .filter(|(_, element)| {
-                if self
-                    .cfg
-                    .experimental
-                    .dependencies_autocomplete_stage_filtering
-                {
+                if stage_filtering {
                     if previous_stages.keys().len() == 0 {
                         return true;
                     }
@@ -888,6 +1684,8 @@ impl LSPHandlers {
                         "```yaml\r\n{}\r\n```",
                         element.clone().content.unwrap_or(String::new())
                     )),
+                    sort_text: None,
+                    snippet: None,
                     location: LSPLocation {
                         range: Range {
                             start: LSPPosition {
@@ -912,6 +1710,7 @@ impl LSPHandlers {
 
     fn on_completion_extends(
         &self,
+        uri: &str,
         line: &str,
         position: Position,
     ) -> anyhow::Result<Vec<LSPCompletion>> {
@@ -920,28 +1719,25 @@ impl LSPHandlers {
             .lock()
             .map_err(|e| anyhow!("failed to lock nodes: {}", e))?;
 
-        let word = parser_utils::ParserUtils::word_before_cursor(
-            line,
-            position.character as usize,
-            |c: char| c.is_whitespace(),
-        );
-
-        let after =
-            parser_utils::ParserUtils::word_after_cursor(line, position.character as usize, |c| {
-                c.is_whitespace()
-            });
+        let (word, after) =
+            parser_utils::ParserUtils::completion_word(line, position.character as usize);
 
         let items = nodes
             .values()
             .flat_map(|n| n.iter())
             .filter(|(node_key, _)| node_key.starts_with('.') && node_key.contains(word))
             .flat_map(|(node_key, element)| -> anyhow::Result<LSPCompletion> {
+                // templates from the edited file are listed first
+                let rank = u8::from(element.uri != uri);
+
                 Ok(LSPCompletion {
-                    label: node_key.to_string(),
+                    label: node_key.clone(),
                     details: Some(format!(
                         "```yaml\r\n{}\r\n```",
                         element.clone().content.unwrap_or(String::new())
                     )),
+                    sort_text: Some(format!("{rank}{node_key}")),
+                    snippet: None,
                     location: LSPLocation {
                         range: Range {
                             start: LSPPosition {
@@ -966,9 +1762,16 @@ impl LSPHandlers {
 
     fn on_completion_variables(
         &self,
+        uri: &str,
+        document: &str,
         line: &str,
         position: Position,
     ) -> anyhow::Result<Vec<LSPCompletion>> {
+        // `$` inside regex literal is an anchor, not a variable
+        if parser_utils::ParserUtils::is_inside_regex(line, position.character as usize) {
+            return Ok(vec![]);
+        }
+
         let variables = self
             .variables
             .lock()
@@ -977,21 +1780,84 @@ impl LSPHandlers {
         let word = parser_utils::ParserUtils::word_before_cursor(
             line,
             position.character as usize,
-            |c: char| c == '$',
+            |c: char| c == '$' || c == '{',
         );
 
         let after =
             parser_utils::ParserUtils::word_after_cursor(line, position.character as usize, |c| {
-                c.is_whitespace()
+                !(c.is_alphanumeric() || c == '_')
             });
 
+        let job = self
+            .parser
+            .get_root_node_at_position(document, position)
+            .and_then(|job| job.content);
+
+        // secrets and id tokens are exposed as variables to the job defining them
+        let mut job_variables: Vec<GitlabElement> = job
+            .as_deref()
+            .map(|job| {
+                ["secrets", "id_tokens"]
+                    .iter()
+                    .flat_map(|keyword| self.parser.get_all_job_definitions(uri, job, keyword))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // variables blocks taken from another node using `!reference`
+        let root_variables = self
+            .parser
+            .get_root_node(uri, document, "variables")
+            .and_then(|variables| variables.content);
+
+        let references: Vec<String> = [job, root_variables]
+            .iter()
+            .flatten()
+            .filter_map(|definition| parser_utils::ParserUtils::variables_reference(definition))
+            .collect();
+
+        if !references.is_empty() {
+            let nodes = self
+                .nodes
+                .lock()
+                .map_err(|err| anyhow!("failed to lock nodes: {}", err))?;
+
+            for reference in &references {
+                for (node_uri, node) in nodes
+                    .iter()
+                    .filter_map(|(node_uri, nodes)| Some((node_uri, nodes.get(reference)?)))
+                {
+                    let Some(content) = node.content.as_deref() else {
+                        continue;
+                    };
+
+                    job_variables.append(&mut self.parser.get_all_job_definitions(
+                        node_uri,
+                        content,
+                        "variables",
+                    ));
+                }
+            }
+
+            let mut seen = HashSet::new();
+            job_variables.retain(|v| seen.insert(v.key.clone()));
+        }
+
         let items = variables
             .keys()
+            .chain(
+                job_variables
+                    .iter()
+                    .map(|v| &v.key)
+                    .filter(|v| !variables.contains_key(*v)),
+            )
             .filter(|v| v.starts_with(word))
             .flat_map(|v| -> anyhow::Result<LSPCompletion> {
                 Ok(LSPCompletion {
                     label: v.clone(),
                     details: None,
+                    sort_text: None,
+                    snippet: None,
                     location: LSPLocation {
                         range: Range {
                             start: LSPPosition {
@@ -1044,6 +1910,8 @@ impl LSPHandlers {
                         "```yaml\r\n{}\r\n```",
                         element.clone().content.unwrap_or(String::new())
                     )),
+                    sort_text: None,
+                    snippet: None,
                     location: LSPLocation {
                         range: Range {
                             start: LSPPosition {
@@ -1064,36 +1932,33 @@ impl LSPHandlers {
         Ok(items)
     }
 
-    fn on_completion_needs(
-        &self,
+    #[allow(clippy::unnecessary_wraps)]
+    fn on_completion_allow_failure(
         line: &str,
         position: Position,
     ) -> anyhow::Result<Vec<LSPCompletion>> {
-        let nodes = self
-            .nodes
-            .lock()
-            .map_err(|err| anyhow!("failed to lock nodes: {}", err))?;
         let word = parser_utils::ParserUtils::word_before_cursor(
             line,
             position.character as usize,
-            |c: char| c.is_whitespace(),
+            char::is_whitespace,
         );
         let after =
             parser_utils::ParserUtils::word_after_cursor(line, position.character as usize, |c| {
-                c.is_whitespace()
+                c.is_whitespace() || c == ':'
             });
 
-        let items = nodes
-            .values()
-            .flat_map(|needs| needs.iter())
-            .filter(|(node_key, _)| !node_key.starts_with('.') && node_key.contains(word))
-            .flat_map(|(node_key, element)| -> anyhow::Result<LSPCompletion> {
+        let items = ["exit_codes"]
+            .iter()
+            .filter(|key| key.starts_with(word))
+            .flat_map(|key| -> anyhow::Result<LSPCompletion> {
                 Ok(LSPCompletion {
-                    label: node_key.clone(),
-                    details: Some(format!(
-                        "```yaml\r\n{}\r\n```",
-                        element.clone().content.unwrap_or(String::new())
-                    )),
+                    label: (*key).to_string(),
+                    details: Some(
+                        "Exit codes which are allowed to fail. Single integer or list of integers."
+                            .to_string(),
+                    ),
+                    sort_text: None,
+                    snippet: None,
                     location: LSPLocation {
                         range: Range {
                             start: LSPPosition {
@@ -1114,91 +1979,550 @@ impl LSPHandlers {
         Ok(items)
     }
 
-    #[allow(clippy::too_many_lines)]
-    fn index_workspace(&self, root_dir: &str) -> anyhow::Result<()> {
-        let mut in_progress = self.indexing_in_progress.lock().unwrap();
-        *in_progress = true;
-
-        let start = Instant::now();
+    #[allow(clippy::unnecessary_wraps)]
+    fn on_completion_deployment_tier(
+        line: &str,
+        position: Position,
+    ) -> anyhow::Result<Vec<LSPCompletion>> {
+        let (word, after) =
+            parser_utils::ParserUtils::completion_word(line, position.character as usize);
 
-        let mut store = self.store.lock().unwrap();
-        let mut all_nodes = self.nodes.lock().unwrap();
-        let mut all_nodes_ordered_list = self.nodes_ordered_list.lock().unwrap();
-        let mut all_stages_ordered_list = self.stages_ordered_list.lock().unwrap();
-        let mut all_stages = self.stages.lock().unwrap();
-        let mut all_variables = self.variables.lock().unwrap();
-        let mut all_components = self.components.lock().unwrap();
+        let items = DEPLOYMENT_TIERS
+            .iter()
+            .filter(|tier| tier.starts_with(word))
+            .flat_map(|tier| -> anyhow::Result<LSPCompletion> {
+                Ok(LSPCompletion {
+                    label: (*tier).to_string(),
+                    details: None,
+                    sort_text: None,
+                    snippet: None,
+                    location: LSPLocation {
+                        range: Range {
+                            start: LSPPosition {
+                                line: position.line,
+                                character: position.character - u32::try_from(word.len())?,
+                            },
+                            end: LSPPosition {
+                                line: position.line,
+                                character: position.character + u32::try_from(after.len())?,
+                            },
+                        },
+                        ..Default::default()
+                    },
+                })
+            })
+            .collect();
 
-        info!("importing files from base");
-        let base_uri = format!("{}base", self.cfg.cache_path);
-        let base_uri_path = Url::parse(format!("file://{base_uri}/").as_str())?;
-        for dir in std::fs::read_dir(&base_uri)?.flatten() {
-            let file_uri = base_uri_path.join(dir.file_name().to_str().unwrap())?;
-            let file_content = std::fs::read_to_string(dir.path())?;
+        Ok(items)
+    }
 
-            if let Some(results) = self.parser.parse_contents(&file_uri, &file_content, false) {
-                for file in results.files {
-                    info!("found file: {:?}", &file);
-                    store.insert(file.path, file.content);
-                }
+    // Key of service attribute written on the line, e.g. `alias` for
+    // `alias: db`. Plain images such as `- postgres:15` have none.
+    fn service_attribute(line: &str) -> Option<&str> {
+        let entry = line.trim_start().trim_start_matches('-').trim_start();
 
-                for node in results.nodes {
-                    info!("found node: {:?}", &node);
+        entry
+            .split_once(": ")
+            .map(|(key, _)| key)
+            .or_else(|| entry.strip_suffix(':'))
+    }
 
-                    all_nodes
-                        .entry(node.uri.clone())
-                        .or_default()
-                        .insert(node.key.clone(), node);
-                }
+    // Offers images already used by `image:` or `services:` anywhere in the
+    // workspace so image references stay consistent across pipeline.
+    #[allow(clippy::unnecessary_wraps)]
+    fn on_completion_image(
+        &self,
+        store: &HashMap<String, String>,
+        uri: &str,
+        line: &str,
+        position: Position,
+    ) -> anyhow::Result<Vec<LSPCompletion>> {
+        let (word, after) =
+            parser_utils::ParserUtils::completion_word(line, position.character as usize);
 
-                for stage in results.stages {
-                    info!("found stage: {:?}", &stage);
-                    all_stages.insert(stage.key.clone(), stage);
-                }
+        // value being typed is not a previously seen image
+        let images: BTreeSet<String> = store
+            .iter()
+            .flat_map(|(store_uri, content)| self.parser.get_all_images(store_uri, content))
+            .filter(|image| !(image.uri == uri && image.range.start.line == position.line))
+            .map(|image| image.key)
+            .filter(|image| image.starts_with(word))
+            .collect();
 
-                for variable in results.variables {
-                    info!("found variable: {:?}", &variable);
-                    all_variables.insert(variable.key.clone(), variable);
-                }
+        let items = images
+            .into_iter()
+            .flat_map(|image| -> anyhow::Result<LSPCompletion> {
+                Ok(LSPCompletion {
+                    label: image,
+                    details: None,
+                    sort_text: None,
+                    snippet: None,
+                    location: LSPLocation {
+                        range: Range {
+                            start: LSPPosition {
+                                line: position.line,
+                                character: position.character - u32::try_from(word.len())?,
+                            },
+                            end: LSPPosition {
+                                line: position.line,
+                                character: position.character + u32::try_from(after.len())?,
+                            },
+                        },
+                        ..Default::default()
+                    },
+                })
+            })
+            .collect();
 
-                for component in results.components {
-                    info!("found component: {:?}", &component);
-                    all_components.insert(component.uri.clone(), component);
-                }
-            }
-        }
+        Ok(items)
+    }
 
-        info!("importing from root file");
-        let mut uri = Url::parse(format!("file://{root_dir}/").as_str())?;
-        info!("uri: {}", &uri);
+    // Completes right hand side of comparisons against predefined variables
+    // with known values, e.g. `$CI_PIPELINE_SOURCE == "push"`.
+    fn on_completion_rule_condition(
+        line: &str,
+        position: Position,
+    ) -> anyhow::Result<Vec<LSPCompletion>> {
+        let before = line
+            .get(..position.character as usize)
+            .ok_or_else(|| anyhow!("position out of line"))?;
+        let Some(captures) = RULE_COMPARISON_RE.captures(before) else {
+            return Ok(vec![]);
+        };
+        let variable = captures.get(1).map_or("", |m| m.as_str());
+        let quote = captures.get(2).map(|m| m.as_str());
+        let word = captures.get(3).map_or("", |m| m.as_str());
 
-        let list = std::fs::read_dir(root_dir)?;
-        let mut root_file: Option<PathBuf> = None;
+        let Some((_, values)) = PREDEFINED_VARIABLE_VALUES
+            .iter()
+            .find(|(name, _)| *name == variable)
+        else {
+            return Ok(vec![]);
+        };
 
-        for item in list.flatten() {
-            if item.file_name() == ".gitlab-ci.yaml" || item.file_name() == ".gitlab-ci.yml" {
-                root_file = Some(item.path());
-                break;
-            }
-        }
+        let items = values
+            .iter()
+            .filter(|value| value.starts_with(word))
+            .flat_map(|value| -> anyhow::Result<LSPCompletion> {
+                Ok(LSPCompletion {
+                    // values have to be compared as strings
+                    label: if quote.is_some() {
+                        (*value).to_string()
+                    } else {
+                        format!("\"{value}\"")
+                    },
+                    details: None,
+                    sort_text: None,
+                    snippet: None,
+                    location: LSPLocation {
+                        range: Range {
+                            start: LSPPosition {
+                                line: position.line,
+                                character: position.character - u32::try_from(word.len())?,
+                            },
+                            end: LSPPosition {
+                                line: position.line,
+                                character: position.character,
+                            },
+                        },
+                        ..Default::default()
+                    },
+                })
+            })
+            .collect();
 
-        let root_file_content = match root_file {
-            Some(root_file) => {
-                let file_name = root_file.file_name().unwrap().to_str().unwrap();
-                uri = uri.join(file_name)?;
+        Ok(items)
+    }
 
-                std::fs::read_to_string(root_file)?
-            }
-            _ => {
-                return Err(anyhow::anyhow!("root file missing"));
-            }
-        };
+    #[allow(clippy::unnecessary_wraps)]
+    fn on_completion_docker_platform(
+        line: &str,
+        position: Position,
+    ) -> anyhow::Result<Vec<LSPCompletion>> {
+        let (word, after) =
+            parser_utils::ParserUtils::completion_word(line, position.character as usize);
 
-        info!("URI: {}", &uri);
-        if let Some(results) = self.parser.parse_contents(&uri, &root_file_content, true) {
-            for file in results.files {
-                info!("found file: {:?}", &file);
-                store.insert(file.path, file.content);
+        let items = DOCKER_PLATFORMS
+            .iter()
+            .filter(|platform| platform.starts_with(word))
+            .flat_map(|platform| -> anyhow::Result<LSPCompletion> {
+                Ok(LSPCompletion {
+                    label: (*platform).to_string(),
+                    details: None,
+                    sort_text: None,
+                    snippet: None,
+                    location: LSPLocation {
+                        range: Range {
+                            start: LSPPosition {
+                                line: position.line,
+                                character: position.character - u32::try_from(word.len())?,
+                            },
+                            end: LSPPosition {
+                                line: position.line,
+                                character: position.character + u32::try_from(after.len())?,
+                            },
+                        },
+                        ..Default::default()
+                    },
+                })
+            })
+            .collect();
+
+        Ok(items)
+    }
+
+    #[allow(clippy::unnecessary_wraps)]
+    fn on_completion_docker_user(
+        line: &str,
+        position: Position,
+    ) -> anyhow::Result<Vec<LSPCompletion>> {
+        let (word, after) =
+            parser_utils::ParserUtils::completion_word(line, position.character as usize);
+
+        let items = DOCKER_USERS
+            .iter()
+            .filter(|user| user.starts_with(word))
+            .flat_map(|user| -> anyhow::Result<LSPCompletion> {
+                Ok(LSPCompletion {
+                    label: (*user).to_string(),
+                    details: None,
+                    sort_text: None,
+                    snippet: None,
+                    location: LSPLocation {
+                        range: Range {
+                            start: LSPPosition {
+                                line: position.line,
+                                character: position.character - u32::try_from(word.len())?,
+                            },
+                            end: LSPPosition {
+                                line: position.line,
+                                character: position.character + u32::try_from(after.len())?,
+                            },
+                        },
+                        ..Default::default()
+                    },
+                })
+            })
+            .collect();
+
+        Ok(items)
+    }
+
+    // Paths of `changes` are completed from workspace files, one directory
+    // level at a time.
+    fn on_completion_changes_path(
+        &self,
+        line: &str,
+        position: Position,
+    ) -> anyhow::Result<Vec<LSPCompletion>> {
+        fn is_separator(c: char) -> bool {
+            c.is_whitespace() || c == '"' || c == '\'' || c == '[' || c == ','
+        }
+
+        let typed =
+            ParserUtils::word_before_cursor(line, position.character as usize, is_separator);
+        let after = ParserUtils::word_after_cursor(line, position.character as usize, |c| {
+            is_separator(c) || c == ']' || c == '/'
+        });
+        let (dir, word) = typed.rsplit_once('/').unwrap_or(("", typed));
+
+        // paths are relative to repository so nothing outside of it is listed
+        let dir = Path::new(dir);
+        if dir.is_absolute()
+            || dir
+                .components()
+                .any(|c| matches!(c, std::path::Component::ParentDir))
+        {
+            return Ok(vec![]);
+        }
+
+        let dir = Path::new(&self.cfg.root_dir).join(dir);
+        let mut items = vec![];
+        for (name, is_dir) in self.fs_utils.read_dir(&dir.to_string_lossy())? {
+            if name == ".git" || !name.contains(word) {
+                continue;
+            }
+
+            let label = if is_dir { format!("{name}/") } else { name };
+
+            items.push(LSPCompletion {
+                label,
+                details: None,
+                sort_text: None,
+                snippet: None,
+                location: LSPLocation {
+                    range: Range {
+                        start: LSPPosition {
+                            line: position.line,
+                            character: position.character - u32::try_from(word.len())?,
+                        },
+                        end: LSPPosition {
+                            line: position.line,
+                            character: position.character + u32::try_from(after.len())?,
+                        },
+                    },
+                    ..Default::default()
+                },
+            });
+        }
+
+        Ok(items)
+    }
+
+    fn on_completion_compare_to(
+        &self,
+        line: &str,
+        position: Position,
+    ) -> anyhow::Result<Vec<LSPCompletion>> {
+        let (word, after) = ParserUtils::completion_word(line, position.character as usize);
+
+        self.parser
+            .git_refs(&self.cfg.root_dir)
+            .into_iter()
+            .filter(|reference| reference.contains(word))
+            .map(|reference| -> anyhow::Result<LSPCompletion> {
+                Ok(LSPCompletion {
+                    label: reference,
+                    details: None,
+                    sort_text: None,
+                    snippet: None,
+                    location: LSPLocation {
+                        range: Range {
+                            start: LSPPosition {
+                                line: position.line,
+                                character: position.character - u32::try_from(word.len())?,
+                            },
+                            end: LSPPosition {
+                                line: position.line,
+                                character: position.character + u32::try_from(after.len())?,
+                            },
+                        },
+                        ..Default::default()
+                    },
+                })
+            })
+            .collect()
+    }
+
+    fn on_completion_needs(
+        &self,
+        line: &str,
+        position: Position,
+    ) -> anyhow::Result<Vec<LSPCompletion>> {
+        let nodes = self
+            .nodes
+            .lock()
+            .map_err(|err| anyhow!("failed to lock nodes: {}", err))?;
+        let node_list = self
+            .nodes_ordered_list
+            .lock()
+            .map_err(|err| anyhow!("failed to lock nodes list: {}", err))?;
+        let (word, after) =
+            parser_utils::ParserUtils::completion_word(line, position.character as usize);
+
+        // jobs generated by `parallel:matrix` are needed as `job: [value, ..]`
+        if let Some((job, selector)) = word.split_once(" [") {
+            let job = job.trim_end_matches(':');
+            let selector_end = after.find(']').unwrap_or(after.len());
+
+            let combinations = nodes
+                .values()
+                .find_map(|n| n.get(job))
+                .and_then(|element| element.content.as_deref())
+                .map(|content| {
+                    self.parser
+                        .job_parallel_matrix_combinations("", content, job)
+                })
+                .unwrap_or_default();
+
+            let items = combinations
+                .iter()
+                .map(|combination| combination.join(", "))
+                .filter(|label| label.starts_with(selector))
+                .flat_map(|label| -> anyhow::Result<LSPCompletion> {
+                    Ok(LSPCompletion {
+                        label,
+                        details: None,
+                        sort_text: None,
+                        snippet: None,
+                        location: LSPLocation {
+                            range: Range {
+                                start: LSPPosition {
+                                    line: position.line,
+                                    character: position.character - u32::try_from(selector.len())?,
+                                },
+                                end: LSPPosition {
+                                    line: position.line,
+                                    character: position.character + u32::try_from(selector_end)?,
+                                },
+                            },
+                            ..Default::default()
+                        },
+                    })
+                })
+                .collect();
+
+            return Ok(items);
+        }
+
+        let items = nodes
+            .values()
+            .flat_map(|needs| needs.iter())
+            .filter(|(node_key, _)| {
+                !node_key.starts_with('.')
+                    && !keywords::is_root_keyword(node_key)
+                    && node_key.contains(word)
+            })
+            .flat_map(|(node_key, element)| -> anyhow::Result<LSPCompletion> {
+                // jobs that never run are unlikely needs so they are listed last
+                let rank = u8::from(self.is_never_run(element, &node_list));
+
+                Ok(LSPCompletion {
+                    label: node_key.clone(),
+                    details: Some(format!(
+                        "```yaml\r\n{}\r\n```",
+                        element.clone().content.unwrap_or(String::new())
+                    )),
+                    sort_text: Some(format!("{rank}{node_key}")),
+                    snippet: None,
+                    location: LSPLocation {
+                        range: Range {
+                            start: LSPPosition {
+                                line: position.line,
+                                character: position.character - u32::try_from(word.len())?,
+                            },
+                            end: LSPPosition {
+                                line: position.line,
+                                character: position.character + u32::try_from(after.len())?,
+                            },
+                        },
+                        ..Default::default()
+                    },
+                })
+            })
+            .collect();
+
+        Ok(items)
+    }
+
+    // Empty `needs:` is completed with job list items, or with `job: name`
+    // mappings when scaffolding is enabled.
+    fn on_completion_empty_needs(
+        &self,
+        line: &str,
+        position: Position,
+    ) -> anyhow::Result<Vec<LSPCompletion>> {
+        let scaffold = self
+            .experimental
+            .lock()
+            .map_err(|err| anyhow!("failed to lock experimental: {}", err))?
+            .needs_job_scaffold_completion;
+
+        let job = if scaffold { "job: " } else { "" };
+
+        let mut items = self.on_completion_needs(line, position)?;
+        for item in &mut items {
+            item.label = format!("- {job}{}", item.label);
+        }
+
+        Ok(items)
+    }
+
+    fn parse_base_files(&self) -> anyhow::Result<Vec<ParseResults>> {
+        let mut base_results = self.base_results.lock().unwrap();
+        if let Some(results) = base_results.as_ref() {
+            return Ok(results.clone());
+        }
+
+        let base_uri = format!("{}base", self.cfg.cache_path);
+        let base_uri_path = Url::parse(format!("file://{base_uri}/").as_str())?;
+
+        let mut results = vec![];
+        for dir in std::fs::read_dir(&base_uri)?.flatten() {
+            let file_uri = base_uri_path.join(dir.file_name().to_str().unwrap())?;
+            let file_content = std::fs::read_to_string(dir.path())?;
+
+            if let Some(mut result) = self.parser.parse_contents(&file_uri, &file_content, false) {
+                self.root_files.lock().unwrap().insert(file_uri.to_string());
+                self.update_reachable(&result);
+                self.remove_excluded(&mut result);
+                results.push(result);
+            }
+        }
+
+        *base_results = Some(results.clone());
+
+        Ok(results)
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn index_workspace(&self, root_dir: &str) -> anyhow::Result<()> {
+        let mut in_progress = self.indexing_in_progress.lock().unwrap();
+        *in_progress = true;
+
+        let start = Instant::now();
+
+        let mut store = self.store.lock().unwrap();
+        let mut all_nodes = self.nodes.lock().unwrap();
+        let mut all_nodes_ordered_list = self.nodes_ordered_list.lock().unwrap();
+        let mut all_stages_ordered_list = self.stages_ordered_list.lock().unwrap();
+        let mut all_stages = self.stages.lock().unwrap();
+        let mut all_variables = self.variables.lock().unwrap();
+        let mut all_components = self.components.lock().unwrap();
+
+        info!("importing files from base");
+        for results in self.parse_base_files()? {
+            for file in results.files {
+                info!("found file: {:?}", &file);
+                store.insert(file.path, file.content);
+            }
+
+            for node in results.nodes {
+                info!("found node: {:?}", &node);
+
+                all_nodes
+                    .entry(node.uri.clone())
+                    .or_default()
+                    .insert(node.key.clone(), node);
+            }
+
+            for stage in results.stages {
+                info!("found stage: {:?}", &stage);
+                all_stages.insert(stage.key.clone(), stage);
+            }
+
+            for variable in results.variables {
+                info!("found variable: {:?}", &variable);
+                self.insert_variable(&mut all_variables, variable);
+            }
+
+            for component in results.components {
+                info!("found component: {:?}", &component);
+                all_components.insert(component.uri.clone(), component);
+            }
+        }
+
+        info!("importing from root file");
+        let root_uri = Url::parse(format!("file://{root_dir}/").as_str())?;
+        info!("uri: {}", &root_uri);
+
+        let root_file = self
+            .resolve_root_file(root_dir)?
+            .ok_or_else(|| anyhow::anyhow!("root file missing"))?;
+
+        let file_name = root_file.file_name().unwrap().to_str().unwrap();
+        let uri = root_uri.join(file_name)?;
+        let root_file_content = std::fs::read_to_string(&root_file)?;
+
+        info!("URI: {}", &uri);
+        if let Some(mut results) = self.parser.parse_contents(&uri, &root_file_content, true) {
+            self.root_files.lock().unwrap().insert(uri.to_string());
+            self.update_reachable(&results);
+            self.remove_excluded(&mut results);
+
+            for file in results.files {
+                info!("found file: {:?}", &file);
+                store.insert(file.path, file.content);
             }
 
             for n in &results.nodes {
@@ -1236,7 +2560,7 @@ impl LSPHandlers {
 
             for variable in results.variables {
                 info!("found variable: {:?}", &variable);
-                all_variables.insert(variable.key.clone(), variable);
+                self.insert_variable(&mut all_variables, variable);
             }
 
             for component in results.components {
@@ -1251,12 +2575,12 @@ impl LSPHandlers {
     }
 
     #[allow(clippy::too_many_lines)]
-    fn generate_diagnostics(&self, document_uri: lsp_types::Url) -> Option<LSPResult> {
+    pub fn generate_diagnostics(&self, document_uri: lsp_types::Url) -> Option<LSPResult> {
         let start = Instant::now();
-        let store = self.store.lock().unwrap();
+        let store = self.document_store(document_uri.as_str());
         let all_nodes = self.nodes.lock().unwrap();
 
-        let content: String = store.get(&document_uri.to_string())?.to_string();
+        let content: String = store.get(&document_uri.to_string())?.clone();
 
         let extends = self
             .parser
@@ -1264,16 +2588,32 @@ impl LSPHandlers {
 
         let mut diagnostics: Vec<Diagnostic> = vec![];
 
-        'extend: for extend in extends {
-            if extend.uri == document_uri.to_string() {
+        // anchors are scoped to the document so only local ones can be mistaken
+        // for extends targets
+        let anchors = self
+            .parser
+            .get_all_anchors(document_uri.as_str(), content.as_str());
+
+        diagnostics.extend(self.circular_extends_diagnostics(&content, &extends, &all_nodes));
+
+        'extend: for extend in extends {
+            if extend.uri == document_uri.to_string() {
                 for (_, root_nodes) in all_nodes.iter() {
                     if root_nodes.get(&extend.key).is_some() {
                         continue 'extend;
                     }
                 }
 
-                diagnostics.push(Diagnostic::new_simple(
-                    lsp_types::Range {
+                let mut message = format!("Rule: {} does not exist.", extend.key);
+                if anchors.iter().any(|anchor| anchor.key == extend.key) {
+                    message = format!(
+                        "{message} Extends can't reference anchor &{0}; use merge key `<<: *{0}` or define it as hidden job `.{0}`.",
+                        extend.key
+                    );
+                }
+
+                diagnostics.push(Diagnostic {
+                    range: lsp_types::Range {
                         start: lsp_types::Position {
                             line: extend.range.start.line,
                             character: extend.range.start.character,
@@ -1283,8 +2623,51 @@ impl LSPHandlers {
                             character: extend.range.end.character,
                         },
                     },
-                    format!("Rule: {} does not exist.", extend.key),
-                ));
+                    message,
+                    ..DiagnosticData::MissingExtend {
+                        name: extend.key.clone(),
+                    }
+                    .into()
+                });
+            }
+        }
+
+        // reserved keywords are parsed as root nodes too, so a job accidentally
+        // named e.g. `variables` silently becomes global configuration
+        if let Some(root_nodes) = all_nodes.get(document_uri.as_str()) {
+            for (key, element) in root_nodes {
+                if !keywords::is_root_keyword(key)
+                    || !ParserUtils::is_job_definition(
+                        element.content.as_deref().unwrap_or_default(),
+                    )
+                {
+                    continue;
+                }
+
+                let Some(node) =
+                    self.parser
+                        .get_root_node_key(document_uri.as_str(), &content, key)
+                else {
+                    continue;
+                };
+
+                diagnostics.push(Diagnostic {
+                    range: lsp_types::Range {
+                        start: lsp_types::Position {
+                            line: node.range.start.line,
+                            character: node.range.start.character,
+                        },
+                        end: lsp_types::Position {
+                            line: node.range.end.line,
+                            character: node.range.end.character,
+                        },
+                    },
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    message: format!(
+                        "Reserved keyword: {key} can't be used as a job name; rename the job."
+                    ),
+                    ..DiagnosticData::ReservedJobName { name: key.clone() }.into()
+                });
             }
         }
 
@@ -1306,8 +2689,8 @@ impl LSPHandlers {
 
         for stage in stages {
             if !all_stages.contains(&stage.key) {
-                diagnostics.push(Diagnostic::new_simple(
-                    lsp_types::Range {
+                diagnostics.push(Diagnostic {
+                    range: lsp_types::Range {
                         start: lsp_types::Position {
                             line: stage.range.start.line,
                             character: stage.range.start.character,
@@ -1317,8 +2700,12 @@ impl LSPHandlers {
                             character: stage.range.end.character,
                         },
                     },
-                    format!("Stage: {} does not exist.", stage.key),
-                ));
+                    message: format!("Stage: {} does not exist.", stage.key),
+                    ..DiagnosticData::MissingStage {
+                        name: stage.key.clone(),
+                    }
+                    .into()
+                });
             }
         }
 
@@ -1326,41 +2713,57 @@ impl LSPHandlers {
             .parser
             .get_all_job_needs(document_uri.to_string(), content.as_str(), None);
 
-        'needs: for need in needs {
-            let need_split = need.key.split(' ').collect::<Vec<&str>>();
+        'needs: for need in &needs {
+            // needs can reference a job generated by `parallel:matrix`
+            // needs: "job-name: [matrix-value-1, matrix-value-2, ..]"
+            let node_key = ParserUtils::matrix_job_name(&need.key).unwrap_or(need.key.as_str());
 
-            match need_split.len() {
-                1 => {
-                    // default needs containing just a reference
-                    // to a job
-                    for (_, node) in all_nodes.iter() {
-                        if node.get(need.key.as_str()).is_some() {
-                            continue 'needs;
-                        }
+            if keywords::is_root_keyword(node_key) {
+                diagnostics.push(Diagnostic {
+                    range: lsp_types::Range {
+                        start: lsp_types::Position {
+                            line: need.range.start.line,
+                            character: need.range.start.character,
+                        },
+                        end: lsp_types::Position {
+                            line: need.range.end.line,
+                            character: need.range.end.character,
+                        },
+                    },
+                    message: format!("Job: {node_key} is a reserved keyword and can't be a job."),
+                    ..DiagnosticData::ReservedNeed {
+                        name: node_key.to_string(),
                     }
-                }
-
-                2 => {
-                    // special needs where it can reference a matrix inside a job
-                    // needs: "job-name [matrix-value-1,matrix-value-2,..]
-                    // currently just check split value that it matches a job
-                    // TODO: handle matrix references
+                    .into()
+                });
+                continue;
+            }
 
-                    let node_key = need_split[0];
-                    for (_, node) in all_nodes.iter() {
-                        if node.get(node_key).is_some() {
-                            continue 'needs;
-                        }
-                    }
+            for (_, node) in all_nodes.iter() {
+                if node.get(node_key).is_some() {
+                    continue 'needs;
                 }
+            }
 
-                invalid => {
-                    warn!("invalid split len. got: {invalid}; needs: {need_split:?}");
-                }
+            // optional needs are skipped by Gitlab when job isn't in pipeline
+            let (severity, message) = if need
+                .content
+                .as_deref()
+                .is_some_and(ParserUtils::is_optional_need)
+            {
+                (
+                    Some(DiagnosticSeverity::HINT),
+                    format!(
+                        "Job: {} does not exist; optional need is ignored.",
+                        need.key
+                    ),
+                )
+            } else {
+                (None, format!("Job: {} does not exist.", need.key))
             };
 
-            diagnostics.push(Diagnostic::new_simple(
-                lsp_types::Range {
+            diagnostics.push(Diagnostic {
+                range: lsp_types::Range {
                     start: lsp_types::Position {
                         line: need.range.start.line,
                         character: need.range.start.character,
@@ -1370,10 +2773,37 @@ impl LSPHandlers {
                         character: need.range.end.character,
                     },
                 },
-                format!("Job: {} does not exist.", need.key),
-            ));
+                severity,
+                message,
+                ..DiagnosticData::MissingNeed {
+                    name: need.key.clone(),
+                }
+                .into()
+            });
         }
 
+        let all_nodes_ordered_list = self.nodes_ordered_list.lock().unwrap();
+        let all_stages_ordered_list = self.stages_ordered_list.lock().unwrap();
+        diagnostics.extend(self.needs_stage_order_diagnostics(
+            document_uri.as_str(),
+            &content,
+            &needs,
+            &all_nodes,
+            &all_nodes_ordered_list,
+            &all_stages_ordered_list,
+        ));
+        drop(all_stages_ordered_list);
+        drop(all_nodes_ordered_list);
+
+        diagnostics.extend(self.duplicate_needs_diagnostics(
+            document_uri.as_str(),
+            &content,
+            &needs,
+        ));
+        diagnostics.extend(self.duplicate_root_keys_diagnostics(&document_uri, &content));
+        diagnostics.extend(self.duplicate_include_diagnostics(&document_uri, &content));
+        diagnostics.extend(self.invalid_root_diagnostics(document_uri.as_str(), &content));
+
         let components = self
             .parser
             .get_all_components(document_uri.as_ref(), content.as_str());
@@ -1393,8 +2823,8 @@ impl LSPHandlers {
                         );
                     } else {
                         // wasn't found in spec -> invalid key
-                        diagnostics.push(Diagnostic::new_simple(
-                            lsp_types::Range {
+                        diagnostics.push(Diagnostic {
+                            range: lsp_types::Range {
                                 start: lsp_types::Position {
                                     line: i.range.start.line,
                                     character: i.range.start.character,
@@ -1404,7 +2834,7 @@ impl LSPHandlers {
                                     character: i.range.end.character,
                                 },
                             },
-                            format!(
+                            message: format!(
                                 "Invalid input key. Key needs to be one of: '{}'.",
                                 spec.inputs
                                     .iter()
@@ -1412,7 +2842,11 @@ impl LSPHandlers {
                                     .collect::<Vec<String>>()
                                     .join(", ")
                             ),
-                        ));
+                            ..DiagnosticData::InvalidInputKey {
+                                name: i.key.clone(),
+                            }
+                            .into()
+                        });
                     }
                 });
             }
@@ -1422,9 +2856,12 @@ impl LSPHandlers {
             .parser
             .get_all_multi_caches(document_uri.as_ref(), content.as_str());
 
-        let cache_diagnostics = caches.iter().flat_map(|c| c.cache_items.iter().skip(MAX_CACHE_ITEMS).map(|el| {
-                Diagnostic::new_simple(
-                    lsp_types::Range {
+        diagnostics.extend(caches.iter().flat_map(|c| {
+            c.cache_items
+                .iter()
+                .skip(MAX_CACHE_ITEMS)
+                .map(|el| Diagnostic {
+                    range: lsp_types::Range {
                         start: lsp_types::Position {
                             line: el.range.start.line,
                             character: el.range.start.character,
@@ -1434,782 +2871,7546 @@ impl LSPHandlers {
                             character: el.range.end.character,
                         },
                     },
-                    "You can have a maximum of 4 caches: https://docs.gitlab.com/ee/ci/caching/#use-multiple-caches".to_string(),
-                )
-            }));
+                    message: MAX_CACHES_MESSAGE.to_string(),
+                    ..DiagnosticData::MaxCaches.into()
+                })
+        }));
 
-        diagnostics.extend(cache_diagnostics);
+        let exit_codes = self
+            .parser
+            .get_all_exit_codes(document_uri.as_ref(), content.as_str());
 
-        info!("DIAGNOSTICS ELAPSED: {:?}", start.elapsed());
-        Some(LSPResult::Diagnostics(DiagnosticsNotification {
-            uri: document_uri,
-            diagnostics,
-        }))
-    }
+        for exit_code in exit_codes {
+            if exit_code.key.parse::<i32>().is_ok() {
+                continue;
+            }
 
-    pub fn on_save(&self, notification: Notification) -> Option<LSPResult> {
-        let params =
-            serde_json::from_value::<DidSaveTextDocumentParams>(notification.params).ok()?;
+            diagnostics.push(Diagnostic {
+                range: lsp_types::Range {
+                    start: lsp_types::Position {
+                        line: exit_code.range.start.line,
+                        character: exit_code.range.start.character,
+                    },
+                    end: lsp_types::Position {
+                        line: exit_code.range.end.line,
+                        character: exit_code.range.end.character,
+                    },
+                },
+                message: format!("Exit code: {} must be an integer.", exit_code.key),
+                ..DiagnosticData::InvalidExitCode {
+                    value: exit_code.key.clone(),
+                }
+                .into()
+            });
+        }
 
-        self.generate_diagnostics(params.text_document.uri)
-    }
+        let experimental = self.experimental.lock().unwrap().clone();
 
-    pub fn on_references(&self, request: Request) -> Option<LSPResult> {
-        let start = Instant::now();
+        if experimental.cache_key_files_diagnostics
+            && self.can_path_be_modified(document_uri.as_str())
+        {
+            diagnostics.extend(self.cache_key_files_diagnostics(document_uri.as_str(), &content));
+        }
 
-        let params = serde_json::from_value::<lsp_types::ReferenceParams>(request.params).ok()?;
+        if experimental.include_ref_diagnostics {
+            let project_includes = self
+                .parser
+                .get_all_project_includes(document_uri.as_ref(), content.as_str());
 
-        let store = self.store.lock().unwrap();
-        let document_uri = &params.text_document_position.text_document.uri;
-        let document = store.get::<String>(&document_uri.to_string())?;
+            diagnostics.extend(
+                project_includes
+                    .iter()
+                    .filter(|include| include.content.is_none())
+                    .map(|include| Diagnostic {
+                        range: lsp_types::Range {
+                            start: lsp_types::Position {
+                                line: include.range.start.line,
+                                character: include.range.start.character,
+                            },
+                            end: lsp_types::Position {
+                                line: include.range.end.line,
+                                character: include.range.end.character,
+                            },
+                        },
+                        severity: Some(DiagnosticSeverity::HINT),
+                        message: format!(
+                            "Project include: {} has no ref; pin it to a tag or commit \
+                            for reproducible pipelines.",
+                            ParserUtils::strip_quotes(&include.key)
+                        ),
+                        ..DiagnosticData::UnpinnedProjectInclude {
+                            project: ParserUtils::strip_quotes(&include.key).to_string(),
+                        }
+                        .into()
+                    }),
+            );
+        }
 
-        let position = params.text_document_position.position;
-        let line = document.lines().nth(position.line as usize)?;
+        if experimental.deprecated_keywords_diagnostics {
+            diagnostics.extend(
+                self.parser
+                    .get_all_job_keywords(
+                        document_uri.as_ref(),
+                        content.as_str(),
+                        &["only", "except"],
+                    )
+                    .into_iter()
+                    .map(|keyword| Diagnostic {
+                        range: lsp_types::Range {
+                            start: lsp_types::Position {
+                                line: keyword.range.start.line,
+                                character: keyword.range.start.character,
+                            },
+                            end: lsp_types::Position {
+                                line: keyword.range.end.line,
+                                character: keyword.range.end.character,
+                            },
+                        },
+                        severity: Some(DiagnosticSeverity::HINT),
+                        message: format!(
+                            "Keyword: {} is deprecated; use rules instead.",
+                            keyword.key
+                        ),
+                        tags: Some(vec![lsp_types::DiagnosticTag::DEPRECATED]),
+                        ..DiagnosticData::DeprecatedKeyword { name: keyword.key }.into()
+                    }),
+            );
+        }
 
-        let position_type = self.parser.get_position_type(document, position);
-        let mut references: Vec<GitlabElement> = vec![];
+        if experimental.shadowed_keys_diagnostics {
+            diagnostics.extend(self.shadowed_keys_diagnostics(
+                document_uri.as_str(),
+                &content,
+                &all_nodes,
+            ));
+        }
 
-        match position_type {
-            parser::PositionType::Extend => {
-                let word =
-                    parser_utils::ParserUtils::extract_word(line, position.character as usize)?;
+        let secrets =
+            self.parser
+                .get_all_job_definitions(document_uri.as_ref(), content.as_str(), "secrets");
 
-                for (uri, content) in store.iter() {
-                    let mut extends =
-                        self.parser
-                            .get_all_extends(uri.to_string(), content.as_str(), Some(word));
-                    references.append(&mut extends);
-                }
+        for secret in secrets {
+            let definition = secret.content.as_deref().unwrap_or_default();
+            if ParserUtils::has_any_key(definition, &SECRET_PROVIDERS) {
+                continue;
             }
-            parser::PositionType::RootNode => {
-                let word =
-                    parser_utils::ParserUtils::extract_word(line, position.character as usize)?
-                        .trim_end_matches(':');
 
-                // currently support only those that are extends
-                if word.starts_with('.') {
-                    for (uri, content) in store.iter() {
-                        let mut extends = self.parser.get_all_extends(
-                            uri.to_string(),
-                            content.as_str(),
-                            Some(word),
-                        );
-                        references.append(&mut extends);
-                    }
-                } else {
-                    for (uri, content) in store.iter() {
-                        let mut extends = self.parser.get_all_job_needs(
-                            uri.to_string(),
-                            content.as_str(),
-                            Some(word),
-                        );
-                        references.append(&mut extends);
-                    }
+            diagnostics.push(Diagnostic {
+                range: lsp_types::Range {
+                    start: lsp_types::Position {
+                        line: secret.range.start.line,
+                        character: secret.range.start.character,
+                    },
+                    end: lsp_types::Position {
+                        line: secret.range.end.line,
+                        character: secret.range.end.character,
+                    },
+                },
+                message: format!(
+                    "Secret: {} must define a provider, one of: {}.",
+                    secret.key,
+                    SECRET_PROVIDERS.join(", ")
+                ),
+                ..DiagnosticData::MissingSecretProvider {
+                    name: secret.key.clone(),
                 }
+                .into()
+            });
+        }
+
+        let id_tokens = self.parser.get_all_job_definitions(
+            document_uri.as_ref(),
+            content.as_str(),
+            "id_tokens",
+        );
+
+        for id_token in id_tokens {
+            let definition = id_token.content.as_deref().unwrap_or_default();
+            if ParserUtils::has_any_key(definition, &["aud"]) {
+                continue;
             }
-            parser::PositionType::Stage => {
-                let word =
-                    parser_utils::ParserUtils::extract_word(line, position.character as usize);
 
-                for (uri, content) in store.iter() {
-                    let mut stages = self.parser.get_all_stages(uri, content.as_str(), word);
-                    references.append(&mut stages);
+            diagnostics.push(Diagnostic {
+                range: lsp_types::Range {
+                    start: lsp_types::Position {
+                        line: id_token.range.start.line,
+                        character: id_token.range.start.character,
+                    },
+                    end: lsp_types::Position {
+                        line: id_token.range.end.line,
+                        character: id_token.range.end.character,
+                    },
+                },
+                message: format!("ID token: {} must define aud.", id_token.key),
+                ..DiagnosticData::MissingIdTokenAud {
+                    name: id_token.key.clone(),
                 }
+                .into()
+            });
+        }
+
+        let tags = self
+            .parser
+            .get_all_tags(document_uri.as_ref(), content.as_str());
+
+        // empty tags remove runner constraints which is usually unintended
+        diagnostics.extend(
+            tags.iter()
+                .filter(|tags| {
+                    tags.content.as_deref().is_none_or(|value| {
+                        value
+                            .trim_start_matches('[')
+                            .trim_end_matches(']')
+                            .trim()
+                            .is_empty()
+                    })
+                })
+                .map(|tags| Diagnostic {
+                    range: lsp_types::Range {
+                        start: lsp_types::Position {
+                            line: tags.range.start.line,
+                            character: tags.range.start.character,
+                        },
+                        end: lsp_types::Position {
+                            line: tags.range.end.line,
+                            character: tags.range.end.character,
+                        },
+                    },
+                    severity: Some(DiagnosticSeverity::HINT),
+                    message: "Tags are empty so job can run on any runner.".to_string(),
+                    ..DiagnosticData::EmptyTags.into()
+                }),
+        );
+
+        diagnostics.extend(
+            self.parser
+                .get_all_nested_keywords(document_uri.as_ref(), content.as_str())
+                .into_iter()
+                .filter(|keyword| keywords::is_job_level_keyword(&keyword.key))
+                .map(|keyword| {
+                    let parent = keyword.content.unwrap_or_default();
+
+                    Diagnostic {
+                        range: lsp_types::Range {
+                            start: lsp_types::Position {
+                                line: keyword.range.start.line,
+                                character: keyword.range.start.character,
+                            },
+                            end: lsp_types::Position {
+                                line: keyword.range.end.line,
+                                character: keyword.range.end.character,
+                            },
+                        },
+                        severity: Some(DiagnosticSeverity::WARNING),
+                        message: format!(
+                            "Keyword: {} is nested under {parent}; it's probably indented too deep.",
+                            keyword.key
+                        ),
+                        ..DiagnosticData::MisnestedKeyword {
+                            name: keyword.key,
+                            parent,
+                        }
+                        .into()
+                    }
+                }),
+        );
+
+        let deployment_tiers = self
+            .parser
+            .get_all_deployment_tiers(document_uri.as_ref(), content.as_str());
+
+        for tier in deployment_tiers {
+            if DEPLOYMENT_TIERS.contains(&ParserUtils::strip_quotes(&tier.key)) {
+                continue;
             }
-            _ => {}
+
+            diagnostics.push(Diagnostic {
+                range: lsp_types::Range {
+                    start: lsp_types::Position {
+                        line: tier.range.start.line,
+                        character: tier.range.start.character,
+                    },
+                    end: lsp_types::Position {
+                        line: tier.range.end.line,
+                        character: tier.range.end.character,
+                    },
+                },
+                message: format!(
+                    "Deployment tier: {} must be one of: {}.",
+                    tier.key,
+                    DEPLOYMENT_TIERS.join(", ")
+                ),
+                ..DiagnosticData::InvalidDeploymentTier {
+                    value: tier.key.clone(),
+                }
+                .into()
+            });
         }
 
-        info!("REFERENCES ELAPSED: {:?}", start.elapsed());
+        let docker_platforms = self
+            .parser
+            .get_all_docker_platforms(document_uri.as_ref(), content.as_str());
 
-        Some(LSPResult::References(ReferencesResult {
-            id: request.id,
-            locations: references,
-        }))
-    }
+        for platform in docker_platforms {
+            let value = ParserUtils::strip_quotes(&platform.key);
+            // platform can be set through a variable
+            if value.contains('$') || ParserUtils::is_docker_platform(value) {
+                continue;
+            }
 
-    #[allow(clippy::unnecessary_wraps, clippy::too_many_lines)]
-    fn on_completion_component(
-        &self,
-        line: &str,
-        position: Position,
-        component: &Component,
-    ) -> anyhow::Result<Vec<LSPCompletion>> {
-        if component.inputs.iter().any(|i| i.hovered) {
-            let word = parser_utils::ParserUtils::word_before_cursor(
-                line,
-                position.character as usize,
-                |c: char| c.is_whitespace(),
-            );
+            diagnostics.push(Diagnostic {
+                range: lsp_types::Range {
+                    start: lsp_types::Position {
+                        line: platform.range.start.line,
+                        character: platform.range.start.character,
+                    },
+                    end: lsp_types::Position {
+                        line: platform.range.end.line,
+                        character: platform.range.end.character,
+                    },
+                },
+                message: format!(
+                    "Docker platform: {} must be in os/arch[/variant] format, e.g. linux/amd64.",
+                    platform.key
+                ),
+                ..DiagnosticData::InvalidDockerPlatform {
+                    value: platform.key.clone(),
+                }
+                .into()
+            });
+        }
 
-            let after = parser_utils::ParserUtils::word_after_cursor(
-                line,
-                position.character as usize,
-                |c| c.is_whitespace() || c == ':',
-            );
+        let docker_users = self
+            .parser
+            .get_all_docker_users(document_uri.as_ref(), content.as_str());
 
-            let components_store = self.components.lock().unwrap();
-            let Some(component_spec) = components_store.get(&component.uri) else {
-                warn!(
-                    "could not find component spec; indexing went wrong!; searching for {}",
-                    component.uri
-                );
+        for user in docker_users {
+            let value = ParserUtils::strip_quotes(&user.key);
+            if value.contains('$') || ParserUtils::is_docker_user(value) {
+                continue;
+            }
 
-                return Ok(vec![]);
+            diagnostics.push(Diagnostic {
+                range: lsp_types::Range {
+                    start: lsp_types::Position {
+                        line: user.range.start.line,
+                        character: user.range.start.character,
+                    },
+                    end: lsp_types::Position {
+                        line: user.range.end.line,
+                        character: user.range.end.character,
+                    },
+                },
+                message: format!(
+                    "Docker user: {} must be a username or UID with an optional group, e.g. 1000:1000.",
+                    user.key
+                ),
+                ..DiagnosticData::InvalidDockerUser {
+                    value: user.key.clone(),
+                }
+                .into()
+            });
+        }
+
+        let rule_conditions = self
+            .parser
+            .get_all_rule_conditions(document_uri.as_ref(), content.as_str());
+
+        for condition in rule_conditions {
+            let Some(variable) = ParserUtils::contradictory_condition(&condition.key) else {
+                continue;
             };
 
-            // filter out those that were already used
-            let valid_input_autocompletes: Vec<super::ComponentInput> = component_spec
-                .inputs
-                .iter()
-                .filter(|&i| !component.inputs.iter().any(|ci| ci.key == i.key))
-                .cloned() // Clone each element to get an owned version
-                .collect();
+            diagnostics.push(Diagnostic {
+                range: lsp_types::Range {
+                    start: lsp_types::Position {
+                        line: condition.range.start.line,
+                        character: condition.range.start.character,
+                    },
+                    end: lsp_types::Position {
+                        line: condition.range.end.line,
+                        character: condition.range.end.character,
+                    },
+                },
+                severity: Some(DiagnosticSeverity::HINT),
+                message: format!(
+                    "Rule will never match: variable ${variable} is compared to different values."
+                ),
+                ..DiagnosticData::ContradictoryRule {
+                    variable: variable.clone(),
+                }
+                .into()
+            });
+        }
 
-            let items = valid_input_autocompletes
-                .into_iter()
-                .filter(|i| i.key.contains(word))
-                .flat_map(|i| -> anyhow::Result<LSPCompletion> {
-                    Ok(LSPCompletion {
-                        label: i.key.clone(),
-                        details: Some(i.autocomplete_details()),
-                        location: LSPLocation {
-                            range: Range {
-                                start: LSPPosition {
-                                    line: position.line,
-                                    character: position.character - u32::try_from(word.len())?,
-                                },
-                                end: LSPPosition {
-                                    line: position.line,
-                                    character: position.character + u32::try_from(after.len())?,
-                                },
-                            },
-                            ..Default::default()
-                        },
-                    })
-                })
-                .collect();
+        diagnostics.extend(self.unreachable_rules_diagnostics(document_uri.as_str(), &content));
 
-            return Ok(items);
-        } else if let Some(hovered_input) = component.inputs.iter().find(|i| i.value_plain.hovered)
-        {
-            let word = parser_utils::ParserUtils::word_before_cursor(
-                line,
-                position.character as usize,
-                |c| c.is_whitespace() || c == ':',
-            );
+        // severities configured in `.gitlab-ci-ls.yaml` override defaults
+        let configured = &self.cfg.configuration.diagnostics;
+        diagnostics.retain_mut(|diagnostic| {
+            let Some(NumberOrString::String(kind)) = &diagnostic.code else {
+                return true;
+            };
+            let Some(level) = configured.get(kind) else {
+                return true;
+            };
 
-            let after = parser_utils::ParserUtils::word_after_cursor(
-                line,
-                position.character as usize,
-                |c: char| c.is_whitespace(),
-            );
+            diagnostic.severity = level.severity();
+            diagnostic.severity.is_some()
+        });
 
-            let components_store = self.components.lock().unwrap();
-            let Some(component_spec) = components_store.get(&component.uri) else {
-                warn!(
-                    "could not find component spec; indexing went wrong!; searching for {}",
-                    component.uri
-                );
+        // checks run over hash maps so order is fixed before publishing
+        diagnostics.sort_by(|a, b| {
+            (a.range.start.line, a.range.start.character, &a.message).cmp(&(
+                b.range.start.line,
+                b.range.start.character,
+                &b.message,
+            ))
+        });
 
-                return Ok(vec![]);
-            };
+        info!("DIAGNOSTICS ELAPSED: {:?}", start.elapsed());
+        Some(LSPResult::Diagnostics(DiagnosticsNotification {
+            uri: document_uri,
+            diagnostics,
+        }))
+    }
 
-            if let Some(input_spec) = component_spec
-                .inputs
-                .iter()
-                .find(|i| i.key == hovered_input.key)
-            {
-                if let Some(options) = &input_spec.options {
-                    let items = options
+    // Reports extends which lead back to the job itself, e.g. `.a -> .b -> .a`.
+    fn circular_extends_diagnostics(
+        &self,
+        content: &str,
+        extends: &[GitlabElement],
+        all_nodes: &HashMap<String, HashMap<String, GitlabElement>>,
+    ) -> Vec<Diagnostic> {
+        // returns path from `from` to `to` if one exists
+        fn find_path(
+            node_extends: &mut impl FnMut(&str) -> Vec<String>,
+            from: &str,
+            to: &str,
+        ) -> Option<Vec<String>> {
+            let mut visited = vec![from.to_string()];
+            let mut stack = vec![vec![from.to_string()]];
+
+            while let Some(path) = stack.pop() {
+                let last = path.last()?;
+                for next in &node_extends(last) {
+                    let mut next_path = path.clone();
+                    next_path.push(next.clone());
+
+                    if next == to {
+                        return Some(next_path);
+                    }
+
+                    if !visited.contains(next) {
+                        visited.push(next.clone());
+                        stack.push(next_path);
+                    }
+                }
+            }
+
+            None
+        }
+
+        if extends.is_empty() {
+            return vec![];
+        }
+
+        // only jobs reachable from extends of the document are parsed, each
+        // one once
+        let mut graph: HashMap<String, Vec<String>> = HashMap::new();
+        let mut node_extends = |name: &str| {
+            graph
+                .entry(name.to_string())
+                .or_insert_with(|| {
+                    all_nodes
                         .iter()
-                        .filter(|option| option.contains(word))
-                        .flat_map(|option| -> anyhow::Result<LSPCompletion> {
-                            Ok(LSPCompletion {
-                                label: option.to_string(),
-                                details: None,
-                                location: LSPLocation {
-                                    range: Range {
-                                        start: LSPPosition {
-                                            line: position.line,
-                                            character: position.character
-                                                - u32::try_from(word.len())?,
-                                        },
-                                        end: LSPPosition {
-                                            line: position.line,
-                                            character: position.character
-                                                + u32::try_from(after.len())?,
-                                        },
-                                    },
-                                    ..Default::default()
-                                },
-                            })
+                        .find_map(|(uri, nodes)| nodes.get(name).map(|node| (uri, node)))
+                        .map(|(uri, node)| {
+                            self.parser
+                                .get_all_extends(
+                                    uri.clone(),
+                                    node.content.as_deref().unwrap_or_default(),
+                                    None,
+                                )
+                                .into_iter()
+                                .map(|e| e.key)
+                                .collect()
                         })
-                        .collect();
+                        .unwrap_or_default()
+                })
+                .clone()
+        };
+
+        let mut diagnostics = vec![];
+        for extend in extends {
+            let Some(job) = self.parser.get_root_node_at_position(
+                content,
+                Position::new(extend.range.start.line, extend.range.start.character),
+            ) else {
+                continue;
+            };
+
+            let Some(path) = find_path(&mut node_extends, &extend.key, &job.key) else {
+                continue;
+            };
 
-                    return Ok(items);
+            diagnostics.push(Diagnostic {
+                range: lsp_types::Range {
+                    start: lsp_types::Position {
+                        line: extend.range.start.line,
+                        character: extend.range.start.character,
+                    },
+                    end: lsp_types::Position {
+                        line: extend.range.end.line,
+                        character: extend.range.end.character,
+                    },
+                },
+                message: format!("Circular extends: {} -> {}.", job.key, path.join(" -> ")),
+                ..DiagnosticData::CircularExtends {
+                    name: extend.key.clone(),
                 }
+                .into()
+            });
+        }
+
+        diagnostics
+    }
+
+    fn shadowed_keys_diagnostics(
+        &self,
+        uri: &str,
+        content: &str,
+        all_nodes: &HashMap<String, HashMap<String, GitlabElement>>,
+    ) -> Vec<Diagnostic> {
+        // keys worth a hint when job overrides value of its template
+        const SHADOWED_KEYS: &[&str] = &["image", "stage"];
+
+        let Some(root_nodes) = all_nodes.get(uri) else {
+            return vec![];
+        };
+        let node_list = self.nodes_ordered_list.lock().unwrap();
+        let job_keywords = self
+            .parser
+            .get_all_job_keywords(uri, content, SHADOWED_KEYS);
+
+        let mut diagnostics = vec![];
+        for (key, element) in root_nodes {
+            if keywords::is_root_keyword(key) {
+                continue;
+            }
+
+            let shadowed =
+                self.parser
+                    .get_shadowed_keys(element.clone(), &node_list, SHADOWED_KEYS);
+            if shadowed.is_empty() {
+                continue;
+            }
+
+            let Some(node) = self.parser.get_root_node_key(uri, content, key) else {
+                continue;
+            };
+
+            for (shadowed_key, template) in shadowed {
+                // diagnostic is shown on job's own key, falling back to job name
+                let range = job_keywords
+                    .iter()
+                    .find(|keyword| {
+                        keyword.key == shadowed_key && keyword.content.as_deref() == Some(key)
+                    })
+                    .map_or(&node.range, |keyword| &keyword.range);
+
+                diagnostics.push(Diagnostic {
+                    range: lsp_types::Range {
+                        start: lsp_types::Position::new(range.start.line, range.start.character),
+                        end: lsp_types::Position::new(range.end.line, range.end.character),
+                    },
+                    severity: Some(DiagnosticSeverity::HINT),
+                    message: format!(
+                        "Job: {key} overrides {shadowed_key} set by extended {template}."
+                    ),
+                    ..DiagnosticData::ShadowedKey {
+                        name: key.clone(),
+                        key: shadowed_key.clone(),
+                        template: template.clone(),
+                    }
+                    .into()
+                });
             }
         }
 
-        Ok(vec![])
+        diagnostics
     }
 
-    #[allow(clippy::too_many_lines)]
-    pub fn on_prepare_rename(&self, request: Request) -> Option<LSPResult> {
-        let start = Instant::now();
-        let params: TextDocumentPositionParams = serde_json::from_value(request.params).ok()?;
+    // Files listed in `cache:key:files` are relative to the project root. Paths
+    // with variables or wildcards can't be resolved so they are skipped.
+    fn cache_key_files_diagnostics(&self, uri: &str, content: &str) -> Vec<Diagnostic> {
+        self.parser
+            .get_all_cache_key_files(uri, content)
+            .into_iter()
+            .filter(|file| !file.key.contains(['$', '*', '?', '[']))
+            .filter(|file| {
+                !PathBuf::from(&self.cfg.root_dir)
+                    .join(file.key.trim_start_matches("./"))
+                    .exists()
+            })
+            .map(|file| Diagnostic {
+                range: lsp_types::Range {
+                    start: lsp_types::Position {
+                        line: file.range.start.line,
+                        character: file.range.start.character,
+                    },
+                    end: lsp_types::Position {
+                        line: file.range.end.line,
+                        character: file.range.end.character,
+                    },
+                },
+                severity: Some(DiagnosticSeverity::HINT),
+                message: format!(
+                    "Cache key file: {} does not exist; cache key will be computed from \
+                    an empty hash.",
+                    file.key
+                ),
+                ..DiagnosticData::MissingCacheKeyFile {
+                    path: file.key.clone(),
+                }
+                .into()
+            })
+            .collect()
+    }
 
-        let store = self.store.lock().unwrap();
-        let document_uri = params.text_document.uri;
+    // Resolves job stage from its full definition (extends and default included).
+    // Jobs without stage are put into `test` stage by Gitlab.
+    fn is_never_run(&self, element: &GitlabElement, node_list: &[GitlabFileElements]) -> bool {
+        let Some(content) = &element.content else {
+            return false;
+        };
 
-        if !self.can_path_be_modified(document_uri.as_ref()) {
-            return Some(LSPResult::PrepareRename(super::PrepareRenameResult {
-                id: request.id,
-                range: None,
-                err: Some("Cannot rename externally included files".to_string()),
-            }));
+        // own rules override inherited ones so definition is only resolved
+        // when rules can come from extends
+        if content.contains("rules:") {
+            ParserUtils::is_never_run(content)
+        } else if content.contains("extends:") {
+            self.parser
+                .get_full_definition(element.clone(), node_list)
+                .is_ok_and(|definition| ParserUtils::is_never_run(&definition))
+        } else {
+            false
         }
+    }
 
-        let document = store.get::<String>(&document_uri.clone().into())?;
+    fn get_job_stage(
+        &self,
+        uri: &str,
+        element: GitlabElement,
+        node_list: &[GitlabFileElements],
+    ) -> String {
+        let content = self
+            .parser
+            .get_full_definition(element.clone(), node_list)
+            .unwrap_or(element.content.unwrap_or_default());
+
+        self.parser
+            .get_all_stages(uri, &content, None)
+            .first()
+            .map_or("test".to_string(), |s| {
+                ParserUtils::strip_quotes(&s.key).to_string()
+            })
+    }
 
-        let position = params.position;
-        let line = document.lines().nth(position.line as usize)?;
+    // Rules are evaluated in order until first match, so rules following one
+    // without any condition are never evaluated.
+    fn unreachable_rules_diagnostics(&self, uri: &str, content: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
 
-        let res = match self.parser.get_position_type(document, position) {
-            parser::PositionType::RootNode => {
-                let word = parser_utils::ParserUtils::word_before_cursor(
-                    line,
-                    position.character as usize,
-                    char::is_whitespace,
-                );
-                let after = parser_utils::ParserUtils::word_after_cursor(
-                    line,
-                    position.character as usize,
-                    char::is_whitespace,
-                )
-                .trim_end_matches(':');
+        for block in self.parser.get_all_rules_blocks(uri, content) {
+            let Some(idx) = block.unconditional_rule else {
+                continue;
+            };
 
-                let full_word = format!("{word}{after}");
-                if LSPHandlers::is_predefined_root_element(&full_word) {
-                    return Some(LSPResult::PrepareRename(super::PrepareRenameResult {
-                        id: request.id,
-                        range: None,
-                        err: Some("Cannot rename Gitlab elements".to_string()),
-                    }));
+            let (Some(first), Some(last)) = (block.rules.get(idx + 1), block.rules.last()) else {
+                continue;
+            };
+
+            diagnostics.push(Diagnostic {
+                range: lsp_types::Range {
+                    start: lsp_types::Position {
+                        line: first.range.start.line,
+                        character: first.range.start.character,
+                    },
+                    end: lsp_types::Position {
+                        line: last.range.end.line,
+                        character: last.range.end.character,
+                    },
+                },
+                severity: Some(DiagnosticSeverity::HINT),
+                message: format!(
+                    "Rules of {} after unconditional rule on line {} are never evaluated.",
+                    block.key,
+                    block.rules[idx].range.start.line + 1
+                ),
+                tags: Some(vec![lsp_types::DiagnosticTag::UNNECESSARY]),
+                ..DiagnosticData::UnreachableRules {
+                    name: block.key.clone(),
                 }
+                .into()
+            });
+        }
 
-                Some(LSPResult::PrepareRename(super::PrepareRenameResult {
-                    id: request.id,
-                    range: Some(Range {
-                        start: LSPPosition {
-                            line: position.line,
-                            character: position.character - u32::try_from(word.len()).ok()?,
-                        },
-                        end: LSPPosition {
-                            line: position.line,
-                            character: position.character + u32::try_from(after.len()).ok()?,
-                        },
-                    }),
-                    err: None,
-                }))
-            }
-            parser::PositionType::Extend
-            | parser::PositionType::Needs(_)
-            | parser::PositionType::RuleReference(_) => {
-                let word = parser_utils::ParserUtils::word_before_cursor(
-                    line,
-                    position.character as usize,
-                    |c| c.is_whitespace() || c == '\'' || c == '"',
-                );
-                let after = parser_utils::ParserUtils::word_after_cursor(
-                    line,
-                    position.character as usize,
-                    |c| c.is_whitespace() || c == '\'' || c == '"',
-                );
+        diagnostics
+    }
 
-                let job = format!("{word}{after}");
-                for (uri, content) in store.iter() {
-                    if !self.can_path_be_modified(uri) {
-                        continue;
-                    }
+    // Flags needs listing the same job more than once. Entries are compared
+    // per `needs:` list so `rules:needs` don't clash with job needs, and
+    // entries with `parallel:matrix` are compared as a whole since they can
+    // target different matrix jobs.
+    // Needs are grouped by their job and, for `rules:needs`, by their rule
+    // item since each of those is a separate list.
+    fn duplicate_needs_diagnostics(
+        &self,
+        uri: &str,
+        content: &str,
+        needs: &[GitlabElement],
+    ) -> Vec<Diagnostic> {
+        let rules: Vec<GitlabElement> = self
+            .parser
+            .get_all_rules_blocks(uri, content)
+            .into_iter()
+            .flat_map(|block| block.rules)
+            .collect();
 
-                    if self.parser.get_root_node_key(uri, content, &job).is_some() {
-                        return Some(LSPResult::PrepareRename(PrepareRenameResult {
-                            id: request.id,
-                            range: Some(Range {
-                                start: LSPPosition {
-                                    line: position.line,
-                                    character: position.character
-                                        - u32::try_from(word.len()).ok()?,
-                                },
-                                end: LSPPosition {
-                                    line: position.line,
-                                    character: position.character
-                                        + u32::try_from(after.len()).ok()?,
-                                },
-                            }),
-                            err: None,
-                        }));
-                    }
+        let mut diagnostics = vec![];
+        let mut seen: HashSet<(String, Option<u32>, String, Option<String>)> = HashSet::new();
+
+        for need in needs {
+            let position = Position {
+                line: need.range.start.line,
+                character: need.range.start.character,
+            };
+            let Some(job) = self.parser.get_root_node_at_position(content, position) else {
+                continue;
+            };
+
+            let rule = rules
+                .iter()
+                .find(|rule| {
+                    rule.range.start.line <= need.range.start.line
+                        && rule.range.end.line >= need.range.end.line
+                })
+                .map(|rule| rule.range.start.line);
+
+            // needs of different matrix combinations of the same job aren't
+            // duplicates
+            let matrix = need.content.as_deref().and_then(|definition| {
+                definition
+                    .lines()
+                    .any(|line| line.trim_start().starts_with("parallel:"))
+                    .then(|| {
+                        definition
+                            .lines()
+                            .map(str::trim)
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    })
+            });
+
+            let name = ParserUtils::strip_quotes(&need.key).to_string();
+            if seen.insert((job.key, rule, name, matrix)) {
+                continue;
+            }
+
+            diagnostics.push(Diagnostic {
+                range: lsp_types::Range {
+                    start: lsp_types::Position {
+                        line: need.range.start.line,
+                        character: need.range.start.character,
+                    },
+                    end: lsp_types::Position {
+                        line: need.range.end.line,
+                        character: need.range.end.character,
+                    },
+                },
+                severity: Some(DiagnosticSeverity::HINT),
+                message: format!("Job: {} is already listed in needs.", need.key),
+                ..DiagnosticData::DuplicateNeed {
+                    name: need.key.clone(),
+                }
+                .into()
+            });
+        }
+
+        diagnostics
+    }
+
+    // Every document, e.g. component `spec` and its jobs, has to be a mapping
+    // of jobs and keywords. Anything else is rejected by GitLab while parser
+    // silently finds no nodes in it.
+    fn invalid_root_diagnostics(&self, uri: &str, content: &str) -> Vec<Diagnostic> {
+        self.parser
+            .get_invalid_root_documents(uri, content)
+            .iter()
+            .map(|document| {
+                // underline only the first line of the invalid document
+                let start = document.range.start.line;
+                let end = content
+                    .lines()
+                    .nth(start as usize)
+                    .and_then(|line| u32::try_from(line.len()).ok())
+                    .unwrap_or(document.range.end.character);
+
+                Diagnostic {
+                    range: lsp_types::Range {
+                        start: lsp_types::Position::new(start, document.range.start.character),
+                        end: lsp_types::Position::new(start, end),
+                    },
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    message: "Document root has to be a mapping of jobs and keywords.".to_string(),
+                    ..DiagnosticData::InvalidRoot.into()
+                }
+            })
+            .collect()
+    }
+
+    fn duplicate_root_keys_diagnostics(&self, uri: &Url, content: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        let mut seen: HashMap<String, lsp_types::Range> = HashMap::new();
+
+        for node in self.parser.get_all_root_nodes(uri.as_str(), content) {
+            let range = LSPHandlers::lsp_range(&LSPHandlers::root_key_range(&node));
+
+            let Some(previous) = seen.insert(node.key.clone(), range) else {
+                continue;
+            };
+
+            diagnostics.push(Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                message: format!(
+                    "Duplicate key: {} is already defined on line {}; only the last definition is used.",
+                    node.key,
+                    previous.start.line + 1
+                ),
+                related_information: Some(vec![lsp_types::DiagnosticRelatedInformation {
+                    location: lsp_types::Location {
+                        uri: uri.clone(),
+                        range: previous,
+                    },
+                    message: format!("{} first defined here", node.key),
+                }]),
+                ..DiagnosticData::DuplicateRootKey { name: node.key.clone() }.into()
+            });
+        }
+
+        diagnostics
+    }
+
+    // Entries including the same files again are redundant. Entries with
+    // `rules` are conditional so they aren't compared.
+    fn duplicate_include_diagnostics(&self, uri: &Url, content: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        let mut seen: HashMap<String, lsp_types::Range> = HashMap::new();
+
+        for item in self.parser.get_all_include_items(uri.as_str(), content) {
+            let Some(value) = item
+                .content
+                .as_deref()
+                .and_then(|c| serde_yaml::from_str::<serde_yaml::Value>(c).ok())
+            else {
+                continue;
+            };
+            if value.get("rules").is_some() {
+                continue;
+            }
+
+            let Some(identity) = serde_yaml::from_value::<IncludeItem>(value)
+                .ok()
+                .and_then(|include| include.identity())
+            else {
+                continue;
+            };
+
+            let range = lsp_types::Range {
+                start: lsp_types::Position::new(item.range.start.line, item.range.start.character),
+                end: lsp_types::Position::new(item.range.end.line, item.range.end.character),
+            };
+            let Some(previous) = seen.insert(identity, range) else {
+                continue;
+            };
+
+            diagnostics.push(Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::HINT),
+                message: format!(
+                    "Duplicate include: same file is already included on line {}.",
+                    previous.start.line + 1
+                ),
+                related_information: Some(vec![lsp_types::DiagnosticRelatedInformation {
+                    location: lsp_types::Location {
+                        uri: uri.clone(),
+                        range: previous,
+                    },
+                    message: "first included here".to_string(),
+                }]),
+                ..DiagnosticData::DuplicateInclude { name: item.key }.into()
+            });
+        }
+
+        diagnostics
+    }
+
+    // Needed jobs have to run in the same or an earlier stage. Same stage
+    // needs are valid since jobs with needs don't wait for stage ordering.
+    // Stage order follows `stages:` wrapped with `.pre` and `.post`, and
+    // falls back to Gitlab defaults when stages aren't defined.
+    fn needs_stage_order_diagnostics(
+        &self,
+        uri: &str,
+        content: &str,
+        needs: &[GitlabElement],
+        all_nodes: &HashMap<String, HashMap<String, GitlabElement>>,
+        node_list: &[GitlabFileElements],
+        stages: &[String],
+    ) -> Vec<Diagnostic> {
+        let default_stages = ["build", "test", "deploy"].map(String::from);
+        let stages = if stages.is_empty() {
+            &default_stages[..]
+        } else {
+            stages
+        };
+
+        let stage_index = |stage: &str| match stage {
+            ".pre" => Some(0),
+            ".post" => Some(stages.len() + 1),
+            stage => stages.iter().position(|s| s == stage).map(|i| i + 1),
+        };
+
+        let mut diagnostics = vec![];
+        let mut job_stages: HashMap<String, String> = HashMap::new();
+
+        for need in needs {
+            let Some(job) = self.parser.get_root_node_at_position(
+                content,
+                Position::new(need.range.start.line, need.range.start.character),
+            ) else {
+                continue;
+            };
+
+            let job_stage = job_stages
+                .entry(job.key.clone())
+                .or_insert_with(|| self.get_job_stage(uri, job, node_list));
+
+            // unknown stages are reported by stage diagnostics
+            let Some(job_stage_index) = stage_index(job_stage) else {
+                continue;
+            };
+
+            let need_key = ParserUtils::strip_quotes(&need.key);
+            let need_name = ParserUtils::matrix_job_name(need_key).unwrap_or(need_key);
+
+            let Some((need_uri, need_element)) = all_nodes
+                .iter()
+                .find_map(|(u, nodes)| nodes.get(need_name).map(|n| (u, n)))
+            else {
+                continue;
+            };
+
+            let need_stage = self.get_job_stage(need_uri, need_element.clone(), node_list);
+            let Some(need_stage_index) = stage_index(&need_stage) else {
+                continue;
+            };
+
+            if need_stage_index > job_stage_index {
+                diagnostics.push(Diagnostic {
+                    range: lsp_types::Range {
+                        start: lsp_types::Position {
+                            line: need.range.start.line,
+                            character: need.range.start.character,
+                        },
+                        end: lsp_types::Position {
+                            line: need.range.end.line,
+                            character: need.range.end.character,
+                        },
+                    },
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    message: format!(
+                        "Job: {need_name} is in stage {need_stage} which runs after \
+                        {job_stage} stage."
+                    ),
+                    ..DiagnosticData::NeedsStageOrder {
+                        name: need_name.to_string(),
+                        stage: need_stage.clone(),
+                    }
+                    .into()
+                });
+            }
+        }
+
+        diagnostics
+    }
+
+    // Settings have the same shape as `initializationOptions`; only toggles
+    // under `options` which are present are changed. Diagnostics of open
+    // documents are published again through `publish`.
+    pub fn on_change_configuration(
+        &self,
+        notification: Notification,
+        publish: &dyn Fn(LSPResult),
+    ) -> Option<LSPResult> {
+        let params =
+            serde_json::from_value::<DidChangeConfigurationParams>(notification.params).ok()?;
+
+        self.experimental
+            .lock()
+            .unwrap()
+            .update(params.settings.get("options")?);
+
+        let mut open_documents: Vec<String> = self
+            .open_documents
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect();
+        open_documents.sort();
+
+        for uri in open_documents {
+            let Ok(uri) = Url::parse(&uri) else {
+                continue;
+            };
+
+            if let Some(diagnostics) = self.generate_diagnostics(uri) {
+                publish(diagnostics);
+            }
+        }
+
+        None
+    }
+
+    // When client sends partial result token, references are streamed per scanned file
+    // through `partial` and final response contains only those that weren't sent.
+    pub fn on_references(
+        &self,
+        request: Request,
+        partial: &dyn Fn(LSPResult),
+    ) -> Option<LSPResult> {
+        let start = Instant::now();
+
+        let params = serde_json::from_value::<lsp_types::ReferenceParams>(request.params).ok()?;
+
+        let store = self.full_store();
+        let document_uri = &params.text_document_position.text_document.uri;
+        let document = store.get::<String>(&document_uri.to_string())?;
+
+        let position = params.text_document_position.position;
+        let line = document.lines().nth(position.line as usize)?;
+
+        let position_type = self.parser.get_position_type(document, position);
+        let include_declaration = params.context.include_declaration;
+
+        let find_references: ReferencesFinder = match position_type {
+            parser::PositionType::Extend => {
+                let word =
+                    parser_utils::ParserUtils::extract_key(line, position.character as usize)?;
+
+                Box::new(move |uri, content| {
+                    self.parser
+                        .get_all_extends(uri.clone(), content.as_str(), Some(word))
+                })
+            }
+            parser::PositionType::RootNode => {
+                let word =
+                    parser_utils::ParserUtils::extract_key(line, position.character as usize)?
+                        .trim_end_matches(':');
+
+                // currently support only those that are extends
+                if word.starts_with('.') {
+                    Box::new(move |uri, content| {
+                        self.parser
+                            .get_all_extends(uri.clone(), content.as_str(), Some(word))
+                    })
+                } else {
+                    Box::new(move |uri, content| {
+                        self.parser
+                            .get_all_job_needs(uri.clone(), content.as_str(), Some(word))
+                    })
+                }
+            }
+            parser::PositionType::Stage => {
+                let word =
+                    parser_utils::ParserUtils::extract_word(line, position.character as usize);
+
+                Box::new(move |uri, content| {
+                    let mut stages = self.parser.get_all_stages(uri, content.as_str(), word);
+                    if include_declaration {
+                        // entry of `stages:` list defining the stage
+                        stages.extend(
+                            self.parser
+                                .get_stage_definitions(uri, content.as_str())
+                                .into_iter()
+                                .filter(|s| {
+                                    word.is_some_and(|w| s.key == ParserUtils::strip_quotes(w))
+                                }),
+                        );
+                    }
+
+                    stages
+                })
+            }
+            parser::PositionType::None => {
+                let variable =
+                    self.variable_definition_name(document_uri.as_str(), document, position)?;
+
+                Box::new(move |uri, content| {
+                    self.variable_references(uri, content, &variable, include_declaration)
+                })
+            }
+            parser::PositionType::Variable | parser::PositionType::Script(_) => {
+                let variable =
+                    ParserUtils::extract_variable(line, position.character as usize)?.to_string();
+
+                Box::new(move |uri, content| {
+                    self.variable_references(uri, content, &variable, include_declaration)
+                })
+            }
+            _ => Box::new(|_, _| vec![]),
+        };
+
+        let token = params.partial_result_params.partial_result_token;
+        let mut references: Vec<GitlabElement> = vec![];
+
+        for (uri, content) in store.iter() {
+            let mut found = find_references(uri, content);
+
+            match &token {
+                Some(token) if !found.is_empty() => {
+                    partial(LSPResult::PartialReferences(PartialReferencesResult {
+                        token: token.clone(),
+                        locations: found,
+                    }));
+                }
+                _ => references.append(&mut found),
+            }
+        }
+
+        info!("REFERENCES ELAPSED: {:?}", start.elapsed());
+
+        Some(LSPResult::References(ReferencesResult {
+            id: request.id,
+            locations: references,
+        }))
+    }
+
+    // Usages of variable, with its definitions when client asks for them.
+    fn variable_references(
+        &self,
+        uri: &str,
+        content: &str,
+        variable: &str,
+        include_declaration: bool,
+    ) -> Vec<GitlabElement> {
+        let mut references = self
+            .parser
+            .get_all_variable_references(uri, content, variable);
+        if include_declaration {
+            references.extend(self.parser.get_all_variable_definitions(
+                uri,
+                content,
+                Some(variable),
+            ));
+        }
+
+        references
+    }
+
+    // Name of variable defined under cursor in a `variables` block.
+    fn variable_definition_name(
+        &self,
+        uri: &str,
+        document: &str,
+        position: Position,
+    ) -> Option<String> {
+        self.parser
+            .get_all_variable_definitions(uri, document, None)
+            .into_iter()
+            .find(|definition| {
+                definition.range.start.line == position.line
+                    && (definition.range.start.character..=definition.range.end.character)
+                        .contains(&position.character)
+            })
+            .map(|definition| definition.key)
+    }
+
+    #[allow(clippy::unnecessary_wraps, clippy::too_many_lines)]
+    // Component uris are suggested from git remotes, configured package map
+    // (including already downloaded component versions) and indexed components.
+    fn on_completion_component_uri(
+        &self,
+        line: &str,
+        position: Position,
+    ) -> anyhow::Result<Vec<LSPCompletion>> {
+        let (word, after) =
+            parser_utils::ParserUtils::completion_word(line, position.character as usize);
+
+        let mut uris: Vec<String> = self
+            .cfg
+            .remote_urls
+            .iter()
+            .filter_map(|origin| parser_utils::ParserUtils::remote_host(origin))
+            .map(|host| format!("{host}/"))
+            .collect();
+
+        for (project, origin) in &self.cfg.package_map {
+            let Some(host) = parser_utils::ParserUtils::remote_host(origin) else {
+                continue;
+            };
+
+            let cached = self.cached_component_uris(host, project);
+            if cached.is_empty() {
+                uris.push(format!("{host}/{project}/"));
+            } else {
+                uris.extend(cached);
+            }
+        }
+
+        uris.extend(self.components.lock().unwrap().keys().cloned());
+
+        uris.sort();
+        uris.dedup();
+
+        let items = uris
+            .into_iter()
+            .filter(|uri| uri.contains(word))
+            .flat_map(|uri| -> anyhow::Result<LSPCompletion> {
+                Ok(LSPCompletion {
+                    label: uri,
+                    details: None,
+                    sort_text: None,
+                    snippet: None,
+                    location: LSPLocation {
+                        range: Range {
+                            start: LSPPosition {
+                                line: position.line,
+                                character: position
+                                    .character
+                                    .saturating_sub(u32::try_from(word.len())?),
+                            },
+                            end: LSPPosition {
+                                line: position.line,
+                                character: position.character + u32::try_from(after.len())?,
+                            },
+                        },
+                        ..Default::default()
+                    },
+                })
+            })
+            .collect();
+
+        Ok(items)
+    }
+
+    // Lists components of already downloaded project versions. Components are
+    // either `templates/<name>.yml` files or `templates/<name>/template.yml`.
+    fn cached_component_uris(&self, host: &str, project: &str) -> Vec<String> {
+        let project_dir = PathBuf::from(format!("{}components/{project}", self.cfg.cache_path));
+        let Ok(versions) = fs::read_dir(project_dir) else {
+            return vec![];
+        };
+
+        let mut uris = vec![];
+        for version in versions.flatten() {
+            let Ok(templates) = fs::read_dir(version.path().join("templates")) else {
+                continue;
+            };
+
+            for template in templates.flatten() {
+                let path = template.path();
+                let name = if path.is_dir() {
+                    path.file_name()
+                } else if path
+                    .extension()
+                    .is_some_and(|ext| ext == "yml" || ext == "yaml")
+                {
+                    path.file_stem()
+                } else {
+                    None
+                };
+
+                if let Some(name) = name {
+                    uris.push(format!(
+                        "{host}/{project}/{}@{}",
+                        name.to_string_lossy(),
+                        version.file_name().to_string_lossy()
+                    ));
+                }
+            }
+        }
+
+        uris
+    }
+
+    #[allow(clippy::too_many_lines)]
+    // Scaffolds `inputs` block of component include with inputs which have no
+    // default value in component spec. Values are left as placeholders.
+    fn on_completion_component_inputs(
+        &self,
+        line: &str,
+        position: Position,
+        component: &Component,
+    ) -> anyhow::Result<Vec<LSPCompletion>> {
+        let (word, after) =
+            parser_utils::ParserUtils::completion_word(line, position.character as usize);
+        if !"inputs".starts_with(word) {
+            return Ok(vec![]);
+        }
+
+        let components = self.components.lock().unwrap();
+        let Some(spec) = components.get(&component.uri) else {
+            return Ok(vec![]);
+        };
+
+        let required: Vec<&str> = spec
+            .inputs
+            .iter()
+            .filter(|input| input.default.is_none())
+            .map(|input| input.key.as_str())
+            .collect();
+        if required.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let start = position.character - u32::try_from(word.len())?;
+        let padding = " ".repeat(start as usize + 2);
+        let snippet = std::iter::once("inputs:".to_string())
+            .chain(
+                required
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, key)| format!("{padding}{key}: ${{{}}}", idx + 1)),
+            )
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Ok(vec![LSPCompletion {
+            label: "inputs".to_string(),
+            details: Some(format!("Required inputs: {}", required.join(", "))),
+            sort_text: None,
+            snippet: Some(snippet),
+            location: LSPLocation {
+                range: Range {
+                    start: LSPPosition {
+                        line: position.line,
+                        character: start,
+                    },
+                    end: LSPPosition {
+                        line: position.line,
+                        character: position.character + u32::try_from(after.len())?,
+                    },
+                },
+                ..Default::default()
+            },
+        }])
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn on_completion_component(
+        &self,
+        line: &str,
+        position: Position,
+        component: &Component,
+    ) -> anyhow::Result<Vec<LSPCompletion>> {
+        if component.uri_hovered {
+            return self.on_completion_component_uri(line, position);
+        }
+
+        if component.inputs.iter().any(|i| i.hovered) {
+            let word = parser_utils::ParserUtils::word_before_cursor(
+                line,
+                position.character as usize,
+                |c: char| c.is_whitespace(),
+            );
+
+            let after = parser_utils::ParserUtils::word_after_cursor(
+                line,
+                position.character as usize,
+                |c| c.is_whitespace() || c == ':',
+            );
+
+            let components_store = self.components.lock().unwrap();
+            let Some(component_spec) = components_store.get(&component.uri) else {
+                warn!(
+                    "could not find component spec; indexing went wrong!; searching for {}",
+                    component.uri
+                );
+
+                return Ok(vec![]);
+            };
+
+            // filter out those that were already used
+            let valid_input_autocompletes: Vec<super::ComponentInput> = component_spec
+                .inputs
+                .iter()
+                .filter(|&i| !component.inputs.iter().any(|ci| ci.key == i.key))
+                .cloned() // Clone each element to get an owned version
+                .collect();
+
+            let items = valid_input_autocompletes
+                .into_iter()
+                .filter(|i| i.key.contains(word))
+                .flat_map(|i| -> anyhow::Result<LSPCompletion> {
+                    Ok(LSPCompletion {
+                        label: i.key.clone(),
+                        details: Some(i.autocomplete_details()),
+                        sort_text: None,
+                        snippet: None,
+                        location: LSPLocation {
+                            range: Range {
+                                start: LSPPosition {
+                                    line: position.line,
+                                    character: position.character - u32::try_from(word.len())?,
+                                },
+                                end: LSPPosition {
+                                    line: position.line,
+                                    character: position.character + u32::try_from(after.len())?,
+                                },
+                            },
+                            ..Default::default()
+                        },
+                    })
+                })
+                .collect();
+
+            return Ok(items);
+        } else if let Some(hovered_input) = component.inputs.iter().find(|i| i.value_plain.hovered)
+        {
+            let word = parser_utils::ParserUtils::word_before_cursor(
+                line,
+                position.character as usize,
+                |c| c.is_whitespace() || c == ':',
+            );
+
+            let after = parser_utils::ParserUtils::word_after_cursor(
+                line,
+                position.character as usize,
+                |c: char| c.is_whitespace(),
+            );
+
+            let components_store = self.components.lock().unwrap();
+            let Some(component_spec) = components_store.get(&component.uri) else {
+                warn!(
+                    "could not find component spec; indexing went wrong!; searching for {}",
+                    component.uri
+                );
+
+                return Ok(vec![]);
+            };
+
+            if let Some(input_spec) = component_spec
+                .inputs
+                .iter()
+                .find(|i| i.key == hovered_input.key)
+            {
+                // without options the default value is the only known value
+                let values: Vec<(String, Option<String>)> = if let Some(options) =
+                    &input_spec.options
+                {
+                    options.iter().map(|o| (o.clone(), None)).collect()
+                } else if let Some(default) = input_spec.default.as_ref().and_then(|d| match d {
+                    serde_yaml::Value::String(s) => Some(s.clone()),
+                    serde_yaml::Value::Number(n) => Some(n.to_string()),
+                    serde_yaml::Value::Bool(b) => Some(b.to_string()),
+                    _ => None,
+                }) {
+                    vec![(default, Some("(default)".to_string()))]
+                } else {
+                    vec![]
+                };
+
+                let items = values
+                    .into_iter()
+                    .filter(|(value, _)| value.contains(word))
+                    .flat_map(|(value, details)| -> anyhow::Result<LSPCompletion> {
+                        Ok(LSPCompletion {
+                            label: value,
+                            details,
+                            sort_text: None,
+                            snippet: None,
+                            location: LSPLocation {
+                                range: Range {
+                                    start: LSPPosition {
+                                        line: position.line,
+                                        character: position.character - u32::try_from(word.len())?,
+                                    },
+                                    end: LSPPosition {
+                                        line: position.line,
+                                        character: position.character + u32::try_from(after.len())?,
+                                    },
+                                },
+                                ..Default::default()
+                            },
+                        })
+                    })
+                    .collect();
+
+                return Ok(items);
+            }
+        }
+
+        Ok(vec![])
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub fn on_prepare_rename(&self, request: Request) -> Option<LSPResult> {
+        let start = Instant::now();
+        let params: TextDocumentPositionParams = serde_json::from_value(request.params).ok()?;
+
+        let document_uri = params.text_document.uri;
+        let store = self.document_store(document_uri.as_str());
+
+        if !self.can_path_be_modified(document_uri.as_ref()) {
+            return Some(LSPResult::PrepareRename(super::PrepareRenameResult {
+                id: request.id,
+                range: None,
+                err: Some("Cannot rename externally included files".to_string()),
+            }));
+        }
+
+        let document = store.get::<String>(&document_uri.clone().into())?;
+
+        let position = params.position;
+        let line = document.lines().nth(position.line as usize)?;
+
+        let res = match self.parser.get_position_type(document, position) {
+            parser::PositionType::RootNode => {
+                let word = parser_utils::ParserUtils::word_before_cursor(
+                    line,
+                    position.character as usize,
+                    char::is_whitespace,
+                );
+                let after = parser_utils::ParserUtils::word_after_cursor(
+                    line,
+                    position.character as usize,
+                    char::is_whitespace,
+                )
+                .trim_end_matches(':');
+
+                let full_word = format!("{word}{after}");
+                if keywords::is_root_keyword(&full_word) {
+                    return Some(LSPResult::PrepareRename(super::PrepareRenameResult {
+                        id: request.id,
+                        range: None,
+                        err: Some("Cannot rename Gitlab elements".to_string()),
+                    }));
+                }
+
+                Some(LSPResult::PrepareRename(super::PrepareRenameResult {
+                    id: request.id,
+                    range: Some(Range {
+                        start: LSPPosition {
+                            line: position.line,
+                            character: position.character - u32::try_from(word.len()).ok()?,
+                        },
+                        end: LSPPosition {
+                            line: position.line,
+                            character: position.character + u32::try_from(after.len()).ok()?,
+                        },
+                    }),
+                    err: None,
+                }))
+            }
+            parser::PositionType::Extend
+            | parser::PositionType::Needs(_)
+            | parser::PositionType::RuleReference(_) => {
+                let word = parser_utils::ParserUtils::word_before_cursor(
+                    line,
+                    position.character as usize,
+                    |c| c.is_whitespace() || c == '\'' || c == '"',
+                );
+                let after = parser_utils::ParserUtils::word_after_cursor(
+                    line,
+                    position.character as usize,
+                    |c| c.is_whitespace() || c == '\'' || c == '"',
+                );
+
+                let job = format!("{word}{after}");
+                for (uri, content) in store.iter() {
+                    if !self.can_path_be_modified(uri) {
+                        continue;
+                    }
+
+                    if self.parser.get_root_node_key(uri, content, &job).is_some() {
+                        return Some(LSPResult::PrepareRename(PrepareRenameResult {
+                            id: request.id,
+                            range: Some(Range {
+                                start: LSPPosition {
+                                    line: position.line,
+                                    character: position.character
+                                        - u32::try_from(word.len()).ok()?,
+                                },
+                                end: LSPPosition {
+                                    line: position.line,
+                                    character: position.character
+                                        + u32::try_from(after.len()).ok()?,
+                                },
+                            }),
+                            err: None,
+                        }));
+                    }
+                }
+                return Some(LSPResult::PrepareRename(super::PrepareRenameResult {
+                    id: request.id,
+                    range: None,
+                    err: Some("Could not find definition".to_string()),
+                }));
+            }
+            _ => Some(LSPResult::PrepareRename(super::PrepareRenameResult {
+                id: request.id,
+                range: None,
+                err: Some("Not supported".to_string()),
+            })),
+        };
+
+        info!("ON PREPARE RENAME ELAPSED: {:?}", start.elapsed());
+
+        res
+    }
+
+    // Local includes are written relative to repository root, with or without
+    // the leading slash, while clients usually send absolute paths or uris.
+    fn relative_include_path<'a>(&self, path: &'a str) -> &'a str {
+        let path = path.strip_prefix("file://").unwrap_or(path);
+        let path = path
+            .strip_prefix(self.cfg.root_dir.trim_end_matches('/'))
+            .unwrap_or(path);
+
+        path.trim_start_matches("./").trim_start_matches('/')
+    }
+
+    pub fn on_folding_range(&self, request: Request) -> Option<LSPResult> {
+        let params = serde_json::from_value::<FoldingRangeParams>(request.params).ok()?;
+
+        let document_uri = params.text_document.uri;
+        let store = self.document_store(document_uri.as_str());
+        let document = store.get::<String>(&document_uri.to_string())?;
+
+        let mut ranges: Vec<FoldingRange> = self
+            .parser
+            .get_all_root_nodes(document_uri.as_str(), document)
+            .iter()
+            .filter_map(|node| LSPHandlers::folding_range(&node.range))
+            .collect();
+
+        ranges.extend(
+            self.parser
+                .get_all_script_blocks(document_uri.as_str(), document)
+                .iter()
+                .filter_map(|block| LSPHandlers::folding_range(&block.range)),
+        );
+
+        for block in self
+            .parser
+            .get_all_rules_blocks(document_uri.as_str(), document)
+        {
+            ranges.extend(LSPHandlers::folding_range(&block.rules_range));
+            ranges.extend(
+                block
+                    .rules
+                    .iter()
+                    .filter_map(|rule| LSPHandlers::folding_range(&rule.range)),
+            );
+        }
+
+        Some(LSPResult::FoldingRange(FoldingRangeResult {
+            id: request.id,
+            ranges,
+        }))
+    }
+
+    // Ranges spanning a single line can't be folded.
+    fn folding_range(range: &Range) -> Option<FoldingRange> {
+        // block nodes can end at the start of the following line
+        let end_line = if range.end.character == 0 {
+            range.end.line.saturating_sub(1)
+        } else {
+            range.end.line
+        };
+
+        if end_line <= range.start.line {
+            return None;
+        }
+
+        Some(FoldingRange {
+            start_line: range.start.line,
+            end_line,
+            kind: Some(FoldingRangeKind::Region),
+            ..Default::default()
+        })
+    }
+
+    // Outline lists root nodes; jobs and templates include their rules and
+    // `stages` its entries.
+    pub fn on_document_symbol(&self, request: Request) -> Option<LSPResult> {
+        let params = serde_json::from_value::<DocumentSymbolParams>(request.params).ok()?;
+
+        let document_uri = params.text_document.uri;
+        let store = self.document_store(document_uri.as_str());
+        let document = store.get::<String>(&document_uri.to_string())?;
+
+        let mut rules_blocks: HashMap<String, DocumentSymbol> = self
+            .parser
+            .get_all_rules_blocks(document_uri.as_str(), document)
+            .into_iter()
+            .map(|block| {
+                let rules = block
+                    .rules
+                    .iter()
+                    .map(|rule| {
+                        LSPHandlers::document_symbol(
+                            &rule.key,
+                            SymbolKind::OBJECT,
+                            &rule.range,
+                            None,
+                        )
+                    })
+                    .collect();
+
+                let rules = LSPHandlers::document_symbol(
+                    "rules",
+                    SymbolKind::ARRAY,
+                    &block.rules_range,
+                    Some(rules),
+                );
+
+                (block.key, rules)
+            })
+            .collect();
+
+        let symbols = self
+            .parser
+            .get_all_root_nodes(document_uri.as_str(), document)
+            .into_iter()
+            .map(|node| {
+                let (kind, children) = if node.key == "stages" {
+                    let stages = self
+                        .parser
+                        .get_stage_definitions(document_uri.as_str(), document)
+                        .iter()
+                        .map(|stage| {
+                            LSPHandlers::document_symbol(
+                                &stage.key,
+                                SymbolKind::ENUM_MEMBER,
+                                &stage.range,
+                                None,
+                            )
+                        })
+                        .collect();
+
+                    (SymbolKind::ARRAY, Some(stages))
+                } else if keywords::is_root_keyword(&node.key) {
+                    (SymbolKind::MODULE, None)
+                } else {
+                    let kind = if node.key.starts_with('.') {
+                        SymbolKind::INTERFACE
+                    } else {
+                        SymbolKind::FUNCTION
+                    };
+
+                    (
+                        kind,
+                        rules_blocks.remove(&node.key).map(|rules| vec![rules]),
+                    )
+                };
+
+                let mut symbol =
+                    LSPHandlers::document_symbol(&node.key, kind, &node.range, children);
+                symbol.selection_range =
+                    LSPHandlers::lsp_range(&LSPHandlers::root_key_range(&node));
+
+                symbol
+            })
+            .collect();
+
+        Some(LSPResult::DocumentSymbol(DocumentSymbolResult {
+            id: request.id,
+            symbols,
+        }))
+    }
+
+    #[allow(deprecated)]
+    fn document_symbol(
+        name: &str,
+        kind: SymbolKind,
+        range: &Range,
+        children: Option<Vec<DocumentSymbol>>,
+    ) -> DocumentSymbol {
+        let range = lsp_types::Range {
+            start: lsp_types::Position {
+                line: range.start.line,
+                character: range.start.character,
+            },
+            end: lsp_types::Position {
+                line: range.end.line,
+                character: range.end.character,
+            },
+        };
+
+        DocumentSymbol {
+            name: name.to_string(),
+            detail: None,
+            kind,
+            tags: None,
+            deprecated: None,
+            range,
+            // symbols start with their key
+            selection_range: lsp_types::Range {
+                start: range.start,
+                end: range.start,
+            },
+            children,
+        }
+    }
+
+    // Include values are linked to files they resolve to, the same way as
+    // goto definition does. Unresolved includes aren't linked.
+    pub fn on_document_link(&self, request: Request) -> Option<LSPResult> {
+        let params = serde_json::from_value::<DocumentLinkParams>(request.params).ok()?;
+
+        let store = self.full_store();
+        let store = &*store;
+        let document_uri = params.text_document.uri;
+        let document = store.get::<String>(&document_uri.to_string())?;
+
+        let mut links = vec![];
+        for include in self
+            .parser
+            .get_all_include_targets(document_uri.as_str(), document)
+        {
+            let start = Position::new(include.range.start.line, include.range.start.character);
+            let end = Position::new(include.range.end.line, include.range.end.character);
+
+            let PositionType::Include(info) = self.parser.get_position_type(document, start) else {
+                continue;
+            };
+
+            let Some(target) = self
+                .on_definition_include(&document_uri, info, store)
+                .and_then(|location| Url::parse(&location.uri).ok())
+            else {
+                continue;
+            };
+
+            links.push(DocumentLink {
+                range: lsp_types::Range { start, end },
+                target: Some(target),
+                tooltip: None,
+                data: None,
+            });
+        }
+
+        Some(LSPResult::DocumentLink(DocumentLinkResult {
+            id: request.id,
+            links,
+        }))
+    }
+
+    // Offers quick fix creating stub job for needs referencing missing jobs
+    // and scaffolds required inputs of a component included without inputs.
+    pub fn on_code_action(&self, request: Request) -> Option<LSPResult> {
+        let params = serde_json::from_value::<CodeActionParams>(request.params).ok()?;
+
+        let document_uri = params.text_document.uri;
+        let store = self.document_store(document_uri.as_str());
+        let document = store.get::<String>(&document_uri.to_string())?;
+
+        // jobs are appended at the end of document
+        let end = match document.lines().last() {
+            Some(last) if !document.ends_with('\n') => Position::new(
+                u32::try_from(document.lines().count() - 1).ok()?,
+                u32::try_from(last.len()).ok()?,
+            ),
+            _ => Position::new(u32::try_from(document.lines().count()).ok()?, 0),
+        };
+        let separator = if document.ends_with('\n') { "" } else { "\n" };
+
+        let mut jobs: Vec<String> = vec![];
+        let mut actions = vec![];
+        for diagnostic in &params.context.diagnostics {
+            let Some(DiagnosticData::MissingNeed { name: job }) = diagnostic
+                .data
+                .clone()
+                .and_then(|data| serde_json::from_value(data).ok())
+            else {
+                continue;
+            };
+
+            // jobs generated by `parallel:matrix` can't be created as stubs
+            if jobs.contains(&job) || ParserUtils::matrix_job_name(&job).is_some() {
+                continue;
+            }
+
+            let edit = TextEdit {
+                range: lsp_types::Range { start: end, end },
+                new_text: format!(
+                    "{separator}\n{job}:\n  stage: test\n  script:\n    - echo todo\n"
+                ),
+            };
+
+            actions.push(CodeAction {
+                title: format!("Create job {job}"),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic.clone()]),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(document_uri.clone(), vec![edit])])),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+            jobs.push(job);
+        }
+
+        Some(LSPResult::CodeAction(CodeActionResult {
+            id: request.id,
+            actions,
+        }))
+    }
+
+    // Call hierarchy maps job relationships: incoming calls are jobs which
+    // extend or need the job and outgoing calls are templates it extends and
+    // jobs it needs.
+    pub fn on_prepare_call_hierarchy(&self, request: Request) -> Option<LSPResult> {
+        let params = serde_json::from_value::<CallHierarchyPrepareParams>(request.params).ok()?;
+
+        let document_uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let store = self.full_store();
+        let document = store.get::<String>(&document_uri.to_string())?;
+        let line = document.lines().nth(position.line as usize)?;
+
+        // referenced jobs are resolved to their definition, otherwise job under
+        // cursor is used
+        let name = match self.parser.get_position_type(document, position) {
+            PositionType::Extend | PositionType::Dependency => {
+                ParserUtils::extract_key(line, position.character as usize)?.to_string()
+            }
+            PositionType::Needs(NodeDefinition { name }) => {
+                let name = ParserUtils::strip_quotes(&name);
+                ParserUtils::matrix_job_name(name)
+                    .unwrap_or(name)
+                    .to_string()
+            }
+            _ => {
+                self.parser
+                    .get_root_node_at_position(document, position)?
+                    .key
+            }
+        };
+
+        let item = self.find_call_hierarchy_item(&store, &name)?;
+
+        Some(LSPResult::PrepareCallHierarchy(
+            PrepareCallHierarchyResult {
+                id: request.id,
+                items: vec![item],
+            },
+        ))
+    }
+
+    pub fn on_incoming_calls(&self, request: Request) -> Option<LSPResult> {
+        let params =
+            serde_json::from_value::<CallHierarchyIncomingCallsParams>(request.params).ok()?;
+        let name = params.item.name;
+
+        let store = self.full_store();
+
+        let mut calls = vec![];
+        for (uri, content) in store.iter() {
+            let references = self
+                .parser
+                .get_all_extends(uri.clone(), content, Some(&name))
+                .into_iter()
+                .chain(
+                    self.parser
+                        .get_all_job_needs(uri.clone(), content, Some(&name)),
+                );
+
+            for (caller, from_ranges) in self.group_by_job(content, references) {
+                if let Some(from) = self.call_hierarchy_item(uri, content, &caller) {
+                    calls.push(CallHierarchyIncomingCall { from, from_ranges });
+                }
+            }
+        }
+
+        Some(LSPResult::IncomingCalls(IncomingCallsResult {
+            id: request.id,
+            calls,
+        }))
+    }
+
+    pub fn on_outgoing_calls(&self, request: Request) -> Option<LSPResult> {
+        let params =
+            serde_json::from_value::<CallHierarchyOutgoingCallsParams>(request.params).ok()?;
+        let name = params.item.name;
+        let uri = params.item.uri.to_string();
+
+        let store = self.full_store();
+        let content = store.get(&uri)?;
+
+        let references = self
+            .parser
+            .get_all_extends(uri.clone(), content, None)
+            .into_iter()
+            .chain(self.parser.get_all_job_needs(uri.clone(), content, None))
+            .filter(|reference| {
+                self.parser
+                    .get_root_node_at_position(content, LSPHandlers::start_position(reference))
+                    .is_some_and(|job| job.key == name)
+            })
+            .map(|reference| GitlabElement {
+                key: ParserUtils::matrix_job_name(&reference.key)
+                    .map_or(reference.key.clone(), str::to_string),
+                ..reference
+            });
+
+        let mut calls: Vec<CallHierarchyOutgoingCall> = vec![];
+        for reference in references {
+            let range = LSPHandlers::lsp_range(&reference.range);
+            if let Some(call) = calls.iter_mut().find(|c| c.to.name == reference.key) {
+                call.from_ranges.push(range);
+            } else if let Some(to) = self.find_call_hierarchy_item(&store, &reference.key) {
+                calls.push(CallHierarchyOutgoingCall {
+                    to,
+                    from_ranges: vec![range],
+                });
+            }
+        }
+
+        Some(LSPResult::OutgoingCalls(OutgoingCallsResult {
+            id: request.id,
+            calls,
+        }))
+    }
+
+    // Groups references by the job in which they are used.
+    fn group_by_job(
+        &self,
+        content: &str,
+        references: impl Iterator<Item = GitlabElement>,
+    ) -> Vec<(String, Vec<lsp_types::Range>)> {
+        let mut jobs: Vec<(String, Vec<lsp_types::Range>)> = vec![];
+        for reference in references {
+            let Some(job) = self
+                .parser
+                .get_root_node_at_position(content, LSPHandlers::start_position(&reference))
+            else {
+                continue;
+            };
+
+            let range = LSPHandlers::lsp_range(&reference.range);
+            if let Some((_, ranges)) = jobs.iter_mut().find(|(key, _)| *key == job.key) {
+                ranges.push(range);
+            } else {
+                jobs.push((job.key, vec![range]));
+            }
+        }
+
+        jobs
+    }
+
+    fn find_call_hierarchy_item(
+        &self,
+        store: &HashMap<String, String>,
+        name: &str,
+    ) -> Option<CallHierarchyItem> {
+        store
+            .iter()
+            .find_map(|(uri, content)| self.call_hierarchy_item(uri, content, name))
+    }
+
+    fn call_hierarchy_item(
+        &self,
+        uri: &str,
+        content: &str,
+        name: &str,
+    ) -> Option<CallHierarchyItem> {
+        let node = self.parser.get_root_node(uri, content, name)?;
+        let key = self.parser.get_root_node_key(uri, content, name)?;
+
+        Some(CallHierarchyItem {
+            name: name.to_string(),
+            kind: SymbolKind::FUNCTION,
+            tags: None,
+            detail: None,
+            uri: Url::parse(uri).ok()?,
+            range: LSPHandlers::lsp_range(&node.range),
+            selection_range: LSPHandlers::lsp_range(&key.range),
+            data: None,
+        })
+    }
+
+    fn start_position(element: &GitlabElement) -> Position {
+        Position {
+            line: element.range.start.line,
+            character: element.range.start.character,
+        }
+    }
+
+    fn lsp_range(range: &Range) -> lsp_types::Range {
+        lsp_types::Range {
+            start: lsp_types::Position {
+                line: range.start.line,
+                character: range.start.character,
+            },
+            end: lsp_types::Position {
+                line: range.end.line,
+                character: range.end.character,
+            },
+        }
+    }
+
+    // Range of root node's key, including its quotes, which starts its
+    // definition.
+    fn root_key_range(node: &GitlabElement) -> Range {
+        let quoted = node
+            .content
+            .as_deref()
+            .is_some_and(|definition| definition.starts_with(['"', '\'']));
+        let len = u32::try_from(node.key.len() + if quoted { 2 } else { 0 }).unwrap_or(0);
+
+        Range {
+            start: node.range.start.clone(),
+            end: LSPPosition {
+                line: node.range.start.line,
+                character: node.range.start.character + len,
+            },
+        }
+    }
+
+    pub fn on_execute_command(&self, request: Request) -> Option<LSPResult> {
+        let params = serde_json::from_value::<ExecuteCommandParams>(request.params).ok()?;
+
+        let result = match params.command.as_str() {
+            REVEAL_CACHED_FILE_COMMAND => self.reveal_cached_file(params.arguments)?,
+            command => {
+                warn!("invalid command: {command}");
+                return None;
+            }
+        };
+
+        Some(LSPResult::ExecuteCommand(ExecuteCommandResult {
+            id: request.id,
+            result,
+        }))
+    }
+
+    // Returns local file of include under given position. Remote includes
+    // are cached under hashed names which are hard to find otherwise.
+    fn reveal_cached_file(&self, arguments: Vec<serde_json::Value>) -> Option<serde_json::Value> {
+        let params =
+            serde_json::from_value::<TextDocumentPositionParams>(arguments.into_iter().next()?)
+                .ok()?;
+
+        let store = self.full_store();
+        let document = store.get(&params.text_document.uri.to_string())?;
+
+        let PositionType::Include(info) = self.parser.get_position_type(document, params.position)
+        else {
+            return None;
+        };
+
+        let location = self.on_definition_include(&params.text_document.uri, info, &store)?;
+        let path = Url::parse(&location.uri).ok()?.to_file_path().ok()?;
+
+        serde_json::to_value(RevealCachedFileResult {
+            uri: location.uri,
+            path: path.to_string_lossy().to_string(),
+        })
+        .ok()
+    }
+
+    pub fn on_export_resolved(&self, request: Request) -> Option<LSPResult> {
+        let params = serde_json::from_value::<ExportResolvedParams>(request.params).ok()?;
+        let uri = params.text_document.uri;
+
+        let content = self.document_store(uri.as_str()).get(uri.as_str())?.clone();
+        let results = self.parser.parse_contents(&uri, &content, true)?;
+
+        let content = match self.resolve_pipeline(&results) {
+            Ok(c) => c,
+            Err(err) => return Some(LSPResult::Error(err)),
+        };
+
+        Some(LSPResult::ExportResolved(ExportResolvedResult {
+            id: request.id,
+            content,
+        }))
+    }
+
+    // Flattens parsed pipeline into a single document. Hidden jobs, includes
+    // and defaults are dropped as they are merged into jobs already, and
+    // `!reference` tags are replaced with values they point to.
+    fn resolve_pipeline(&self, results: &ParseResults) -> anyhow::Result<String> {
+        let mut node_list: Vec<GitlabFileElements> = vec![];
+        for node in &results.nodes {
+            match node_list.iter_mut().find(|f| f.uri == node.uri) {
+                Some(file) => file.elements.push(node.clone()),
+                None => node_list.push(GitlabFileElements {
+                    uri: node.uri.clone(),
+                    elements: vec![node.clone()],
+                }),
+            }
+        }
+
+        let mut pipeline = serde_yaml::Mapping::new();
+        if !results.stages.is_empty() {
+            pipeline.insert(
+                "stages".into(),
+                results
+                    .stages
+                    .iter()
+                    .map(|s| serde_yaml::Value::from(s.key.as_str()))
+                    .collect(),
+            );
+        }
+
+        // hidden jobs are resolved as well since `!reference` tags can
+        // point to them
+        let mut definitions = serde_yaml::Mapping::new();
+        for node in &results.nodes {
+            if ["include", "default", "stages", "spec"].contains(&node.key.as_str()) {
+                continue;
+            }
+
+            let definition = if keywords::is_root_keyword(&node.key) {
+                node.content.clone().unwrap_or_default()
+            } else {
+                self.parser.get_full_definition(node.clone(), &node_list)?
+            };
+
+            let serde_yaml::Value::Mapping(mut definition) =
+                serde_yaml::from_str::<serde_yaml::Value>(&definition)?
+            else {
+                continue;
+            };
+
+            // later definitions of the same job override earlier ones
+            for (key, mut value) in std::mem::take(&mut definition) {
+                if let serde_yaml::Value::Mapping(job) = &mut value {
+                    job.remove("extends");
+                }
+                definitions.insert(key, value);
+            }
+        }
+
+        for (key, value) in &definitions {
+            if key.as_str().is_some_and(|k| k.starts_with('.')) {
+                continue;
+            }
+
+            let mut value = value.clone();
+            ParserUtils::resolve_references(&mut value, &definitions);
+            pipeline.insert(key.clone(), value);
+        }
+
+        Ok(serde_yaml::to_string(&pipeline)?)
+    }
+
+    pub fn on_update_include_paths(&self, request: Request) -> Option<LSPResult> {
+        let params = serde_json::from_value::<UpdateIncludePathsParams>(request.params).ok()?;
+
+        info!("got update include paths params: {params:?}");
+
+        let old_path = self.relative_include_path(&params.old_path);
+        let new_path = self.relative_include_path(&params.new_path);
+
+        let store = self.full_store();
+        let mut edits: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+
+        for (uri, content) in store.iter() {
+            if !self.can_path_be_modified(uri) {
+                continue;
+            }
+
+            for include in self.parser.get_all_local_includes(uri, content) {
+                if self.relative_include_path(&include.key) != old_path {
+                    continue;
+                }
+
+                let Ok(url) = Url::parse(uri) else {
+                    continue;
+                };
+
+                // keep the same style as the original entry
+                let leading_slash = if include.key.starts_with('/') {
+                    "/"
+                } else {
+                    ""
+                };
+                let quote = include
+                    .content
+                    .as_deref()
+                    .and_then(|c| c.chars().next())
+                    .filter(|c| *c == '"' || *c == '\'')
+                    .map(String::from)
+                    .unwrap_or_default();
+
+                edits.entry(url).or_default().push(TextEdit {
+                    new_text: format!("{quote}{leading_slash}{new_path}{quote}"),
+                    range: lsp_types::Range {
+                        start: Position {
+                            line: include.range.start.line,
+                            character: include.range.start.character,
+                        },
+                        end: Position {
+                            line: include.range.end.line,
+                            character: include.range.end.character,
+                        },
+                    },
+                });
+            }
+        }
+
+        Some(LSPResult::Rename(RenameResult {
+            id: request.id,
+            edits: Some(edits),
+            err: None,
+        }))
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub fn on_rename(&self, request: Request) -> Option<LSPResult> {
+        let start = Instant::now();
+        let params: RenameParams = serde_json::from_value(request.params).ok()?;
+
+        info!("got rename params: {params:?}");
+
+        let store = self.full_store();
+        let document_uri = params.text_document_position.text_document.uri;
+
+        // This is redundant but I guess could be needed for when prepare_rename isn't supported
+        // by the client
+        if !self.can_path_be_modified(document_uri.as_ref()) {
+            return Some(LSPResult::Rename(super::RenameResult {
+                id: request.id,
+                edits: None,
+                err: Some("Cannot rename externally included files".to_string()),
+            }));
+        }
+
+        let document = store.get::<String>(&document_uri.clone().into())?;
+
+        let position = params.text_document_position.position;
+        let line = document.lines().nth(position.line as usize)?;
+
+        let mut edits: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+        match self.parser.get_position_type(document, position) {
+            parser::PositionType::RootNode => {
+                let text_edits = edits.entry(document_uri.clone()).or_default();
+
+                let word = parser_utils::ParserUtils::word_before_cursor(
+                    line,
+                    position.character as usize,
+                    char::is_whitespace,
+                );
+                let after = parser_utils::ParserUtils::word_after_cursor(
+                    line,
+                    position.character as usize,
+                    char::is_whitespace,
+                )
+                .trim_end_matches(':');
+
+                let full_word = format!("{word}{after}");
+
+                if keywords::is_root_keyword(&full_word) {
+                    return Some(LSPResult::Rename(super::RenameResult {
+                        id: request.id,
+                        edits: None,
+                        err: Some("Cannot rename Gitlab elements".to_string()),
+                    }));
+                }
+
+                text_edits.push(TextEdit {
+                    new_text: params.new_name.clone(),
+                    range: lsp_types::Range {
+                        start: Position {
+                            line: position.line,
+                            character: position.character - u32::try_from(word.len()).ok()?,
+                        },
+                        end: Position {
+                            line: position.line,
+                            character: position.character + u32::try_from(after.len()).ok()?,
+                        },
+                    },
+                });
+
+                for (uri, content) in store.iter() {
+                    if !self.can_path_be_modified(uri) {
+                        continue;
+                    }
+
+                    // TODO: ? should be removed and just skip this entry
+                    let text_edits = edits.entry(Url::parse(uri).ok()?).or_default();
+
+                    text_edits.append(&mut self.rename_extends(
+                        uri,
+                        content,
+                        &full_word,
+                        &params.new_name,
+                    ));
+
+                    text_edits.append(&mut self.rename_needs(
+                        uri,
+                        content,
+                        &full_word,
+                        &params.new_name,
+                    ));
+
+                    text_edits.append(&mut self.rename_rule_references(
+                        uri,
+                        content,
+                        &full_word,
+                        &params.new_name,
+                    ));
+                }
+            }
+            position_type @ (parser::PositionType::Extend
+            | parser::PositionType::RuleReference(_)
+            | parser::PositionType::Needs(_)) => {
+                let word = parser_utils::ParserUtils::word_before_cursor(
+                    line,
+                    position.character as usize,
+                    |c| c.is_whitespace() || c == '\'' || c == '"',
+                );
+
+                let after = parser_utils::ParserUtils::word_after_cursor(
+                    line,
+                    position.character as usize,
+                    |c| c.is_whitespace() || c == '\'' || c == '"',
+                );
+
+                // need of a job generated by `parallel:matrix` is renamed
+                // without its ` [matrix...]` suffix
+                let job = match &position_type {
+                    parser::PositionType::Needs(NodeDefinition { name }) => {
+                        ParserUtils::matrix_job_name(name)
+                            .map_or_else(|| format!("{word}{after}"), ToString::to_string)
+                    }
+                    _ => format!("{word}{after}"),
+                };
+
+                let mut is_renamed_job_inside_the_project = false;
+
+                for (uri, content) in store.iter() {
+                    if !self.can_path_be_modified(uri) {
+                        continue;
+                    }
+
+                    // TODO: ? should be removed and just skip this entry
+                    let text_edits = edits.entry(Url::parse(uri).ok()?).or_default();
+
+                    if let Some(r) = self.rename_root_node(uri, content, &job, &params.new_name) {
+                        is_renamed_job_inside_the_project = true;
+                        text_edits.push(r);
+                    }
+
+                    text_edits.append(&mut self.rename_extends(
+                        uri,
+                        content,
+                        &job,
+                        &params.new_name,
+                    ));
+
+                    text_edits.append(&mut self.rename_needs(uri, content, &job, &params.new_name));
+
+                    text_edits.append(&mut self.rename_rule_references(
+                        uri,
+                        content,
+                        &job,
+                        &params.new_name,
+                    ));
+                }
+
+                // adding this at the bottom because if we are trying to rename some extend that
+                // was declared only in cached files this wont be reached
+                if !is_renamed_job_inside_the_project {
+                    return Some(LSPResult::Rename(super::RenameResult {
+                        id: request.id,
+                        edits: None,
+                        err: Some(
+                            "Cannot rename extend which has definition outside project scope"
+                                .to_string(),
+                        ),
+                    }));
+                }
+            }
+            _ => {
+                warn!("invalid type for rename");
+            }
+        }
+
+        info!("ON RENAME ELAPSED: {:?}", start.elapsed());
+
+        Some(LSPResult::Rename(RenameResult {
+            id: request.id,
+            edits: Some(edits),
+            err: None,
+        }))
+    }
+
+    fn rename_extends(
+        &self,
+        uri: &str,
+        content: &str,
+        current_name: &str,
+        new_name: &str,
+    ) -> Vec<TextEdit> {
+        let extends = self
+            .parser
+            .get_all_extends(uri.to_string(), content, Some(current_name));
+
+        let mut text_edits = vec![];
+        for e in extends {
+            text_edits.push(TextEdit {
+                range: lsp_types::Range {
+                    start: Position {
+                        line: e.range.start.line,
+                        character: e.range.start.character,
+                    },
+                    end: Position {
+                        line: e.range.end.line,
+                        character: e.range.end.character,
+                    },
+                },
+                new_text: new_name.to_string(),
+            });
+        }
+
+        text_edits
+    }
+
+    fn rename_needs(
+        &self,
+        uri: &str,
+        content: &str,
+        current_name: &str,
+        new_name: &str,
+    ) -> Vec<TextEdit> {
+        // all needs are searched as matrix needs, e.g. `build: [linux, arm64]`,
+        // don't match their job name exactly
+        let needs = self
+            .parser
+            .get_all_job_needs(uri.to_string(), content, None);
+
+        let mut text_edits = vec![];
+        for e in needs {
+            let job = ParserUtils::matrix_job_name(&e.key).unwrap_or(&e.key);
+            if job != current_name {
+                continue;
+            }
+
+            // only job name is edited so matrix suffix stays untouched
+            let Ok(len) = u32::try_from(job.len()) else {
+                continue;
+            };
+
+            text_edits.push(TextEdit {
+                range: lsp_types::Range {
+                    start: Position {
+                        line: e.range.start.line,
+                        character: e.range.start.character,
+                    },
+                    end: Position {
+                        line: e.range.start.line,
+                        character: e.range.start.character + len,
+                    },
+                },
+                new_text: new_name.to_string(),
+            });
+        }
+
+        text_edits
+    }
+
+    fn rename_rule_references(
+        &self,
+        uri: &str,
+        content: &str,
+        full_word: &str,
+        new_name: &str,
+    ) -> Vec<TextEdit> {
+        let rule_references =
+            self.parser
+                .get_all_rule_references(uri.to_string(), content, Some(full_word));
+
+        let mut text_edits = vec![];
+        for r in rule_references {
+            text_edits.push(TextEdit {
+                range: lsp_types::Range {
+                    start: Position {
+                        line: r.range.start.line,
+                        character: r.range.start.character,
+                    },
+                    end: Position {
+                        line: r.range.end.line,
+                        character: r.range.end.character,
+                    },
+                },
+                new_text: new_name.to_string(),
+            });
+        }
+
+        text_edits
+    }
+
+    fn rename_root_node(
+        &self,
+        uri: &str,
+        content: &str,
+        current_name: &str,
+        new_name: &str,
+    ) -> Option<TextEdit> {
+        if let Some(e) = self.parser.get_root_node_key(uri, content, current_name) {
+            return Some(TextEdit {
+                range: lsp_types::Range {
+                    start: Position {
+                        line: e.range.start.line,
+                        character: e.range.start.character,
+                    },
+                    end: Position {
+                        line: e.range.end.line,
+                        character: e.range.end.character,
+                    },
+                },
+                new_text: new_name.to_string(),
+            });
+        }
+
+        None
+    }
+
+    fn on_completion_remote(
+        &self,
+        line: &str,
+        position: Position,
+        remote: &RemoteInclude,
+    ) -> anyhow::Result<Vec<LSPCompletion>> {
+        let Some(project) = &remote.project else {
+            return Ok(vec![]);
+        };
+
+        let word = parser_utils::ParserUtils::word_before_cursor(
+            line,
+            position.character as usize,
+            |c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '/' || c == '\\',
+        );
+
+        let after =
+            parser_utils::ParserUtils::word_after_cursor(line, position.character as usize, |c| {
+                c.is_whitespace() || c == '"' || c == '\'' || c == '/' || c == '\\'
+            });
+
+        let reference = remote
+            .reference
+            .as_deref()
+            .or(self.cfg.default_include_ref.as_deref())
+            .unwrap_or(DEFAULT_BRANCH_SUBFOLDER);
+        let path = format!("{project}/{reference}/");
+
+        let (current, previous) =
+            ParserUtils::find_path_at_cursor(line, usize::try_from(position.character).unwrap());
+
+        let cache = &self.cfg.cache_path;
+        let full_path = format!("{cache}{path}{previous}");
+
+        let mut lsp_completions = vec![];
+        for entry in fs::read_dir(full_path)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            let path_str = path.file_name().unwrap().to_string_lossy();
+
+            if path_str.starts_with('.') {
+                debug!("path starts with .; skipping");
+                continue;
+            }
+
+            if !current.trim().is_empty() && !path_str.contains(&current) {
+                debug!("path: {:?} doesnt contain: {:?}", path_str, current);
+                continue;
+            }
+
+            if path.is_file() && !path_str.ends_with(".yaml") && !path_str.ends_with(".yml") {
+                continue;
+            }
+
+            let c = LSPCompletion {
+                label: path_str.to_string(),
+                details: None,
+                sort_text: None,
+                snippet: None,
+                location: LSPLocation {
+                    range: Range {
+                        start: LSPPosition {
+                            line: position.line,
+                            character: position.character - u32::try_from(word.len())?,
+                        },
+                        end: LSPPosition {
+                            line: position.line,
+                            character: position.character + u32::try_from(after.len())?,
+                        },
+                    },
+                    ..Default::default()
+                },
+            };
+
+            lsp_completions.push(c);
+        }
+
+        Ok(lsp_completions)
+    }
+}
+
+fn generate_component_diagnostics_from_spec(
+    i: &GitlabInputElement,
+    spec_definition: &ComponentInput,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    if let Some(input_value_element) = &i.value_plain {
+        if let Some(input_value) = &input_value_element.content {
+            // check options
+            if let Some(options) = &spec_definition.options {
+                if !options.contains(input_value) {
+                    diagnostics.push(Diagnostic {
+                        range: lsp_types::Range {
+                            start: lsp_types::Position {
+                                line: input_value_element.range.start.line,
+                                character: input_value_element.range.start.character,
+                            },
+                            end: lsp_types::Position {
+                                line: input_value_element.range.end.line,
+                                character: input_value_element.range.end.character,
+                            },
+                        },
+                        message: format!(
+                            "Invalid input value. Value needs to be one of: '{}'.",
+                            options.join(", ")
+                        ),
+                        ..DiagnosticData::InvalidInputValue {
+                            name: i.key.clone(),
+                        }
+                        .into()
+                    });
+                }
+            }
+
+            // check if it matches to the spec pattern
+            if let Some(pattern) = &spec_definition.regex {
+                if let Ok(regex) = Regex::new(pattern.trim_matches('/')) {
+                    if !regex.is_match(input_value) {
+                        diagnostics.push(Diagnostic {
+                            range: lsp_types::Range {
+                                start: lsp_types::Position {
+                                    line: input_value_element.range.start.line,
+                                    character: input_value_element.range.start.character,
+                                },
+                                end: lsp_types::Position {
+                                    line: input_value_element.range.end.line,
+                                    character: input_value_element.range.end.character,
+                                },
+                            },
+                            message: format!(
+                                "Invalid value. Value needs to match the pattern: {pattern}"
+                            ),
+                            ..DiagnosticData::InputPatternMismatch {
+                                name: i.key.clone(),
+                            }
+                            .into()
+                        });
+                    }
+                } else {
+                    error!("could not parse regex from input spec regex: {pattern}");
+                }
+            }
+
+            input_type_diagnostic(i, spec_definition, input_value_element, false, diagnostics);
+        }
+    } else if let Some(input_value_element) = &i.value_block {
+        input_type_diagnostic(i, spec_definition, input_value_element, true, diagnostics);
+    } else {
+        diagnostics.push(Diagnostic {
+            range: lsp_types::Range {
+                start: lsp_types::Position {
+                    line: i.range.start.line,
+                    character: i.range.start.character,
+                },
+                end: lsp_types::Position {
+                    line: i.range.end.line,
+                    character: i.range.end.character,
+                },
+            },
+            message: "Missing value.".to_string(),
+            ..DiagnosticData::MissingInputValue {
+                name: i.key.clone(),
+            }
+            .into()
+        });
+    }
+}
+
+// Arrays can be written as block or flow sequence while numbers and
+// booleans have to be plain values. Interpolated values are skipped.
+fn input_type_diagnostic(
+    i: &GitlabInputElement,
+    spec_definition: &ComponentInput,
+    input_value_element: &GitlabElement,
+    is_block: bool,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let (Some(prop_type), Some(input_value)) =
+        (&spec_definition.prop_type, &input_value_element.content)
+    else {
+        return;
+    };
+    let input_value = input_value.trim();
+    if input_value.contains("$[[") {
+        return;
+    }
+
+    let matches = match prop_type.as_str() {
+        "array" => is_block || input_value.starts_with('['),
+        "number" => !is_block && input_value.parse::<f64>().is_ok(),
+        "boolean" => !is_block && matches!(input_value, "true" | "false"),
+        _ => true,
+    };
+    if matches {
+        return;
+    }
+
+    diagnostics.push(Diagnostic {
+        range: lsp_types::Range {
+            start: lsp_types::Position {
+                line: input_value_element.range.start.line,
+                character: input_value_element.range.start.character,
+            },
+            end: lsp_types::Position {
+                line: input_value_element.range.end.line,
+                character: input_value_element.range.end.character,
+            },
+        },
+        message: format!(
+            "Input {} expects type {prop_type}, got '{input_value}'",
+            i.key
+        ),
+        ..DiagnosticData::InputTypeMismatch {
+            name: i.key.clone(),
+            expected: prop_type.clone(),
+        }
+        .into()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use fs_utils::MockFSUtils;
+    use lsp_server::RequestId;
+    use lsp_types::{
+        PartialResultParams, TextDocumentContentChangeEvent, TextDocumentIdentifier,
+        TextDocumentItem, VersionedTextDocumentIdentifier, WorkDoneProgressParams,
+    };
+
+    use crate::gitlab_ci_ls_parser::{DiagnosticLevel, LSPFileConfiguration, DIAGNOSTIC_KINDS};
+
+    use super::*;
+
+    fn new_handlers() -> LSPHandlers {
+        new_handlers_with(|_| {})
+    }
+
+    // Handlers with test defaults; `configure` changes only fields a test needs.
+    fn new_handlers_with(configure: impl FnOnce(&mut LSPConfig)) -> LSPHandlers {
+        new_handlers_with_fs(Box::new(MockFSUtils::new()), configure)
+    }
+
+    fn new_handlers_with_fs(
+        fs_utils: Box<dyn fs_utils::FSUtils>,
+        configure: impl FnOnce(&mut LSPConfig),
+    ) -> LSPHandlers {
+        let mut cfg = LSPConfig {
+            root_dir: "/nonexistent/gitlab-ci-ls".to_string(),
+            cache_path: "/nonexistent/gitlab-ci-ls-cache/".to_string(),
+            package_map: HashMap::new(),
+            remote_urls: vec![],
+            completion_limit: None,
+            store_idle_timeout: None,
+            default_include_ref: None,
+            configuration: LSPFileConfiguration::default(),
+        };
+        configure(&mut cfg);
+
+        LSPHandlers::new(cfg, LSPExperimental::default(), fs_utils)
+    }
+
+    fn open_document(handlers: &LSPHandlers, uri: &Url, content: &str) -> Option<LSPResult> {
+        let params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "yaml".to_string(),
+                version: 1,
+                text: content.to_string(),
+            },
+        };
+
+        let uri = handlers.on_open(Notification {
+            method: "textDocument/didOpen".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        })?;
+
+        handlers.generate_diagnostics(uri)
+    }
+
+    fn change_document(
+        handlers: &LSPHandlers,
+        uri: &Url,
+        content_changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Option<LSPResult> {
+        let params = DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version: 2,
+            },
+            content_changes,
+        };
+
+        handlers.on_change(Notification {
+            method: "textDocument/didChange".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        })
+    }
+
+    fn completion_request(uri: &Url, position: Position) -> Request {
+        let params = CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: None,
+        };
+
+        Request {
+            id: RequestId::from(1),
+            method: "textDocument/completion".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }
+    }
+
+    fn references_request(uri: &Url, position: Position, include_declaration: bool) -> Request {
+        let params = lsp_types::ReferenceParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: lsp_types::ReferenceContext {
+                include_declaration,
+            },
+        };
+
+        Request {
+            id: RequestId::from(1),
+            method: "textDocument/references".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }
+    }
+
+    fn rename_request(uri: &Url, position: Position, new_name: &str) -> Request {
+        let params = RenameParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position,
+            },
+            new_name: new_name.to_string(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+
+        Request {
+            id: RequestId::from(1),
+            method: "textDocument/rename".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }
+    }
+
+    fn hover_request(uri: &Url, position: Position) -> Request {
+        let params = HoverParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+
+        Request {
+            id: RequestId::from(1),
+            method: "textDocument/hover".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }
+    }
+
+    fn definition_request(uri: &Url, position: Position) -> Request {
+        let params = GotoTypeDefinitionParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+
+        Request {
+            id: RequestId::from(1),
+            method: "textDocument/definition".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }
+    }
+
+    const NEEDS_CONTENT: &str = r"
+job_a:
+  script: echo a
+job_b:
+  script: echo b
+job_c:
+  script: echo c
+job_d:
+  needs:
+    - job: job
+";
+
+    #[test]
+    fn test_completion_limit_caps_items() {
+        let handlers = new_handlers_with(|cfg| cfg.completion_limit = Some(2));
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        open_document(&handlers, &uri, NEEDS_CONTENT);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(9, 14)))
+        else {
+            panic!("expected completion result");
+        };
+
+        assert!(result.is_incomplete);
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["job_a", "job_b"]);
+    }
+
+    #[test]
+    fn test_completion_without_limit_is_complete() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        open_document(&handlers, &uri, NEEDS_CONTENT);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(9, 14)))
+        else {
+            panic!("expected completion result");
+        };
+
+        assert!(!result.is_incomplete);
+        assert_eq!(result.list.len(), 4);
+    }
+
+    #[test]
+    fn test_hover_service_mapping() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+job_a:
+  services:
+    - name: postgres:15
+      alias: db
+  script: echo a
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Hover(result)) =
+            handlers.on_hover(hover_request(&uri, Position::new(3, 15)))
+        else {
+            panic!("expected hover result");
+        };
+
+        assert!(result.content.contains("image: `postgres:15`"));
+        assert!(result.content.contains("alias: `db`"));
+    }
+
+    #[test]
+    fn test_pre_stage_job_needs_later_stage_job() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build_job:
+  stage: build
+  script: echo build
+
+prepare_job:
+  stage: .pre
+  needs:
+    - job: build_job
+  script: echo prepare
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 8);
+        assert_eq!(
+            result.diagnostics[0].severity,
+            Some(DiagnosticSeverity::WARNING)
+        );
+    }
+
+    #[test]
+    fn test_update_include_paths() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r#"
+include:
+  - local: "/templates/build.yml"
+  - local: templates/test.yml
+  - "templates/build.yml"
+
+job_a:
+  script: echo a
+"#;
+        let cached_uri = "file:///nonexistent/gitlab-ci-ls-cache/base/remote.yml";
+
+        // included files don't exist so documents are put into store directly
+        let mut store = handlers.store.lock().unwrap();
+        store.insert(uri.to_string(), content.to_string());
+        store.insert(cached_uri.to_string(), content.to_string());
+        drop(store);
+
+        let request = Request {
+            id: RequestId::from(1),
+            method: "gitlabCiLs/updateIncludePaths".to_string(),
+            params: serde_json::json!({
+                "oldPath": "file:///nonexistent/gitlab-ci-ls/templates/build.yml",
+                "newPath": "file:///nonexistent/gitlab-ci-ls/ci/build.yml",
+            }),
+        };
+
+        let Some(LSPResult::Rename(result)) = handlers.on_update_include_paths(request) else {
+            panic!("expected rename result");
+        };
+
+        let edits = result.edits.unwrap();
+        assert!(!edits.contains_key(&Url::parse(cached_uri).unwrap()));
+
+        let mut file_edits = edits.get(&uri).unwrap().clone();
+        file_edits.sort_by_key(|e| e.range.start.line);
+
+        let changes: Vec<(u32, &str)> = file_edits
+            .iter()
+            .map(|e| (e.range.start.line, e.new_text.as_str()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![(2, "\"/ci/build.yml\""), (4, "\"ci/build.yml\"")]
+        );
+    }
+
+    #[test]
+    fn test_excluded_files_are_not_indexed() {
+        let root_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(cache_dir.path().join("base")).unwrap();
+        std::fs::create_dir_all(root_dir.path().join("examples")).unwrap();
+        std::fs::create_dir_all(root_dir.path().join("ci")).unwrap();
+
+        let root_content = r"
+include:
+  - local: ci/jobs.yml
+  - local: examples/generated.yml
+
+job_a:
+  needs:
+    - job: job
+";
+        std::fs::write(root_dir.path().join(".gitlab-ci.yml"), root_content).unwrap();
+        std::fs::write(
+            root_dir.path().join("ci/jobs.yml"),
+            "job_included:\n  script: echo included\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root_dir.path().join("examples/generated.yml"),
+            "job_generated:\n  script: echo generated\n",
+        )
+        .unwrap();
+
+        let root_dir = root_dir.path().to_string_lossy().to_string();
+        let handlers = new_handlers_with(|cfg| {
+            cfg.root_dir.clone_from(&root_dir);
+            cfg.cache_path = format!("{}/", cache_dir.path().to_string_lossy());
+            cfg.configuration.exclude = vec!["examples/**".to_string()];
+        });
+
+        let uri = Url::parse(&format!("file://{root_dir}/.gitlab-ci.yml")).unwrap();
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(7, 14)))
+        else {
+            panic!("expected completion result");
+        };
+
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert!(labels.contains(&"job_included"));
+        assert!(!labels.contains(&"job_generated"));
+    }
+
+    #[test]
+    fn test_strict_indexing() {
+        let root_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(cache_dir.path().join("base")).unwrap();
+        std::fs::create_dir_all(root_dir.path().join("ci")).unwrap();
+
+        let root_content = r"
+include:
+  - local: ci/jobs.yml
+
+job_a:
+  needs:
+    - job: job
+";
+        std::fs::write(root_dir.path().join(".gitlab-ci.yml"), root_content).unwrap();
+        std::fs::write(
+            root_dir.path().join("ci/jobs.yml"),
+            "job_included:\n  script: echo included\n",
+        )
+        .unwrap();
+
+        let root_dir = root_dir.path().to_string_lossy().to_string();
+        let handlers = new_handlers_with(|cfg| {
+            cfg.root_dir.clone_from(&root_dir);
+            cfg.cache_path = format!("{}/", cache_dir.path().to_string_lossy());
+            cfg.configuration.strict_indexing = true;
+        });
+
+        // unreferenced template opened in the editor
+        let template_uri = Url::parse(&format!("file://{root_dir}/ci/template.yml")).unwrap();
+        open_document(
+            &handlers,
+            &template_uri,
+            "job_template:\n  script: echo template\n",
+        );
+
+        let uri = Url::parse(&format!("file://{root_dir}/.gitlab-ci.yml")).unwrap();
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(6, 14)))
+        else {
+            panic!("expected completion result");
+        };
+
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert!(labels.contains(&"job_included"));
+        assert!(!labels.contains(&"job_template"));
+
+        // once include is removed, file stops being indexed
+        let full_change = |text: &str| {
+            vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: text.to_string(),
+            }]
+        };
+        change_document(
+            &handlers,
+            &uri,
+            full_change("job_a:\n  needs:\n    - job: job\n"),
+        );
+        let jobs_uri = Url::parse(&format!("file://{root_dir}/ci/jobs.yml")).unwrap();
+        change_document(
+            &handlers,
+            &jobs_uri,
+            full_change("job_included:\n  script: echo changed\n"),
+        );
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(2, 14)))
+        else {
+            panic!("expected completion result");
+        };
+
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert!(!labels.contains(&"job_included"));
+    }
+
+    #[test]
+    fn test_hover_variable_with_description_and_options() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r#"
+variables:
+  DEPLOY_ENV:
+    value: "staging"
+    description: "Environment to deploy to"
+    options:
+      - "staging"
+      - "production"
+
+deploy:
+  script:
+    - echo $DEPLOY_ENV
+"#;
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Hover(result)) =
+            handlers.on_hover(hover_request(&uri, Position::new(11, 15)))
+        else {
+            panic!("expected hover result");
+        };
+
+        assert!(result.content.contains("Environment to deploy to"));
+        assert!(result.content.contains("value: `staging`"));
+        assert!(result.content.contains("options: `staging`, `production`"));
+    }
+
+    #[test]
+    fn test_hover_component_input() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let component_uri = "gitlab.com/my-group/ci-catalog/build@1.0.0";
+        handlers.components.lock().unwrap().insert(
+            component_uri.to_string(),
+            Component {
+                uri: component_uri.to_string(),
+                inputs: vec![ComponentInput {
+                    key: "registry".to_string(),
+                    description: Some("Registry images are pushed to".to_string()),
+                    prop_type: Some("string".to_string()),
+                    default: Some(serde_yaml::Value::String("docker.io".to_string())),
+                    options: Some(vec!["docker.io".to_string(), "quay.io".to_string()]),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        );
+
+        let content = format!(
+            r"
+include:
+  - component: {component_uri}
+    inputs:
+      registry: quay.io
+"
+        );
+        // component isn't fetched so its inputs come from components above
+        handlers
+            .store
+            .lock()
+            .unwrap()
+            .insert(uri.to_string(), content);
+
+        let Some(LSPResult::Hover(result)) =
+            handlers.on_hover(hover_request(&uri, Position::new(4, 8)))
+        else {
+            panic!("expected hover result");
+        };
+
+        assert!(result.content.contains("Registry images are pushed to"));
+        assert!(result.content.contains("## Type: \nstring"));
+        assert!(result.content.contains("## Default: \ndocker.io"));
+        assert!(result.content.contains("## Options: \ndocker.io, quay.io"));
+
+        // values aren't hovered
+        assert!(handlers
+            .on_hover(hover_request(&uri, Position::new(4, 18)))
+            .is_none());
+    }
+
+    #[test]
+    fn test_predefined_variables_are_parsed_once() {
+        let first = new_handlers();
+        let second = new_handlers();
+
+        assert!(std::ptr::eq(
+            first.predefined_variables,
+            second.predefined_variables
+        ));
+        assert!(first.predefined_variables.contains("CI_COMMIT_SHA"));
+
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        open_document(&first, &uri, "job:\n  script: echo $CI_COMMIT_SHA\n");
+
+        let Some(LSPResult::Hover(result)) =
+            first.on_hover(hover_request(&uri, Position::new(1, 20)))
+        else {
+            panic!("expected hover result");
+        };
+
+        assert_eq!(
+            result.content,
+            "**$CI_COMMIT_SHA**\n\nPredefined GitLab CI/CD variable."
+        );
+    }
+
+    #[test]
+    fn test_hover_variable_in_entrypoint() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r#"
+variables:
+  ENTRY: sh
+
+job:
+  image:
+    name: alpine
+    entrypoint: ["/bin/sh", "-c", "$ENTRY"]
+  script: make
+"#;
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Hover(result)) =
+            handlers.on_hover(hover_request(&uri, Position::new(7, 38)))
+        else {
+            panic!("expected hover result");
+        };
+
+        assert!(result.content.starts_with("**$ENTRY**"));
+        assert!(result.content.contains("value: `sh`"));
+    }
+
+    #[test]
+    fn test_hover_variable_in_precedence_order() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+variables:
+  DEPLOY_ENV: staging
+
+.deploy:
+  variables:
+    DEPLOY_ENV: review
+
+deploy:
+  extends: .deploy
+  variables:
+    DEPLOY_ENV: production
+  script:
+    - echo $DEPLOY_ENV
+";
+        open_document(&handlers, &uri, content);
+        let Some(LSPResult::Hover(result)) =
+            handlers.on_hover(hover_request(&uri, Position::new(13, 15)))
+        else {
+            panic!("expected hover result");
+        };
+
+        assert_eq!(
+            result.content,
+            [
+                "**$DEPLOY_ENV**",
+                "value: `production`",
+                "defined in `tmp/gitlab-ci-ls/.gitlab-ci.yml`",
+                "---",
+                "value: `review`",
+                "defined in `tmp/gitlab-ci-ls/.gitlab-ci.yml`",
+                "---",
+                "value: `staging`",
+                "defined in `tmp/gitlab-ci-ls/.gitlab-ci.yml`",
+            ]
+            .join("\n\n")
+        );
+    }
+
+    #[test]
+    fn test_references_streamed_in_partial_batches() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  script: echo build
+";
+
+        let mut store = handlers.store.lock().unwrap();
+        store.insert(uri.to_string(), content.to_string());
+        for idx in 0..3 {
+            store.insert(
+                format!("file:///tmp/gitlab-ci-ls/jobs_{idx}.yml"),
+                format!("test_{idx}:\n  needs:\n    - job: build\n"),
+            );
+        }
+        drop(store);
+
+        let params = lsp_types::ReferenceParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position: Position::new(1, 2),
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams {
+                partial_result_token: Some(lsp_types::NumberOrString::String(
+                    "references".to_string(),
+                )),
+            },
+            context: lsp_types::ReferenceContext {
+                include_declaration: false,
+            },
+        };
+
+        let batches = std::cell::RefCell::new(vec![]);
+        let result = handlers.on_references(
+            Request {
+                id: RequestId::from(1),
+                method: "textDocument/references".to_string(),
+                params: serde_json::to_value(params).unwrap(),
+            },
+            &|partial| {
+                if let LSPResult::PartialReferences(partial) = partial {
+                    batches.borrow_mut().push(partial.locations);
                 }
-                return Some(LSPResult::PrepareRename(super::PrepareRenameResult {
-                    id: request.id,
-                    range: None,
-                    err: Some("Could not find definition".to_string()),
-                }));
+            },
+        );
+
+        let Some(LSPResult::References(result)) = result else {
+            panic!("expected references result");
+        };
+
+        assert!(result.locations.is_empty());
+
+        let batches = batches.into_inner();
+        assert_eq!(batches.len(), 3);
+        assert!(batches.iter().all(|b| b.len() == 1));
+    }
+
+    #[test]
+    fn test_variable_references() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+variables:
+  DEPLOY_ENV: prod
+
+deploy:
+  image: deployer:${DEPLOY_ENV}
+  script:
+    - echo $DEPLOY_ENV
+    - echo $DEPLOY_ENVIRONMENT
+    - |
+      echo start
+      deploy --env $DEPLOY_ENV
+
+lint:
+  variables:
+    DEPLOY_ENV: dev
+  script: echo
+";
+
+        let mut store = handlers.store.lock().unwrap();
+        store.insert(uri.to_string(), content.to_string());
+        store.insert(
+            "file:///tmp/gitlab-ci-ls/jobs.yml".to_string(),
+            "test:\n  rules:\n    - if: $DEPLOY_ENV == \"prod\"\n  script: echo\n".to_string(),
+        );
+        drop(store);
+
+        // root and job level definitions
+        for position in [Position::new(2, 4), Position::new(15, 6)] {
+            let Some(LSPResult::References(result)) =
+                handlers.on_references(references_request(&uri, position, false), &|_| {})
+            else {
+                panic!("expected references result");
+            };
+
+            let mut locations: Vec<(String, u32, u32)> = result
+                .locations
+                .iter()
+                .map(|l| (l.uri.clone(), l.range.start.line, l.range.start.character))
+                .collect();
+            locations.sort();
+            assert_eq!(
+                locations,
+                vec![
+                    (uri.to_string(), 5, 20),
+                    (uri.to_string(), 7, 12),
+                    (uri.to_string(), 11, 20),
+                    ("file:///tmp/gitlab-ci-ls/jobs.yml".to_string(), 2, 11),
+                ]
+            );
+            assert!(result
+                .locations
+                .iter()
+                .all(|l| l.range.end.character - l.range.start.character == 10));
+        }
+
+        // values of variables aren't definitions
+        assert!(handlers
+            .on_references(
+                references_request(&uri, Position::new(2, 15), false),
+                &|_| {},
+            )
+            .is_none_or(|result| matches!(
+                result,
+                LSPResult::References(result) if result.locations.is_empty()
+            )));
+    }
+
+    #[test]
+    fn test_variable_usage_references() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+variables:
+  REGISTRY: docker.io
+
+build:
+  script:
+    - docker build -t $REGISTRY/app .
+
+deploy:
+  script:
+    - docker push ${REGISTRY}/app
+";
+        handlers
+            .store
+            .lock()
+            .unwrap()
+            .insert(uri.to_string(), content.to_string());
+
+        let locations = |include_declaration: bool| {
+            let Some(LSPResult::References(result)) = handlers.on_references(
+                references_request(&uri, Position::new(10, 22), include_declaration),
+                &|_| {},
+            ) else {
+                panic!("expected references result");
+            };
+
+            let mut locations: Vec<(u32, u32)> = result
+                .locations
+                .iter()
+                .map(|l| (l.range.start.line, l.range.start.character))
+                .collect();
+            locations.sort_unstable();
+            locations
+        };
+
+        assert_eq!(locations(false), vec![(6, 23), (10, 20)]);
+        assert_eq!(locations(true), vec![(2, 2), (6, 23), (10, 20)]);
+    }
+
+    #[test]
+    fn test_stage_references() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+stages:
+  - build
+  - deploy
+
+deploy_app:
+  stage: deploy
+  script: make deploy
+";
+        handlers
+            .store
+            .lock()
+            .unwrap()
+            .insert(uri.to_string(), content.to_string());
+
+        let locations = |include_declaration: bool| {
+            let Some(LSPResult::References(result)) = handlers.on_references(
+                references_request(&uri, Position::new(6, 11), include_declaration),
+                &|_| {},
+            ) else {
+                panic!("expected references result");
+            };
+
+            let mut locations: Vec<(u32, u32)> = result
+                .locations
+                .iter()
+                .map(|l| (l.range.start.line, l.range.start.character))
+                .collect();
+            locations.sort_unstable();
+            locations
+        };
+
+        assert_eq!(locations(false), vec![(6, 9)]);
+        assert_eq!(locations(true), vec![(3, 4), (6, 9)]);
+    }
+
+    #[test]
+    fn test_allow_failure_valid_exit_codes() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+job_a:
+  script: exit 1
+  allow_failure:
+    exit_codes: [1, 137]
+";
+
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert!(result.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_allow_failure_invalid_exit_code() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r#"
+job_a:
+  script: exit 1
+  allow_failure:
+    exit_codes:
+      - 1
+      - "two"
+"#;
+
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 6);
+    }
+
+    #[test]
+    fn test_allow_failure_exit_codes_completion() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+job_a:
+  script: exit 1
+  allow_failure:
+    exit
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(4, 8)))
+        else {
+            panic!("expected completion result");
+        };
+
+        assert_eq!(result.list.len(), 1);
+        assert_eq!(result.list[0].label, "exit_codes");
+    }
+
+    #[test]
+    fn test_unreachable_rules() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  script: make
+  rules:
+    - if: $CI_COMMIT_TAG
+    - when: always
+    - if: $CI_MERGE_REQUEST_ID
+      when: never
+    - changes:
+        - src/*
+
+test:
+  script: make test
+  rules:
+    - if: $CI_COMMIT_TAG
+    - when: manual
+
+deploy:
+  script: make deploy
+  rules:
+    - when: manual
+      allow_failure: true
+    - if: $CI_COMMIT_TAG
+    - !reference [.rules, rules]
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        let hints: Vec<(u32, u32, &str)> = result
+            .diagnostics
+            .iter()
+            .filter(|d| d.severity == Some(DiagnosticSeverity::HINT))
+            .map(|d| (d.range.start.line, d.range.end.line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            hints,
+            vec![
+                (
+                    6,
+                    9,
+                    "Rules of build after unconditional rule on line 6 are never evaluated."
+                ),
+                (
+                    22,
+                    23,
+                    "Rules of deploy after unconditional rule on line 21 are never evaluated."
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_contradictory_rule_condition() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r#"
+job_a:
+  script: echo
+  rules:
+    - if: '$CI_COMMIT_BRANCH == "main"'
+    - if: '$DEPLOY_ENV == "staging" && $DEPLOY_ENV == "production"'
+"#;
+
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 5);
+        assert_eq!(
+            result.diagnostics[0].severity,
+            Some(DiagnosticSeverity::HINT)
+        );
+    }
+
+    #[test]
+    fn test_definition_matrix_need() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r#"
+build:
+  script: make
+  parallel:
+    matrix:
+      - PLATFORM: [linux, macos]
+        ARCH: [amd64, arm64]
+deploy:
+  script: deploy
+  needs:
+    - job: "build: [linux, arm64]"
+"#;
+        let Some(LSPResult::Diagnostics(diagnostics)) = open_document(&handlers, &uri, content)
+        else {
+            panic!("expected diagnostics result");
+        };
+        assert!(diagnostics.diagnostics.is_empty());
+
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(10, 16)))
+        else {
+            panic!("expected definition result");
+        };
+
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(result.locations[0].range.start.line, 4);
+        assert_eq!(result.locations[0].range.start.character, 4);
+    }
+
+    #[test]
+    fn test_base_files_are_parsed_once() {
+        let root_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let base_dir = cache_dir.path().join("base");
+        std::fs::create_dir_all(&base_dir).unwrap();
+
+        std::fs::write(
+            base_dir.join("base.yml"),
+            ".base_job:\n  script: echo base\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root_dir.path().join(".gitlab-ci.yml"),
+            "job_yml:\n  script: echo yml\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root_dir.path().join(".gitlab-ci.yaml"),
+            "job_yaml:\n  script: echo yaml\n",
+        )
+        .unwrap();
+
+        let root_dir = root_dir.path().to_string_lossy().to_string();
+        let handlers = new_handlers_with(|cfg| {
+            cfg.root_dir.clone_from(&root_dir);
+            cfg.cache_path = format!("{}/", cache_dir.path().to_string_lossy());
+        });
+
+        assert_eq!(
+            handlers
+                .base_results
+                .lock()
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // base files are not read again so indexing works even when they are gone
+        std::fs::remove_dir_all(&base_dir).unwrap();
+        handlers.index_workspace(&root_dir).unwrap();
+
+        let nodes = handlers.nodes.lock().unwrap();
+        let keys: Vec<&String> = nodes.values().flat_map(HashMap::keys).collect();
+        assert!(keys.contains(&&".base_job".to_string()));
+        // only one root file is indexed
+        assert!(keys.contains(&&"job_yaml".to_string()));
+        assert!(!keys.contains(&&"job_yml".to_string()));
+    }
+
+    const QUOTED_TEMPLATE_CONTENT: &str = r#"
+".my template":
+  image: alpine
+"build: linux":
+  extends: ".my template"
+  script: make
+job_a:
+  extends:
+    - ".my te"
+  needs:
+    - job: "build: linux"
+"#;
+
+    #[test]
+    fn test_quoted_template_name() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+
+        let Some(LSPResult::Diagnostics(result)) =
+            open_document(&handlers, &uri, QUOTED_TEMPLATE_CONTENT)
+        else {
+            panic!("expected diagnostics result");
+        };
+
+        // only the partially typed extends is reported
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 8);
+
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(4, 18)))
+        else {
+            panic!("expected definition result");
+        };
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(result.locations[0].range.start.line, 1);
+
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(10, 16)))
+        else {
+            panic!("expected definition result");
+        };
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(result.locations[0].range.start.line, 3);
+    }
+
+    #[test]
+    fn test_definition_extends_without_leading_dot() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r#"
+.base:
+  image: alpine
+lint:
+  script: make lint
+build:
+  extends: base
+test:
+  extends: "base"
+deploy:
+  extends: lint
+.lint:
+  image: golang
+"#;
+        open_document(&handlers, &uri, content);
+
+        let definition = |position: Position| {
+            let Some(LSPResult::Definition(result)) =
+                handlers.on_definition(definition_request(&uri, position))
+            else {
+                panic!("expected definition result");
+            };
+
+            result
+                .locations
+                .iter()
+                .map(|l| l.range.start.line)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(definition(Position::new(6, 12)), vec![1]);
+        assert_eq!(definition(Position::new(8, 13)), vec![1]);
+        // exact match wins over template
+        assert_eq!(definition(Position::new(10, 12)), vec![3]);
+    }
+
+    #[test]
+    fn test_quoted_template_name_completion() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        open_document(&handlers, &uri, QUOTED_TEMPLATE_CONTENT);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(8, 12)))
+        else {
+            panic!("expected completion result");
+        };
+
+        assert_eq!(result.list.len(), 1);
+        assert_eq!(result.list[0].label, ".my template");
+        assert_eq!(result.list[0].location.range.start.character, 7);
+        assert_eq!(result.list[0].location.range.end.character, 13);
+    }
+
+    #[test]
+    fn test_extends_completion_ranks_same_file_templates_first() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        open_document(&handlers, &uri, "\n.a_build:\n  image: alpine\n");
+
+        let template_uri = Url::parse("file:///tmp/gitlab-ci-ls/templates/build.yml").unwrap();
+        let content = r"
+.z_build:
+  image: golang
+job:
+  extends: .
+";
+        open_document(&handlers, &template_uri, content);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&template_uri, Position::new(4, 12)))
+        else {
+            panic!("expected completion result");
+        };
+
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec![".z_build", ".a_build"]);
+    }
+
+    #[test]
+    fn test_component_uri_completion() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let templates = cache_dir
+            .path()
+            .join("components/my-group/ci-catalog/1.0.0/templates");
+        std::fs::create_dir_all(templates.join("deploy")).unwrap();
+        std::fs::write(templates.join("build.yml"), "spec:\n").unwrap();
+        std::fs::write(templates.join("deploy/template.yml"), "spec:\n").unwrap();
+
+        let handlers = new_handlers_with(|cfg| {
+            cfg.cache_path = format!("{}/", cache_dir.path().to_string_lossy());
+            cfg.package_map = HashMap::from([(
+                "my-group/ci-catalog".to_string(),
+                "git@gitlab.com:".to_string(),
+            )]);
+            cfg.remote_urls = vec!["https://gitlab.instance.com/".to_string()];
+        });
+
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+include:
+  - component: gitlab
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(2, 21)))
+        else {
+            panic!("expected completion result");
+        };
+
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            vec![
+                "gitlab.com/my-group/ci-catalog/build@1.0.0",
+                "gitlab.com/my-group/ci-catalog/deploy@1.0.0",
+                "gitlab.instance.com/",
+            ]
+        );
+        assert_eq!(result.list[0].location.range.start.character, 15);
+    }
+
+    #[test]
+    fn test_component_input_default_completion() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let component_uri = "gitlab.com/my-group/ci-catalog/build@1.0.0";
+        handlers.components.lock().unwrap().insert(
+            component_uri.to_string(),
+            Component {
+                uri: component_uri.to_string(),
+                inputs: vec![ComponentInput {
+                    key: "registry".to_string(),
+                    default: Some(serde_yaml::Value::String("docker.io".to_string())),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        );
+
+        let content = format!(
+            r"
+include:
+  - component: {component_uri}
+    inputs:
+      registry: d
+"
+        );
+        // component isn't fetched so its inputs come from components above
+        handlers
+            .store
+            .lock()
+            .unwrap()
+            .insert(uri.to_string(), content);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(4, 17)))
+        else {
+            panic!("expected completion result");
+        };
+
+        assert_eq!(result.list.len(), 1);
+        assert_eq!(result.list[0].label, "docker.io");
+        assert_eq!(result.list[0].details, Some("(default)".to_string()));
+        assert_eq!(result.list[0].location.range.start.character, 16);
+        assert_eq!(result.list[0].location.range.end.character, 17);
+    }
+
+    #[test]
+    fn test_rules_changes_object_form_completion() {
+        let root_dir = tempfile::tempdir().unwrap();
+        std::fs::write(root_dir.path().join("Cargo.toml"), "").unwrap();
+        crate::gitlab_ci_ls_parser::git::commit_all(root_dir.path(), Some("v1.0.0"));
+        let output = std::process::Command::new("git")
+            .args(["branch", "feature/changes"])
+            .current_dir(root_dir.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let root_path = root_dir.path().to_string_lossy().to_string();
+
+        let mut fs_utils = MockFSUtils::new();
+        fs_utils
+            .expect_read_dir()
+            .with(mockall::predicate::eq(format!("{root_path}/src")))
+            .returning(|_| {
+                Ok(vec![
+                    ("bin".to_string(), true),
+                    ("main.rs".to_string(), false),
+                ])
+            });
+        let handlers = new_handlers_with_fs(Box::new(fs_utils), |cfg| {
+            cfg.root_dir.clone_from(&root_path);
+        });
+
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  rules:
+    - changes:
+        paths:
+          - src/m
+        compare_to: refs/
+";
+        handlers
+            .store
+            .lock()
+            .unwrap()
+            .insert(uri.to_string(), content.to_string());
+
+        let labels = |position: Position| {
+            let Some(LSPResult::Completion(result)) =
+                handlers.on_completion(completion_request(&uri, position))
+            else {
+                panic!("expected completion result");
+            };
+
+            result
+                .list
+                .iter()
+                .map(|c| {
+                    (
+                        c.label.clone(),
+                        c.location.range.start.character,
+                        c.location.range.end.character,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            labels(Position::new(5, 17)),
+            vec![("main.rs".to_string(), 16, 17)]
+        );
+        assert_eq!(
+            labels(Position::new(5, 16)),
+            vec![
+                ("bin/".to_string(), 16, 17),
+                ("main.rs".to_string(), 16, 17)
+            ]
+        );
+        assert_eq!(
+            labels(Position::new(6, 25)),
+            vec![
+                ("refs/heads/feature/changes".to_string(), 20, 25),
+                ("refs/heads/main".to_string(), 20, 25),
+                ("refs/tags/v1.0.0".to_string(), 20, 25),
+            ]
+        );
+
+        // only paths inside of repository are listed
+        for path in ["../", "/etc/"] {
+            let content = format!("build:\n  rules:\n    - changes:\n        - {path}\n");
+            handlers
+                .store
+                .lock()
+                .unwrap()
+                .insert(uri.to_string(), content);
+            let position = Position::new(3, 10 + u32::try_from(path.len()).unwrap());
+            assert_eq!(labels(position), vec![]);
+        }
+    }
+
+    #[test]
+    fn test_deployment_tier_completion() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+deploy:
+  script: deploy
+  environment:
+    name: review
+    deployment_tier: st
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(5, 23)))
+        else {
+            panic!("expected completion result");
+        };
+
+        assert_eq!(result.list.len(), 1);
+        assert_eq!(result.list[0].label, "staging");
+    }
+
+    #[test]
+    fn test_invalid_deployment_tier() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+deploy:
+  script: deploy
+  environment:
+    name: production
+    deployment_tier: prod
+review:
+  script: deploy
+  environment:
+    name: review
+    deployment_tier: development
+";
+
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 5);
+    }
+
+    #[test]
+    fn test_missing_cache_key_file() {
+        let root_dir = tempfile::tempdir().unwrap();
+        std::fs::write(root_dir.path().join("Gemfile.lock"), "").unwrap();
+
+        let handlers = new_handlers_with(|cfg| {
+            cfg.root_dir = root_dir.path().to_string_lossy().to_string();
+        });
+        handlers
+            .experimental
+            .lock()
+            .unwrap()
+            .cache_key_files_diagnostics = true;
+
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+job_a:
+  script: bundle install
+  cache:
+    key:
+      files:
+        - ./Gemfile.lock
+        - package-lock.json
+        - $LOCK_FILE
+";
+
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 7);
+        assert_eq!(
+            result.diagnostics[0].severity,
+            Some(DiagnosticSeverity::HINT)
+        );
+    }
+
+    #[test]
+    fn test_project_include_without_ref() {
+        // avoid cloning included projects
+        let mut fs_utils = MockFSUtils::new();
+        fs_utils
+            .expect_create_dir_all()
+            .returning(|_| Err(anyhow::anyhow!("no cache in tests")));
+
+        let handlers = new_handlers_with_fs(Box::new(fs_utils), |_| {});
+        handlers
+            .experimental
+            .lock()
+            .unwrap()
+            .include_ref_diagnostics = true;
+
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+include:
+  - project: group/pinned
+    ref: 1.0.0
+    file: ci.yml
+  - project: group/unpinned
+    file: ci.yml
+";
+
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 5);
+        assert_eq!(
+            result.diagnostics[0].severity,
+            Some(DiagnosticSeverity::HINT)
+        );
+    }
+
+    #[test]
+    fn test_missing_cache_key_file_disabled_by_default() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+job_a:
+  script: npm ci
+  cache:
+    key:
+      files:
+        - package-lock.json
+";
+
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert!(result.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_idle_documents_are_evicted_and_reloaded() {
+        let root_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(cache_dir.path().join("base")).unwrap();
+        std::fs::write(
+            root_dir.path().join(".gitlab-ci.yml"),
+            "job_a:\n  script: echo a\n",
+        )
+        .unwrap();
+
+        let handlers = new_handlers_with(|cfg| {
+            cfg.root_dir = root_dir.path().to_string_lossy().to_string();
+            cfg.cache_path = format!("{}/", cache_dir.path().to_string_lossy());
+            cfg.store_idle_timeout = Some(std::time::Duration::ZERO);
+        });
+
+        let root_uri = Url::from_file_path(root_dir.path().join(".gitlab-ci.yml"))
+            .unwrap()
+            .to_string();
+        let open_uri = Url::parse("file:///tmp/gitlab-ci-ls/opened.yml").unwrap();
+        open_document(&handlers, &open_uri, "job_b:\n  script: echo b\n");
+
+        handlers.evict_idle_documents();
+
+        {
+            let store = handlers.store.lock().unwrap();
+            assert!(!store.contains_key(&root_uri));
+            // opened documents can contain unsaved changes
+            assert!(store.contains_key(&open_uri.to_string()));
+        }
+
+        // node metadata stays in memory
+        assert!(handlers.nodes.lock().unwrap().contains_key(&root_uri));
+
+        let store = handlers.document_store(&root_uri);
+        assert_eq!(
+            store.get(&root_uri).map(String::as_str),
+            Some("job_a:\n  script: echo a\n")
+        );
+        drop(store);
+
+        assert!(handlers.evicted.lock().unwrap().is_empty());
+
+        // closed documents are evicted as well
+        handlers.on_close(Notification {
+            method: "textDocument/didClose".to_string(),
+            params: serde_json::to_value(lsp_types::DidCloseTextDocumentParams {
+                text_document: TextDocumentIdentifier {
+                    uri: open_uri.clone(),
+                },
+            })
+            .unwrap(),
+        });
+        handlers.evict_idle_documents();
+        assert!(!handlers
+            .store
+            .lock()
+            .unwrap()
+            .contains_key(&open_uri.to_string()));
+    }
+
+    #[test]
+    fn test_needs_job_from_project_include() {
+        let root_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(cache_dir.path().join("base")).unwrap();
+
+        // already cloned repository so it isn't fetched again
+        let repo_dir = cache_dir.path().join("group/templates/1.0.0/ci");
+        std::fs::create_dir_all(&repo_dir).unwrap();
+        std::fs::write(
+            repo_dir.join("jobs.yml"),
+            "remote_job:\n  script: echo remote\n",
+        )
+        .unwrap();
+
+        let root_content = r"
+include:
+  - project: group/templates
+    ref: 1.0.0
+    file: ci/jobs.yml
+
+job_a:
+  script: echo a
+  needs:
+    - job: remote_job
+    - job: missing_job
+";
+        std::fs::write(root_dir.path().join(".gitlab-ci.yml"), root_content).unwrap();
+
+        let mut fs_utils = MockFSUtils::new();
+        fs_utils.expect_create_dir_all().returning(|_| Ok(()));
+
+        let root_dir = root_dir.path().to_string_lossy().to_string();
+        let handlers = new_handlers_with_fs(Box::new(fs_utils), |cfg| {
+            cfg.root_dir.clone_from(&root_dir);
+            cfg.cache_path = format!("{}/", cache_dir.path().to_string_lossy());
+        });
+
+        let uri = Url::parse(&format!("file://{root_dir}/.gitlab-ci.yml")).unwrap();
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, root_content)
+        else {
+            panic!("expected diagnostics result");
+        };
+
+        let messages: Vec<&str> = result
+            .diagnostics
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect();
+        assert_eq!(messages, vec!["Job: missing_job does not exist."]);
+    }
+
+    #[test]
+    fn test_variable_completion_in_after_script() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+variables:
+  DEPLOY_ENV: prod
+job_a:
+  script:
+    - make
+  after_script:
+    - echo $DEP
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(7, 15)))
+        else {
+            panic!("expected completion result");
+        };
+
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["DEPLOY_ENV"]);
+    }
+
+    #[test]
+    fn test_docker_platform_completion() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  script: make
+  image:
+    name: alpine
+    docker:
+      platform: linux/a
+      user: dave
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(6, 23)))
+        else {
+            panic!("expected completion result");
+        };
+
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            vec!["linux/amd64", "linux/arm/v6", "linux/arm/v7", "linux/arm64"]
+        );
+        assert_eq!(result.list[0].location.range.start.character, 16);
+    }
+
+    #[test]
+    fn test_invalid_docker_platform() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  script: make
+  image:
+    name: alpine
+    docker:
+      platform: arm64
+test:
+  script: make
+  image:
+    name: alpine
+    docker:
+      platform: $PLATFORM
+";
+
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 6);
+    }
+
+    #[test]
+    fn test_docker_user_completion() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  script: make
+  image:
+    name: alpine
+    docker:
+      platform: linux/amd64
+      user: ro
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(7, 14)))
+        else {
+            panic!("expected completion result");
+        };
+
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["root"]);
+        assert_eq!(result.list[0].location.range.start.character, 12);
+    }
+
+    #[test]
+    fn test_invalid_docker_user() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r#"
+build:
+  script: make
+  image:
+    name: alpine
+    docker:
+      user: "dave smith"
+test:
+  script: make
+  image:
+    name: alpine
+    docker:
+      user: 1000:1000
+lint:
+  script: make
+  image:
+    name: alpine
+    docker:
+      user: $CI_USER
+"#;
+
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 6);
+    }
+
+    #[test]
+    fn test_duplicate_needs() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  script: make
+  parallel:
+    matrix:
+      - ARCH: [amd64, arm64]
+test:
+  script: make test
+  needs:
+    - job: build
+      parallel:
+        matrix:
+          - ARCH: amd64
+    - job: build
+      parallel:
+        matrix:
+          - ARCH: arm64
+deploy:
+  script: make deploy
+  needs:
+    - job: test
+    - job: build
+    - job: test
+  rules:
+    - if: $CI_COMMIT_TAG
+      needs:
+        - job: test
+";
+
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        let hints: Vec<&Diagnostic> = result
+            .diagnostics
+            .iter()
+            .filter(|d| d.severity == Some(DiagnosticSeverity::HINT))
+            .collect();
+
+        assert_eq!(hints.len(), 1);
+        assert_eq!(hints[0].message, "Job: test is already listed in needs.");
+        assert_eq!(hints[0].range.start.line, 22);
+    }
+
+    const RULES_CONTENT: &str = r"
+build:
+  script: make
+  rules:
+    - if: $CI_COMMIT_TAG
+      when: never
+    - when: always
+";
+
+    #[test]
+    fn test_folding_rules_block() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        open_document(&handlers, &uri, RULES_CONTENT);
+
+        let params = FoldingRangeParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let Some(LSPResult::FoldingRange(result)) = handlers.on_folding_range(Request {
+            id: RequestId::from(1),
+            method: "textDocument/foldingRange".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }) else {
+            panic!("expected folding range result");
+        };
+
+        let ranges: Vec<(u32, u32)> = result
+            .ranges
+            .iter()
+            .map(|r| (r.start_line, r.end_line))
+            .collect();
+        // job, whole rules block and multiline rule, single line rule can't be folded
+        assert_eq!(ranges, vec![(1, 6), (3, 6), (4, 5)]);
+    }
+
+    #[test]
+    fn test_folding_jobs_and_scripts() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  before_script:
+    - make deps
+  script:
+    - make
+    - make install
+test:
+  script: make test
+  after_script:
+    - make clean
+deploy:
+  script: make deploy
+  when: manual
+";
+        open_document(&handlers, &uri, content);
+
+        let params = FoldingRangeParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let Some(LSPResult::FoldingRange(result)) = handlers.on_folding_range(Request {
+            id: RequestId::from(1),
+            method: "textDocument/foldingRange".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }) else {
+            panic!("expected folding range result");
+        };
+
+        let ranges: Vec<(u32, u32)> = result
+            .ranges
+            .iter()
+            .map(|r| (r.start_line, r.end_line))
+            .collect();
+        assert!(ranges.len() >= 3);
+        assert_eq!(
+            ranges,
+            vec![(1, 6), (7, 10), (11, 13), (2, 3), (4, 6), (9, 10)]
+        );
+        assert!(result
+            .ranges
+            .iter()
+            .all(|r| r.kind == Some(FoldingRangeKind::Region)));
+    }
+
+    #[test]
+    fn test_document_symbol_rules_block() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        open_document(&handlers, &uri, RULES_CONTENT);
+
+        let params = DocumentSymbolParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let Some(LSPResult::DocumentSymbol(result)) = handlers.on_document_symbol(Request {
+            id: RequestId::from(1),
+            method: "textDocument/documentSymbol".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }) else {
+            panic!("expected document symbol result");
+        };
+
+        assert_eq!(result.symbols.len(), 1);
+        assert_eq!(result.symbols[0].name, "build");
+
+        let rules = &result.symbols[0].children.as_ref().unwrap()[0];
+        assert_eq!(rules.name, "rules");
+        let items: Vec<&str> = rules
+            .children
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect();
+        assert_eq!(items, vec!["if: $CI_COMMIT_TAG", "when: always"]);
+    }
+
+    #[test]
+    fn test_document_symbol_root_nodes() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+stages:
+  - build
+  - test
+
+.base:
+  image: alpine
+
+build:
+  extends: .base
+  script: make
+";
+        open_document(&handlers, &uri, content);
+
+        let params = DocumentSymbolParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let Some(LSPResult::DocumentSymbol(result)) = handlers.on_document_symbol(Request {
+            id: RequestId::from(1),
+            method: "textDocument/documentSymbol".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }) else {
+            panic!("expected document symbol result");
+        };
+
+        let symbols: Vec<(&str, SymbolKind)> = result
+            .symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.kind))
+            .collect();
+        assert_eq!(
+            symbols,
+            vec![
+                ("stages", SymbolKind::ARRAY),
+                (".base", SymbolKind::INTERFACE),
+                ("build", SymbolKind::FUNCTION),
+            ]
+        );
+
+        let stages: Vec<&str> = result.symbols[0]
+            .children
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect();
+        assert_eq!(stages, vec!["build", "test"]);
+
+        let build = &result.symbols[2];
+        assert_eq!(build.range.start, Position::new(8, 0));
+        assert_eq!(build.range.end, Position::new(11, 0));
+        assert_eq!(build.selection_range.start, Position::new(8, 0));
+        assert_eq!(build.selection_range.end, Position::new(8, 5));
+    }
+
+    #[test]
+    fn test_needs_job_in_later_stage() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+stages:
+  - build
+  - test
+  - deploy
+
+build_job:
+  stage: build
+  needs:
+    - job: deploy_job
+    - job: lint_job
+  script: make
+
+lint_job:
+  stage: build
+  script: make lint
+
+test_job:
+  needs:
+    - job: build_job
+  script: make test
+
+deploy_job:
+  stage: deploy
+  needs:
+    - job: test_job
+  script: make deploy
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        let messages: Vec<(u32, &str)> = result
+            .diagnostics
+            .iter()
+            .map(|d| (d.range.start.line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![(
+                9,
+                "Job: deploy_job is in stage deploy which runs after build stage."
+            )]
+        );
+    }
+
+    #[test]
+    fn test_needs_stage_order_default_stage() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build_job:
+  stage: build
+  needs:
+    - unit_job
+  script: make
+
+unit_job:
+  needs:
+    - deploy_job
+  script: make test
+
+deploy_job:
+  stage: deploy
+  script: make deploy
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        let messages: Vec<(u32, &str)> = result
+            .diagnostics
+            .iter()
+            .map(|d| (d.range.start.line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    4,
+                    "Job: unit_job is in stage test which runs after build stage."
+                ),
+                (
+                    9,
+                    "Job: deploy_job is in stage deploy which runs after test stage."
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diagnostics_are_ordered() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+job_b:
+  stage: missing
+  needs:
+    - job: missing_b
+    - job: missing_a
+  extends: .missing
+job_a:
+  needs:
+    - job: missing_c
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        let positions: Vec<(u32, u32)> = result
+            .diagnostics
+            .iter()
+            .map(|d| (d.range.start.line, d.range.start.character))
+            .collect();
+        assert_eq!(positions, vec![(2, 9), (4, 11), (5, 11), (6, 11), (9, 11)]);
+    }
+
+    #[test]
+    fn test_invalid_root_diagnostic() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"# jobs
+- build:
+    script: make
+- test:
+    script: make test
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert_eq!(result.diagnostics.len(), 1);
+        let diagnostic = &result.diagnostics[0];
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(
+            diagnostic.code,
+            Some(NumberOrString::String("invalid_root".to_string()))
+        );
+        assert_eq!(diagnostic.range.start, Position::new(1, 0));
+        assert_eq!(diagnostic.range.end, Position::new(1, 8));
+
+        // component jobs are in second document after `spec`
+        let component = "spec:\n  inputs:\n    stage:\n---\n- job\n";
+        let diagnostics = handlers.invalid_root_diagnostics(uri.as_str(), component);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start, Position::new(4, 0));
+
+        let valid = "spec:\n  inputs:\n    stage:\n---\njob:\n  script: make\n";
+        assert!(handlers
+            .invalid_root_diagnostics(uri.as_str(), valid)
+            .is_empty());
+
+        // empty and comment only documents are valid
+        for empty in [
+            "",
+            "# nothing yet\n",
+            "---\n# nothing yet\n---\njob:\n  script: make\n",
+        ] {
+            assert!(handlers
+                .invalid_root_diagnostics(uri.as_str(), empty)
+                .is_empty());
+        }
+
+        // scalar root is still invalid
+        assert_eq!(
+            handlers
+                .invalid_root_diagnostics(uri.as_str(), "hello\n")
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_misnested_keyword() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  variables:
+    URL: example.com
+    stage: build
+  script: make
+  artifacts:
+    when: always
+  retry:
+    max: 2
+    when: runner_system_failure
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert_eq!(result.diagnostics.len(), 1);
+        let diagnostic = &result.diagnostics[0];
+        assert_eq!(diagnostic.range.start, Position::new(4, 4));
+        assert_eq!(diagnostic.range.end, Position::new(4, 9));
+        assert_eq!(
+            diagnostic.message,
+            "Keyword: stage is nested under variables; it's probably indented too deep."
+        );
+        assert_eq!(
+            diagnostic.code,
+            Some(NumberOrString::String("misnested_keyword".to_string()))
+        );
+
+        // component inputs can be named like job keywords
+        let component_uri = Url::parse("file:///tmp/gitlab-ci-ls/templates/build.yml").unwrap();
+        let component = "spec:\n  inputs:\n    stage:\n      default: test\n---\nbuild:\n  stage: $[[ inputs.stage ]]\n  script: make\n";
+        let Some(LSPResult::Diagnostics(result)) =
+            open_document(&handlers, &component_uri, component)
+        else {
+            panic!("expected diagnostics result");
+        };
+        assert!(!result
+            .diagnostics
+            .iter()
+            .any(|d| { d.code == Some(NumberOrString::String("misnested_keyword".to_string())) }));
+    }
+
+    #[test]
+    fn test_deprecated_keywords() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+variables:
+  only: main
+
+.deploy:
+  except:
+    - tags
+
+build:
+  only:
+    - main
+  script: make
+";
+        let deprecated = |diagnostics: &[Diagnostic]| {
+            diagnostics
+                .iter()
+                .filter(|d| {
+                    d.code == Some(NumberOrString::String("deprecated_keyword".to_string()))
+                })
+                .map(|d| {
+                    (
+                        d.range.start.line,
+                        d.range.start.character,
+                        d.message.clone(),
+                        d.severity,
+                        d.tags.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+        assert!(deprecated(&result.diagnostics).is_empty());
+
+        handlers
+            .experimental
+            .lock()
+            .unwrap()
+            .deprecated_keywords_diagnostics = true;
+
+        let Some(LSPResult::Diagnostics(result)) = handlers.generate_diagnostics(uri) else {
+            panic!("expected diagnostics result");
+        };
+        let tags = Some(vec![lsp_types::DiagnosticTag::DEPRECATED]);
+        assert_eq!(
+            deprecated(&result.diagnostics),
+            vec![
+                (
+                    5,
+                    2,
+                    "Keyword: except is deprecated; use rules instead.".to_string(),
+                    Some(DiagnosticSeverity::HINT),
+                    tags.clone(),
+                ),
+                (
+                    9,
+                    2,
+                    "Keyword: only is deprecated; use rules instead.".to_string(),
+                    Some(DiagnosticSeverity::HINT),
+                    tags,
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_include() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+include:
+  - local: /templates/build.yml
+  - project: my-group/ci
+    file: /deploy.yml
+  - templates/build.yml
+  - local: templates/build.yml
+    rules:
+      - if: $CI_COMMIT_TAG
+  - project: my-group/ci
+    ref: v1.0.0
+    file: /deploy.yml
+
+build:
+  script: make
+";
+        // included files aren't fetched
+        handlers
+            .store
+            .lock()
+            .unwrap()
+            .insert(uri.to_string(), content.to_string());
+
+        let Some(LSPResult::Diagnostics(result)) = handlers.generate_diagnostics(uri) else {
+            panic!("expected diagnostics result");
+        };
+
+        let duplicates: Vec<&Diagnostic> = result
+            .diagnostics
+            .iter()
+            .filter(|d| d.code == Some(NumberOrString::String("duplicate_include".to_string())))
+            .collect();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].severity, Some(DiagnosticSeverity::HINT));
+        assert_eq!(duplicates[0].range.start, Position::new(5, 4));
+        assert_eq!(
+            duplicates[0].message,
+            "Duplicate include: same file is already included on line 3."
+        );
+    }
+
+    #[test]
+    fn test_duplicate_root_keys() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  script: make
+test:
+  script: make test
+build:
+  script: make
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert_eq!(result.diagnostics.len(), 1);
+        let diagnostic = &result.diagnostics[0];
+        assert_eq!(diagnostic.range.start, Position::new(5, 0));
+        assert_eq!(diagnostic.range.end, Position::new(5, 5));
+        assert_eq!(
+            diagnostic.message,
+            "Duplicate key: build is already defined on line 2; only the last definition is used."
+        );
+        assert_eq!(
+            diagnostic.related_information.as_ref().unwrap()[0]
+                .location
+                .range
+                .start,
+            Position::new(1, 0)
+        );
+    }
+
+    #[test]
+    fn test_trigger_hover() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+deploy:
+  trigger:
+    project: group/deployments
+    branch: main
+    strategy: depend
+child:
+  trigger:
+    include: ci/child.yml
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Hover(hover)) =
+            handlers.on_hover(hover_request(&uri, Position::new(2, 4)))
+        else {
+            panic!("expected hover result");
+        };
+        assert_eq!(
+            hover.content,
+            "**Trigger**\n\nproject: `group/deployments`\n\nbranch: `main`\n\nJob waits for downstream pipeline and mirrors its status."
+        );
+
+        let Some(LSPResult::Hover(hover)) =
+            handlers.on_hover(hover_request(&uri, Position::new(7, 4)))
+        else {
+            panic!("expected hover result");
+        };
+        assert_eq!(
+            hover.content,
+            "**Trigger**\n\nchild pipeline:\n- `ci/child.yml`"
+        );
+    }
+
+    #[test]
+    fn test_trigger_definition() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let child_uri = Url::parse("file:///tmp/gitlab-ci-ls/ci/child.yml").unwrap();
+        let content = r"
+deploy:
+  trigger:
+    project: group/deployments
+child:
+  trigger:
+    include:
+      - local: ci/child.yml
+";
+        open_document(&handlers, &child_uri, "child_job:\n  script: make\n");
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(5, 4)))
+        else {
+            panic!("expected definition result");
+        };
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(result.locations[0].uri, child_uri.to_string());
+
+        // downstream project without files has nothing to go to
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(2, 4)))
+        else {
+            panic!("expected definition result");
+        };
+        assert!(result.locations.is_empty());
+    }
+
+    #[test]
+    fn test_configured_mirrors() {
+        let root_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            root_dir.path().join(".gitlab-ci-ls.yaml"),
+            "mirrors:\n  gitlab.com: mirrors/gitlab\n  gitlab.example.com: /srv/mirror\n",
+        )
+        .unwrap();
+
+        let configuration = LSPFileConfiguration::load(&root_dir.path().to_string_lossy());
+        assert_eq!(
+            configuration.mirrors,
+            HashMap::from([
+                (
+                    "gitlab.com".to_string(),
+                    format!("{}/mirrors/gitlab", root_dir.path().to_string_lossy())
+                ),
+                ("gitlab.example.com".to_string(), "/srv/mirror".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_definition_project_include_from_mirror() {
+        let mirror_dir = tempfile::tempdir().unwrap();
+        let project_dir = mirror_dir.path().join("group/project");
+        std::fs::create_dir_all(&project_dir).unwrap();
+        std::fs::write(project_dir.join("ci.yml"), "job:\n  script: echo\n").unwrap();
+        crate::gitlab_ci_ls_parser::git::commit_all(&project_dir, Some("1.0.0"));
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = format!("{}/", cache_dir.path().to_string_lossy());
+
+        let mut fs_utils = MockFSUtils::new();
+        fs_utils.expect_create_dir_all().returning(|_| Ok(()));
+        let handlers = new_handlers_with_fs(Box::new(fs_utils), |cfg| {
+            cfg.cache_path.clone_from(&cache_path);
+            cfg.remote_urls = vec!["git@gitlab.example.com:".to_string()];
+            cfg.configuration.mirrors = HashMap::from([(
+                "gitlab.example.com".to_string(),
+                mirror_dir.path().to_string_lossy().to_string(),
+            )]);
+        });
+
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+include:
+  - project: group/project
+    ref: 1.0.0
+    file: /ci.yml
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(4, 12)))
+        else {
+            panic!("expected definition result");
+        };
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(
+            result.locations[0].uri,
+            format!("file://{cache_path}group/project/1.0.0/ci.yml")
+        );
+    }
+
+    #[test]
+    fn test_definition_trigger_includes() {
+        let mirror_dir = tempfile::tempdir().unwrap();
+        let project_dir = mirror_dir.path().join("group/child");
+        std::fs::create_dir_all(&project_dir).unwrap();
+        std::fs::write(project_dir.join("child.yml"), "job:\n  script: echo\n").unwrap();
+        crate::gitlab_ci_ls_parser::git::commit_all(&project_dir, Some("1.0.0"));
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = format!("{}/", cache_dir.path().to_string_lossy());
+
+        let mut fs_utils = MockFSUtils::new();
+        fs_utils.expect_create_dir_all().returning(|_| Ok(()));
+        let handlers = new_handlers_with_fs(Box::new(fs_utils), |cfg| {
+            cfg.cache_path.clone_from(&cache_path);
+            cfg.remote_urls = vec!["git@gitlab.example.com:".to_string()];
+            cfg.configuration.mirrors = HashMap::from([(
+                "gitlab.example.com".to_string(),
+                mirror_dir.path().to_string_lossy().to_string(),
+            )]);
+        });
+
+        let root_dir = tempfile::tempdir().unwrap();
+        std::fs::write(root_dir.path().join("child.yml"), "job:\n  script: echo\n").unwrap();
+        let uri = Url::from_file_path(root_dir.path().join(".gitlab-ci.yml")).unwrap();
+        let content = r"
+project_child:
+  trigger:
+    include:
+      - project: group/child
+        ref: 1.0.0
+        file: /child.yml
+local_child:
+  trigger:
+    include:
+      - local: child.yml
+";
+        open_document(&handlers, &uri, content);
+
+        // child pipelines are only fetched once navigated to
+        let child_uri = format!("file://{cache_path}group/child/1.0.0/child.yml");
+        assert!(!handlers.store.lock().unwrap().contains_key(&child_uri));
+        assert!(!cache_dir.path().join("group/child").exists());
+
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(6, 16)))
+        else {
+            panic!("expected definition result");
+        };
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(result.locations[0].uri, child_uri);
+
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(10, 17)))
+        else {
+            panic!("expected definition result");
+        };
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(
+            result.locations[0].uri,
+            Url::from_file_path(root_dir.path().join("child.yml"))
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_configuration_ignores_invalid_fields() {
+        let root_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            root_dir.path().join(".gitlab-ci-ls.yaml"),
+            "exclude:\n  - vendor/**\nstrict_indexing: maybe\ndiagnostics:\n  missing_stage: loud\n  max_caches: hint\n",
+        )
+        .unwrap();
+
+        let configuration = LSPFileConfiguration::load(&root_dir.path().to_string_lossy());
+        assert_eq!(configuration.exclude, vec!["vendor/**".to_string()]);
+        assert!(!configuration.strict_indexing);
+        assert_eq!(
+            configuration.diagnostics,
+            HashMap::from([("max_caches".to_string(), DiagnosticLevel::Hint)])
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_kinds_cover_diagnostic_data() {
+        let name = String::new;
+        let all = [
+            DiagnosticData::MissingExtend { name: name() },
+            DiagnosticData::CircularExtends { name: name() },
+            DiagnosticData::ReservedJobName { name: name() },
+            DiagnosticData::MissingStage { name: name() },
+            DiagnosticData::MissingNeed { name: name() },
+            DiagnosticData::ReservedNeed { name: name() },
+            DiagnosticData::DuplicateNeed { name: name() },
+            DiagnosticData::NeedsStageOrder {
+                name: name(),
+                stage: name(),
+            },
+            DiagnosticData::InvalidInputKey { name: name() },
+            DiagnosticData::InvalidInputValue { name: name() },
+            DiagnosticData::InputPatternMismatch { name: name() },
+            DiagnosticData::InputTypeMismatch {
+                name: name(),
+                expected: name(),
+            },
+            DiagnosticData::MissingInputValue { name: name() },
+            DiagnosticData::MaxCaches,
+            DiagnosticData::MissingCacheKeyFile { path: name() },
+            DiagnosticData::InvalidExitCode { value: name() },
+            DiagnosticData::UnpinnedProjectInclude { project: name() },
+            DiagnosticData::MissingSecretProvider { name: name() },
+            DiagnosticData::MissingIdTokenAud { name: name() },
+            DiagnosticData::EmptyTags,
+            DiagnosticData::InvalidDeploymentTier { value: name() },
+            DiagnosticData::InvalidDockerPlatform { value: name() },
+            DiagnosticData::InvalidDockerUser { value: name() },
+            DiagnosticData::ContradictoryRule { variable: name() },
+            DiagnosticData::UnreachableRules { name: name() },
+            DiagnosticData::ShadowedKey {
+                name: name(),
+                key: name(),
+                template: name(),
+            },
+            DiagnosticData::DuplicateRootKey { name: name() },
+            DiagnosticData::DuplicateInclude { name: name() },
+            DiagnosticData::InvalidRoot,
+            DiagnosticData::MisnestedKeyword {
+                name: name(),
+                parent: name(),
+            },
+            DiagnosticData::DeprecatedKeyword { name: name() },
+        ];
+
+        // exhaustive so a new variant fails to compile until it's listed above
+        for data in &all {
+            match data {
+                DiagnosticData::MissingExtend { .. }
+                | DiagnosticData::CircularExtends { .. }
+                | DiagnosticData::ReservedJobName { .. }
+                | DiagnosticData::MissingStage { .. }
+                | DiagnosticData::MissingNeed { .. }
+                | DiagnosticData::ReservedNeed { .. }
+                | DiagnosticData::DuplicateNeed { .. }
+                | DiagnosticData::NeedsStageOrder { .. }
+                | DiagnosticData::InvalidInputKey { .. }
+                | DiagnosticData::InvalidInputValue { .. }
+                | DiagnosticData::InputPatternMismatch { .. }
+                | DiagnosticData::InputTypeMismatch { .. }
+                | DiagnosticData::MissingInputValue { .. }
+                | DiagnosticData::MaxCaches
+                | DiagnosticData::MissingCacheKeyFile { .. }
+                | DiagnosticData::InvalidExitCode { .. }
+                | DiagnosticData::UnpinnedProjectInclude { .. }
+                | DiagnosticData::MissingSecretProvider { .. }
+                | DiagnosticData::MissingIdTokenAud { .. }
+                | DiagnosticData::EmptyTags
+                | DiagnosticData::InvalidDeploymentTier { .. }
+                | DiagnosticData::InvalidDockerPlatform { .. }
+                | DiagnosticData::InvalidDockerUser { .. }
+                | DiagnosticData::ContradictoryRule { .. }
+                | DiagnosticData::UnreachableRules { .. }
+                | DiagnosticData::ShadowedKey { .. }
+                | DiagnosticData::DuplicateRootKey { .. }
+                | DiagnosticData::DuplicateInclude { .. }
+                | DiagnosticData::InvalidRoot
+                | DiagnosticData::MisnestedKeyword { .. }
+                | DiagnosticData::DeprecatedKeyword { .. } => {}
             }
-            _ => Some(LSPResult::PrepareRename(super::PrepareRenameResult {
-                id: request.id,
+        }
+
+        let kinds: Vec<String> = all
+            .into_iter()
+            .map(|data| match Diagnostic::from(data).code {
+                Some(NumberOrString::String(kind)) => kind,
+                code => panic!("expected kind, got {code:?}"),
+            })
+            .collect();
+        for kind in &kinds {
+            assert!(DIAGNOSTIC_KINDS.contains(&kind.as_str()), "{kind} missing");
+        }
+        assert_eq!(kinds.len(), DIAGNOSTIC_KINDS.len());
+    }
+
+    #[test]
+    fn test_configured_diagnostic_severities() {
+        let root_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            root_dir.path().join(".gitlab-ci-ls.yaml"),
+            "diagnostics:\n  missing_stage: off\n  invalid_exit_code: info\n  unknown_kind: hint\n",
+        )
+        .unwrap();
+        let configuration = LSPFileConfiguration::load(&root_dir.path().to_string_lossy());
+        assert_eq!(
+            configuration.diagnostics,
+            HashMap::from([
+                ("missing_stage".to_string(), DiagnosticLevel::Off),
+                (
+                    "invalid_exit_code".to_string(),
+                    DiagnosticLevel::Information
+                ),
+            ])
+        );
+
+        let handlers = new_handlers_with(|cfg| {
+            cfg.configuration = configuration;
+        });
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  stage: missing
+  script: make
+  allow_failure:
+    exit_codes: [abc]
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(
+            result.diagnostics[0].message,
+            "Exit code: abc must be an integer."
+        );
+        assert_eq!(
+            result.diagnostics[0].severity,
+            Some(DiagnosticSeverity::INFORMATION)
+        );
+    }
+
+    #[test]
+    fn test_change_configuration_toggles_diagnostics() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+.base:
+  image: node:20
+
+build:
+  extends: .base
+  image: node:22
+  script: make
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+        assert!(result.diagnostics.is_empty());
+
+        let published = std::cell::RefCell::new(vec![]);
+        let result = handlers.on_change_configuration(
+            Notification {
+                method: "workspace/didChangeConfiguration".to_string(),
+                params: serde_json::json!({
+                    "settings": {"options": {"shadowed_keys_diagnostics": true}}
+                }),
+            },
+            &|result| published.borrow_mut().push(result),
+        );
+        assert!(result.is_none());
+
+        let published = published.into_inner();
+        assert_eq!(published.len(), 1);
+        let LSPResult::Diagnostics(result) = &published[0] else {
+            panic!("expected diagnostics result");
+        };
+        assert_eq!(result.uri, uri);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(
+            result.diagnostics[0].message,
+            "Job: build overrides image set by extended .base."
+        );
+
+        // toggles missing from settings keep their value
+        let experimental = handlers.experimental.lock().unwrap();
+        assert!(experimental.shadowed_keys_diagnostics);
+        assert!(!experimental.include_ref_diagnostics);
+    }
+
+    #[test]
+    fn test_dependencies_stage_filtering_toggle() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+stages:
+  - build
+  - test
+  - deploy
+
+build_job:
+  stage: build
+  script: make
+
+deploy_job:
+  stage: deploy
+  script: make deploy
+
+test_job:
+  stage: test
+  script: make test
+  dependencies:
+    - job
+";
+        open_document(&handlers, &uri, content);
+        change_document(
+            &handlers,
+            &uri,
+            vec![TextDocumentContentChangeEvent {
                 range: None,
-                err: Some("Not supported".to_string()),
-            })),
+                range_length: None,
+                text: content.to_string(),
+            }],
+        );
+
+        let labels = || -> Vec<String> {
+            let Some(LSPResult::Completion(completion)) =
+                handlers.on_completion(completion_request(&uri, Position::new(18, 9)))
+            else {
+                panic!("expected completion");
+            };
+
+            let mut labels: Vec<String> = completion.list.into_iter().map(|c| c.label).collect();
+            labels.sort();
+            labels
+        };
+
+        assert_eq!(labels(), vec!["build_job", "deploy_job", "test_job"]);
+
+        handlers.on_change_configuration(
+            Notification {
+                method: "workspace/didChangeConfiguration".to_string(),
+                params: serde_json::json!({
+                    "settings": {"options": {"dependencies_autocomplete_stage_filtering": true}}
+                }),
+            },
+            &|_| {},
+        );
+
+        // jobs from later stages aren't offered anymore
+        assert_eq!(labels(), vec!["build_job", "test_job"]);
+    }
+
+    #[test]
+    fn test_needs_stage_severity() {
+        // `needs_stage_severity` is still read as `diagnostics.needs_stage_order`
+        let root_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            root_dir.path().join(".gitlab-ci-ls.yaml"),
+            "needs_stage_severity: information\n",
+        )
+        .unwrap();
+        let configuration = LSPFileConfiguration::load(&root_dir.path().to_string_lossy());
+        assert_eq!(
+            configuration.diagnostics,
+            HashMap::from([(
+                "needs_stage_order".to_string(),
+                DiagnosticLevel::Information
+            )])
+        );
+
+        let handlers = new_handlers_with(|cfg| {
+            cfg.configuration = configuration;
+        });
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build_job:
+  stage: build
+  needs:
+    - job: lint_job
+    - job: deploy_job
+  script: make
+
+lint_job:
+  stage: build
+  script: make lint
+
+deploy_job:
+  stage: deploy
+  script: make deploy
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+
+        // same stage needs are allowed
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 5);
+        assert_eq!(
+            result.diagnostics[0].severity,
+            Some(DiagnosticSeverity::INFORMATION)
+        );
+
+        let handlers = new_handlers();
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
+        assert_eq!(
+            result.diagnostics[0].severity,
+            Some(DiagnosticSeverity::WARNING)
+        );
+    }
+
+    #[test]
+    fn test_variable_completion_in_variable_value() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r#"
+variables:
+  REGISTRY: registry.example.com
+  IMAGE: $REG
+  TAG:
+    value: "$REG"
+    description: image tag
+job_a:
+  variables:
+    FULL_IMAGE: "${REG}/app"
+  script: echo
+"#;
+        open_document(&handlers, &uri, content);
+
+        for position in [
+            Position::new(3, 13),
+            Position::new(5, 16),
+            Position::new(9, 22),
+        ] {
+            let Some(LSPResult::Completion(result)) =
+                handlers.on_completion(completion_request(&uri, position))
+            else {
+                panic!("expected completion result at {position:?}");
+            };
+
+            let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+            assert_eq!(labels, vec!["REGISTRY"], "at {position:?}");
+
+            // only variable name is replaced, surrounding quotes and braces are kept
+            let range = &result.list[0].location.range;
+            assert_eq!(range.start.character, position.character - 3);
+            assert_eq!(range.end.character, position.character);
+        }
+    }
+
+    #[test]
+    fn test_reveal_cached_remote_include() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+include:
+  - remote: https://example.com/ci/build.yml
+
+job_a:
+  script: echo a
+";
+        let remote_url = "https://example.com/ci/build.yml";
+        let cached_uri = format!(
+            "file:///nonexistent/gitlab-ci-ls-cache/remotes/build_{}.yaml",
+            ParserUtils::remote_path_to_hash(remote_url)
+        );
+
+        // remote file isn't fetched so it is put into store directly
+        let mut store = handlers.store.lock().unwrap();
+        store.insert(uri.to_string(), content.to_string());
+        store.insert(cached_uri.clone(), "build:\n  script: make\n".to_string());
+        drop(store);
+
+        let params = ExecuteCommandParams {
+            command: REVEAL_CACHED_FILE_COMMAND.to_string(),
+            arguments: vec![serde_json::to_value(TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position: Position::new(2, 20),
+            })
+            .unwrap()],
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+
+        let Some(LSPResult::ExecuteCommand(result)) = handlers.on_execute_command(Request {
+            id: RequestId::from(1),
+            method: "workspace/executeCommand".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }) else {
+            panic!("expected execute command result");
+        };
+
+        assert_eq!(result.result["uri"], cached_uri.as_str());
+        assert_eq!(
+            result.result["path"],
+            cached_uri.trim_start_matches("file://")
+        );
+    }
+
+    #[test]
+    fn test_variable_completion_in_regex_rule() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+variables:
+  RELEASE_TAG: v1
+job_a:
+  script: echo
+  rules:
+    - if: $RELEASE =~ /^v$/
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(6, 18)))
+        else {
+            panic!("expected completion result");
+        };
+
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["RELEASE_TAG"]);
+        assert_eq!(result.list[0].location.range.start.character, 11);
+        assert_eq!(result.list[0].location.range.end.character, 18);
+
+        // `$` anchor inside regex
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(6, 26)))
+        else {
+            panic!("expected completion result");
+        };
+
+        assert!(result.list.is_empty());
+    }
+
+    #[test]
+    fn test_variable_completion_in_rules_if() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r#"
+variables:
+  RELEASE_TAG: v1
+workflow:
+  rules:
+    - if: '$RELEASE == "main"'
+job_a:
+  script: echo
+  rules:
+    - changes:
+        - src/*
+      if: "${RELEASE} == 'main'"
+    - if: >-
+        $RELEASE
+"#;
+        open_document(&handlers, &uri, content);
+
+        for position in [
+            Position::new(5, 19),
+            Position::new(11, 20),
+            Position::new(13, 16),
+        ] {
+            let Some(LSPResult::Completion(result)) =
+                handlers.on_completion(completion_request(&uri, position))
+            else {
+                panic!("expected completion result at {position:?}");
+            };
+
+            let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+            assert_eq!(labels, vec!["RELEASE_TAG"], "at {position:?}");
+            assert_eq!(
+                result.list[0].location.range.start.character,
+                position.character - 7
+            );
+        }
+    }
+
+    #[test]
+    fn test_referenced_variables() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+.vars:
+  variables:
+    DEPLOY_ENV: prod
+job:
+  variables: !reference [.vars, variables]
+  script:
+    - echo $DEPLOY_ENV
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(7, 14)))
+        else {
+            panic!("expected completion result");
+        };
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["DEPLOY_ENV"]);
+
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(7, 14)))
+        else {
+            panic!("expected definition result");
+        };
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(result.locations[0].range.start.line, 3);
+    }
+
+    #[test]
+    fn test_reference_to_nested_key() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+.setup:
+  script:
+    - echo setup
+.vars:
+  variables:
+    URL: example.com
+job:
+  script: !reference [.setup, script]
+  variables:
+    URL: !reference [.vars, variables, URL]
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Hover(result)) =
+            handlers.on_hover(hover_request(&uri, Position::new(8, 24)))
+        else {
+            panic!("expected hover result");
+        };
+        assert_eq!(result.content, "```yaml\nscript:\n- echo setup\n\n```");
+
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(10, 23)))
+        else {
+            panic!("expected definition result");
+        };
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(
+            result.locations[0].range,
+            Range {
+                start: LSPPosition {
+                    line: 6,
+                    character: 4
+                },
+                end: LSPPosition {
+                    line: 6,
+                    character: 7
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_definition_of_inherited_key() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+.base:
+  variables:
+    URL: example.com
+  script: make
+
+.middle:
+  extends: .base
+
+build:
+  extends: .middle
+  variables:
+    URL: override.com
+  script: make build
+";
+        open_document(&handlers, &uri, content);
+        for (position, want) in [
+            (
+                Position::new(12, 5),
+                lsp_types::Range::new(Position::new(3, 4), Position::new(3, 7)),
+            ),
+            (
+                Position::new(13, 3),
+                lsp_types::Range::new(Position::new(4, 2), Position::new(4, 8)),
+            ),
+        ] {
+            let Some(LSPResult::Definition(result)) =
+                handlers.on_definition(definition_request(&uri, position))
+            else {
+                panic!("expected definition result");
+            };
+
+            assert_eq!(result.locations.len(), 1, "{:?}", result.locations);
+            assert_eq!(result.locations[0].uri, uri.to_string());
+            assert_eq!(
+                lsp_types::Range::new(
+                    Position::new(
+                        result.locations[0].range.start.line,
+                        result.locations[0].range.start.character
+                    ),
+                    Position::new(
+                        result.locations[0].range.end.line,
+                        result.locations[0].range.end.character
+                    ),
+                ),
+                want
+            );
+        }
+    }
+
+    #[test]
+    fn test_image_completion() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  image: golang:1.22
+  services:
+    - postgres:15
+    - name: redis:7
+      alias: cache
+lint:
+  image:
+    name: golangci/golangci-lint:v1
+test:
+  image: go
+  services:
+    - p
+";
+        open_document(&handlers, &uri, content);
+
+        let labels = |position: Position| -> Option<Vec<String>> {
+            let Some(LSPResult::Completion(result)) =
+                handlers.on_completion(completion_request(&uri, position))
+            else {
+                return None;
+            };
+
+            Some(result.list.into_iter().map(|c| c.label).collect())
+        };
+
+        assert_eq!(
+            labels(Position::new(11, 11)).unwrap(),
+            vec!["golang:1.22", "golangci/golangci-lint:v1"]
+        );
+        assert_eq!(labels(Position::new(13, 7)).unwrap(), vec!["postgres:15"]);
+        assert!(labels(Position::new(6, 18)).is_none());
+    }
+
+    #[test]
+    fn test_needs_completion_matrix_values() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r#"
+build:
+  script: make
+  parallel:
+    matrix:
+      - PROVIDER: aws
+        STACK: [monitoring, app1]
+      - PROVIDER: gcp
+        STACK: data
+deploy:
+  script: make
+  needs:
+    - job: "build: [aws]"
+"#;
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(12, 23)))
+        else {
+            panic!("expected completion result");
+        };
+
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["aws, app1", "aws, monitoring"]);
+        assert_eq!(result.list[0].location.range.start.character, 20);
+        assert_eq!(result.list[0].location.range.end.character, 23);
+    }
+
+    #[test]
+    fn test_needs_completion_ranks_never_run_jobs_last() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+job_a:
+  script: echo
+  rules:
+    - when: never
+job_b:
+  script: echo
+job_c:
+  needs:
+    - job: job
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(9, 14)))
+        else {
+            panic!("expected completion result");
+        };
+
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["job_b", "job_c", "job_a"]);
+        assert_eq!(result.list[2].sort_text.as_deref(), Some("1job_a"));
+
+        let Some(LSPResult::Hover(hover)) =
+            handlers.on_hover(hover_request(&uri, Position::new(1, 3)))
+        else {
+            panic!("expected hover result");
+        };
+
+        assert!(hover.content.contains("only rule is `when: never`"));
+    }
+
+    #[test]
+    fn test_needs_completion_job_scaffold() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  script: echo
+deploy:
+  needs:
+    - bu
+test:
+  needs:
+    
+  script: make
+release:
+  needs:
+    bu
+";
+        open_document(&handlers, &uri, content);
+
+        let edits = |position: Position| {
+            let Some(LSPResult::Completion(result)) =
+                handlers.on_completion(completion_request(&uri, position))
+            else {
+                return vec![];
+            };
+
+            result
+                .list
+                .iter()
+                .filter(|c| c.label.ends_with("build"))
+                .map(|c| {
+                    (
+                        c.label.clone(),
+                        c.location.range.start.character,
+                        c.location.range.end.character,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            edits(Position::new(8, 4)),
+            vec![("- build".to_string(), 4, 4)]
+        );
+
+        handlers
+            .experimental
+            .lock()
+            .unwrap()
+            .needs_job_scaffold_completion = true;
+
+        assert_eq!(
+            edits(Position::new(8, 4)),
+            vec![("- job: build".to_string(), 4, 4)]
+        );
+        assert_eq!(
+            edits(Position::new(12, 6)),
+            vec![("- job: build".to_string(), 4, 6)]
+        );
+        // existing list items are never scaffolded
+        assert_eq!(edits(Position::new(5, 8)), vec![]);
+    }
+
+    #[test]
+    fn test_rename_keeps_matrix_suffix_of_needs() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r#"
+build:
+  parallel:
+    matrix:
+      - PROVIDER: [aws, gcp]
+  script: make
+deploy:
+  needs:
+    - job: build
+    - "build: [aws]"
+    - job: "build [gcp]"
+  script: make deploy
+"#;
+        open_document(&handlers, &uri, content);
+
+        let edits = |position: Position| {
+            let Some(LSPResult::Rename(result)) =
+                handlers.on_rename(rename_request(&uri, position, "compile"))
+            else {
+                panic!("expected rename result");
+            };
+
+            let mut edits: Vec<(u32, u32, u32, String)> = result
+                .edits
+                .unwrap()
+                .remove(&uri)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|e| {
+                    (
+                        e.range.start.line,
+                        e.range.start.character,
+                        e.range.end.character,
+                        e.new_text,
+                    )
+                })
+                .collect();
+            edits.sort_unstable();
+            edits
+        };
+
+        let want = vec![
+            (1, 0, 5, "compile".to_string()),
+            (8, 11, 16, "compile".to_string()),
+            (9, 7, 12, "compile".to_string()),
+            (10, 12, 17, "compile".to_string()),
+        ];
+        assert_eq!(edits(Position::new(1, 2)), want);
+        assert_eq!(edits(Position::new(10, 14)), want);
+    }
+
+    #[test]
+    fn test_call_hierarchy() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+.template:
+  image: alpine
+job_a:
+  extends: .template
+  script: echo
+job_b:
+  script: echo
+  needs:
+    - job: job_a
+";
+        open_document(&handlers, &uri, content);
+
+        let params = CallHierarchyPrepareParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position: Position::new(3, 2),
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+        let Some(LSPResult::PrepareCallHierarchy(result)) =
+            handlers.on_prepare_call_hierarchy(Request {
+                id: RequestId::from(1),
+                method: "textDocument/prepareCallHierarchy".to_string(),
+                params: serde_json::to_value(params).unwrap(),
+            })
+        else {
+            panic!("expected prepare call hierarchy result");
+        };
+
+        assert_eq!(result.items.len(), 1);
+        let item = result.items[0].clone();
+        assert_eq!(item.name, "job_a");
+        assert_eq!(item.selection_range.start.line, 3);
+
+        let params = CallHierarchyIncomingCallsParams {
+            item: item.clone(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let Some(LSPResult::IncomingCalls(result)) = handlers.on_incoming_calls(Request {
+            id: RequestId::from(2),
+            method: "callHierarchy/incomingCalls".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }) else {
+            panic!("expected incoming calls result");
         };
 
-        info!("ON PREPARE RENAME ELAPSED: {:?}", start.elapsed());
+        assert_eq!(result.calls.len(), 1);
+        assert_eq!(result.calls[0].from.name, "job_b");
+        assert_eq!(result.calls[0].from_ranges[0].start.line, 9);
 
-        res
+        let params = CallHierarchyOutgoingCallsParams {
+            item,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let Some(LSPResult::OutgoingCalls(result)) = handlers.on_outgoing_calls(Request {
+            id: RequestId::from(3),
+            method: "callHierarchy/outgoingCalls".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }) else {
+            panic!("expected outgoing calls result");
+        };
+
+        assert_eq!(result.calls.len(), 1);
+        assert_eq!(result.calls[0].to.name, ".template");
+        assert_eq!(result.calls[0].from_ranges[0].start.line, 4);
     }
 
-    #[allow(clippy::too_many_lines)]
-    pub fn on_rename(&self, request: Request) -> Option<LSPResult> {
-        let start = Instant::now();
-        let params: RenameParams = serde_json::from_value(request.params).ok()?;
+    #[test]
+    fn test_secrets() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+job_a:
+  secrets:
+    DATABASE_PASSWORD:
+      vault: production/db/password@ops
+      file: false
+    API_TOKEN:
+      file: false
+  script:
+    - echo $DATA
+    - echo $DATABASE_PASSWORD
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
 
-        info!("got rename params: {params:?}");
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 6);
 
-        let store = self.store.lock().unwrap();
-        let document_uri = params.text_document_position.text_document.uri;
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(9, 16)))
+        else {
+            panic!("expected completion result");
+        };
 
-        // This is redundant but I guess could be needed for when prepare_rename isn't supported
-        // by the client
-        if !self.can_path_be_modified(document_uri.as_ref()) {
-            return Some(LSPResult::Rename(super::RenameResult {
-                id: request.id,
-                edits: None,
-                err: Some("Cannot rename externally included files".to_string()),
-            }));
-        }
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["DATABASE_PASSWORD"]);
 
-        let document = store.get::<String>(&document_uri.clone().into())?;
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(10, 14)))
+        else {
+            panic!("expected definition result");
+        };
 
-        let position = params.text_document_position.position;
-        let line = document.lines().nth(position.line as usize)?;
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(result.locations[0].range.start.line, 3);
+    }
 
-        let mut edits: HashMap<Url, Vec<TextEdit>> = HashMap::new();
-        match self.parser.get_position_type(document, position) {
-            parser::PositionType::RootNode => {
-                let text_edits = edits.entry(document_uri.clone()).or_default();
+    #[test]
+    fn test_id_tokens() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+job_a:
+  id_tokens:
+    VAULT_ID_TOKEN:
+      aud: https://vault.example.com
+    OTHER_TOKEN: { sub: job }
+  script:
+    - vault login $VAULT
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
 
-                let word = parser_utils::ParserUtils::word_before_cursor(
-                    line,
-                    position.character as usize,
-                    char::is_whitespace,
-                );
-                let after = parser_utils::ParserUtils::word_after_cursor(
-                    line,
-                    position.character as usize,
-                    char::is_whitespace,
-                )
-                .trim_end_matches(':');
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 5);
+        assert_eq!(
+            result.diagnostics[0].message,
+            "ID token: OTHER_TOKEN must define aud."
+        );
 
-                let full_word = format!("{word}{after}");
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(7, 23)))
+        else {
+            panic!("expected completion result");
+        };
 
-                if LSPHandlers::is_predefined_root_element(&full_word) {
-                    return Some(LSPResult::Rename(super::RenameResult {
-                        id: request.id,
-                        edits: None,
-                        err: Some("Cannot rename Gitlab elements".to_string()),
-                    }));
-                }
+        let labels: Vec<&str> = result.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["VAULT_ID_TOKEN"]);
+    }
 
-                text_edits.push(TextEdit {
-                    new_text: params.new_name.clone(),
-                    range: lsp_types::Range {
-                        start: Position {
-                            line: position.line,
-                            character: position.character - u32::try_from(word.len()).ok()?,
-                        },
-                        end: Position {
-                            line: position.line,
-                            character: position.character + u32::try_from(after.len()).ok()?,
-                        },
-                    },
-                });
+    #[test]
+    fn test_empty_tags() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+job_a:
+  script: echo
+  tags: []
+job_b:
+  script: echo
+  tags:
+    - docker
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
 
-                for (uri, content) in store.iter() {
-                    if !self.can_path_be_modified(uri) {
-                        continue;
-                    }
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 3);
+        assert_eq!(
+            result.diagnostics[0].severity,
+            Some(DiagnosticSeverity::HINT)
+        );
+    }
 
-                    // TODO: ? should be removed and just skip this entry
-                    let text_edits = edits.entry(Url::parse(uri).ok()?).or_default();
+    #[test]
+    fn test_incremental_change() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+job_a:
+  script: echo
+job_b:
+  needs:
+    - job: job_a
+";
+        open_document(&handlers, &uri, content);
+
+        change_document(
+            &handlers,
+            &uri,
+            vec![
+                TextDocumentContentChangeEvent {
+                    range: Some(lsp_types::Range::new(
+                        Position::new(1, 0),
+                        Position::new(1, 5),
+                    )),
+                    range_length: None,
+                    text: "build".to_string(),
+                },
+                TextDocumentContentChangeEvent {
+                    range: Some(lsp_types::Range::new(
+                        Position::new(5, 11),
+                        Position::new(5, 16),
+                    )),
+                    range_length: None,
+                    text: "build".to_string(),
+                },
+            ],
+        );
 
-                    text_edits.append(&mut self.rename_extends(
-                        uri,
-                        content,
-                        &full_word,
-                        &params.new_name,
-                    ));
+        let store = handlers.store.lock().unwrap();
+        assert_eq!(
+            store.get(uri.as_str()).unwrap(),
+            "\nbuild:\n  script: echo\njob_b:\n  needs:\n    - job: build\n"
+        );
+        drop(store);
 
-                    text_edits.append(&mut self.rename_needs(
-                        uri,
-                        content,
-                        &full_word,
-                        &params.new_name,
-                    ));
+        let nodes = handlers.nodes.lock().unwrap();
+        let nodes = nodes.get(uri.as_str()).unwrap();
+        assert!(nodes.contains_key("build"));
+        assert!(!nodes.contains_key("job_a"));
+    }
 
-                    text_edits.append(&mut self.rename_rule_references(
-                        uri,
-                        content,
-                        &full_word,
-                        &params.new_name,
-                    ));
-                }
-            }
-            parser::PositionType::Extend
-            | parser::PositionType::RuleReference(_)
-            | parser::PositionType::Needs(_) => {
-                let word = parser_utils::ParserUtils::word_before_cursor(
-                    line,
-                    position.character as usize,
-                    |c| c.is_whitespace() || c == '\'' || c == '"',
-                );
+    #[test]
+    fn test_extends_anchor_only_target() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+variables: &base
+  GIT_DEPTH: 1
+job_a:
+  extends: base
+  script: echo
+";
+        let Some(LSPResult::Diagnostics(result)) = open_document(&handlers, &uri, content) else {
+            panic!("expected diagnostics result");
+        };
 
-                let after = parser_utils::ParserUtils::word_after_cursor(
-                    line,
-                    position.character as usize,
-                    |c| c.is_whitespace() || c == '\'' || c == '"',
-                );
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].range.start.line, 4);
+        assert!(result.diagnostics[0]
+            .message
+            .contains("use merge key `<<: *base`"));
+    }
 
-                let job = format!("{word}{after}");
+    #[test]
+    fn test_definition_input_referencing_input() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/templates/build.yml").unwrap();
+        let content = r"
+spec:
+  inputs:
+    stage:
+      default: test
+    job-stage:
+      default: $[[ inputs.stage ]]
+---
+build:
+  stage: $[[ inputs.job-stage ]]
+  script: make
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(6, 24)))
+        else {
+            panic!("expected definition result");
+        };
 
-                let mut is_renamed_job_inside_the_project = false;
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(result.locations[0].range.start.line, 3);
 
-                for (uri, content) in store.iter() {
-                    if !self.can_path_be_modified(uri) {
-                        continue;
-                    }
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(9, 20)))
+        else {
+            panic!("expected definition result");
+        };
 
-                    // TODO: ? should be removed and just skip this entry
-                    let text_edits = edits.entry(Url::parse(uri).ok()?).or_default();
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(result.locations[0].range.start.line, 5);
+    }
 
-                    if let Some(r) = self.rename_root_node(uri, content, &job, &params.new_name) {
-                        is_renamed_job_inside_the_project = true;
-                        text_edits.push(r);
-                    }
+    #[test]
+    fn test_reserved_job_names() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+variables:
+  stage: build
+  script: make
+
+stages:
+  - build
+
+build:
+  stage: build
+  script: make
+
+test:
+  needs:
+    - job: variables
+  script: make test
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Diagnostics(diagnostics)) = handlers.generate_diagnostics(uri) else {
+            panic!("expected diagnostics");
+        };
 
-                    text_edits.append(&mut self.rename_extends(
-                        uri,
-                        content,
-                        &job,
-                        &params.new_name,
-                    ));
+        let messages: Vec<(u32, &str)> = diagnostics
+            .diagnostics
+            .iter()
+            .map(|d| (d.range.start.line, d.message.as_str()))
+            .collect();
 
-                    text_edits.append(&mut self.rename_needs(uri, content, &job, &params.new_name));
+        assert_eq!(messages.len(), 2, "{messages:?}");
+        assert!(messages.contains(&(
+            1,
+            "Reserved keyword: variables can't be used as a job name; rename the job."
+        )));
+        assert!(messages.contains(&(
+            14,
+            "Job: variables is a reserved keyword and can't be a job."
+        )));
+    }
 
-                    text_edits.append(&mut self.rename_rule_references(
-                        uri,
-                        content,
-                        &job,
-                        &params.new_name,
-                    ));
-                }
+    #[test]
+    fn test_newer_root_keywords() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+workflow:
+  auto_cancel:
+    on_new_commit: interruptible
+    on_job_failure: all
+  rules:
+    - when: always
+
+default:
+  hooks:
+    pre_get_sources_script:
+      - echo hello
+
+build:
+  stage: build
+  script: make
+
+test:
+  stage: test
+  needs:
+    - job: build
+  script: make test
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Diagnostics(diagnostics)) = handlers.generate_diagnostics(uri.clone())
+        else {
+            panic!("expected diagnostics");
+        };
+        assert!(
+            diagnostics.diagnostics.is_empty(),
+            "{:?}",
+            diagnostics.diagnostics
+        );
 
-                // adding this at the bottom because if we are trying to rename some extend that
-                // was declared only in cached files this wont be reached
-                if !is_renamed_job_inside_the_project {
-                    return Some(LSPResult::Rename(super::RenameResult {
-                        id: request.id,
-                        edits: None,
-                        err: Some(
-                            "Cannot rename extend which has definition outside project scope"
-                                .to_string(),
-                        ),
-                    }));
-                }
-            }
-            _ => {
-                warn!("invalid type for rename");
-            }
+        let Some(LSPResult::Completion(completion)) =
+            handlers.on_completion(completion_request(&uri, Position::new(20, 11)))
+        else {
+            panic!("expected completion");
         };
 
-        info!("ON RENAME ELAPSED: {:?}", start.elapsed());
+        // `workflow` and `default` are global configuration and can't be needed
+        let labels: Vec<&str> = completion.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["build", "test"]);
+    }
 
-        Some(LSPResult::Rename(RenameResult {
-            id: request.id,
-            edits: Some(edits),
-            err: None,
-        }))
+    #[test]
+    fn test_default_keyword_hover() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+default:
+  image: alpine
+  tags:
+    - docker
+  stage: build
+
+.node:
+  image: node
+
+build:
+  extends: .node
+  script: make
+
+test:
+  image: golang
+  script: go test
+
+lint:
+  script: make lint
+";
+        open_document(&handlers, &uri, content);
+        let Some(LSPResult::Hover(hover)) =
+            handlers.on_hover(hover_request(&uri, Position::new(2, 10)))
+        else {
+            panic!("expected hover result");
+        };
+        assert_eq!(
+            hover.content,
+            "```yaml\nimage: alpine\n```\n\nOverridden by jobs: `build`, `test`"
+        );
+
+        let Some(LSPResult::Hover(hover)) =
+            handlers.on_hover(hover_request(&uri, Position::new(4, 6)))
+        else {
+            panic!("expected hover result");
+        };
+        assert!(hover.content.ends_with("No jobs override `tags`."));
+
+        // only keywords which jobs inherit from `default` are described
+        let hover = handlers.on_hover(hover_request(&uri, Position::new(5, 4)));
+        assert!(
+            !matches!(hover, Some(LSPResult::Hover(hover)) if hover.content.contains("override"))
+        );
     }
 
-    fn rename_extends(
-        &self,
-        uri: &str,
-        content: &str,
-        current_name: &str,
-        new_name: &str,
-    ) -> Vec<TextEdit> {
-        let extends = self
-            .parser
-            .get_all_extends(uri.to_string(), content, Some(current_name));
+    #[test]
+    fn test_code_action_creates_missing_needed_job() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+deploy:
+  script: make deploy
+  needs:
+    - job: build
+    - job: build";
+        let Some(LSPResult::Diagnostics(diagnostics)) = open_document(&handlers, &uri, content)
+        else {
+            panic!("expected diagnostics result");
+        };
 
-        let mut text_edits = vec![];
-        for e in extends {
-            text_edits.push(TextEdit {
-                range: lsp_types::Range {
-                    start: Position {
-                        line: e.range.start.line,
-                        character: e.range.start.character,
-                    },
-                    end: Position {
-                        line: e.range.end.line,
-                        character: e.range.end.character,
-                    },
-                },
-                new_text: new_name.to_string(),
-            });
-        }
+        let params = CodeActionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range: lsp_types::Range::default(),
+            context: lsp_types::CodeActionContext {
+                diagnostics: diagnostics.diagnostics,
+                ..Default::default()
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let Some(LSPResult::CodeAction(result)) = handlers.on_code_action(Request {
+            id: RequestId::from(1),
+            method: "textDocument/codeAction".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }) else {
+            panic!("expected code action result");
+        };
 
-        text_edits
+        assert_eq!(result.actions.len(), 1);
+        assert_eq!(result.actions[0].title, "Create job build");
+
+        let edits = &result.actions[0]
+            .edit
+            .as_ref()
+            .unwrap()
+            .changes
+            .as_ref()
+            .unwrap()[&uri];
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].range.start, Position::new(5, 16));
+        assert_eq!(
+            edits[0].new_text,
+            "\n\nbuild:\n  stage: test\n  script:\n    - echo todo\n"
+        );
     }
 
-    fn rename_needs(
-        &self,
-        uri: &str,
-        content: &str,
-        current_name: &str,
-        new_name: &str,
-    ) -> Vec<TextEdit> {
-        let extends = self
-            .parser
-            .get_all_job_needs(uri.to_string(), content, Some(current_name));
+    #[test]
+    fn test_optional_missing_need_is_hint() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+deploy:
+  script: make deploy
+  needs:
+    - job: build
+      optional: true
+    - job: test";
+        let Some(LSPResult::Diagnostics(diagnostics)) = open_document(&handlers, &uri, content)
+        else {
+            panic!("expected diagnostics result");
+        };
 
-        let mut text_edits = vec![];
-        for e in extends {
-            text_edits.push(TextEdit {
-                range: lsp_types::Range {
-                    start: Position {
-                        line: e.range.start.line,
-                        character: e.range.start.character,
+        let needs: Vec<_> = diagnostics
+            .diagnostics
+            .iter()
+            .map(|d| (d.range.start.line, d.severity, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            needs,
+            vec![
+                (
+                    4,
+                    Some(DiagnosticSeverity::HINT),
+                    "Job: build does not exist; optional need is ignored."
+                ),
+                (6, None, "Job: test does not exist."),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_component_input_type_mismatch() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let component_uri = "gitlab.com/my-group/ci-catalog/build@1.0.0";
+        let input = |key: &str, prop_type: &str| ComponentInput {
+            key: key.to_string(),
+            prop_type: Some(prop_type.to_string()),
+            ..Default::default()
+        };
+        handlers.components.lock().unwrap().insert(
+            component_uri.to_string(),
+            Component {
+                uri: component_uri.to_string(),
+                inputs: vec![
+                    input("retries", "number"),
+                    input("verbose", "boolean"),
+                    input("targets", "array"),
+                    input("platforms", "array"),
+                    input("jobs", "number"),
+                    input("debug", "boolean"),
+                    input("stages", "array"),
+                ],
+                ..Default::default()
+            },
+        );
+
+        let content = format!(
+            r"
+include:
+  - component: {component_uri}
+    inputs:
+      retries: abc
+      verbose: yes
+      targets: linux
+      platforms:
+        - linux
+      jobs: 2
+      debug: $[[ inputs.debug ]]
+      stages: [build, test]
+"
+        );
+        handlers
+            .store
+            .lock()
+            .unwrap()
+            .insert(uri.to_string(), content);
+
+        let Some(LSPResult::Diagnostics(result)) = handlers.generate_diagnostics(uri) else {
+            panic!("expected diagnostics result");
+        };
+
+        let diagnostics: Vec<(u32, &str)> = result
+            .diagnostics
+            .iter()
+            .map(|d| (d.range.start.line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                (4, "Input retries expects type number, got 'abc'"),
+                (5, "Input verbose expects type boolean, got 'yes'"),
+                (6, "Input targets expects type array, got 'linux'"),
+            ]
+        );
+        assert_eq!(
+            result.diagnostics[0].code,
+            Some(NumberOrString::String("input_type_mismatch".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_component_inputs_snippet_completion() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let component_uri = "gitlab.com/my-group/ci-catalog/build@1.0.0";
+        // component isn't fetched so its spec is known upfront
+        handlers.components.lock().unwrap().insert(
+            component_uri.to_string(),
+            Component {
+                uri: component_uri.to_string(),
+                inputs: vec![
+                    ComponentInput {
+                        key: "stage".to_string(),
+                        default: Some(serde_yaml::Value::String("build".to_string())),
+                        ..Default::default()
+                    },
+                    ComponentInput {
+                        key: "image".to_string(),
+                        ..Default::default()
                     },
-                    end: Position {
-                        line: e.range.end.line,
-                        character: e.range.end.character,
+                    ComponentInput {
+                        key: "target".to_string(),
+                        ..Default::default()
                     },
-                },
-                new_text: new_name.to_string(),
-            });
-        }
+                ],
+                ..Default::default()
+            },
+        );
 
-        text_edits
+        let content = format!(
+            r"
+include:
+  - component: {component_uri}
+    inp
+  - component: {component_uri}
+    inputs:
+      image: alpine
+    inp
+
+build:
+  script: make"
+        );
+        open_document(&handlers, &uri, &content);
+
+        let Some(LSPResult::Completion(result)) =
+            handlers.on_completion(completion_request(&uri, Position::new(3, 7)))
+        else {
+            panic!("expected completion result");
+        };
+
+        assert_eq!(result.list.len(), 1);
+        assert_eq!(result.list[0].label, "inputs");
+        assert_eq!(
+            result.list[0].snippet.as_deref(),
+            Some("inputs:\n      image: ${1}\n      target: ${2}")
+        );
+        assert_eq!(result.list[0].location.range.start.character, 4);
+        assert_eq!(result.list[0].location.range.end.character, 7);
+
+        // include which already has inputs isn't scaffolded
+        assert!(!matches!(
+            handlers.on_completion(completion_request(&uri, Position::new(7, 7))),
+            Some(LSPResult::Completion(CompletionResult { list, .. })) if !list.is_empty()
+        ));
     }
 
-    fn rename_rule_references(
-        &self,
-        uri: &str,
-        content: &str,
-        full_word: &str,
-        new_name: &str,
-    ) -> Vec<TextEdit> {
-        let rule_references =
-            self.parser
-                .get_all_rule_references(uri.to_string(), content, Some(full_word));
+    #[test]
+    fn test_diagnostic_data_missing_extend() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+build:
+  extends: .missing
+  script: make";
+        let Some(LSPResult::Diagnostics(diagnostics)) = open_document(&handlers, &uri, content)
+        else {
+            panic!("expected diagnostics result");
+        };
 
-        let mut text_edits = vec![];
-        for r in rule_references {
-            text_edits.push(TextEdit {
-                range: lsp_types::Range {
-                    start: Position {
-                        line: r.range.start.line,
-                        character: r.range.start.character,
-                    },
-                    end: Position {
-                        line: r.range.end.line,
-                        character: r.range.end.character,
-                    },
-                },
-                new_text: new_name.to_string(),
-            });
-        }
+        assert_eq!(diagnostics.diagnostics.len(), 1);
+        let diagnostic = &diagnostics.diagnostics[0];
+        assert_eq!(
+            diagnostic.code,
+            Some(lsp_types::NumberOrString::String(
+                "missing_extend".to_string()
+            ))
+        );
+        assert_eq!(diagnostic.source.as_deref(), Some("gitlab-ci-ls"));
+        assert_eq!(
+            diagnostic.data,
+            Some(serde_json::json!({"kind": "missing_extend", "name": ".missing"}))
+        );
 
-        text_edits
+        // data survives the trip to the client and back
+        let value = serde_json::to_value(diagnostic).unwrap();
+        let diagnostic: Diagnostic = serde_json::from_value(value).unwrap();
+        assert_eq!(
+            serde_json::from_value::<DiagnosticData>(diagnostic.data.unwrap()).unwrap(),
+            DiagnosticData::MissingExtend {
+                name: ".missing".to_string()
+            }
+        );
     }
 
-    fn is_predefined_root_element(full_word: &str) -> bool {
-        let predefined = ["default", "variables", "include", "stages", "image"];
-        predefined.contains(&full_word)
+    #[test]
+    fn test_document_link_includes() {
+        let root_dir = tempfile::tempdir().unwrap();
+        let root_path = std::fs::canonicalize(root_dir.path()).unwrap();
+        std::fs::write(root_path.join("build.yml"), "build:\n  script: make\n").unwrap();
+        std::fs::write(root_path.join("test.yml"), "test:\n  script: make\n").unwrap();
+
+        let handlers = new_handlers();
+        let uri = Url::from_file_path(root_path.join(".gitlab-ci.yml")).unwrap();
+        let content = r"
+include:
+  - local: build.yml
+  - test.yml
+  - local: missing.yml
+";
+        open_document(&handlers, &uri, content);
+
+        let params = DocumentLinkParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let Some(LSPResult::DocumentLink(result)) = handlers.on_document_link(Request {
+            id: RequestId::from(1),
+            method: "textDocument/documentLink".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }) else {
+            panic!("expected document link result");
+        };
+
+        let links: Vec<(u32, u32, u32, String)> = result
+            .links
+            .iter()
+            .map(|l| {
+                (
+                    l.range.start.line,
+                    l.range.start.character,
+                    l.range.end.character,
+                    l.target.as_ref().unwrap().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                (
+                    2,
+                    11,
+                    20,
+                    Url::from_file_path(root_path.join("build.yml"))
+                        .unwrap()
+                        .to_string()
+                ),
+                (
+                    3,
+                    4,
+                    12,
+                    Url::from_file_path(root_path.join("test.yml"))
+                        .unwrap()
+                        .to_string()
+                ),
+            ]
+        );
     }
 
-    fn rename_root_node(
-        &self,
-        uri: &str,
-        content: &str,
-        current_name: &str,
-        new_name: &str,
-    ) -> Option<TextEdit> {
-        if let Some(e) = self.parser.get_root_node_key(uri, content, current_name) {
-            return Some(TextEdit {
-                range: lsp_types::Range {
-                    start: Position {
-                        line: e.range.start.line,
-                        character: e.range.start.character,
-                    },
-                    end: Position {
-                        line: e.range.end.line,
-                        character: e.range.end.character,
-                    },
-                },
-                new_text: new_name.to_string(),
-            });
+    #[test]
+    fn test_document_link_fetched_includes() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+include:
+  - remote: https://example.com/ci/build.yml
+  - project: group/project
+    ref: 1.0.0
+    file:
+      - /ci.yml
+  - component: gitlab.example.com/group/components/lint@1.0.0
+  - https://example.com/ci/other.yml
+";
+        let remote_uri = |url: &str| {
+            format!(
+                "file:///nonexistent/gitlab-ci-ls-cache/remotes/etag_{}.yaml",
+                ParserUtils::remote_path_to_hash(url)
+            )
+        };
+        let build_uri = remote_uri("https://example.com/ci/build.yml");
+        let other_uri = remote_uri("https://example.com/ci/other.yml");
+        let project_uri = "file:///nonexistent/gitlab-ci-ls-cache/group/project/1.0.0/ci.yml";
+        let component_uri = "file:///nonexistent/gitlab-ci-ls-cache/components/lint.yml";
+
+        // included files aren't fetched so they are put into store directly
+        let mut store = handlers.store.lock().unwrap();
+        store.insert(uri.to_string(), content.to_string());
+        for included in [&build_uri, &other_uri, project_uri] {
+            store.insert(included.to_string(), "job:\n  script: echo\n".to_string());
         }
+        drop(store);
+        handlers.components.lock().unwrap().insert(
+            "gitlab.example.com/group/components/lint@1.0.0".to_string(),
+            Component {
+                uri: "gitlab.example.com/group/components/lint@1.0.0".to_string(),
+                local_path: component_uri.to_string(),
+                ..Default::default()
+            },
+        );
 
-        None
+        let params = DocumentLinkParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let Some(LSPResult::DocumentLink(result)) = handlers.on_document_link(Request {
+            id: RequestId::from(1),
+            method: "textDocument/documentLink".to_string(),
+            params: serde_json::to_value(params).unwrap(),
+        }) else {
+            panic!("expected document link result");
+        };
+
+        let links: Vec<(u32, u32, u32, String)> = result
+            .links
+            .iter()
+            .map(|l| {
+                (
+                    l.range.start.line,
+                    l.range.start.character,
+                    l.range.end.character,
+                    l.target.as_ref().unwrap().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                (2, 12, 44, build_uri),
+                (6, 8, 15, project_uri.to_string()),
+                (7, 15, 61, component_uri.to_string()),
+                (8, 4, 36, other_uri),
+            ]
+        );
     }
+    #[test]
+    fn test_symlinked_include() {
+        let root_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root_dir.path().join("shared")).unwrap();
+        std::fs::create_dir_all(root_dir.path().join("ci")).unwrap();
+        std::fs::write(
+            root_dir.path().join("shared/build.yml"),
+            "build:\n  script: make\n",
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(
+            root_dir.path().join("shared/build.yml"),
+            root_dir.path().join("ci/build.yml"),
+        )
+        .unwrap();
+
+        let handlers = new_handlers();
+        let uri = Url::from_file_path(root_dir.path().join(".gitlab-ci.yml")).unwrap();
+        let content = r"
+include:
+  - local: ci/build.yml
+
+test:
+  needs:
+    - job: build
+  script: make test
+";
+        open_document(&handlers, &uri, content);
+
+        let target = Url::from_file_path(
+            std::fs::canonicalize(root_dir.path().join("shared/build.yml")).unwrap(),
+        )
+        .unwrap()
+        .to_string();
+
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(2, 15)))
+        else {
+            panic!("expected definition result");
+        };
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(result.locations[0].uri, target);
 
-    fn on_completion_remote(
-        &self,
-        line: &str,
-        position: Position,
-        remote: &RemoteInclude,
-    ) -> anyhow::Result<Vec<LSPCompletion>> {
-        let Some(project) = &remote.project else {
-            return Ok(vec![]);
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(6, 14)))
+        else {
+            panic!("expected definition result");
         };
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(result.locations[0].uri, target);
+    }
 
-        let word = parser_utils::ParserUtils::word_before_cursor(
-            line,
-            position.character as usize,
-            |c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '/' || c == '\\',
+    #[test]
+    fn test_shadowed_keys() {
+        let handlers = new_handlers();
+        handlers
+            .experimental
+            .lock()
+            .unwrap()
+            .shadowed_keys_diagnostics = true;
+
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+.base:
+  image: node:20
+  stage: build
+
+.same:
+  image: alpine
+
+build:
+  extends: .base
+  variables:
+    image: custom
+  image: node:22
+  stage: build
+  script: make
+
+lint:
+  extends: .same
+  image: alpine
+  script: make lint
+";
+        open_document(&handlers, &uri, content);
+        let Some(LSPResult::Diagnostics(result)) = handlers.generate_diagnostics(uri.clone())
+        else {
+            panic!("expected diagnostics");
+        };
+
+        // nested `image` variable is reported as misnested keyword only
+        let hints: Vec<&Diagnostic> = result
+            .diagnostics
+            .iter()
+            .filter(|d| d.severity == Some(DiagnosticSeverity::HINT))
+            .collect();
+
+        assert_eq!(hints.len(), 1, "{:?}", result.diagnostics);
+        assert_eq!(
+            hints[0].message,
+            "Job: build overrides image set by extended .base."
+        );
+        assert_eq!(
+            hints[0].range,
+            lsp_types::Range::new(Position::new(12, 2), Position::new(12, 7))
         );
 
-        let after =
-            parser_utils::ParserUtils::word_after_cursor(line, position.character as usize, |c| {
-                c.is_whitespace() || c == '"' || c == '\'' || c == '/' || c == '\\'
-            });
+        handlers
+            .experimental
+            .lock()
+            .unwrap()
+            .shadowed_keys_diagnostics = false;
+        let Some(LSPResult::Diagnostics(result)) = handlers.generate_diagnostics(uri) else {
+            panic!("expected diagnostics");
+        };
+        assert!(!result
+            .diagnostics
+            .iter()
+            .any(|d| d.severity == Some(DiagnosticSeverity::HINT)));
+    }
 
-        let path = if let Some(reference) = &remote.reference {
-            format!("{project}/{reference}/")
-        } else {
-            format!("{project}/{DEFAULT_BRANCH_SUBFOLDER}/")
+    #[test]
+    fn test_rule_condition_completion() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r#"
+job:
+  script: make
+  rules:
+    - if: $CI_PIPELINE_SOURCE == "merge
+    - if: $CI_PIPELINE_SOURCE != s
+    - if: $CI_COMMIT_BRANCH == "ma
+"#;
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Completion(completion)) =
+            handlers.on_completion(completion_request(&uri, Position::new(4, 39)))
+        else {
+            panic!("expected completion");
+        };
+        let labels: Vec<&str> = completion.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["merge_request_event"]);
+        assert_eq!(completion.list[0].location.range.start.character, 34);
+
+        let Some(LSPResult::Completion(completion)) =
+            handlers.on_completion(completion_request(&uri, Position::new(5, 34)))
+        else {
+            panic!("expected completion");
         };
+        let labels: Vec<&str> = completion.list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            vec!["\"schedule\"", "\"security_orchestration_policy\""]
+        );
 
-        let (current, previous) =
-            ParserUtils::find_path_at_cursor(line, usize::try_from(position.character).unwrap());
+        // values of other variables are unknown
+        let Some(LSPResult::Completion(completion)) =
+            handlers.on_completion(completion_request(&uri, Position::new(6, 34)))
+        else {
+            panic!("expected completion");
+        };
+        assert!(completion.list.is_empty());
+    }
 
-        let cache = &self.cfg.cache_path;
-        let full_path = format!("{cache}{path}{previous}");
+    #[test]
+    fn test_multiline_extends() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+.base:
+  image: alpine
+
+job:
+  extends: >-
+    .base
+  script: make
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Definition(result)) =
+            handlers.on_definition(definition_request(&uri, Position::new(6, 6)))
+        else {
+            panic!("expected definition result");
+        };
+        assert_eq!(result.locations.len(), 1);
+        assert_eq!(result.locations[0].range.start.line, 1);
 
-        let mut lsp_completions = vec![];
-        for entry in fs::read_dir(full_path)? {
-            let entry = entry?;
-            let path = entry.path();
+        let Some(LSPResult::Diagnostics(result)) = handlers.generate_diagnostics(uri) else {
+            panic!("expected diagnostics");
+        };
+        assert!(result.diagnostics.is_empty());
+    }
 
-            let path_str = path.file_name().unwrap().to_string_lossy();
+    #[test]
+    fn test_circular_extends() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+.a:
+  extends: .b
 
-            if path_str.starts_with('.') {
-                debug!("path starts with .; skipping");
-                continue;
-            }
+.b:
+  extends: .a
 
-            if !current.trim().is_empty() && !path_str.contains(&current) {
-                debug!("path: {:?} doesnt contain: {:?}", path_str, current);
-                continue;
-            }
+.x:
+  extends: .y
 
-            if path.is_file() && !path_str.ends_with(".yaml") && !path_str.ends_with(".yml") {
-                continue;
-            }
+.y:
+  extends: .z
 
-            let c = LSPCompletion {
-                label: path_str.to_string(),
-                details: None,
-                location: LSPLocation {
-                    range: Range {
-                        start: LSPPosition {
-                            line: position.line,
-                            character: position.character - u32::try_from(word.len())?,
-                        },
-                        end: LSPPosition {
-                            line: position.line,
-                            character: position.character + u32::try_from(after.len())?,
-                        },
-                    },
-                    ..Default::default()
-                },
+.z:
+  extends: .x
+
+.base:
+  image: alpine
+
+job:
+  extends:
+    - .base
+    - .a
+  script: make
+";
+        open_document(&handlers, &uri, content);
+
+        let Some(LSPResult::Diagnostics(result)) = handlers.generate_diagnostics(uri) else {
+            panic!("expected diagnostics");
+        };
+
+        let mut messages: Vec<(u32, &str)> = result
+            .diagnostics
+            .iter()
+            .map(|d| (d.range.start.line, d.message.as_str()))
+            .collect();
+        messages.sort_unstable();
+
+        assert_eq!(
+            messages,
+            vec![
+                (2, "Circular extends: .a -> .b -> .a."),
+                (5, "Circular extends: .b -> .a -> .b."),
+                (8, "Circular extends: .x -> .y -> .z -> .x."),
+                (11, "Circular extends: .y -> .z -> .x -> .y."),
+                (14, "Circular extends: .z -> .x -> .y -> .z."),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_resolved() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+stages:
+  - build
+  - test
+
+default:
+  tags: [docker]
+
+.base:
+  image: alpine
+  before_script:
+    - apk add make
+
+.rules:
+  rules:
+    - if: $CI_COMMIT_TAG
+
+build:
+  extends: .base
+  stage: build
+  script: make
+  rules: !reference [.rules, rules]
+
+test:
+  extends: .base
+  image: golang
+  stage: test
+  script: go test
+";
+        open_document(&handlers, &uri, content);
+
+        let request = Request {
+            id: RequestId::from(1),
+            method: "gitlabCiLs/exportResolved".to_string(),
+            params: serde_json::json!({
+                "textDocument": { "uri": uri.to_string() },
+            }),
+        };
+
+        let Some(LSPResult::ExportResolved(result)) = handlers.on_export_resolved(request) else {
+            panic!("expected export result");
+        };
+
+        let exported: serde_yaml::Value = serde_yaml::from_str(&result.content).unwrap();
+        let expected: serde_yaml::Value = serde_yaml::from_str(
+            r"
+stages:
+  - build
+  - test
+build:
+  image: alpine
+  before_script:
+    - apk add make
+  tags: [docker]
+  stage: build
+  script: make
+  rules:
+    - if: $CI_COMMIT_TAG
+test:
+  image: golang
+  before_script:
+    - apk add make
+  tags: [docker]
+  stage: test
+  script: go test
+",
+        )
+        .unwrap();
+
+        assert_eq!(exported, expected);
+    }
+
+    #[test]
+    fn test_change_removes_stale_variables() {
+        let handlers = new_handlers();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+variables:
+  DEPLOY_TARGET: staging
+  DEPLOY_USER: ci
+
+job:
+  script: echo $DEPLOY
+";
+        open_document(&handlers, &uri, content);
+
+        let labels = |handlers: &LSPHandlers, line: u32| -> Vec<String> {
+            let Some(LSPResult::Completion(completion)) =
+                handlers.on_completion(completion_request(&uri, Position::new(line, 22)))
+            else {
+                panic!("expected completion");
             };
 
-            lsp_completions.push(c);
-        }
+            completion.list.into_iter().map(|c| c.label).collect()
+        };
 
-        Ok(lsp_completions)
+        let before = labels(&handlers, 6);
+        assert!(before.contains(&"DEPLOY_TARGET".to_string()));
+        assert!(before.contains(&"DEPLOY_USER".to_string()));
+
+        change_document(
+            &handlers,
+            &uri,
+            vec![TextDocumentContentChangeEvent {
+                range: Some(lsp_types::Range::new(
+                    Position::new(3, 0),
+                    Position::new(4, 0),
+                )),
+                range_length: None,
+                text: String::new(),
+            }],
+        );
+
+        let after = labels(&handlers, 5);
+        assert!(after.contains(&"DEPLOY_TARGET".to_string()));
+        assert!(!after.contains(&"DEPLOY_USER".to_string()));
     }
-}
 
-fn generate_component_diagnostics_from_spec(
-    i: &GitlabInputElement,
-    spec_definition: &ComponentInput,
-    diagnostics: &mut Vec<Diagnostic>,
-) {
-    if let Some(input_value_element) = &i.value_plain {
-        if let Some(input_value) = &input_value_element.content {
-            // check options
-            if let Some(options) = &spec_definition.options {
-                if !options.contains(input_value) {
-                    diagnostics.push(Diagnostic::new_simple(
-                        lsp_types::Range {
-                            start: lsp_types::Position {
-                                line: input_value_element.range.start.line,
-                                character: input_value_element.range.start.character,
-                            },
-                            end: lsp_types::Position {
-                                line: input_value_element.range.end.line,
-                                character: input_value_element.range.end.character,
-                            },
-                        },
-                        format!(
-                            "Invalid input value. Value needs to be one of: '{}'.",
-                            options.join(", ")
-                        ),
-                    ));
-                }
-            }
+    #[test]
+    fn test_change_keeps_variables_defined_in_other_files() {
+        let handlers = new_handlers();
+        let other_uri = Url::parse("file:///tmp/gitlab-ci-ls/other.yml").unwrap();
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        open_document(
+            &handlers,
+            &other_uri,
+            r"
+variables:
+  DEPLOY_USER: ci
+",
+        );
+        open_document(
+            &handlers,
+            &uri,
+            r"
+variables:
+  DEPLOY_USER: ci
+
+job:
+  script: echo $DEPLOY
+",
+        );
 
-            // check if it matches to the spec pattern
-            if let Some(pattern) = &spec_definition.regex {
-                if let Ok(regex) = Regex::new(pattern.trim_matches('/')) {
-                    if !regex.is_match(input_value) {
-                        diagnostics.push(Diagnostic::new_simple(
-                            lsp_types::Range {
-                                start: lsp_types::Position {
-                                    line: input_value_element.range.start.line,
-                                    character: input_value_element.range.start.character,
-                                },
-                                end: lsp_types::Position {
-                                    line: input_value_element.range.end.line,
-                                    character: input_value_element.range.end.character,
-                                },
-                            },
-                            format!("Invalid value. Value needs to match the pattern: {pattern}"),
-                        ));
-                    }
-                } else {
-                    error!("could not parse regex from input spec regex: {pattern}");
-                }
-            }
-        }
-    } else {
-        diagnostics.push(Diagnostic::new_simple(
-            lsp_types::Range {
-                start: lsp_types::Position {
-                    line: i.range.start.line,
-                    character: i.range.start.character,
-                },
-                end: lsp_types::Position {
-                    line: i.range.end.line,
-                    character: i.range.end.character,
-                },
-            },
-            "Missing value.".to_string(),
-        ));
+        change_document(
+            &handlers,
+            &uri,
+            vec![TextDocumentContentChangeEvent {
+                range: Some(lsp_types::Range::new(
+                    Position::new(1, 0),
+                    Position::new(3, 0),
+                )),
+                range_length: None,
+                text: String::new(),
+            }],
+        );
+
+        let variables = handlers.variables.lock().unwrap();
+        let variable = variables
+            .get("DEPLOY_USER")
+            .expect("variable should be kept");
+        assert_eq!(variable.uri, other_uri.to_string());
+    }
+
+    #[test]
+    fn test_hover_project_include_fetch_metadata() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let repo_git = cache_dir.path().join("group/project/1.0.0/.git");
+        std::fs::create_dir_all(&repo_git).unwrap();
+        std::fs::write(
+            repo_git.join("gitlab-ci-ls-fetch.json"),
+            r#"{"ref":"1.0.0","commit":"0a1b2c3d","fetched_at":1709210700}"#,
+        )
+        .unwrap();
+
+        let handlers = new_handlers_with(|cfg| {
+            cfg.root_dir = "/tmp/gitlab-ci-ls/".to_string();
+            cfg.cache_path = format!("{}/", cache_dir.path().to_string_lossy());
+        });
+
+        let uri = Url::parse("file:///tmp/gitlab-ci-ls/.gitlab-ci.yml").unwrap();
+        let content = r"
+include:
+  - project: group/project
+    ref: 1.0.0
+    file: /ci.yml
+";
+        handlers
+            .store
+            .lock()
+            .unwrap()
+            .insert(uri.to_string(), content.to_string());
+
+        let Some(LSPResult::Hover(hover)) =
+            handlers.on_hover(hover_request(&uri, Position::new(4, 12)))
+        else {
+            panic!("expected hover result");
+        };
+        assert_eq!(
+            hover.content,
+            "**Cached include**\n\nref: `1.0.0`\n\ncommit: `0a1b2c3d`\n\nfetched: 2024-02-29 12:45 UTC"
+        );
     }
 }
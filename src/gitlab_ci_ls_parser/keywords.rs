@@ -0,0 +1,135 @@
+// Known GitLab CI keywords: https://docs.gitlab.com/ee/ci/yaml/
+// Keep these in sync with GitLab releases so newer keys aren't treated as jobs
+// or reported as invalid.
+
+// Global keywords; root nodes with these names aren't jobs.
+pub const ROOT_KEYWORDS: [&str; 11] = [
+    "after_script",
+    "before_script",
+    "cache",
+    "default",
+    "image",
+    "include",
+    "services",
+    "spec",
+    "stages",
+    "variables",
+    "workflow",
+];
+
+pub const JOB_KEYWORDS: [&str; 37] = [
+    "after_script",
+    "allow_failure",
+    "artifacts",
+    "before_script",
+    "cache",
+    "coverage",
+    "dast_configuration",
+    "dependencies",
+    "environment",
+    "except",
+    "extends",
+    "hooks",
+    "id_tokens",
+    "identity",
+    "image",
+    "inherit",
+    "interruptible",
+    "manual_confirmation",
+    "needs",
+    "only",
+    "pages",
+    "parallel",
+    "publish",
+    "release",
+    "resource_group",
+    "retry",
+    "rules",
+    "run",
+    "script",
+    "secrets",
+    "services",
+    "stage",
+    "tags",
+    "timeout",
+    "trigger",
+    "variables",
+    "when",
+];
+
+// Job keywords which can be set globally under `default:`.
+pub const DEFAULT_KEYWORDS: [&str; 12] = [
+    "after_script",
+    "artifacts",
+    "before_script",
+    "cache",
+    "hooks",
+    "id_tokens",
+    "image",
+    "interruptible",
+    "retry",
+    "services",
+    "tags",
+    "timeout",
+];
+
+// Job defines something to execute only with one of these.
+pub const JOB_RUN_KEYWORDS: [&str; 3] = ["script", "trigger", "run"];
+
+// Job keywords which are also keys of other job keywords, e.g.
+// `artifacts:when` or `inherit:variables`.
+pub const NESTABLE_JOB_KEYWORDS: [&str; 3] = ["publish", "variables", "when"];
+
+pub fn is_root_keyword(key: &str) -> bool {
+    ROOT_KEYWORDS.contains(&key)
+}
+
+pub fn is_default_keyword(key: &str) -> bool {
+    DEFAULT_KEYWORDS.contains(&key)
+}
+
+// Job keywords which aren't valid inside any other job keyword so finding
+// them nested means they are indented too deep.
+pub fn is_job_level_keyword(key: &str) -> bool {
+    JOB_KEYWORDS.contains(&key) && !NESTABLE_JOB_KEYWORDS.contains(&key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_keywords_are_unique() {
+        for keywords in [
+            ROOT_KEYWORDS.as_slice(),
+            JOB_KEYWORDS.as_slice(),
+            DEFAULT_KEYWORDS.as_slice(),
+        ] {
+            let unique: HashSet<_> = keywords.iter().collect();
+            assert_eq!(unique.len(), keywords.len());
+        }
+    }
+
+    #[test]
+    fn test_keywords_cover_newer_keys() {
+        assert!(is_root_keyword("workflow"));
+        assert!(!is_root_keyword("build"));
+
+        assert!(is_default_keyword("hooks"));
+        assert!(!is_default_keyword("stage"));
+
+        assert!(is_job_level_keyword("manual_confirmation"));
+        assert!(!is_job_level_keyword("when"));
+    }
+
+    #[test]
+    fn test_keyword_sets_are_consistent() {
+        assert!(DEFAULT_KEYWORDS.iter().all(|k| JOB_KEYWORDS.contains(k)));
+        assert!(JOB_RUN_KEYWORDS.iter().all(|k| JOB_KEYWORDS.contains(k)));
+        assert!(NESTABLE_JOB_KEYWORDS
+            .iter()
+            .all(|k| JOB_KEYWORDS.contains(k)));
+    }
+}
@@ -1,35 +1,106 @@
-use std::process::exit;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
+use crossbeam_channel::RecvTimeoutError;
 use log::{error, info, warn};
-use lsp_server::{Connection, Message, Response, ResponseError};
+use lsp_server::{Connection, Message, Notification, Response, ResponseError};
 use lsp_types::{
     CompletionItem, CompletionItemKind, CompletionList, CompletionTextEdit, Hover, HoverContents,
-    LocationLink, MarkedString, MarkupContent, Position, TextEdit, WorkspaceEdit,
+    InsertTextFormat, InsertTextMode, LocationLink, MarkedString, MarkupContent, Position,
+    TextDocumentIdentifier, TextEdit, WorkspaceEdit,
 };
 use reqwest::Url;
 
 use crate::gitlab_ci_ls_parser::LSPResult;
 
 use super::{
-    handlers::LSPHandlers, CompletionResult, DefinitionResult, DiagnosticsNotification,
-    HoverResult, PrepareRenameResult, ReferencesResult, RenameResult,
+    handlers::LSPHandlers, CodeActionResult, CompletionResult, DefinitionResult,
+    DiagnosticsNotification, DocumentLinkResult, DocumentSymbolResult, ExecuteCommandResult,
+    ExportResolvedResult, FoldingRangeResult, GitlabElement, HoverResult, IncomingCallsResult,
+    OutgoingCallsResult, PartialReferencesResult, PrepareCallHierarchyResult, PrepareRenameResult,
+    ReferencesResult, RenameResult,
 };
 
+// Diagnostics are generated only after document wasn't edited for this long
+// so bursts of changes while typing are coalesced into a single run.
+const DIAGNOSTICS_DEBOUNCE: Duration = Duration::from_millis(250);
+
+// Idle documents are looked for at most this often instead of after every
+// message.
+const EVICTION_INTERVAL: Duration = Duration::from_secs(30);
+
 pub struct Messages {
     connection: Connection,
     events: LSPHandlers,
+    // Deadlines of pending diagnostics per document. Newer edit moves the
+    // deadline which cancels previously scheduled run.
+    pending_diagnostics: Mutex<HashMap<Url, Instant>>,
+    next_eviction: Mutex<Instant>,
 }
 
 impl Messages {
     pub fn new(connection: Connection, events: LSPHandlers) -> Self {
-        Self { connection, events }
+        Self {
+            connection,
+            events,
+            pending_diagnostics: Mutex::new(HashMap::new()),
+            next_eviction: Mutex::new(Instant::now() + EVICTION_INTERVAL),
+        }
     }
 
+    // Returns once client finishes shutdown sequence so caller can join io
+    // threads. Messages received before `shutdown` are handled first since
+    // they are processed in order.
     pub fn handle(&self) {
-        self.connection
-            .receiver
-            .iter()
-            .for_each(|msg| self.handle_message(&msg));
+        loop {
+            let next_eviction = *self.next_eviction.lock().unwrap();
+            let deadline = self
+                .pending_diagnostics
+                .lock()
+                .unwrap()
+                .values()
+                .min()
+                .copied()
+                .map_or(next_eviction, |deadline| deadline.min(next_eviction));
+
+            let msg = match self
+                .connection
+                .receiver
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(msg) => msg,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.publish_pending_diagnostics(false);
+                    self.evict_idle_documents();
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+
+            if let Message::Request(request) = &msg {
+                // last edits before shutdown still get their diagnostics
+                if request.method == "shutdown" {
+                    self.publish_pending_diagnostics(true);
+                }
+
+                match self.connection.handle_shutdown(request) {
+                    Ok(true) => {
+                        info!("shutdown");
+                        return;
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        error!("error handling shutdown: {err}");
+                        return;
+                    }
+                }
+            }
+
+            self.handle_message(&msg);
+        }
     }
 
     fn handle_message(&self, msg: &Message) {
@@ -37,11 +108,34 @@ impl Messages {
 
         let msg_clone = msg.clone();
         let result = match msg_clone {
-            // TODO: implement workspace/didChangeConfiguration
             Message::Notification(notification) => match notification.method.as_str() {
-                "textDocument/didOpen" => self.events.on_open(notification),
-                "textDocument/didChange" => self.events.on_change(notification),
-                "textDocument/didSave" => self.events.on_save(notification),
+                "textDocument/didOpen" => {
+                    if let Some(uri) = self.events.on_open(notification) {
+                        self.schedule_diagnostics(uri);
+                    }
+                    None
+                }
+                "textDocument/didChange" => {
+                    let uri = document_uri(&notification);
+                    let result = self.events.on_change(notification);
+                    if let Some(uri) = uri {
+                        self.schedule_diagnostics(uri);
+                    }
+                    result
+                }
+                "textDocument/didClose" => self.events.on_close(notification),
+                "textDocument/didSave" => {
+                    if let Some(uri) = document_uri(&notification) {
+                        self.schedule_diagnostics(uri);
+                    }
+                    None
+                }
+                "workspace/didChangeConfiguration" => {
+                    self.events
+                        .on_change_configuration(notification, &|diagnostics| {
+                            self.send(handle_result(msg, Some(diagnostics)));
+                        })
+                }
                 _ => {
                     warn!("invalid notification method: {:?}", notification);
                     None
@@ -50,14 +144,24 @@ impl Messages {
             Message::Request(request) => match request.method.as_str() {
                 "textDocument/hover" => self.events.on_hover(request),
                 "textDocument/definition" => self.events.on_definition(request),
-                "textDocument/references" => self.events.on_references(request),
+                "textDocument/references" => self.events.on_references(request, &|partial| {
+                    self.send(handle_result(msg, Some(partial)));
+                }),
                 "textDocument/completion" => self.events.on_completion(request),
                 "textDocument/prepareRename" => self.events.on_prepare_rename(request),
                 "textDocument/rename" => self.events.on_rename(request),
-                "shutdown" => {
-                    error!("SHUTDOWN!!");
-                    exit(0);
+                "textDocument/foldingRange" => self.events.on_folding_range(request),
+                "textDocument/documentSymbol" => self.events.on_document_symbol(request),
+                "textDocument/documentLink" => self.events.on_document_link(request),
+                "textDocument/codeAction" => self.events.on_code_action(request),
+                "textDocument/prepareCallHierarchy" => {
+                    self.events.on_prepare_call_hierarchy(request)
                 }
+                "callHierarchy/incomingCalls" => self.events.on_incoming_calls(request),
+                "callHierarchy/outgoingCalls" => self.events.on_outgoing_calls(request),
+                "gitlabCiLs/updateIncludePaths" => self.events.on_update_include_paths(request),
+                "gitlabCiLs/exportResolved" => self.events.on_export_resolved(request),
+                "workspace/executeCommand" => self.events.on_execute_command(request),
                 method => {
                     warn!("invalid request method: {:?}", method);
                     None
@@ -69,7 +173,58 @@ impl Messages {
             }
         };
 
-        let sent = match handle_result(msg, result) {
+        self.send(handle_result(msg, result));
+
+        self.evict_idle_documents();
+    }
+
+    fn evict_idle_documents(&self) {
+        let mut next_eviction = self.next_eviction.lock().unwrap();
+        let now = Instant::now();
+        if now < *next_eviction {
+            return;
+        }
+
+        self.events.evict_idle_documents();
+        *next_eviction = now + EVICTION_INTERVAL;
+    }
+
+    fn schedule_diagnostics(&self, uri: Url) {
+        self.pending_diagnostics
+            .lock()
+            .unwrap()
+            .insert(uri, Instant::now() + DIAGNOSTICS_DEBOUNCE);
+    }
+
+    // Publishes diagnostics whose deadline passed, or all pending ones when
+    // `all` is set.
+    fn publish_pending_diagnostics(&self, all: bool) {
+        let now = Instant::now();
+        let mut due: Vec<Url> = vec![];
+        self.pending_diagnostics
+            .lock()
+            .unwrap()
+            .retain(|uri, deadline| {
+                if all || *deadline <= now {
+                    due.push(uri.clone());
+                    return false;
+                }
+
+                true
+            });
+        due.sort();
+
+        for uri in due {
+            if let Some(LSPResult::Diagnostics(notification)) =
+                self.events.generate_diagnostics(uri)
+            {
+                self.send(Some(diagnostics(notification)));
+            }
+        }
+    }
+
+    fn send(&self, msg: Option<Message>) {
+        let sent = match msg {
             Some(msg) => self.connection.sender.send(msg),
             None => Ok(()),
         };
@@ -80,6 +235,14 @@ impl Messages {
     }
 }
 
+fn document_uri(notification: &Notification) -> Option<Url> {
+    let document = notification.params.get("textDocument")?;
+
+    serde_json::from_value::<TextDocumentIdentifier>(document.clone())
+        .ok()
+        .map(|d| d.uri)
+}
+
 fn handle_result(msg: &Message, result: Option<LSPResult>) -> Option<Message> {
     info!("got result {:?}", &result);
 
@@ -100,6 +263,10 @@ fn handle_result(msg: &Message, result: Option<LSPResult>) -> Option<Message> {
             info!("send references msg: {:?}", references_result);
             Some(references(references_result))
         }
+        Some(LSPResult::PartialReferences(partial_result)) => {
+            info!("send partial references msg: {:?}", partial_result);
+            Some(partial_references(&partial_result))
+        }
         Some(LSPResult::Diagnostics(diagnostics_result)) => {
             info!("send definition msg: {:?}", diagnostics_result);
             Some(diagnostics(diagnostics_result))
@@ -112,6 +279,42 @@ fn handle_result(msg: &Message, result: Option<LSPResult>) -> Option<Message> {
             info!("send prepare rename msg: {:?}", res);
             Some(rename(res))
         }
+        Some(LSPResult::FoldingRange(res)) => {
+            info!("send folding range msg: {:?}", res);
+            Some(folding_range(res))
+        }
+        Some(LSPResult::DocumentSymbol(res)) => {
+            info!("send document symbol msg: {:?}", res);
+            Some(document_symbol(res))
+        }
+        Some(LSPResult::DocumentLink(res)) => {
+            info!("send document link msg: {:?}", res);
+            Some(document_link(res))
+        }
+        Some(LSPResult::CodeAction(res)) => {
+            info!("send code action msg: {:?}", res);
+            Some(code_action(res))
+        }
+        Some(LSPResult::PrepareCallHierarchy(res)) => {
+            info!("send prepare call hierarchy msg: {:?}", res);
+            Some(prepare_call_hierarchy(res))
+        }
+        Some(LSPResult::IncomingCalls(res)) => {
+            info!("send incoming calls msg: {:?}", res);
+            Some(incoming_calls(res))
+        }
+        Some(LSPResult::OutgoingCalls(res)) => {
+            info!("send outgoing calls msg: {:?}", res);
+            Some(outgoing_calls(res))
+        }
+        Some(LSPResult::ExecuteCommand(res)) => {
+            info!("send execute command msg: {:?}", res);
+            Some(execute_command(res))
+        }
+        Some(LSPResult::ExportResolved(res)) => {
+            info!("send export resolved msg: {:?}", res);
+            Some(export_resolved(res))
+        }
         Some(LSPResult::Error(err)) => {
             error!("error handling message: {:?} got error: {:?}", msg, err);
             null_response(msg)
@@ -121,7 +324,7 @@ fn handle_result(msg: &Message, result: Option<LSPResult>) -> Option<Message> {
 }
 
 fn rename(res: RenameResult) -> Message {
-    let mut res = Response {
+    let mut r = Response {
         id: res.id,
         result: serde_json::to_value(WorkspaceEdit {
             changes: res.edits,
@@ -131,15 +334,87 @@ fn rename(res: RenameResult) -> Message {
         error: None,
     };
 
-    if let Some(err) = res.error {
-        res.error = Some(ResponseError {
+    if let Some(err) = res.err {
+        r.error = Some(ResponseError {
             code: -1,
-            message: err.message,
+            message: err,
             data: None,
         });
     }
 
-    Message::Response(res)
+    Message::Response(r)
+}
+
+fn folding_range(res: FoldingRangeResult) -> Message {
+    Message::Response(Response {
+        id: res.id,
+        result: serde_json::to_value(res.ranges).ok(),
+        error: None,
+    })
+}
+
+fn document_symbol(res: DocumentSymbolResult) -> Message {
+    Message::Response(Response {
+        id: res.id,
+        result: serde_json::to_value(res.symbols).ok(),
+        error: None,
+    })
+}
+
+fn document_link(res: DocumentLinkResult) -> Message {
+    Message::Response(Response {
+        id: res.id,
+        result: serde_json::to_value(res.links).ok(),
+        error: None,
+    })
+}
+
+fn code_action(res: CodeActionResult) -> Message {
+    Message::Response(Response {
+        id: res.id,
+        result: serde_json::to_value(res.actions).ok(),
+        error: None,
+    })
+}
+
+fn prepare_call_hierarchy(res: PrepareCallHierarchyResult) -> Message {
+    Message::Response(Response {
+        id: res.id,
+        result: serde_json::to_value(res.items).ok(),
+        error: None,
+    })
+}
+
+fn incoming_calls(res: IncomingCallsResult) -> Message {
+    Message::Response(Response {
+        id: res.id,
+        result: serde_json::to_value(res.calls).ok(),
+        error: None,
+    })
+}
+
+fn outgoing_calls(res: OutgoingCallsResult) -> Message {
+    Message::Response(Response {
+        id: res.id,
+        result: serde_json::to_value(res.calls).ok(),
+        error: None,
+    })
+}
+
+fn execute_command(res: ExecuteCommandResult) -> Message {
+    Message::Response(Response {
+        id: res.id,
+        result: Some(res.result),
+        error: None,
+    })
+}
+
+fn export_resolved(res: ExportResolvedResult) -> Message {
+    Message::Response(Response {
+        id: res.id.clone(),
+        result: serde_json::to_value(res).ok(),
+        error: None,
+    })
 }
 
 fn null_response(msg: &Message) -> Option<Message> {
@@ -176,8 +451,9 @@ fn completion(result: CompletionResult) -> Message {
                     let mut item = CompletionItem {
                         label: c.label.clone(),
                         kind: Some(CompletionItemKind::KEYWORD),
+                        sort_text: c.sort_text.clone(),
                         text_edit: Some(CompletionTextEdit::Edit(TextEdit {
-                            new_text: c.label.clone(),
+                            new_text: c.snippet.clone().unwrap_or_else(|| c.label.clone()),
                             range: lsp_types::Range {
                                 start: Position {
                                     line: c.location.range.start.line,
@@ -192,6 +468,12 @@ fn completion(result: CompletionResult) -> Message {
                         ..Default::default()
                     };
 
+                    // snippet is indented by the server so client keeps it as is
+                    if c.snippet.is_some() {
+                        item.insert_text_format = Some(InsertTextFormat::SNIPPET);
+                        item.insert_text_mode = Some(InsertTextMode::AS_IS);
+                    }
+
                     if let Some(documentation) = c.details.clone() {
                         item.documentation =
                             Some(lsp_types::Documentation::MarkupContent(MarkupContent {
@@ -203,7 +485,7 @@ fn completion(result: CompletionResult) -> Message {
                     item
                 })
                 .collect(),
-            is_incomplete: false,
+            is_incomplete: result.is_incomplete,
         })
         .ok(),
         error: None,
@@ -247,9 +529,8 @@ fn definition(result: DefinitionResult) -> Message {
     })
 }
 
-fn references(result: ReferencesResult) -> Message {
-    let locations: Vec<LocationLink> = result
-        .locations
+fn location_links(locations: &[GitlabElement]) -> Vec<LocationLink> {
+    locations
         .iter()
         .map(|l| LocationLink {
             target_uri: Url::parse(&l.uri).unwrap(),
@@ -275,15 +556,27 @@ fn references(result: ReferencesResult) -> Message {
                 },
             },
         })
-        .collect();
+        .collect()
+}
 
+fn references(result: ReferencesResult) -> Message {
     Message::Response(Response {
         id: result.id,
-        result: serde_json::to_value(locations).ok(),
+        result: serde_json::to_value(location_links(&result.locations)).ok(),
         error: None,
     })
 }
 
+fn partial_references(result: &PartialReferencesResult) -> Message {
+    Message::Notification(lsp_server::Notification {
+        method: "$/progress".to_string(),
+        params: serde_json::json!({
+            "token": result.token,
+            "value": location_links(&result.locations),
+        }),
+    })
+}
+
 fn diagnostics(notification: DiagnosticsNotification) -> Message {
     Message::Notification(lsp_server::Notification {
         method: "textDocument/publishDiagnostics".to_string(),
@@ -328,3 +621,195 @@ fn prepare_rename(res: PrepareRenameResult) -> Message {
 
     Message::Response(r)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use lsp_server::{Notification, Request, RequestId};
+
+    use crate::gitlab_ci_ls_parser::{
+        fs_utils::MockFSUtils, LSPConfig, LSPExperimental, LSPFileConfiguration,
+    };
+
+    use super::*;
+
+    fn new_events() -> LSPHandlers {
+        LSPHandlers::new(
+            LSPConfig {
+                root_dir: "/nonexistent/gitlab-ci-ls".to_string(),
+                cache_path: "/nonexistent/gitlab-ci-ls-cache/".to_string(),
+                package_map: HashMap::new(),
+                remote_urls: vec![],
+                completion_limit: None,
+                store_idle_timeout: None,
+                default_include_ref: None,
+                configuration: LSPFileConfiguration::default(),
+            },
+            LSPExperimental::default(),
+            Box::new(MockFSUtils::new()),
+        )
+    }
+
+    fn notification(method: &str, params: serde_json::Value) -> Message {
+        Message::Notification(Notification::new(method.to_string(), params))
+    }
+
+    fn shutdown_messages() -> [Message; 2] {
+        [
+            Message::Request(Request::new(
+                RequestId::from(1),
+                "shutdown".to_string(),
+                serde_json::Value::Null,
+            )),
+            notification("exit", serde_json::Value::Null),
+        ]
+    }
+
+    fn published_diagnostics(msg: &Message) -> Option<lsp_types::PublishDiagnosticsParams> {
+        match msg {
+            Message::Notification(n) if n.method == "textDocument/publishDiagnostics" => {
+                serde_json::from_value(n.params.clone()).ok()
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_handle_returns_on_shutdown() {
+        let (server, client) = Connection::memory();
+        let events = new_events();
+
+        let uri = "file:///tmp/gitlab-ci-ls/.gitlab-ci.yml";
+        let messages = [
+            Message::Notification(Notification::new(
+                "textDocument/didOpen".to_string(),
+                serde_json::json!({
+                    "textDocument": {
+                        "uri": uri,
+                        "languageId": "yaml",
+                        "version": 1,
+                        "text": "job:\n  script: make\n",
+                    }
+                }),
+            )),
+            Message::Request(Request::new(
+                RequestId::from(1),
+                "shutdown".to_string(),
+                serde_json::Value::Null,
+            )),
+            Message::Notification(Notification::new(
+                "exit".to_string(),
+                serde_json::Value::Null,
+            )),
+            // never handled since server already exited
+            Message::Request(Request::new(
+                RequestId::from(2),
+                "textDocument/hover".to_string(),
+                serde_json::Value::Null,
+            )),
+        ];
+        for msg in messages {
+            client.sender.send(msg).unwrap();
+        }
+
+        Messages::new(server, events).handle();
+
+        let received: Vec<Message> = client.receiver.try_iter().collect();
+        assert_eq!(received.len(), 2);
+        assert!(matches!(
+            &received[0],
+            Message::Notification(n) if n.method == "textDocument/publishDiagnostics"
+        ));
+        assert!(matches!(
+            &received[1],
+            Message::Response(r) if r.id == RequestId::from(1) && r.error.is_none()
+        ));
+    }
+
+    #[test]
+    fn test_diagnostics_are_debounced() {
+        let (server, client) = Connection::memory();
+        let uri = "file:///tmp/gitlab-ci-ls/.gitlab-ci.yml";
+
+        let mut messages = vec![notification(
+            "textDocument/didOpen",
+            serde_json::json!({
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": "yaml",
+                    "version": 1,
+                    "text": "job:\n  script: make\n",
+                }
+            }),
+        )];
+        for (version, text) in [
+            "job:\n  extends: .a\n  script: make\n",
+            "job:\n  extends: .missing\n  script: make\n",
+        ]
+        .iter()
+        .enumerate()
+        {
+            messages.push(notification(
+                "textDocument/didChange",
+                serde_json::json!({
+                    "textDocument": { "uri": uri, "version": version + 2 },
+                    "contentChanges": [{ "text": text }],
+                }),
+            ));
+        }
+        messages.push(notification(
+            "textDocument/didSave",
+            serde_json::json!({ "textDocument": { "uri": uri } }),
+        ));
+        messages.extend(shutdown_messages());
+
+        for msg in messages {
+            client.sender.send(msg).unwrap();
+        }
+
+        Messages::new(server, new_events()).handle();
+
+        let received: Vec<Message> = client.receiver.try_iter().collect();
+        let published: Vec<_> = received.iter().filter_map(published_diagnostics).collect();
+
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].diagnostics.len(), 1);
+        assert!(published[0].diagnostics[0].message.contains(".missing"));
+    }
+
+    #[test]
+    fn test_pending_diagnostics_published_after_debounce() {
+        let (server, client) = Connection::memory();
+        let messages = Messages::new(server, new_events());
+
+        std::thread::scope(|s| {
+            s.spawn(|| messages.handle());
+
+            client
+                .sender
+                .send(notification(
+                    "textDocument/didOpen",
+                    serde_json::json!({
+                        "textDocument": {
+                            "uri": "file:///tmp/gitlab-ci-ls/.gitlab-ci.yml",
+                            "languageId": "yaml",
+                            "version": 1,
+                            "text": "job:\n  script: make\n",
+                        }
+                    }),
+                ))
+                .unwrap();
+
+            let msg = client
+                .receiver
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap();
+            assert!(published_diagnostics(&msg).is_some());
+
+            for msg in shutdown_messages() {
+                client.sender.send(msg).unwrap();
+            }
+        });
+    }
+}
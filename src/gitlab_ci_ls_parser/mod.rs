@@ -1,12 +1,18 @@
 use std::collections::HashMap;
 
+use log::{error, warn};
 use lsp_server::RequestId;
-use lsp_types::{Diagnostic, TextEdit, Url};
+use lsp_types::{
+    CallHierarchyIncomingCall, CallHierarchyItem, CallHierarchyOutgoingCall, CodeAction,
+    Diagnostic, DiagnosticSeverity, DocumentLink, DocumentSymbol, FoldingRange, NumberOrString,
+    ProgressToken, TextEdit, Url,
+};
 use serde::{Deserialize, Deserializer, Serialize};
 
 pub mod fs_utils;
 pub mod git;
 pub mod handlers;
+pub mod keywords;
 pub mod messages;
 pub mod parser;
 pub mod parser_utils;
@@ -37,6 +43,61 @@ pub struct ReferencesResult {
     pub locations: Vec<GitlabElement>,
 }
 
+// Batch of references sent through `$/progress` while workspace is still being scanned.
+#[derive(Debug)]
+pub struct PartialReferencesResult {
+    pub token: ProgressToken,
+    pub locations: Vec<GitlabElement>,
+}
+
+#[derive(Debug)]
+pub struct FoldingRangeResult {
+    pub id: RequestId,
+    pub ranges: Vec<FoldingRange>,
+}
+
+#[derive(Debug)]
+pub struct DocumentLinkResult {
+    pub id: RequestId,
+    pub links: Vec<DocumentLink>,
+}
+
+#[derive(Debug)]
+pub struct CodeActionResult {
+    pub id: RequestId,
+    pub actions: Vec<CodeAction>,
+}
+
+#[derive(Debug)]
+pub struct DocumentSymbolResult {
+    pub id: RequestId,
+    pub symbols: Vec<DocumentSymbol>,
+}
+
+#[derive(Debug)]
+pub struct PrepareCallHierarchyResult {
+    pub id: RequestId,
+    pub items: Vec<CallHierarchyItem>,
+}
+
+#[derive(Debug)]
+pub struct IncomingCallsResult {
+    pub id: RequestId,
+    pub calls: Vec<CallHierarchyIncomingCall>,
+}
+
+#[derive(Debug)]
+pub struct OutgoingCallsResult {
+    pub id: RequestId,
+    pub calls: Vec<CallHierarchyOutgoingCall>,
+}
+
+#[derive(Debug)]
+pub struct ExecuteCommandResult {
+    pub id: RequestId,
+    pub result: serde_json::Value,
+}
+
 #[derive(Debug)]
 pub struct PrepareRenameResult {
     pub id: RequestId,
@@ -55,12 +116,17 @@ pub struct RenameResult {
 pub struct CompletionResult {
     pub id: RequestId,
     pub list: Vec<LSPCompletion>,
+    pub is_incomplete: bool,
 }
 
 #[derive(Debug)]
 pub struct LSPCompletion {
     pub label: String,
     pub details: Option<String>,
+    // overrides label when client orders items
+    pub sort_text: Option<String>,
+    // inserted instead of label, may contain placeholders
+    pub snippet: Option<String>,
     pub location: LSPLocation,
 }
 
@@ -82,6 +148,161 @@ pub struct DiagnosticsNotification {
     pub diagnostics: Vec<Diagnostic>,
 }
 
+pub const DIAGNOSTIC_SOURCE: &str = "gitlab-ci-ls";
+
+// Machine readable diagnostic kind sent as `Diagnostic.data` so code actions
+// and clients don't have to parse messages.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DiagnosticData {
+    MissingExtend {
+        name: String,
+    },
+    CircularExtends {
+        name: String,
+    },
+    ReservedJobName {
+        name: String,
+    },
+    MissingStage {
+        name: String,
+    },
+    MissingNeed {
+        name: String,
+    },
+    ReservedNeed {
+        name: String,
+    },
+    DuplicateNeed {
+        name: String,
+    },
+    NeedsStageOrder {
+        name: String,
+        stage: String,
+    },
+    InvalidInputKey {
+        name: String,
+    },
+    InvalidInputValue {
+        name: String,
+    },
+    InputPatternMismatch {
+        name: String,
+    },
+    InputTypeMismatch {
+        name: String,
+        expected: String,
+    },
+    MissingInputValue {
+        name: String,
+    },
+    MaxCaches,
+    MissingCacheKeyFile {
+        path: String,
+    },
+    InvalidExitCode {
+        value: String,
+    },
+    UnpinnedProjectInclude {
+        project: String,
+    },
+    MissingSecretProvider {
+        name: String,
+    },
+    MissingIdTokenAud {
+        name: String,
+    },
+    EmptyTags,
+    InvalidDeploymentTier {
+        value: String,
+    },
+    InvalidDockerPlatform {
+        value: String,
+    },
+    InvalidDockerUser {
+        value: String,
+    },
+    ContradictoryRule {
+        variable: String,
+    },
+    UnreachableRules {
+        name: String,
+    },
+    ShadowedKey {
+        name: String,
+        key: String,
+        template: String,
+    },
+    DuplicateRootKey {
+        name: String,
+    },
+    DuplicateInclude {
+        name: String,
+    },
+    InvalidRoot,
+    MisnestedKeyword {
+        name: String,
+        parent: String,
+    },
+    DeprecatedKeyword {
+        name: String,
+    },
+}
+
+// Kinds of `DiagnosticData` which severity can be configured.
+pub const DIAGNOSTIC_KINDS: &[&str] = &[
+    "missing_extend",
+    "circular_extends",
+    "reserved_job_name",
+    "missing_stage",
+    "missing_need",
+    "reserved_need",
+    "duplicate_need",
+    "needs_stage_order",
+    "invalid_input_key",
+    "invalid_input_value",
+    "input_pattern_mismatch",
+    "input_type_mismatch",
+    "missing_input_value",
+    "max_caches",
+    "missing_cache_key_file",
+    "invalid_exit_code",
+    "unpinned_project_include",
+    "missing_secret_provider",
+    "missing_id_token_aud",
+    "empty_tags",
+    "invalid_deployment_tier",
+    "invalid_docker_platform",
+    "invalid_docker_user",
+    "contradictory_rule",
+    "unreachable_rules",
+    "shadowed_key",
+    "duplicate_root_key",
+    "duplicate_include",
+    "invalid_root",
+    "misnested_keyword",
+    "deprecated_keyword",
+];
+
+// Used as base of a `Diagnostic` struct literal, e.g.
+// `Diagnostic { range, message, ..data.into() }`.
+impl From<DiagnosticData> for Diagnostic {
+    fn from(data: DiagnosticData) -> Self {
+        let data = serde_json::to_value(&data).ok();
+
+        Diagnostic {
+            code: data
+                .as_ref()
+                .and_then(|d| d.get("kind"))
+                .and_then(serde_json::Value::as_str)
+                .map(|kind| NumberOrString::String(kind.to_string())),
+            source: Some(DIAGNOSTIC_SOURCE.to_string()),
+            data,
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum LSPResult {
     Hover(HoverResult),
@@ -89,8 +310,18 @@ pub enum LSPResult {
     Definition(DefinitionResult),
     Diagnostics(DiagnosticsNotification),
     References(ReferencesResult),
+    PartialReferences(PartialReferencesResult),
     PrepareRename(PrepareRenameResult),
     Rename(RenameResult),
+    FoldingRange(FoldingRangeResult),
+    DocumentSymbol(DocumentSymbolResult),
+    DocumentLink(DocumentLinkResult),
+    CodeAction(CodeActionResult),
+    PrepareCallHierarchy(PrepareCallHierarchyResult),
+    IncomingCalls(IncomingCallsResult),
+    OutgoingCalls(OutgoingCallsResult),
+    ExecuteCommand(ExecuteCommandResult),
+    ExportResolved(ExportResolvedResult),
     Error(anyhow::Error),
 }
 
@@ -121,6 +352,7 @@ pub struct GitlabElementWithParentAndLvl {
     pub lvl: usize,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Default, Clone)]
 pub struct GitlabCacheElement {
     pub key: String,
@@ -130,6 +362,20 @@ pub struct GitlabCacheElement {
     pub cache_items: Vec<GitlabElement>,
 }
 
+// Job `rules:` block; range covers the job while `rules_range` covers only
+// the `rules:` pair.
+#[derive(Debug, Default, Clone)]
+pub struct GitlabRulesElement {
+    pub key: String,
+    pub range: Range,
+    pub rules_range: Range,
+    pub rules: Vec<GitlabElement>,
+    // index of the first rule which always matches, i.e. has no `if`,
+    // `changes` or `exists` clause
+    pub unconditional_rule: Option<usize>,
+}
+
+#[allow(dead_code)]
 #[derive(Debug, Default, Clone)]
 pub struct GitlabInputElement {
     pub key: String,
@@ -142,6 +388,7 @@ pub struct GitlabInputElement {
     pub value_block: Option<GitlabElement>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Default, Clone)]
 pub struct GitlabComponentElement {
     pub key: String,
@@ -151,18 +398,190 @@ pub struct GitlabComponentElement {
     pub inputs: Vec<GitlabInputElement>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ParseResults {
     pub files: Vec<GitlabFile>,
     pub nodes: Vec<GitlabElement>,
     pub stages: Vec<GitlabElement>,
     pub components: Vec<Component>,
     pub variables: Vec<GitlabElement>,
+    // Include edges as (including file, included file), also for includes
+    // which weren't followed.
+    pub includes: Vec<(String, String)>,
 }
 
-#[derive(Clone, Debug)]
+// Opt-in features are independent toggles.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Debug, Default)]
 pub struct LSPExperimental {
     pub dependencies_autocomplete_stage_filtering: bool,
+    pub cache_key_files_diagnostics: bool,
+    pub include_ref_diagnostics: bool,
+    pub shadowed_keys_diagnostics: bool,
+    pub needs_job_scaffold_completion: bool,
+    pub deprecated_keywords_diagnostics: bool,
+}
+
+// Project level configuration read from `.gitlab-ci-ls.yaml` placed in root dir.
+// Invalid field values are logged and replaced by defaults so a typo doesn't
+// reset the rest of the configuration.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct LSPFileConfiguration {
+    // globs, relative to root dir, of files which shouldn't be indexed
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    pub exclude: Vec<String>,
+    // only files reachable from root files through includes are indexed;
+    // other opened files are handled as excluded
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    pub strict_indexing: bool,
+    // deprecated alias of `diagnostics: {needs_stage_order: ...}`, moved into
+    // `diagnostics` when loaded
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    needs_stage_severity: Option<DiagnosticLevel>,
+    // severity overrides keyed by diagnostic kind, e.g. `max_caches: hint`
+    #[serde(default, deserialize_with = "deserialize_diagnostic_levels")]
+    pub diagnostics: HashMap<String, DiagnosticLevel>,
+    // local directories, relative to root dir or absolute, containing
+    // projects of a remote host, e.g. `gitlab.com: /srv/mirrors/gitlab.com`
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    pub mirrors: HashMap<String, String>,
+}
+
+// Falls back to default value when field's value is invalid.
+fn deserialize_lenient<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: serde::de::DeserializeOwned + Default,
+{
+    let value = serde_yaml::Value::deserialize(deserializer)?;
+
+    Ok(serde_yaml::from_value(value).unwrap_or_else(|err| {
+        error!("invalid configuration value; got err: {err}");
+        T::default()
+    }))
+}
+
+// Only diagnostics with invalid level are dropped, others keep their level.
+fn deserialize_diagnostic_levels<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, DiagnosticLevel>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let levels: HashMap<String, serde_yaml::Value> = deserialize_lenient(deserializer)?;
+
+    Ok(levels
+        .into_iter()
+        .filter_map(|(kind, level)| match serde_yaml::from_value(level) {
+            Ok(level) => Some((kind, level)),
+            Err(err) => {
+                error!("invalid level of diagnostic {kind}; got err: {err}");
+                None
+            }
+        })
+        .collect())
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticLevel {
+    Error,
+    #[default]
+    Warning,
+    #[serde(alias = "info")]
+    Information,
+    Hint,
+    // diagnostic isn't reported
+    Off,
+}
+
+impl DiagnosticLevel {
+    pub fn severity(self) -> Option<DiagnosticSeverity> {
+        match self {
+            DiagnosticLevel::Error => Some(DiagnosticSeverity::ERROR),
+            DiagnosticLevel::Warning => Some(DiagnosticSeverity::WARNING),
+            DiagnosticLevel::Information => Some(DiagnosticSeverity::INFORMATION),
+            DiagnosticLevel::Hint => Some(DiagnosticSeverity::HINT),
+            DiagnosticLevel::Off => None,
+        }
+    }
+}
+
+impl LSPExperimental {
+    // Changes toggles present in `options`; the rest keep their value.
+    pub fn update(&mut self, options: &serde_json::Value) {
+        let toggle = |key: &str, current: bool| {
+            options
+                .get(key)
+                .and_then(serde_json::Value::as_bool)
+                .unwrap_or(current)
+        };
+
+        self.dependencies_autocomplete_stage_filtering = toggle(
+            "dependencies_autocomplete_stage_filtering",
+            self.dependencies_autocomplete_stage_filtering,
+        );
+        self.cache_key_files_diagnostics = toggle(
+            "cache_key_files_diagnostics",
+            self.cache_key_files_diagnostics,
+        );
+        self.include_ref_diagnostics =
+            toggle("include_ref_diagnostics", self.include_ref_diagnostics);
+        self.shadowed_keys_diagnostics =
+            toggle("shadowed_keys_diagnostics", self.shadowed_keys_diagnostics);
+        self.needs_job_scaffold_completion = toggle(
+            "needs_job_scaffold_completion",
+            self.needs_job_scaffold_completion,
+        );
+        self.deprecated_keywords_diagnostics = toggle(
+            "deprecated_keywords_diagnostics",
+            self.deprecated_keywords_diagnostics,
+        );
+    }
+}
+
+impl LSPFileConfiguration {
+    pub fn load(root_dir: &str) -> LSPFileConfiguration {
+        let path = std::path::Path::new(root_dir).join(".gitlab-ci-ls.yaml");
+
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return LSPFileConfiguration::default();
+        };
+
+        match serde_yaml::from_str::<LSPFileConfiguration>(&content) {
+            Ok(mut cfg) => {
+                if let Some(level) = cfg.needs_stage_severity.take() {
+                    warn!("needs_stage_severity is deprecated; use diagnostics.needs_stage_order");
+                    cfg.diagnostics
+                        .entry("needs_stage_order".to_string())
+                        .or_insert(level);
+                }
+
+                cfg.diagnostics.retain(|kind, _| {
+                    let known = DIAGNOSTIC_KINDS.contains(&kind.as_str());
+                    if !known {
+                        warn!("unknown diagnostic kind in configuration: {kind}; ignoring it");
+                    }
+
+                    known
+                });
+
+                for dir in cfg.mirrors.values_mut() {
+                    *dir = std::path::Path::new(root_dir)
+                        .join(&dir)
+                        .to_string_lossy()
+                        .to_string();
+                }
+
+                cfg
+            }
+            Err(err) => {
+                error!("invalid configuration: {:?}; got err: {err}", path);
+
+                LSPFileConfiguration::default()
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -171,7 +590,15 @@ pub struct LSPConfig {
     pub cache_path: String,
     pub package_map: HashMap<String, String>,
     pub remote_urls: Vec<String>,
-    pub experimental: LSPExperimental,
+    // caps number of returned completion items; None means unlimited
+    pub completion_limit: Option<usize>,
+    // documents not accessed for this long are dropped from memory and read
+    // again from disk when needed; None keeps everything in memory
+    pub store_idle_timeout: Option<std::time::Duration>,
+    // ref used for `include: project` entries without `ref`; None follows
+    // project's default branch
+    pub default_include_ref: Option<String>,
+    pub configuration: LSPFileConfiguration,
 }
 
 #[derive(Debug)]
@@ -203,6 +630,100 @@ pub struct IncludeInformation {
 #[derive(Debug, Default)]
 pub struct RuleReference {
     pub node: String,
+    // keys following the node, e.g. `[script]` for `!reference [.setup, script]`
+    pub path: Vec<String>,
+}
+
+// Variable declared either as plain value or in expanded form which is used
+// to prefill manually run pipelines.
+#[derive(Debug, Default, Deserialize)]
+pub struct VariableDefinition {
+    pub value: Option<String>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+impl VariableDefinition {
+    pub fn from_content(content: &str) -> Option<VariableDefinition> {
+        match serde_yaml::from_str::<serde_yaml::Value>(content).ok()? {
+            serde_yaml::Value::Mapping(map) => {
+                serde_yaml::from_value(serde_yaml::Value::Mapping(map)).ok()
+            }
+            serde_yaml::Value::String(value) => Some(VariableDefinition {
+                value: Some(value),
+                ..Default::default()
+            }),
+            serde_yaml::Value::Number(value) => Some(VariableDefinition {
+                value: Some(value.to_string()),
+                ..Default::default()
+            }),
+            serde_yaml::Value::Bool(value) => Some(VariableDefinition {
+                value: Some(value.to_string()),
+                ..Default::default()
+            }),
+            _ => None,
+        }
+    }
+
+    // Description, value and options sections used when rendering hover.
+    pub fn details(&self) -> Vec<String> {
+        let mut sections = vec![];
+
+        if let Some(description) = &self.description {
+            sections.push(description.clone());
+        }
+
+        if let Some(value) = &self.value {
+            sections.push(format!("value: `{value}`"));
+        }
+
+        if !self.options.is_empty() {
+            let options = self
+                .options
+                .iter()
+                .map(|o| format!("`{o}`"))
+                .collect::<Vec<String>>()
+                .join(", ");
+            sections.push(format!("options: {options}"));
+        }
+
+        sections
+    }
+}
+
+// Params for `gitlabCiLs/updateIncludePaths` request which is sent by clients
+// when a locally included file is renamed or moved.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateIncludePathsParams {
+    pub old_path: String,
+    pub new_path: String,
+}
+
+// Params for `gitlabCiLs/exportResolved` request. Pipeline is resolved
+// starting from given document, usually `.gitlab-ci.yml`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportResolvedParams {
+    pub text_document: lsp_types::TextDocumentIdentifier,
+}
+
+// Resolved pipeline with includes expanded and extends merged into jobs.
+// Shape matches body of GitLab's `POST /ci/lint` endpoint.
+#[derive(Debug, Serialize)]
+pub struct ExportResolvedResult {
+    #[serde(skip)]
+    pub id: RequestId,
+    pub content: String,
+}
+
+// Local file backing an include, returned by `gitlabCiLs/revealCachedFile`
+// command so editors can open cached remote files.
+#[derive(Debug, Serialize)]
+pub struct RevealCachedFileResult {
+    pub uri: String,
+    pub path: String,
 }
 
 #[derive(Debug)]
@@ -210,12 +731,91 @@ pub struct NodeDefinition {
     pub name: String,
 }
 
+// Script section (`before_script`, `script` or `after_script`) being edited.
+#[derive(Debug)]
+pub struct ScriptDefinition {
+    pub key: String,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ServiceDefinition {
+    pub image: String,
+    pub alias: Option<String>,
+}
+
+impl ServiceDefinition {
+    // Hostnames under which Gitlab exposes service when alias isn't set.
+    // Tag and digest are dropped and slashes are replaced by `__` and `-`.
+    pub fn default_aliases(&self) -> Vec<String> {
+        let image = self.image.split('@').next().unwrap_or_default();
+        let name = match image.rfind(':') {
+            Some(idx) if !image[idx..].contains('/') => &image[..idx],
+            _ => image,
+        };
+
+        let mut aliases = vec![name.replace('/', "__"), name.replace('/', "-")];
+        aliases.dedup();
+
+        aliases
+    }
+
+    pub fn hover(&self) -> String {
+        let alias = if let Some(alias) = &self.alias {
+            format!("alias: `{alias}`")
+        } else {
+            let aliases = self
+                .default_aliases()
+                .iter()
+                .map(|a| format!("`{a}`"))
+                .collect::<Vec<String>>()
+                .join(", ");
+
+            format!("alias (default): {aliases}")
+        };
+
+        format!("**Service**\n\nimage: `{}`\n\n{alias}", self.image)
+    }
+}
+
+// Recorded when project include is cloned or pulled into cache so it's
+// known which revision cached files come from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FetchMetadata {
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
+    pub commit: Option<String>,
+    pub fetched_at: u64,
+}
+
+impl FetchMetadata {
+    pub fn hover(&self) -> String {
+        let mut sections = vec![
+            "**Cached include**".to_string(),
+            format!(
+                "ref: `{}`",
+                self.reference.as_deref().unwrap_or("default branch")
+            ),
+        ];
+        if let Some(commit) = &self.commit {
+            sections.push(format!("commit: `{commit}`"));
+        }
+        sections.push(format!(
+            "fetched: {}",
+            parser_utils::ParserUtils::format_unix_timestamp(self.fetched_at)
+        ));
+
+        sections.join("\n\n")
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Debug, Default, Clone)]
 pub struct ComponentInputValuePlain {
     value: String,
     hovered: bool,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Default, Clone)]
 pub struct ComponentInputValueBlock {
     value: String,
@@ -280,15 +880,27 @@ impl ComponentInput {
             );
         }
 
+        if let Some(d) = &self.options {
+            details = format!(
+                "{}
+## Options: 
+{}
+",
+                details,
+                d.join(", ")
+            );
+        }
+
         details
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Component {
     pub uri: String,
     pub local_path: String,
     pub inputs: Vec<ComponentInput>,
+    pub uri_hovered: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -320,6 +932,145 @@ struct IncludeNode {
     include: Vec<IncludeItem>,
 }
 
+// Jobs triggering downstream or child pipelines.
+#[derive(Debug, Serialize, Deserialize)]
+struct TriggerJob {
+    trigger: Option<Trigger>,
+}
+
+// `trigger` is either a downstream project path or a mapping.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Trigger {
+    Project(String),
+    Pipeline(TriggerNode),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TriggerNode {
+    project: Option<String>,
+    branch: Option<String>,
+    strategy: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_trigger_include")]
+    include: Vec<IncludeItem>,
+}
+
+impl Trigger {
+    // Downstream status can't be resolved statically so only the target is
+    // described.
+    fn hover(&self) -> String {
+        let node = match self {
+            Trigger::Project(project) => {
+                return format!("**Trigger**\n\nproject: `{project}`");
+            }
+            Trigger::Pipeline(node) => node,
+        };
+
+        let mut sections = vec!["**Trigger**".to_string()];
+
+        if let Some(project) = &node.project {
+            sections.push(format!("project: `{project}`"));
+        }
+
+        if let Some(branch) = &node.branch {
+            sections.push(format!("branch: `{branch}`"));
+        }
+
+        if !node.include.is_empty() {
+            let includes = node
+                .include
+                .iter()
+                .map(|include| match include {
+                    IncludeItem::Local(local) => format!("- `{}`", local.local),
+                    IncludeItem::Basic(path) => format!("- `{path}`"),
+                    IncludeItem::Remote(remote) => format!("- `{}`", remote.remote),
+                    IncludeItem::Component(component) => format!("- `{}`", component.component),
+                    IncludeItem::Project(project) => {
+                        let files = match &project.file {
+                            ProjectFile::Single(file) => file.clone(),
+                            ProjectFile::Multi(files) => files.join("`, `"),
+                        };
+                        let reference = project
+                            .reference
+                            .as_ref()
+                            .map(|r| format!(" ({r})"))
+                            .unwrap_or_default();
+
+                        format!("- `{}`: `{files}`{reference}", project.project)
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+
+            sections.push(format!("child pipeline:\n{includes}"));
+        }
+
+        if node.strategy.as_deref() == Some("depend") {
+            sections.push("Job waits for downstream pipeline and mirrors its status.".to_string());
+        }
+
+        sections.join("\n\n")
+    }
+
+    // Files of child pipeline, one entry per included file.
+    fn includes(&self) -> Vec<IncludeInformation> {
+        let Trigger::Pipeline(node) = self else {
+            return vec![];
+        };
+
+        node.include
+            .iter()
+            .flat_map(|include| match include {
+                IncludeItem::Local(local) => vec![IncludeInformation {
+                    local: Some(Include {
+                        path: local.local.clone(),
+                    }),
+                    ..Default::default()
+                }],
+                // single path in `trigger:include` is a local file
+                IncludeItem::Basic(path) => vec![IncludeInformation {
+                    local: Some(Include { path: path.clone() }),
+                    ..Default::default()
+                }],
+                IncludeItem::Remote(remote) => vec![IncludeInformation {
+                    remote_url: Some(Include {
+                        path: remote.remote.clone(),
+                    }),
+                    ..Default::default()
+                }],
+                IncludeItem::Component(component) => vec![IncludeInformation {
+                    component: Some(Component {
+                        uri: component.component.clone(),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }],
+                IncludeItem::Project(project) => {
+                    let files = match &project.file {
+                        ProjectFile::Single(file) => vec![file.clone()],
+                        ProjectFile::Multi(files) => files.clone(),
+                    };
+
+                    files
+                        .into_iter()
+                        .map(|file| IncludeInformation {
+                            remote: Some(RemoteInclude {
+                                project: Some(project.project.clone()),
+                                reference: project.reference.clone(),
+                                file: Some(file),
+                            }),
+                            ..Default::default()
+                        })
+                        .collect()
+                }
+            })
+            .collect()
+    }
+}
+
+// Include entries can also set `rules` and `inputs`. Unknown fields are
+// ignored so such entries still deserialize; rules aren't evaluated and
+// included files are always indexed.
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)] // This attribute allows for different structs in the same Vec
 pub enum IncludeItem {
@@ -330,6 +1081,41 @@ pub enum IncludeItem {
     Component(ComponentInclude),
 }
 
+impl IncludeItem {
+    // Identifies included files so duplicate entries can be found. Components
+    // aren't identified as they can be included multiple times with
+    // different inputs.
+    pub fn identity(&self) -> Option<String> {
+        match self {
+            IncludeItem::Basic(url)
+                if url.starts_with("http://") || url.starts_with("https://") =>
+            {
+                Some(format!("remote:{url}"))
+            }
+            IncludeItem::Local(Local { local: path }) | IncludeItem::Basic(path) => {
+                Some(format!("local:{}", path.trim_start_matches('/')))
+            }
+            IncludeItem::Remote(Remote { remote: url }) => Some(format!("remote:{url}")),
+            IncludeItem::Project(Project {
+                project,
+                reference,
+                file,
+            }) => {
+                let files = match file {
+                    ProjectFile::Single(file) => file.clone(),
+                    ProjectFile::Multi(files) => files.join(","),
+                };
+
+                Some(format!(
+                    "project:{project}@{}:{files}",
+                    reference.as_deref().unwrap_or_default()
+                ))
+            }
+            IncludeItem::Component(_) => None,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)] // This attribute allows for different structs in the same Vec
 pub enum ProjectFile {
@@ -362,7 +1148,8 @@ pub enum InputValue {
 pub struct ComponentInclude {
     component: String,
 
-    #[serde(deserialize_with = "deserialize_inputs")]
+    // components without required inputs don't need `inputs` at all
+    #[serde(default, deserialize_with = "deserialize_inputs")]
     inputs: Option<HashMap<String, InputValue>>,
 }
 
@@ -389,5 +1176,79 @@ where
     }
 }
 
+// `trigger:include` accepts a single path as well as a list of includes.
+fn deserialize_trigger_include<'de, D>(deserializer: D) -> Result<Vec<IncludeItem>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum TriggerInclude {
+        Single(String),
+        Multi(Vec<IncludeItem>),
+    }
+
+    Ok(match TriggerInclude::deserialize(deserializer)? {
+        TriggerInclude::Single(path) => vec![IncludeItem::Basic(path)],
+        TriggerInclude::Multi(includes) => includes,
+    })
+}
+
 const DEFAULT_BRANCH_SUBFOLDER: &str = "default";
+pub const REVEAL_CACHED_FILE_COMMAND: &str = "gitlabCiLs/revealCachedFile";
+// Written to cache as a base file so predefined variables are indexed like
+// ones defined in workspace.
+pub const GITLAB_PREDEFINED_VARIABLES: &str =
+    include_str!("../resources/gitlab_predefined_vars.yaml");
 const MAX_CACHE_ITEMS: usize = 4;
+const MAX_CACHES_MESSAGE: &str =
+    "You can have a maximum of 4 caches: https://docs.gitlab.com/ee/ci/caching/#use-multiple-caches";
+const DEPLOYMENT_TIERS: [&str; 5] = ["production", "staging", "testing", "development", "other"];
+const DOCKER_PLATFORMS: [&str; 8] = [
+    "linux/amd64",
+    "linux/arm64",
+    "linux/arm/v7",
+    "linux/arm/v6",
+    "linux/386",
+    "linux/ppc64le",
+    "linux/s390x",
+    "windows/amd64",
+];
+const DOCKER_USERS: [&str; 2] = ["root", "nobody"];
+// Known values of predefined variables commonly compared in `rules:if`.
+const PREDEFINED_VARIABLE_VALUES: [(&str, &[&str]); 5] = [
+    (
+        "CI_PIPELINE_SOURCE",
+        &[
+            "api",
+            "chat",
+            "external",
+            "external_pull_request_event",
+            "merge_request_event",
+            "ondemand_dast_scan",
+            "ondemand_dast_validation",
+            "parent_pipeline",
+            "pipeline",
+            "push",
+            "schedule",
+            "security_orchestration_policy",
+            "trigger",
+            "web",
+            "webide",
+        ],
+    ),
+    (
+        "CI_MERGE_REQUEST_EVENT_TYPE",
+        &["detached", "merged_result", "merge_train"],
+    ),
+    ("CI_ENVIRONMENT_TIER", &DEPLOYMENT_TIERS),
+    ("CI_COMMIT_REF_PROTECTED", &["true", "false"]),
+    ("CI_JOB_STATUS", &["success", "failed", "canceled"]),
+];
+const SECRET_PROVIDERS: [&str; 5] = [
+    "vault",
+    "azure_key_vault",
+    "gcp_secret_manager",
+    "aws_secrets_manager",
+    "akeyless",
+];
@@ -6,12 +6,14 @@ use std::{
 use anyhow::anyhow;
 use log::{error, info};
 use lsp_types::{Position, Url};
+use tree_sitter::InputEdit;
 
 use super::{
-    fs_utils, git, parser_utils::ParserUtils, treesitter, Component, ComponentSpec,
+    fs_utils, git, parser_utils::ParserUtils, treesitter, Component, ComponentSpec, FetchMetadata,
     GitlabCacheElement, GitlabComponentElement, GitlabElement, GitlabElementWithParentAndLvl,
-    GitlabFile, GitlabFileElements, IncludeInformation, IncludeItem, IncludeNode, NodeDefinition,
-    ParseResults, RuleReference,
+    GitlabFile, GitlabFileElements, GitlabRulesElement, IncludeInformation, IncludeItem,
+    IncludeNode, NodeDefinition, ParseResults, ProjectFile, RuleReference, ScriptDefinition,
+    ServiceDefinition,
 };
 
 unsafe impl Sync for ParserImpl {}
@@ -37,13 +39,88 @@ pub trait Parser: Sync {
     ) -> Vec<GitlabElement>;
     fn get_all_components(&self, uri: &str, content: &str) -> Vec<GitlabComponentElement>;
     fn get_all_multi_caches(&self, uri: &str, content: &str) -> Vec<GitlabCacheElement>;
+    fn get_all_rules_blocks(&self, uri: &str, content: &str) -> Vec<GitlabRulesElement>;
+    fn get_all_local_includes(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_include_items(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_include_targets(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_exit_codes(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_rule_conditions(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_deployment_tiers(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_docker_platforms(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_docker_users(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_cache_key_files(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_project_includes(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_job_definitions(
+        &self,
+        uri: &str,
+        content: &str,
+        keyword: &str,
+    ) -> Vec<GitlabElement>;
+    fn get_all_tags(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_nested_keywords(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_job_keywords(
+        &self,
+        uri: &str,
+        content: &str,
+        keywords: &[&'static str],
+    ) -> Vec<GitlabElement>;
+    fn get_all_variable_references(
+        &self,
+        uri: &str,
+        content: &str,
+        variable: &str,
+    ) -> Vec<GitlabElement>;
+    fn get_all_variable_definitions(
+        &self,
+        uri: &str,
+        content: &str,
+        variable: Option<&str>,
+    ) -> Vec<GitlabElement>;
+    fn get_all_anchors(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_spec_inputs(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_default_keywords(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_script_blocks(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_images(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn fetch_metadata(&self, project: &str, reference: Option<&str>) -> Option<FetchMetadata>;
+    fn fetch_project_file(
+        &self,
+        project: &str,
+        reference: Option<&str>,
+        file: &str,
+    ) -> Option<GitlabFile>;
+    fn git_refs(&self, repo_dir: &str) -> Vec<String>;
+    fn get_all_root_nodes(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_invalid_root_documents(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_stage_definitions(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn job_parallel_matrix(
+        &self,
+        uri: &str,
+        content: &str,
+        job_name: &str,
+    ) -> Option<GitlabElement>;
+    fn job_parallel_matrix_combinations(
+        &self,
+        uri: &str,
+        content: &str,
+        job_name: &str,
+    ) -> Vec<Vec<String>>;
     fn get_all_stages(&self, uri: &str, content: &str, stage: Option<&str>) -> Vec<GitlabElement>;
     fn get_position_type(&self, content: &str, position: Position) -> PositionType;
     fn get_root_node(&self, uri: &str, content: &str, node_key: &str) -> Option<GitlabElement>;
     fn get_root_node_key(&self, uri: &str, content: &str, node_key: &str) -> Option<GitlabElement>;
     fn get_root_node_at_position(&self, content: &str, position: Position)
         -> Option<GitlabElement>;
+    fn job_key_definition(
+        &self,
+        uri: &str,
+        content: &str,
+        job_name: &str,
+        path: &[String],
+    ) -> Option<GitlabElement>;
+    fn get_key_path(&self, content: &str, position: Position) -> Option<(String, Vec<String>)>;
     fn parse_contents(&self, uri: &Url, content: &str, _follow: bool) -> Option<ParseResults>;
+    fn edit_tree(&self, uri: &str, old_content: &str, edits: &[InputEdit], content: &str);
+    fn drop_tree(&self, uri: &str);
     fn parse_contents_recursive(
         &self,
         parse_results: &mut ParseResults,
@@ -65,6 +142,18 @@ pub trait Parser: Sync {
         element: GitlabElement,
         node_list: &[GitlabFileElements],
     ) -> anyhow::Result<String>;
+    fn get_shadowed_keys(
+        &self,
+        element: GitlabElement,
+        node_list: &[GitlabFileElements],
+        keys: &[&str],
+    ) -> Vec<(String, String)>;
+    fn get_inherited_key_definitions(
+        &self,
+        element: GitlabElement,
+        node_list: &[GitlabFileElements],
+        path: &[String],
+    ) -> Vec<GitlabElement>;
 }
 
 #[allow(clippy::module_name_repetitions)]
@@ -84,14 +173,31 @@ pub enum PositionType {
     Dependency,
     Include(IncludeInformation),
     Needs(NodeDefinition),
+    // empty `needs:` value or a job name being typed into it
+    EmptyNeeds,
+    Script(ScriptDefinition),
     RuleReference(RuleReference),
+    Service(ServiceDefinition),
+    AllowFailure,
+    DeploymentTier,
+    DockerPlatform,
+    DockerUser,
+    Image,
+    ChangesPath,
+    ChangesCompareTo,
+    // new key of component include item which has no `inputs` yet
+    ComponentInputs(Component),
+    // `trigger` key of a job
+    Trigger,
 }
 
 impl ParserImpl {
     pub fn new(
         remote_urls: Vec<String>,
         package_map: HashMap<String, String>,
+        mirrors: HashMap<String, String>,
         cache_path: String,
+        default_include_ref: Option<String>,
         treesitter: Box<dyn treesitter::Treesitter>,
         fs_utils: Box<dyn fs_utils::FSUtils>,
     ) -> ParserImpl {
@@ -100,7 +206,9 @@ impl ParserImpl {
             git: Box::new(git::GitImpl::new(
                 remote_urls,
                 package_map,
+                mirrors,
                 cache_path,
+                default_include_ref,
                 fs_utils,
             )),
         }
@@ -233,7 +341,14 @@ impl ParserImpl {
         }
     }
 
-    fn parse_remote_files(&self, parse_results: &mut ParseResults, remote_files: &[GitlabFile]) {
+    fn parse_remote_files(
+        &self,
+        parse_results: &mut ParseResults,
+        remote_files: &[GitlabFile],
+        project_root: Option<&Url>,
+        follow: bool,
+        iteration: i32,
+    ) {
         for remote_file in remote_files {
             parse_results.nodes.append(
                 &mut self
@@ -257,10 +372,77 @@ impl ParserImpl {
                     .treesitter
                     .get_root_variables(remote_file.path.as_str(), remote_file.content.as_str()),
             );
+
+            self.parse_remote_local_includes(
+                parse_results,
+                remote_file,
+                project_root,
+                follow,
+                iteration,
+            );
+        }
+    }
+
+    // Local includes inside project files are resolved against the project
+    // checkout, while remote files only have their own location to go by.
+    fn parse_remote_local_includes(
+        &self,
+        parse_results: &mut ParseResults,
+        remote_file: &GitlabFile,
+        project_root: Option<&Url>,
+        follow: bool,
+        iteration: i32,
+    ) {
+        let Ok(uri) = Url::parse(&remote_file.path) else {
+            return;
+        };
+
+        let Some(element) =
+            self.treesitter
+                .get_root_node(uri.as_str(), remote_file.content.as_str(), "include")
+        else {
+            return;
+        };
+
+        let Some(include_node) = element.content.and_then(|content| {
+            serde_yaml::from_str::<IncludeNode>(&content)
+                .or_else(|_| ParserImpl::include_node_with_aliases(&remote_file.content))
+                .ok()
+        }) else {
+            return;
+        };
+
+        for include_node in include_node.include {
+            let local = match include_node {
+                IncludeItem::Local(node) => node.local,
+                IncludeItem::Basic(include_url) if Url::parse(&include_url).is_err() => include_url,
+                _ => continue,
+            };
+
+            if let Some(included) = self.parse_local_file(
+                project_root.unwrap_or(&uri),
+                local.trim_start_matches('/'),
+                follow,
+                parse_results,
+                iteration,
+            ) {
+                parse_results
+                    .includes
+                    .push((remote_file.path.clone(), included.to_string()));
+            } else {
+                error!("could not resolve local include: {local} from: {uri}");
+            }
         }
     }
 
-    fn parse_remote_file(&self, remote_url: &str, parse_results: &mut ParseResults) {
+    fn parse_remote_file(
+        &self,
+        uri: &Url,
+        remote_url: &str,
+        parse_results: &mut ParseResults,
+        follow: bool,
+        iteration: i32,
+    ) {
         let remote_url = match Url::parse(remote_url) {
             Ok(f) => f,
             Err(err) => {
@@ -284,7 +466,25 @@ impl ParserImpl {
             }
         };
 
-        self.parse_remote_files(parse_results, &[file]);
+        parse_results
+            .includes
+            .push((uri.to_string(), file.path.clone()));
+        self.parse_remote_files(parse_results, &[file], None, follow, iteration);
+    }
+
+    // Include list can reference anchors defined elsewhere in the document
+    // (`include: *common_includes`) which can't be resolved from the include
+    // node alone, so whole document is deserialized to expand aliases.
+    fn include_node_with_aliases(content: &str) -> anyhow::Result<IncludeNode> {
+        let document: serde_yaml::Value = serde_yaml::from_str(content)?;
+        let include = document
+            .get("include")
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("include node not found"))?;
+
+        Ok(IncludeNode {
+            include: serde_yaml::from_value(include)?,
+        })
     }
 
     fn parse_local_file(
@@ -294,9 +494,9 @@ impl ParserImpl {
         follow: bool,
         parse_results: &mut ParseResults,
         iteration: i32,
-    ) -> Option<()> {
-        let current_uri = uri.join(local_url).ok()?;
-        let current_content = std::fs::read_to_string(current_uri.path()).ok()?;
+    ) -> Option<Url> {
+        let current_uri = ParserUtils::canonical_uri(&uri.join(local_url).ok()?);
+        let current_content = std::fs::read_to_string(current_uri.to_file_path().ok()?).ok()?;
         if follow {
             self.parse_contents_recursive(
                 parse_results,
@@ -305,8 +505,8 @@ impl ParserImpl {
                 follow,
                 iteration + 1,
             );
-        };
-        Some(())
+        }
+        Some(current_uri)
     }
 
     fn parse_component(
@@ -378,6 +578,7 @@ impl ParserImpl {
                     ..Default::default()
                 })
                 .collect(),
+            ..Default::default()
         });
 
         Ok(())
@@ -410,12 +611,21 @@ impl Parser for ParserImpl {
         self.treesitter.get_root_node(uri, content, node_key)
     }
 
+    fn edit_tree(&self, uri: &str, old_content: &str, edits: &[InputEdit], content: &str) {
+        self.treesitter.edit_tree(uri, old_content, edits, content);
+    }
+
+    fn drop_tree(&self, uri: &str) {
+        self.treesitter.drop_tree(uri);
+    }
+
     fn parse_contents(&self, uri: &Url, content: &str, follow: bool) -> Option<ParseResults> {
         let files: Vec<GitlabFile> = vec![];
         let nodes: Vec<GitlabElement> = vec![];
         let stages: Vec<GitlabElement> = vec![];
         let components: Vec<Component> = vec![];
         let variables: Vec<GitlabElement> = vec![];
+        let includes: Vec<(String, String)> = vec![];
 
         let mut parse_results = ParseResults {
             files,
@@ -423,6 +633,7 @@ impl Parser for ParserImpl {
             stages,
             components,
             variables,
+            includes,
         };
 
         self.parse_contents_recursive(&mut parse_results, uri, content, follow, 0)?;
@@ -448,6 +659,7 @@ impl Parser for ParserImpl {
             path: uri.as_str().into(),
             content: content.into(),
         });
+        self.treesitter.update_tree(uri.as_str(), content);
 
         parse_results
             .nodes
@@ -467,7 +679,9 @@ impl Parser for ParserImpl {
             .treesitter
             .get_root_node(uri.as_str(), content, "include")
         {
-            let include_node: IncludeNode = match serde_yaml::from_str(&element.content.clone()?) {
+            let include_node = match serde_yaml::from_str::<IncludeNode>(&element.content.clone()?)
+                .or_else(|_| ParserImpl::include_node_with_aliases(content))
+            {
                 Ok(y) => y,
                 Err(err) => {
                     error!(
@@ -482,27 +696,57 @@ impl Parser for ParserImpl {
             for include_node in include_node.include {
                 match include_node {
                     IncludeItem::Local(node) => {
-                        self.parse_local_file(uri, &node.local, follow, parse_results, iteration)?;
+                        // missing include shouldn't hide rest of the document
+                        if let Some(included) = self.parse_local_file(
+                            uri,
+                            &node.local,
+                            follow,
+                            parse_results,
+                            iteration,
+                        ) {
+                            parse_results
+                                .includes
+                                .push((uri.to_string(), included.to_string()));
+                        } else {
+                            error!("could not resolve local include: {}", node.local);
+                        }
                     }
                     IncludeItem::Remote(node) => {
-                        self.parse_remote_file(&node.remote, parse_results);
+                        self.parse_remote_file(uri, &node.remote, parse_results, follow, iteration);
                     }
                     IncludeItem::Basic(include_url) => {
                         if let Ok(url) = Url::parse(&include_url) {
                             info!("got remote URL: {url}");
-                            self.parse_remote_file(url.as_str(), parse_results);
+                            self.parse_remote_file(
+                                uri,
+                                url.as_str(),
+                                parse_results,
+                                follow,
+                                iteration,
+                            );
                         } else {
                             info!("got local URL: {include_url}");
-                            self.parse_local_file(
+                            if let Some(included) = self.parse_local_file(
                                 uri,
                                 &include_url,
                                 follow,
                                 parse_results,
                                 iteration,
-                            )?;
+                            ) {
+                                parse_results
+                                    .includes
+                                    .push((uri.to_string(), included.to_string()));
+                            } else {
+                                error!("could not resolve local include: {include_url}");
+                            }
                         }
                     }
                     IncludeItem::Project(node) => {
+                        let project_root = self
+                            .git
+                            .project_root(node.project.as_str(), node.reference.as_deref())
+                            .ok()
+                            .and_then(|root| Url::from_directory_path(root).ok());
                         let remote_files = match self.git.fetch_remote_repository(
                             node.project.as_str(),
                             node.reference.as_deref(),
@@ -516,7 +760,18 @@ impl Parser for ParserImpl {
                             }
                         };
 
-                        self.parse_remote_files(parse_results, &remote_files);
+                        parse_results.includes.extend(
+                            remote_files
+                                .iter()
+                                .map(|file| (uri.to_string(), file.path.clone())),
+                        );
+                        self.parse_remote_files(
+                            parse_results,
+                            &remote_files,
+                            project_root.as_ref(),
+                            follow,
+                            iteration,
+                        );
                     }
                     IncludeItem::Component(node) => {
                         if let Err(err) = self.parse_component(parse_results, &node.component) {
@@ -560,9 +815,10 @@ impl Parser for ParserImpl {
         let mut all_nodes = vec![];
 
         if let Some(content) = store.get(uri) {
-            let element = self
+            let mut element = self
                 .treesitter
                 .get_root_node_at_position(content, position)?;
+            element.uri = uri.to_string();
 
             let el = GitlabElementWithParentAndLvl {
                 el: element,
@@ -573,20 +829,145 @@ impl Parser for ParserImpl {
             self.get_all_nodes(node_list, &mut all_nodes, el);
         }
 
-        Some(
-            all_nodes
+        let mut definitions: Vec<GitlabElement> = vec![];
+        for e in &all_nodes {
+            let Some(cnt) = store.get(&e.el.uri) else {
+                continue;
+            };
+
+            // job under cursor can also be found in node list
+            if let Some(definition) =
+                self.treesitter
+                    .job_variable_definition(e.el.uri.as_str(), cnt, variable, &e.el.key)
+            {
+                if !definitions.contains(&definition) {
+                    definitions.push(definition);
+                }
+            }
+        }
+
+        // variables block can be taken from another node using `!reference`
+        let root_variables = store
+            .get(uri)
+            .and_then(|content| self.treesitter.get_root_node(uri, content, "variables"));
+
+        let references: Vec<String> = all_nodes
+            .iter()
+            .map(|e| &e.el)
+            .chain(root_variables.iter())
+            .filter_map(|e| ParserUtils::variables_reference(e.content.as_deref()?))
+            .collect();
+
+        for reference in references {
+            for n in node_list
                 .iter()
-                .filter_map(|e| {
-                    let cnt = store.get(&e.el.uri)?;
-                    self.treesitter.job_variable_definition(
-                        e.el.uri.as_str(),
-                        cnt,
-                        variable,
-                        &e.el.key,
+                .flat_map(|file| &file.elements)
+                .filter(|n| n.key == reference)
+            {
+                let Some(cnt) = store.get(&n.uri) else {
+                    continue;
+                };
+
+                if let Some(definition) =
+                    self.treesitter
+                        .job_variable_definition(n.uri.as_str(), cnt, variable, &n.key)
+                {
+                    if !definitions.contains(&definition) {
+                        definitions.push(definition);
+                    }
+                }
+            }
+        }
+
+        Some(definitions)
+    }
+
+    // Returns keys which are set by the job and also set to a different value
+    // by one of its extends, together with the nearest such template.
+    fn get_shadowed_keys(
+        &self,
+        element: GitlabElement,
+        node_list: &[GitlabFileElements],
+        keys: &[&str],
+    ) -> Vec<(String, String)> {
+        fn definition(element: &GitlabElement) -> Option<serde_yaml::Value> {
+            let content = element.content.as_deref()?;
+            let content = content.lines().skip(1).collect::<Vec<_>>().join("\n");
+
+            serde_yaml::from_str(&content).ok()
+        }
+
+        let Some(job) = definition(&element) else {
+            return vec![];
+        };
+
+        let mut all_nodes: Vec<GitlabElementWithParentAndLvl> = Vec::new();
+        self.get_all_nodes(
+            node_list,
+            &mut all_nodes,
+            GitlabElementWithParentAndLvl {
+                el: element,
+                lvl: 0,
+                parents: "root".to_string(),
+            },
+        );
+
+        let templates: Vec<(usize, &str, serde_yaml::Value)> = all_nodes
+            .iter()
+            .filter(|n| n.lvl > 0)
+            .filter_map(|n| Some((n.lvl, n.el.key.as_str(), definition(&n.el)?)))
+            .collect();
+
+        keys.iter()
+            .filter_map(|key| {
+                let value = job.get(key)?;
+
+                let (_, template, template_value) = templates
+                    .iter()
+                    .filter_map(|(lvl, name, t)| Some((lvl, name, t.get(key)?)))
+                    .min_by_key(|(lvl, _, _)| **lvl)?;
+
+                (template_value != value).then(|| ((*key).to_string(), (*template).to_string()))
+            })
+            .collect()
+    }
+
+    // Templates from extends chain which define key at `path`, closest first.
+    fn get_inherited_key_definitions(
+        &self,
+        element: GitlabElement,
+        node_list: &[GitlabFileElements],
+        path: &[String],
+    ) -> Vec<GitlabElement> {
+        let mut all_nodes: Vec<GitlabElementWithParentAndLvl> = Vec::new();
+        self.get_all_nodes(
+            node_list,
+            &mut all_nodes,
+            GitlabElementWithParentAndLvl {
+                el: element,
+                lvl: 0,
+                parents: "root".to_string(),
+            },
+        );
+
+        let mut templates: Vec<GitlabElementWithParentAndLvl> =
+            all_nodes.into_iter().filter(|n| n.lvl > 0).collect();
+        templates.sort_by_key(|n| n.lvl);
+
+        templates
+            .into_iter()
+            .map(|n| n.el)
+            .filter(|el| {
+                self.treesitter
+                    .job_key_definition(
+                        "",
+                        el.content.as_deref().unwrap_or_default(),
+                        &el.key,
+                        path,
                     )
-                })
-                .collect(),
-        )
+                    .is_some()
+            })
+            .collect()
     }
 
     fn get_full_definition(
@@ -677,6 +1058,177 @@ impl Parser for ParserImpl {
         self.treesitter.get_all_multi_caches(uri, content)
     }
 
+    fn get_all_rules_blocks(&self, uri: &str, content: &str) -> Vec<GitlabRulesElement> {
+        self.treesitter.get_all_rules_blocks(uri, content)
+    }
+
+    fn get_all_include_items(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_include_items(uri, content)
+    }
+
+    fn get_all_include_targets(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_include_targets(uri, content)
+    }
+
+    fn get_all_local_includes(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_local_includes(uri, content)
+    }
+
+    fn get_all_exit_codes(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_exit_codes(uri, content)
+    }
+
+    fn get_all_rule_conditions(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_rule_conditions(uri, content)
+    }
+
+    fn get_all_docker_platforms(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_docker_platforms(uri, content)
+    }
+
+    fn get_all_docker_users(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_docker_users(uri, content)
+    }
+
+    fn get_all_deployment_tiers(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_deployment_tiers(uri, content)
+    }
+
+    fn get_all_cache_key_files(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_cache_key_files(uri, content)
+    }
+
+    fn get_all_project_includes(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_project_includes(uri, content)
+    }
+
+    fn get_all_job_definitions(
+        &self,
+        uri: &str,
+        content: &str,
+        keyword: &str,
+    ) -> Vec<GitlabElement> {
+        self.treesitter
+            .get_all_job_definitions(uri, content, keyword)
+    }
+
+    fn get_all_tags(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_tags(uri, content)
+    }
+
+    fn get_all_nested_keywords(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_nested_keywords(uri, content)
+    }
+
+    fn get_all_job_keywords(
+        &self,
+        uri: &str,
+        content: &str,
+        keywords: &[&'static str],
+    ) -> Vec<GitlabElement> {
+        self.treesitter.get_all_job_keywords(uri, content, keywords)
+    }
+
+    fn get_all_variable_definitions(
+        &self,
+        uri: &str,
+        content: &str,
+        variable: Option<&str>,
+    ) -> Vec<GitlabElement> {
+        self.treesitter
+            .get_all_variable_definitions(uri, content, variable)
+    }
+
+    fn get_all_variable_references(
+        &self,
+        uri: &str,
+        content: &str,
+        variable: &str,
+    ) -> Vec<GitlabElement> {
+        self.treesitter
+            .get_all_variable_references(uri, content, variable)
+    }
+
+    fn get_all_anchors(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_anchors(uri, content)
+    }
+
+    fn get_all_spec_inputs(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_spec_inputs(uri, content)
+    }
+
+    fn get_all_default_keywords(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_default_keywords(uri, content)
+    }
+
+    fn get_all_script_blocks(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_script_blocks(uri, content)
+    }
+
+    fn get_all_images(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_images(uri, content)
+    }
+
+    fn fetch_metadata(&self, project: &str, reference: Option<&str>) -> Option<FetchMetadata> {
+        self.git.fetch_metadata(project, reference)
+    }
+
+    // Used for includes that aren't fetched while parsing, e.g. child
+    // pipelines, so they are only cloned once they are navigated to.
+    fn fetch_project_file(
+        &self,
+        project: &str,
+        reference: Option<&str>,
+        file: &str,
+    ) -> Option<GitlabFile> {
+        match self.git.fetch_remote_repository(
+            project,
+            reference,
+            ProjectFile::Single(file.to_string()),
+        ) {
+            Ok(files) => files.into_iter().next(),
+            Err(err) => {
+                error!("error retrieving project file: {project}{file}; got err: {err}");
+                None
+            }
+        }
+    }
+
+    fn git_refs(&self, repo_dir: &str) -> Vec<String> {
+        self.git.refs(repo_dir)
+    }
+
+    fn get_all_root_nodes(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_all_root_nodes(uri, content)
+    }
+
+    fn get_invalid_root_documents(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_invalid_root_documents(uri, content)
+    }
+
+    fn get_stage_definitions(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.treesitter.get_stage_definitions(uri, content)
+    }
+
+    fn job_parallel_matrix(
+        &self,
+        uri: &str,
+        content: &str,
+        job_name: &str,
+    ) -> Option<GitlabElement> {
+        self.treesitter.job_parallel_matrix(uri, content, job_name)
+    }
+
+    fn job_parallel_matrix_combinations(
+        &self,
+        uri: &str,
+        content: &str,
+        job_name: &str,
+    ) -> Vec<Vec<String>> {
+        self.treesitter
+            .job_parallel_matrix_combinations(uri, content, job_name)
+    }
+
     fn get_root_node_at_position(
         &self,
         content: &str,
@@ -684,6 +1236,21 @@ impl Parser for ParserImpl {
     ) -> Option<GitlabElement> {
         self.treesitter.get_root_node_at_position(content, position)
     }
+
+    fn job_key_definition(
+        &self,
+        uri: &str,
+        content: &str,
+        job_name: &str,
+        path: &[String],
+    ) -> Option<GitlabElement> {
+        self.treesitter
+            .job_key_definition(uri, content, job_name, path)
+    }
+
+    fn get_key_path(&self, content: &str, position: Position) -> Option<(String, Vec<String>)> {
+        self.treesitter.get_key_path(content, position)
+    }
 }
 
 #[cfg(test)]
@@ -699,7 +1266,9 @@ mod tests {
         let parser = ParserImpl::new(
             vec![],
             HashMap::new(),
+            HashMap::new(),
             String::new(),
+            None,
             Box::new(TreesitterImpl::new()),
             Box::new(MockFSUtils::new()),
         );
@@ -879,7 +1448,9 @@ mod tests {
         let parser = ParserImpl::new(
             vec![],
             HashMap::new(),
+            HashMap::new(),
             String::new(),
+            None,
             Box::new(TreesitterImpl::new()),
             Box::new(MockFSUtils::new()),
         );
@@ -1013,4 +1584,176 @@ mod tests {
 
         assert_eq!(full_definition.unwrap(), want);
     }
+
+    #[test]
+    fn test_parse_contents_include_alias() {
+        let root_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            root_dir.path().join("build.yml"),
+            "build:\n  script: make\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root_dir.path().join("test.yml"),
+            "test:\n  script: make test\n",
+        )
+        .unwrap();
+
+        let parser = ParserImpl::new(
+            vec![],
+            HashMap::new(),
+            HashMap::new(),
+            String::new(),
+            None,
+            Box::new(TreesitterImpl::new()),
+            Box::new(MockFSUtils::new()),
+        );
+
+        let content = r"
+.common_includes: &common_includes
+  - local: build.yml
+  - test.yml
+
+include: *common_includes
+";
+        let uri = Url::parse(&format!(
+            "file://{}/.gitlab-ci.yml",
+            root_dir.path().to_string_lossy()
+        ))
+        .unwrap();
+
+        let results = parser.parse_contents(&uri, content, true).unwrap();
+
+        let nodes: Vec<&str> = results.nodes.iter().map(|n| n.key.as_str()).collect();
+        assert!(nodes.contains(&"build"));
+        assert!(nodes.contains(&"test"));
+    }
+
+    #[test]
+    fn test_parse_contents_remote_local_include() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let repo_dest = cache_dir.path().join("group/project/1.0.0");
+        std::fs::create_dir_all(repo_dest.join("ci/nested")).unwrap();
+        std::fs::create_dir_all(repo_dest.join("templates")).unwrap();
+        std::fs::write(
+            repo_dest.join("ci/nested/remote.yml"),
+            "include:\n  - local: /templates/common.yml\n  - templates/other.yml\n\nremote_job:\n  script: echo\n",
+        )
+        .unwrap();
+        std::fs::write(
+            repo_dest.join("templates/common.yml"),
+            "common_job:\n  script: echo\n",
+        )
+        .unwrap();
+        std::fs::write(
+            repo_dest.join("templates/other.yml"),
+            "other_job:\n  script: echo\n",
+        )
+        .unwrap();
+
+        let mut fs_utils = MockFSUtils::new();
+        fs_utils.expect_create_dir_all().returning(|_| Ok(()));
+
+        let parser = ParserImpl::new(
+            vec![],
+            HashMap::new(),
+            HashMap::new(),
+            format!("{}/", cache_dir.path().to_string_lossy()),
+            None,
+            Box::new(TreesitterImpl::new()),
+            Box::new(fs_utils),
+        );
+
+        let content = r"
+include:
+  - project: group/project
+    ref: 1.0.0
+    file: ci/nested/remote.yml
+";
+        let uri = Url::parse("file:///tmp/.gitlab-ci.yml").unwrap();
+
+        let results = parser.parse_contents(&uri, content, true).unwrap();
+
+        let nodes: Vec<&str> = results.nodes.iter().map(|n| n.key.as_str()).collect();
+        // local includes of project files are relative to project root
+        assert!(nodes.contains(&"remote_job"));
+        assert!(nodes.contains(&"common_job"));
+        assert!(nodes.contains(&"other_job"));
+    }
+
+    #[test]
+    fn test_parse_contents_include_rules_and_inputs() {
+        let root_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            root_dir.path().join("build.yml"),
+            "spec:\n  inputs:\n    stage:\n---\nbuild:\n  stage: $[[ inputs.stage ]]\n  script: make\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root_dir.path().join("test.yml"),
+            "test:\n  script: make test\n",
+        )
+        .unwrap();
+
+        let mirror_dir = tempfile::tempdir().unwrap();
+        let templates = mirror_dir.path().join("group/components/templates");
+        std::fs::create_dir_all(&templates).unwrap();
+        std::fs::write(
+            templates.join("lint.yml"),
+            "spec:\n  inputs:\n    image:\n      default: alpine\n---\nlint:\n  script: lint\n",
+        )
+        .unwrap();
+        git::commit_all(&mirror_dir.path().join("group/components"), Some("1.0.0"));
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        let parser = ParserImpl::new(
+            vec![],
+            HashMap::new(),
+            HashMap::from([(
+                "gitlab.example.com".to_string(),
+                mirror_dir.path().to_string_lossy().to_string(),
+            )]),
+            format!("{}/", cache_dir.path().to_string_lossy()),
+            None,
+            Box::new(TreesitterImpl::new()),
+            Box::new(MockFSUtils::new()),
+        );
+
+        // rules aren't evaluated so every included file is indexed
+        let content = r#"
+include:
+  - component: gitlab.example.com/group/components/lint@1.0.0
+    rules:
+      - if: $CI_PIPELINE_SOURCE == "merge_request_event"
+  - local: build.yml
+    inputs:
+      stage: build
+      targets:
+        - linux
+        - windows
+    rules:
+      - if: $CI_COMMIT_BRANCH == "main"
+  - local: test.yml
+    rules:
+      - exists:
+          - Makefile
+        when: never
+"#;
+        let uri = Url::parse(&format!(
+            "file://{}/.gitlab-ci.yml",
+            root_dir.path().to_string_lossy()
+        ))
+        .unwrap();
+
+        let results = parser.parse_contents(&uri, content, true).unwrap();
+
+        let nodes: Vec<&str> = results.nodes.iter().map(|n| n.key.as_str()).collect();
+        assert!(nodes.contains(&"build"));
+        assert!(nodes.contains(&"test"));
+        assert_eq!(results.components.len(), 1);
+        assert_eq!(
+            results.components[0].uri,
+            "gitlab.example.com/group/components/lint@1.0.0"
+        );
+    }
 }
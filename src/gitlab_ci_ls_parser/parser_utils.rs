@@ -1,6 +1,22 @@
+use std::{collections::HashMap, sync::LazyLock};
+
 use log::info;
+use regex::Regex;
+use tree_sitter::{InputEdit, Point};
+
+use super::{keywords, GitlabElement};
+
+// `$VAR == "value"` comparison, with variable on either side.
+static EQUALITY_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"^\$\{?(\w+)\}?\s*==\s*(?:"([^"]*)"|'([^']*)')$|^(?:"([^"]*)"|'([^']*)')\s*==\s*\$\{?(\w+)\}?$"#,
+    )
+    .unwrap()
+});
 
-use super::GitlabElement;
+// `$[[ inputs.name ]]` interpolation, optionally followed by functions.
+static INPUT_INTERPOLATION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$\[\[\s*inputs\.([\w-]+)[^\]]*\]\]").unwrap());
 
 pub struct ParserUtils {}
 
@@ -72,6 +88,182 @@ impl ParserUtils {
         &line[start..end]
     }
 
+    // Byte range of quoted value content surrounding the cursor, if any.
+    fn quoted_range(line: &str, char_index: usize) -> Option<(usize, usize)> {
+        let before = &line[..char_index];
+        let (start, quote) = ['"', '\'']
+            .iter()
+            .filter(|&&q| before.matches(q).count() % 2 == 1)
+            .filter_map(|&q| before.rfind(q).map(|idx| (idx, q)))
+            .max_by_key(|&(idx, _)| idx)?;
+
+        let end = line[char_index..]
+            .find(quote)
+            .map_or(line.len(), |idx| idx + char_index);
+
+        Some((start + 1, end))
+    }
+
+    // Same as extract_word but keeps quoted values containing spaces or colons
+    // together, e.g. ".my template".
+    pub fn extract_key(line: &str, char_index: usize) -> Option<&str> {
+        if char_index >= line.len() {
+            return None;
+        }
+
+        match ParserUtils::quoted_range(line, char_index) {
+            Some((start, end)) => Some(&line[start..end]),
+            None => ParserUtils::extract_word(line, char_index),
+        }
+    }
+
+    // Returns the word before and after cursor which is going to be replaced by
+    // completion. When the cursor is inside a quoted value the whole quoted
+    // content is used so values containing spaces or colons are replaced fully.
+    pub fn completion_word(line: &str, char_index: usize) -> (&str, &str) {
+        let is_whitespace = |c: char| c.is_whitespace();
+        if char_index > line.len() {
+            return ("", "");
+        }
+
+        match ParserUtils::quoted_range(line, char_index) {
+            Some((start, end)) => (&line[start..char_index], &line[char_index..end]),
+            None => (
+                ParserUtils::word_before_cursor(line, char_index, is_whitespace),
+                ParserUtils::word_after_cursor(line, char_index, is_whitespace),
+            ),
+        }
+    }
+
+    // Extracts host from git remote origins used for cloning, e.g.
+    // `git@gitlab.com:`, `ssh://git@gitlab.com:4242/` or `https://gitlab.com/`.
+    pub fn remote_host(origin: &str) -> Option<&str> {
+        ParserUtils::split_remote(origin).map(|(host, _)| host)
+    }
+
+    // Splits remote into host and path, dropping scheme, user and port, e.g.
+    // `https://gitlab.com:8443/group/project` into `gitlab.com` and
+    // `group/project`.
+    fn split_remote(origin: &str) -> Option<(&str, &str)> {
+        let origin = origin
+            .trim_start_matches("ssh://")
+            .trim_start_matches("https://")
+            .trim_start_matches("http://");
+        let origin = match origin.split_once('@') {
+            Some((user, host)) if !user.contains([':', '/']) => host,
+            _ => origin,
+        };
+
+        let (host, path) = origin.split_once([':', '/']).unwrap_or((origin, ""));
+        let path = match path.split_once('/') {
+            Some((port, path))
+                if origin[host.len()..].starts_with(':')
+                    && !port.is_empty()
+                    && port.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                path
+            }
+            _ => path,
+        };
+
+        (!host.is_empty()).then_some((host, path))
+    }
+
+    // Docker platforms are written as `os/arch` with an optional variant,
+    // e.g. `linux/arm/v7`.
+    pub fn is_docker_platform(value: &str) -> bool {
+        let parts: Vec<&str> = value.split('/').collect();
+
+        (2..=3).contains(&parts.len())
+            && parts.iter().all(|part| {
+                !part.is_empty()
+                    && part
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            })
+    }
+
+    // Docker user is a name or UID with an optional group, e.g. `dave`,
+    // `1000` or `1000:1000`.
+    pub fn is_docker_user(value: &str) -> bool {
+        let (user, group) = match value.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (value, None),
+        };
+
+        [Some(user), group].into_iter().flatten().all(|part| {
+            !part.is_empty()
+                && !part.starts_with('-')
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        })
+    }
+
+    // Checks if mapping contains one of the given keys, written either as
+    // block or flow mapping, e.g. secret providers.
+    pub fn has_any_key(definition: &str, keys: &[&str]) -> bool {
+        definition
+            .split(|c: char| c.is_whitespace() || c == '{' || c == ',')
+            .filter_map(|token| token.split_once(':').map(|(key, _)| key))
+            .any(|key| keys.contains(&key))
+    }
+
+    // Converts LSP position to byte offset in content. Characters are counted
+    // in UTF-16 code units and positions past line end point to line end.
+    pub fn position_offset(content: &str, position: lsp_types::Position) -> Option<usize> {
+        let mut line_start = 0;
+        for _ in 0..position.line {
+            line_start += content[line_start..].find('\n')? + 1;
+        }
+
+        let line = &content[line_start..];
+        let line = &line[..line.find('\n').unwrap_or(line.len())];
+
+        let mut units = 0;
+        for (idx, c) in line.char_indices() {
+            if units >= position.character as usize {
+                return Some(line_start + idx);
+            }
+            units += c.len_utf16();
+        }
+
+        Some(line_start + line.len())
+    }
+
+    // Describes replacement of `content[start..end]` with `text` for
+    // tree-sitter so cached tree can be reused when parsing edited content.
+    pub fn input_edit(content: &str, start: usize, end: usize, text: &str) -> InputEdit {
+        let point = |text: &str, offset: usize| {
+            let before = &text[..offset];
+            Point {
+                row: before.matches('\n').count(),
+                column: offset - before.rfind('\n').map_or(0, |idx| idx + 1),
+            }
+        };
+
+        let start_position = point(content, start);
+        let new_end_position = match text.rfind('\n') {
+            Some(idx) => Point {
+                row: start_position.row + text.matches('\n').count(),
+                column: text.len() - idx - 1,
+            },
+            None => Point {
+                row: start_position.row,
+                column: start_position.column + text.len(),
+            },
+        };
+
+        InputEdit {
+            start_byte: start,
+            old_end_byte: end,
+            new_end_byte: start + text.len(),
+            start_position,
+            old_end_position: point(content, end),
+            new_end_position,
+        }
+    }
+
     pub fn remote_path_to_hash(uri: &str) -> String {
         crc64::crc64(0, uri.as_bytes()).to_string()
     }
@@ -172,20 +364,34 @@ impl ParserUtils {
                 Err(err) => {
                     info!("could not find component; path: {p}, got err: {err}");
                 }
-            };
+            }
         }
         Err(anyhow::anyhow!("could not find component"))
     }
 
+    // Project can be nested in any number of subgroups, e.g.
+    // `host/group/subgroup/project/component@version`. Host can also be
+    // written the same way as git remotes, e.g. `git@host:group/...`,
+    // `ssh://git@host:4242/group/...` or `https://host:8443/group/...`, in
+    // which case only the host name is kept.
     pub fn extract_component_from_uri(uri: &str) -> anyhow::Result<ComponentInfo> {
-        let mut component_parts = uri.split('/').collect::<Vec<&str>>();
-        if component_parts.len() < 2 {
+        let Some((host, path)) = ParserUtils::split_remote(uri) else {
+            return Err(anyhow::anyhow!(
+                "invalid component uri structure; got: {uri}"
+            ));
+        };
+
+        let mut component_parts = path.split('/').collect::<Vec<&str>>();
+        if component_parts.len() < 2
+            || component_parts
+                .iter()
+                .any(|p| p.is_empty() || p.contains(char::is_whitespace))
+        {
             return Err(anyhow::anyhow!(
                 "invalid component uri structure; got: {uri}"
             ));
         }
 
-        let host = component_parts.remove(0);
         let Some(component) = component_parts.pop() else {
             return Err(anyhow::anyhow!(
                 "could not get last element from component uri"
@@ -206,6 +412,379 @@ impl ParserUtils {
             version: component_identificator[1].to_string(),
         })
     }
+
+    // Checks if index is inside regex literal of `=~` or `!~` expression,
+    // e.g. `$CI_COMMIT_TAG =~ /^v$/`.
+    pub fn is_inside_regex(line: &str, char_index: usize) -> bool {
+        let Some(before) = line.get(..char_index) else {
+            return false;
+        };
+
+        let Some(operator) = [before.rfind("=~"), before.rfind("!~")]
+            .into_iter()
+            .flatten()
+            .max()
+        else {
+            return false;
+        };
+
+        let pattern = before[operator + 2..].trim_start();
+        let Some(pattern) = pattern.strip_prefix('/') else {
+            return false;
+        };
+
+        // regex is closed by first unescaped slash
+        let mut escaped = false;
+        for c in pattern.chars() {
+            match c {
+                '\\' if !escaped => escaped = true,
+                '/' if !escaped => return false,
+                _ => escaped = false,
+            }
+        }
+
+        true
+    }
+
+    // Extracts logical value of scalar spanning multiple rows. Rows of block
+    // scalars (`|`, `>`) and quoted or plain scalars are folded into a single
+    // line as job names can't contain line breaks.
+    pub fn multiline_scalar_value(text: &str) -> String {
+        let mut lines = text.lines().peekable();
+        if lines
+            .peek()
+            .is_some_and(|header| header.starts_with('|') || header.starts_with('>'))
+        {
+            lines.next();
+        }
+
+        let mut value = String::new();
+        for line in lines.map(str::trim).filter(|line| !line.is_empty()) {
+            // escaped line break in double quoted scalar joins rows directly
+            if let Some(stripped) = value.strip_suffix('\\') {
+                value = stripped.to_string();
+            } else if !value.is_empty() {
+                value.push(' ');
+            }
+
+            value.push_str(line);
+        }
+
+        ParserUtils::strip_quotes(&value).to_string()
+    }
+
+    // Resolves symlinks so files are stored under a single path no matter
+    // how they were reached. Non-file or missing paths are returned as is.
+    pub fn canonical_uri(uri: &lsp_types::Url) -> lsp_types::Url {
+        uri.to_file_path()
+            .ok()
+            .and_then(|path| std::fs::canonicalize(path).ok())
+            .and_then(|path| lsp_types::Url::from_file_path(path).ok())
+            .unwrap_or_else(|| uri.clone())
+    }
+
+    // Returns input name of `$[[ inputs.name ]]` interpolation under cursor.
+    // Interpolations can also use functions, e.g. `$[[ inputs.name | truncate(0,8) ]]`.
+    pub fn input_interpolation(line: &str, char_index: usize) -> Option<&str> {
+        let name = INPUT_INTERPOLATION_RE
+            .captures_iter(line)
+            .find(|c| {
+                c.get(0)
+                    .is_some_and(|m| (m.start()..=m.end()).contains(&char_index))
+            })
+            .and_then(|c| c.get(1))
+            .map(|name| name.as_str());
+
+        name
+    }
+
+    // Components can be referenced relative to the current instance, e.g.
+    // `$CI_SERVER_FQDN/group/project/component@1.0.0`.
+    pub fn is_instance_host(host: &str) -> bool {
+        matches!(
+            host.trim_start_matches('$')
+                .trim_start_matches('{')
+                .trim_end_matches('}'),
+            "CI_SERVER_FQDN" | "CI_SERVER_HOST"
+        )
+    }
+
+    // Checks if job definition is always skipped, which is the case when its
+    // only rule is `when: never`. Any other rule could match so such jobs are
+    // treated as runnable.
+    pub fn is_never_run(definition: &str) -> bool {
+        let Ok(serde_yaml::Value::Mapping(root)) = serde_yaml::from_str(definition) else {
+            return false;
+        };
+
+        let Some(serde_yaml::Value::Sequence(rules)) =
+            root.values().next().and_then(|job| job.get("rules"))
+        else {
+            return false;
+        };
+
+        match rules.as_slice() {
+            [serde_yaml::Value::Mapping(rule)] => {
+                rule.len() == 1 && rule.get("when").and_then(|w| w.as_str()) == Some("never")
+            }
+            _ => false,
+        }
+    }
+
+    // Resolves `!reference` path inside node definition, e.g. `[script]`,
+    // and returns referenced key with its value.
+    pub fn reference_content(definition: &str, path: &[String]) -> Option<String> {
+        let Ok(serde_yaml::Value::Mapping(root)) = serde_yaml::from_str(definition) else {
+            return None;
+        };
+
+        let mut value = root.values().next()?;
+        for key in path {
+            value = value.get(key.as_str())?;
+        }
+
+        let mut referenced = serde_yaml::Mapping::new();
+        referenced.insert(path.last()?.as_str().into(), value.clone());
+
+        serde_yaml::to_string(&referenced).ok()
+    }
+
+    // Replaces `!reference [.node, key, ...]` tags inside value with values
+    // they point to in `definitions`, keyed by node name. Referenced lists
+    // inside lists are inlined like GitLab does for e.g. `script`.
+    // References which can't be resolved are kept as they are.
+    pub fn resolve_references(value: &mut serde_yaml::Value, definitions: &serde_yaml::Mapping) {
+        // same nesting limit as GitLab has
+        const MAX_DEPTH: usize = 10;
+
+        fn referenced(
+            value: &serde_yaml::Value,
+            definitions: &serde_yaml::Mapping,
+        ) -> Option<serde_yaml::Value> {
+            let serde_yaml::Value::Tagged(tagged) = value else {
+                return None;
+            };
+            if tagged.tag != "reference" {
+                return None;
+            }
+
+            let mut path = tagged.value.as_sequence()?.iter();
+            let mut referenced = definitions.get(path.next()?)?;
+            for key in path {
+                referenced = referenced.get(key)?;
+            }
+
+            Some(referenced.clone())
+        }
+
+        fn resolve(value: &mut serde_yaml::Value, definitions: &serde_yaml::Mapping, depth: usize) {
+            if depth > MAX_DEPTH {
+                return;
+            }
+
+            match value {
+                serde_yaml::Value::Sequence(items) => {
+                    for mut item in std::mem::take(items) {
+                        let Some(mut referenced) = referenced(&item, definitions) else {
+                            resolve(&mut item, definitions, depth);
+                            items.push(item);
+                            continue;
+                        };
+
+                        resolve(&mut referenced, definitions, depth + 1);
+                        match referenced {
+                            serde_yaml::Value::Sequence(referenced) => items.extend(referenced),
+                            referenced => items.push(referenced),
+                        }
+                    }
+                }
+                serde_yaml::Value::Mapping(mapping) => {
+                    for item in mapping.values_mut() {
+                        resolve(item, definitions, depth);
+                    }
+                }
+                serde_yaml::Value::Tagged(_) => {
+                    if let Some(mut referenced) = referenced(value, definitions) {
+                        resolve(&mut referenced, definitions, depth + 1);
+                        *value = referenced;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        resolve(value, definitions, 0);
+    }
+
+    // Byte offsets of `variable` name in its `$variable` and `${variable}`
+    // usages inside `text`.
+    pub fn variable_offsets(text: &str, variable: &str) -> Vec<usize> {
+        if variable.is_empty() {
+            return vec![];
+        }
+
+        text.match_indices('$')
+            .filter_map(|(index, _)| {
+                let after = &text[index + 1..];
+                if let Some(braced) = after.strip_prefix('{') {
+                    braced
+                        .strip_prefix(variable)
+                        .is_some_and(|rest| rest.starts_with('}'))
+                        .then_some(index + 2)
+                } else {
+                    after
+                        .strip_prefix(variable)
+                        .is_some_and(|rest| {
+                            !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+                        })
+                        .then_some(index + 1)
+                }
+            })
+            .collect()
+    }
+
+    // Formats seconds since unix epoch as UTC date and time, e.g.
+    // `2024-05-01 13:45 UTC`.
+    pub fn format_unix_timestamp(secs: u64) -> String {
+        let days = i64::try_from(secs / 86_400).unwrap_or_default();
+        let time = secs % 86_400;
+
+        // civil date from days since epoch
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        format!(
+            "{year:04}-{month:02}-{day:02} {:02}:{:02} UTC",
+            time / 3_600,
+            time % 3_600 / 60
+        )
+    }
+
+    // Checks if `needs:` entry written in mapping form, e.g. `job: build`
+    // followed by `optional: true`, is optional.
+    pub fn is_optional_need(definition: &str) -> bool {
+        definition.lines().any(|line| {
+            line.trim()
+                .strip_prefix("optional:")
+                .is_some_and(|value| value.trim() == "true")
+        })
+    }
+
+    // Checks if root node definition looks like a job, meaning it defines
+    // something to execute.
+    pub fn is_job_definition(definition: &str) -> bool {
+        let Ok(serde_yaml::Value::Mapping(root)) = serde_yaml::from_str(definition) else {
+            return false;
+        };
+
+        let Some(serde_yaml::Value::Mapping(job)) = root.values().next() else {
+            return false;
+        };
+
+        keywords::JOB_RUN_KEYWORDS
+            .iter()
+            .any(|keyword| job.contains_key(*keyword))
+    }
+
+    // Returns the node whose variables are used when `variables` block of the
+    // given job, or the root `variables` node itself, is written as
+    // `!reference [.vars, variables]`.
+    pub fn variables_reference(definition: &str) -> Option<String> {
+        let Ok(serde_yaml::Value::Mapping(root)) = serde_yaml::from_str(definition) else {
+            return None;
+        };
+
+        let (key, value) = root.iter().next()?;
+        let variables = if key.as_str() == Some("variables") {
+            value
+        } else {
+            value.get("variables")?
+        };
+
+        let serde_yaml::Value::Tagged(tagged) = variables else {
+            return None;
+        };
+
+        if tagged.tag != "reference" {
+            return None;
+        }
+
+        match tagged.value.as_sequence()?.as_slice() {
+            [serde_yaml::Value::String(node), serde_yaml::Value::String(keyword)]
+                if keyword == "variables" =>
+            {
+                Some(node.clone())
+            }
+            _ => None,
+        }
+    }
+
+    // Jobs using `parallel:matrix` are expanded into jobs named like
+    // `build: [linux, arm64]`. Returns the base job name for such names.
+    pub fn matrix_job_name(name: &str) -> Option<&str> {
+        let name = ParserUtils::strip_quotes(name);
+        if !name.ends_with(']') {
+            return None;
+        }
+
+        let (job, _) = name.split_once(" [")?;
+        Some(job.trim_end_matches(':'))
+    }
+
+    // Detects trivially unsatisfiable `rules:if` expressions such as
+    // `$X == "a" && $X == "b"`. Expressions with parentheses are skipped and
+    // only string equalities are compared to avoid false positives. Returns the
+    // variable that causes the contradiction.
+    pub fn contradictory_condition(expression: &str) -> Option<String> {
+        let expression = expression.trim();
+        let expression = match expression.chars().next() {
+            Some(q @ ('\'' | '"')) if expression.len() > 1 && expression.ends_with(q) => {
+                &expression[1..expression.len() - 1]
+            }
+            _ => expression,
+        };
+
+        if expression.contains(['(', ')']) {
+            return None;
+        }
+
+        let mut contradiction = None;
+        for disjunct in expression.split("||") {
+            let mut values: HashMap<&str, &str> = HashMap::new();
+            let mut conflicting = None;
+
+            for conjunct in disjunct.split("&&") {
+                let Some(captures) = EQUALITY_RE.captures(conjunct.trim()) else {
+                    continue;
+                };
+
+                let variable = captures.get(1).or(captures.get(6))?.as_str();
+                let value = [2, 3, 4, 5].iter().find_map(|&i| captures.get(i))?.as_str();
+
+                match values.get(variable) {
+                    Some(existing) if *existing != value => {
+                        conflicting = Some(variable.to_string());
+                        break;
+                    }
+                    _ => {
+                        values.insert(variable, value);
+                    }
+                }
+            }
+
+            // every alternative has to be unsatisfiable for the rule to never match
+            contradiction.get_or_insert(conflicting?);
+        }
+
+        contradiction
+    }
 }
 
 #[cfg(test)]
@@ -232,6 +811,79 @@ mod tests {
         assert_eq!(got, want);
     }
 
+    #[test]
+    fn test_extract_component_from_nested_subgroups() {
+        let got = ParserUtils::extract_component_from_uri(
+            "gitlab.com/group/subgroup/nested/project/component@2.1.0",
+        )
+        .unwrap();
+
+        assert_eq!(got.host, "gitlab.com");
+        assert_eq!(got.project, "group/subgroup/nested/project");
+        assert_eq!(got.component, "component");
+        assert_eq!(got.version, "2.1.0");
+        assert_eq!(
+            ParserUtils::get_component_dest_dir("/cache/", &got),
+            "/cache/components/group/subgroup/nested/project/2.1.0"
+        );
+
+        assert!(ParserUtils::extract_component_from_uri("gitlab.com/component@1.0.0").is_err());
+        assert!(
+            ParserUtils::extract_component_from_uri("gitlab.com/group//component@1.0.0").is_err()
+        );
+    }
+
+    #[test]
+    fn test_extract_component_from_ssh_uri() {
+        for uri in [
+            "git@gitlab.example.com:group/project/component@1.0.0",
+            "ssh://git@gitlab.example.com:4242/group/project/component@1.0.0",
+        ] {
+            let got = ParserUtils::extract_component_from_uri(uri).unwrap();
+
+            assert_eq!(got.host, "gitlab.example.com", "invalid host of {uri}");
+            assert_eq!(got.project, "group/project", "invalid project of {uri}");
+            assert_eq!(got.component, "component");
+            assert_eq!(got.version, "1.0.0");
+        }
+    }
+
+    #[test]
+    fn test_extract_component_from_uri_with_port() {
+        for uri in [
+            "https://gitlab.example.com:8443/group/project/component@1.0.0",
+            "gitlab.example.com:8443/group/project/component@1.0.0",
+        ] {
+            let got = ParserUtils::extract_component_from_uri(uri).unwrap();
+
+            assert_eq!(got.host, "gitlab.example.com", "invalid host of {uri}");
+            assert_eq!(got.project, "group/project", "invalid project of {uri}");
+        }
+    }
+
+    #[test]
+    fn test_is_inside_regex() {
+        let line = r"    - if: $CI_COMMIT_TAG =~ /^v\/$/ && $CI_COMMIT_BRANCH";
+
+        assert!(!ParserUtils::is_inside_regex(line, 18));
+        assert!(ParserUtils::is_inside_regex(line, 30));
+        assert!(ParserUtils::is_inside_regex(line, 33));
+        assert!(!ParserUtils::is_inside_regex(line, 35));
+        assert!(!ParserUtils::is_inside_regex(line, line.len()));
+    }
+
+    #[test]
+    fn test_extract_component_from_instance_relative_uri() {
+        let got =
+            ParserUtils::extract_component_from_uri("$CI_SERVER_FQDN/some-project/component@1.0.0")
+                .unwrap();
+
+        assert!(ParserUtils::is_instance_host(&got.host));
+        assert!(ParserUtils::is_instance_host("${CI_SERVER_HOST}"));
+        assert!(!ParserUtils::is_instance_host("gitlab.com"));
+        assert_eq!(got.project, "some-project");
+    }
+
     #[test]
     fn test_find_path_at_cursor() {
         let line = "/test/please/here";
@@ -249,4 +901,346 @@ mod tests {
         assert_eq!(path, "h");
         assert_eq!(parent, "/test/please");
     }
+
+    #[test]
+    fn test_contradictory_condition() {
+        assert_eq!(
+            ParserUtils::contradictory_condition(r#"'$X == "a" && $X == "b"'"#),
+            Some("X".to_string())
+        );
+        assert_eq!(
+            ParserUtils::contradictory_condition(
+                r#"$CI_COMMIT_BRANCH == "main" && "dev" == ${CI_COMMIT_BRANCH}"#
+            ),
+            Some("CI_COMMIT_BRANCH".to_string())
+        );
+        assert_eq!(
+            ParserUtils::contradictory_condition(r#"$X == "a" && $X == "a""#),
+            None
+        );
+        assert_eq!(
+            ParserUtils::contradictory_condition(r#"$X == "a" && $X == "b" || $Y == "c""#),
+            None
+        );
+        assert_eq!(
+            ParserUtils::contradictory_condition(r#"($X == "a" && $X == "b")"#),
+            None
+        );
+        assert_eq!(
+            ParserUtils::contradictory_condition(r#"$X == "a" && $X != "b""#),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_never_run() {
+        assert!(ParserUtils::is_never_run(
+            "job:\n  script: echo\n  rules:\n    - when: never\n"
+        ));
+        assert!(!ParserUtils::is_never_run(
+            "job:\n  rules:\n    - if: $CI\n      when: never\n"
+        ));
+        assert!(!ParserUtils::is_never_run(
+            "job:\n  rules:\n    - when: never\n    - when: always\n"
+        ));
+        assert!(!ParserUtils::is_never_run("job:\n  script: echo\n"));
+    }
+
+    #[test]
+    fn test_matrix_job_name() {
+        assert_eq!(
+            ParserUtils::matrix_job_name(r#""build: [linux, arm64]""#),
+            Some("build")
+        );
+        assert_eq!(
+            ParserUtils::matrix_job_name("build job [linux]"),
+            Some("build job")
+        );
+        assert_eq!(ParserUtils::matrix_job_name("build"), None);
+    }
+
+    #[test]
+    fn test_reference_path() {
+        let definition = ".vars:\n  script:\n    - echo setup\n  variables:\n    URL: example.com\n    PORT: 80\n";
+        let path = |keys: &[&str]| keys.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        assert_eq!(
+            ParserUtils::reference_content(definition, &path(&["variables", "PORT"])),
+            Some("PORT: 80\n".to_string())
+        );
+        assert_eq!(
+            ParserUtils::reference_content(definition, &path(&["script"])),
+            Some("script:\n- echo setup\n".to_string())
+        );
+        assert_eq!(
+            ParserUtils::reference_content(definition, &path(&["rules"])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_resolve_references() {
+        let definitions: serde_yaml::Mapping = serde_yaml::from_str(
+            r"
+.setup:
+  script:
+    - echo setup
+.vars:
+  variables:
+    URL: !reference [.urls, main]
+.urls:
+  main: example.com
+",
+        )
+        .unwrap();
+        let mut job: serde_yaml::Value = serde_yaml::from_str(
+            r"
+script:
+  - !reference [.setup, script]
+  - make
+variables: !reference [.vars, variables]
+after_script: !reference [.missing, script]
+",
+        )
+        .unwrap();
+
+        ParserUtils::resolve_references(&mut job, &definitions);
+
+        let expected: serde_yaml::Value = serde_yaml::from_str(
+            r"
+script:
+  - echo setup
+  - make
+variables:
+  URL: example.com
+after_script: !reference [.missing, script]
+",
+        )
+        .unwrap();
+        assert_eq!(job, expected);
+    }
+
+    #[test]
+    fn test_variable_offsets() {
+        assert_eq!(
+            ParserUtils::variable_offsets("$URL/${URL}/$URL_PATH/${URL_PATH}", "URL"),
+            vec![1, 7]
+        );
+        assert!(ParserUtils::variable_offsets("URL", "URL").is_empty());
+    }
+
+    #[test]
+    fn test_input_edit() {
+        let content = "job:\n  stage: build\n";
+
+        let edit = ParserUtils::input_edit(content, 14, 19, "test\n  script: make");
+        assert_eq!(edit.start_position, Point { row: 1, column: 9 });
+        assert_eq!(edit.old_end_position, Point { row: 1, column: 14 });
+        assert_eq!(edit.new_end_position, Point { row: 2, column: 14 });
+        assert_eq!(edit.new_end_byte, 14 + 19);
+
+        let edit = ParserUtils::input_edit(content, 0, 3, "build");
+        assert_eq!(edit.old_end_position, Point { row: 0, column: 3 });
+        assert_eq!(edit.new_end_position, Point { row: 0, column: 5 });
+    }
+
+    #[test]
+    fn test_format_unix_timestamp() {
+        assert_eq!(
+            ParserUtils::format_unix_timestamp(0),
+            "1970-01-01 00:00 UTC"
+        );
+        assert_eq!(
+            ParserUtils::format_unix_timestamp(1_709_210_700),
+            "2024-02-29 12:45 UTC"
+        );
+    }
+
+    #[test]
+    fn test_variables_reference() {
+        assert_eq!(
+            ParserUtils::variables_reference(
+                "build:\n  variables: !reference [.vars, variables]\n"
+            ),
+            Some(".vars".to_string())
+        );
+        assert_eq!(
+            ParserUtils::variables_reference("variables: !reference ['.vars', 'variables']\n"),
+            Some(".vars".to_string())
+        );
+        assert_eq!(
+            ParserUtils::variables_reference("build:\n  script: !reference [.vars, script]\n"),
+            None
+        );
+        assert_eq!(
+            ParserUtils::variables_reference("build:\n  variables:\n    A: b\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_completion_word() {
+        let line = r#"  extends: ".my tem""#;
+        assert_eq!(ParserUtils::completion_word(line, 19), (".my tem", ""));
+
+        let line = r"    - job: 'build: [lin'";
+        assert_eq!(ParserUtils::completion_word(line, 20), ("build: [", "lin"));
+
+        let line = "  extends: .my";
+        assert_eq!(ParserUtils::completion_word(line, 14), (".my", ""));
+    }
+
+    #[test]
+    fn test_extract_key() {
+        let line = r#"".my template":"#;
+        assert_eq!(ParserUtils::extract_key(line, 5), Some(".my template"));
+
+        let line = "  extends: .my_template";
+        assert_eq!(ParserUtils::extract_key(line, 14), Some(".my_template"));
+    }
+
+    #[test]
+    fn test_remote_host() {
+        assert_eq!(
+            ParserUtils::remote_host("git@gitlab.com:"),
+            Some("gitlab.com")
+        );
+        assert_eq!(
+            ParserUtils::remote_host("ssh://git@gitlab.instance.com:4242/"),
+            Some("gitlab.instance.com")
+        );
+        assert_eq!(
+            ParserUtils::remote_host("https://gitlab.com/"),
+            Some("gitlab.com")
+        );
+        assert_eq!(ParserUtils::remote_host(""), None);
+    }
+
+    #[test]
+    fn test_has_any_key() {
+        let providers = ["vault", "akeyless"];
+        assert!(ParserUtils::has_any_key(
+            "vault: db/password@ops\n      file: false",
+            &providers
+        ));
+        assert!(ParserUtils::has_any_key(
+            "{ akeyless: { name: token } }",
+            &providers
+        ));
+        assert!(!ParserUtils::has_any_key("file: false", &providers));
+        assert!(!ParserUtils::has_any_key("", &providers));
+    }
+
+    #[test]
+    fn test_position_offset() {
+        let content = "job:\n  script: echo 🚀 done\n";
+
+        assert_eq!(
+            ParserUtils::position_offset(content, lsp_types::Position::new(1, 2)),
+            Some(7)
+        );
+        // emoji takes two UTF-16 code units and four bytes
+        assert_eq!(
+            ParserUtils::position_offset(content, lsp_types::Position::new(1, 17)),
+            Some(24)
+        );
+        assert_eq!(
+            ParserUtils::position_offset(content, lsp_types::Position::new(1, 100)),
+            Some(29)
+        );
+        assert_eq!(
+            ParserUtils::position_offset(content, lsp_types::Position::new(2, 0)),
+            Some(30)
+        );
+        assert_eq!(
+            ParserUtils::position_offset(content, lsp_types::Position::new(3, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_multiline_scalar_value() {
+        assert_eq!(
+            ParserUtils::multiline_scalar_value(">-\n    .first\n"),
+            ".first"
+        );
+        assert_eq!(
+            ParserUtils::multiline_scalar_value("|\n  .first\n\n"),
+            ".first"
+        );
+        assert_eq!(
+            ParserUtils::multiline_scalar_value("\".very\n    long\""),
+            ".very long"
+        );
+        assert_eq!(
+            ParserUtils::multiline_scalar_value("\".very\\\n    -long\""),
+            ".very-long"
+        );
+    }
+
+    #[test]
+    fn test_is_job_definition() {
+        assert!(ParserUtils::is_job_definition(
+            "variables:\n  stage: test\n  script: make"
+        ));
+        assert!(ParserUtils::is_job_definition(
+            "deploy:\n  trigger: group/project"
+        ));
+        assert!(!ParserUtils::is_job_definition(
+            "variables:\n  SCRIPT: make"
+        ));
+        assert!(!ParserUtils::is_job_definition("stages:\n  - build"));
+    }
+
+    #[test]
+    fn test_input_interpolation() {
+        let line = "  stage: $[[ inputs.stage ]]-$[[ inputs.job-name | truncate(0,8) ]]";
+
+        assert_eq!(ParserUtils::input_interpolation(line, 15), Some("stage"));
+        assert_eq!(ParserUtils::input_interpolation(line, 40), Some("job-name"));
+        assert_eq!(ParserUtils::input_interpolation(line, 3), None);
+    }
+
+    #[test]
+    fn test_is_docker_platform() {
+        assert!(ParserUtils::is_docker_platform("linux/amd64"));
+        assert!(ParserUtils::is_docker_platform("linux/arm/v7"));
+        assert!(!ParserUtils::is_docker_platform("amd64"));
+        assert!(!ParserUtils::is_docker_platform("linux/"));
+        assert!(!ParserUtils::is_docker_platform("Linux/AMD64"));
+        assert!(!ParserUtils::is_docker_platform("linux/arm/v7/extra"));
+    }
+
+    #[test]
+    fn test_is_docker_user() {
+        assert!(ParserUtils::is_docker_user("dave"));
+        assert!(ParserUtils::is_docker_user("1000"));
+        assert!(ParserUtils::is_docker_user("1000:1000"));
+        assert!(ParserUtils::is_docker_user("app-user:staff"));
+        assert!(!ParserUtils::is_docker_user(""));
+        assert!(!ParserUtils::is_docker_user("dave:"));
+        assert!(!ParserUtils::is_docker_user("dave smith"));
+        assert!(!ParserUtils::is_docker_user("1000:1000:1000"));
+    }
+
+    #[test]
+    fn test_canonical_uri() {
+        let root_dir = tempfile::tempdir().unwrap();
+        let root_path = std::fs::canonicalize(root_dir.path()).unwrap();
+        let dir = root_path.join("ci templates");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("build.yml"), "build:\n  script: make\n").unwrap();
+        std::os::unix::fs::symlink(dir.join("build.yml"), dir.join("link.yml")).unwrap();
+
+        // path is percent encoded in uri
+        let link = lsp_types::Url::from_file_path(dir.join("link.yml")).unwrap();
+        assert!(link.path().contains("%20"));
+        assert_eq!(
+            ParserUtils::canonical_uri(&link),
+            lsp_types::Url::from_file_path(dir.join("build.yml")).unwrap()
+        );
+
+        let remote = lsp_types::Url::parse("https://gitlab.com/ci.yml").unwrap();
+        assert_eq!(ParserUtils::canonical_uri(&remote), remote);
+    }
 }
@@ -1,28 +1,42 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
 use log::error;
 use lsp_types::Position;
 use streaming_iterator::StreamingIterator;
-use tree_sitter::{Node, Query, QueryCursor};
+use tree_sitter::{InputEdit, Node, Point, Query, QueryCursor, Tree};
 
 use super::{
     parser, parser_utils::ParserUtils, treesitter_queries::TreesitterQueries, Component,
     ComponentInput, ComponentInputValueBlock, ComponentInputValuePlain, GitlabCacheElement,
-    GitlabComponentElement, GitlabElement, GitlabInputElement, Include, IncludeInformation,
-    LSPPosition, NodeDefinition, Range, RemoteInclude, RuleReference,
+    GitlabComponentElement, GitlabElement, GitlabInputElement, GitlabRulesElement, Include,
+    IncludeInformation, LSPPosition, NodeDefinition, Range, RemoteInclude, RuleReference,
+    ScriptDefinition, ServiceDefinition,
 };
-use mockall::{automock, predicate::str};
+use mockall::predicate::str;
 
-// TODO: initialize tree only once
+// Queries built per job or stage name are cached as well so their number is
+// capped; least recently used query is dropped once exceeded.
+const MAX_CACHED_QUERIES: usize = 256;
 
 #[allow(clippy::ref_option_ref)]
-#[cfg_attr(test, automock)]
+#[cfg_attr(test, mockall::automock)]
 pub trait Treesitter {
+    fn update_tree(&self, uri: &str, content: &str);
+    fn edit_tree(&self, uri: &str, old_content: &str, edits: &[InputEdit], content: &str);
+    fn drop_tree(&self, uri: &str);
     fn get_root_node(&self, uri: &str, content: &str, node_key: &str) -> Option<GitlabElement>;
     fn get_root_node_key(&self, uri: &str, content: &str, node_key: &str) -> Option<GitlabElement>;
     fn get_all_root_nodes(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_invalid_root_documents(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
     fn get_root_variables(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
     fn get_stage_definitions(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
     fn get_all_components(&self, uri: &str, content: &str) -> Vec<GitlabComponentElement>;
     fn get_all_multi_caches(&self, uri: &str, content: &str) -> Vec<GitlabCacheElement>;
+    fn get_all_rules_blocks(&self, uri: &str, content: &str) -> Vec<GitlabRulesElement>;
     fn get_all_stages<'a>(
         &self,
         uri: &'a str,
@@ -57,16 +71,178 @@ pub trait Treesitter {
         variable_name: &str,
         job_name: &str,
     ) -> Option<GitlabElement>;
+    fn job_key_definition(
+        &self,
+        uri: &str,
+        content: &str,
+        job_name: &str,
+        path: &[String],
+    ) -> Option<GitlabElement>;
+    fn get_key_path(&self, content: &str, position: Position) -> Option<(String, Vec<String>)>;
+    fn job_parallel_matrix(
+        &self,
+        uri: &str,
+        content: &str,
+        job_name: &str,
+    ) -> Option<GitlabElement>;
+    fn job_parallel_matrix_combinations(
+        &self,
+        uri: &str,
+        content: &str,
+        job_name: &str,
+    ) -> Vec<Vec<String>>;
     fn get_component_spec_inputs(&self, content: &str) -> Option<String>;
+    fn get_all_local_includes(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_include_items(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_include_targets(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_exit_codes(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_rule_conditions(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_deployment_tiers(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_docker_platforms(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_docker_users(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_cache_key_files(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_project_includes(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_job_definitions(
+        &self,
+        uri: &str,
+        content: &str,
+        keyword: &str,
+    ) -> Vec<GitlabElement>;
+    fn get_all_tags(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_nested_keywords(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_job_keywords(
+        &self,
+        uri: &str,
+        content: &str,
+        keywords: &[&'static str],
+    ) -> Vec<GitlabElement>;
+    fn get_all_variable_references(
+        &self,
+        uri: &str,
+        content: &str,
+        variable: &str,
+    ) -> Vec<GitlabElement>;
+    fn get_all_variable_definitions<'a>(
+        &self,
+        uri: &'a str,
+        content: &'a str,
+        variable: Option<&'a str>,
+    ) -> Vec<GitlabElement>;
+    fn get_all_anchors(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_spec_inputs(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_default_keywords(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_script_blocks(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+    fn get_all_images(&self, uri: &str, content: &str) -> Vec<GitlabElement>;
+}
+
+#[derive(Default)]
+struct QueryCache {
+    queries: HashMap<String, (Arc<Query>, u64)>,
+    tick: u64,
 }
 
 #[allow(clippy::module_name_repetitions)]
-pub struct TreesitterImpl {}
+pub struct TreesitterImpl {
+    // compiled queries keyed by query source with tick of their last use;
+    // dynamic queries are cached per argument as it's part of the source
+    queries: Mutex<QueryCache>,
+    parser: Mutex<tree_sitter::Parser>,
+    // tree of latest document version keyed by URI, with hash of its content
+    trees: Mutex<HashMap<String, (u64, Tree)>>,
+}
 
 #[allow(clippy::module_name_repetitions)]
 impl TreesitterImpl {
     pub fn new() -> Self {
-        Self {}
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_yaml::LANGUAGE.into())
+            .expect("Error loading YAML grammar");
+
+        Self {
+            queries: Mutex::new(QueryCache::default()),
+            parser: Mutex::new(parser),
+            trees: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn content_hash(content: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Cached tree is used only for the document version it was parsed from.
+    // Anything else, e.g. job definitions parsed under their document URI,
+    // is parsed without being cached.
+    fn tree(&self, uri: &str, content: &str) -> Tree {
+        let hash = TreesitterImpl::content_hash(content);
+        if let Some((cached, tree)) = self.trees.lock().unwrap().get(uri) {
+            if *cached == hash {
+                return tree.clone();
+            }
+        }
+
+        self.parser.lock().unwrap().parse(content, None).unwrap()
+    }
+
+    fn query(&self, source: &str) -> Result<Arc<Query>, tree_sitter::QueryError> {
+        let mut cache = self.queries.lock().unwrap();
+        cache.tick += 1;
+        let tick = cache.tick;
+
+        if let Some((query, used)) = cache.queries.get_mut(source) {
+            *used = tick;
+            return Ok(query.clone());
+        }
+
+        let query = Arc::new(Query::new(&tree_sitter_yaml::LANGUAGE.into(), source)?);
+        if cache.queries.len() >= MAX_CACHED_QUERIES {
+            let oldest = cache
+                .queries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(source, _)| source.clone());
+            if let Some(oldest) = oldest {
+                cache.queries.remove(&oldest);
+            }
+        }
+        cache
+            .queries
+            .insert(source.to_string(), (query.clone(), tick));
+
+        Ok(query)
+    }
+
+    // Elements of all captures named `name`, keyed by the captured text.
+    fn captures(&self, uri: &str, content: &str, query: &str, name: &str) -> Vec<GitlabElement> {
+        let tree = self.tree(uri, content);
+        let root_node = tree.root_node();
+
+        let query = self.query(query).unwrap();
+        let mut cursor_qry = QueryCursor::new();
+        let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
+
+        let capture_index = query.capture_index_for_name(name).unwrap();
+
+        let mut elements: Vec<GitlabElement> = vec![];
+        while let Some(mat) = matches.next() {
+            for c in mat.captures {
+                if c.index != capture_index {
+                    continue;
+                }
+
+                let text = &content[c.node.byte_range()];
+                elements.push(GitlabElement {
+                    key: text.to_string(),
+                    content: None,
+                    uri: uri.to_string(),
+                    range: get_range(c.node, text).unwrap_or_default(),
+                });
+            }
+        }
+
+        elements
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -105,6 +281,10 @@ impl TreesitterImpl {
                     idx if idx == component_uri_index => {
                         let value = content[c.node.byte_range()].to_string();
                         component.uri = ParserUtils::strip_quotes(&value).to_string();
+                        component.uri_hovered = c.node.start_position().row
+                            == position.line as usize
+                            && position.character as usize >= c.node.start_position().column
+                            && position.character as usize <= c.node.end_position().column;
                     }
                     idx if idx == component_input_index => {
                         if let Some(i) = input {
@@ -160,8 +340,8 @@ impl TreesitterImpl {
                             }
                         }
                     }
-                    _ => continue,
-                };
+                    _ => {}
+                }
             }
 
             if let Some(i) = input {
@@ -181,22 +361,312 @@ impl TreesitterImpl {
 
         None
     }
+
+    // Service can be either a plain image or a mapping with name/alias,
+    // both forms are resolved into the same definition.
+    // Node is a `!reference [...]` value, either on its own or as a
+    // sequence item.
+    fn is_reference_tag(node: Node<'_>, content: &str) -> bool {
+        let node = if node.kind() == "block_sequence_item" {
+            let Some(value) = node.named_child(0) else {
+                return false;
+            };
+            value
+        } else {
+            node
+        };
+
+        let mut cursor = node.walk();
+        let is_reference = node
+            .children(&mut cursor)
+            .any(|child| child.kind() == "tag" && &content[child.byte_range()] == "!reference");
+
+        is_reference
+    }
+
+    // Key of `matrix` under job's `parallel`.
+    fn parallel_matrix_key<'t>(
+        &self,
+        tree: &'t Tree,
+        content: &str,
+        job_name: &str,
+    ) -> Option<Node<'t>> {
+        let query = self
+            .query(&TreesitterQueries::get_job_parallel_matrix(job_name))
+            .unwrap();
+
+        let matrix_key_index = query.capture_index_for_name("matrix_key").unwrap();
+
+        let mut cursor_qry = QueryCursor::new();
+        let mut matches = cursor_qry.matches(&query, tree.root_node(), content.as_bytes());
+
+        while let Some(m) = matches.next() {
+            if let Some(capture) = m.captures.iter().find(|c| c.index == matrix_key_index) {
+                return Some(capture.node);
+            }
+        }
+
+        None
+    }
+
+    // Pair with `key` in mapping which is the value of `node`, e.g. document
+    // or another pair's value. Comments around the mapping are skipped.
+    fn mapping_pair<'t>(node: Node<'t>, key: &str, content: &str) -> Option<Node<'t>> {
+        let mut cursor = node.walk();
+        let mut children = node
+            .named_children(&mut cursor)
+            .filter(|child| child.kind() != "comment");
+
+        match node.kind() {
+            "block_mapping" | "flow_mapping" => children.find(|pair| {
+                pair.child_by_field_name("key")
+                    .is_some_and(|k| ParserUtils::strip_quotes(&content[k.byte_range()]) == key)
+            }),
+            "stream" | "document" | "block_node" | "flow_node" => {
+                TreesitterImpl::mapping_pair(children.next()?, key, content)
+            }
+            _ => None,
+        }
+    }
+
+    // Rule item always matches when it's a mapping without `if`, `changes`
+    // or `exists` keys. Other items, e.g. `!reference`, can't be checked.
+    fn is_unconditional_rule(item: Node<'_>, content: &str) -> bool {
+        let Some(mapping) = item
+            .named_child(0)
+            .and_then(|value| value.named_child(0))
+            .filter(|mapping| ["block_mapping", "flow_mapping"].contains(&mapping.kind()))
+        else {
+            return false;
+        };
+
+        let mut cursor = mapping.walk();
+        let keys: Vec<&str> = mapping
+            .named_children(&mut cursor)
+            .filter_map(|pair| pair.child_by_field_name("key"))
+            .map(|key| ParserUtils::strip_quotes(&content[key.byte_range()]))
+            .collect();
+
+        !keys.is_empty()
+            && !keys
+                .iter()
+                .any(|key| ["if", "changes", "exists"].contains(key))
+    }
+
+    fn get_position_type_service(
+        mat: &tree_sitter::QueryMatch<'_, '_>,
+        position: Position,
+        content: &str,
+        service_item_index: u32,
+        service_image_index: u32,
+        service_attribute_key_index: u32,
+        service_attribute_value_index: u32,
+    ) -> Option<parser::PositionType> {
+        let item = mat
+            .captures
+            .iter()
+            .find(|c| c.index == service_item_index)?;
+
+        if item.node.start_position().row > position.line as usize
+            || item.node.end_position().row < position.line as usize
+        {
+            return None;
+        }
+
+        let mut service = ServiceDefinition::default();
+        let mut attribute_key = "";
+
+        for c in mat.captures {
+            let value = ParserUtils::strip_quotes(&content[c.node.byte_range()]);
+
+            match c.index {
+                idx if idx == service_image_index => service.image = value.to_string(),
+                idx if idx == service_attribute_key_index => attribute_key = value,
+                idx if idx == service_attribute_value_index => match attribute_key {
+                    "name" => service.image = value.to_string(),
+                    "alias" => service.alias = Some(value.to_string()),
+                    // commands are classified as scripts so variables in them resolve
+                    "command" | "entrypoint"
+                        if c.node.start_position().row <= position.line as usize
+                            && c.node.end_position().row >= position.line as usize =>
+                    {
+                        return None;
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+
+        if service.image.is_empty() {
+            return None;
+        }
+
+        Some(parser::PositionType::Service(service))
+    }
+
+    // Position is below `needs:` which has no value yet, or whose value is a
+    // single word being typed, e.g. `bu` on the next row.
+    fn is_in_empty_needs(pair: Node<'_>, position: Position) -> bool {
+        let Some(key) = pair.child_by_field_name("key") else {
+            return false;
+        };
+        let line = position.line as usize;
+        if line <= key.end_position().row
+            || position.character as usize <= key.start_position().column
+        {
+            return false;
+        }
+
+        if let Some(value) = pair.child_by_field_name("value") {
+            return value.kind() == "flow_node"
+                && value
+                    .named_child(0)
+                    .is_some_and(|scalar| scalar.kind() == "plain_scalar")
+                && value.start_position().row == line
+                && value.end_position().row == line;
+        }
+
+        // empty value spans up to whatever follows the pair
+        let mut node = pair;
+        loop {
+            if let Some(next) = node.next_named_sibling() {
+                return line < next.start_position().row;
+            }
+
+            match node.parent() {
+                Some(parent) => node = parent,
+                None => return true,
+            }
+        }
+    }
+
+    // Component uri is being typed; value can still be empty so position is
+    // matched against the whole `component:` line.
+    fn get_position_type_component_uri(
+        mat: &tree_sitter::QueryMatch<'_, '_>,
+        position: Position,
+        content: &str,
+        component_uri_pair_index: u32,
+        component_uri_key_index: u32,
+        component_uri_value_index: u32,
+        component_new_key_index: u32,
+    ) -> Option<parser::PositionType> {
+        let pair = mat
+            .captures
+            .iter()
+            .find(|c| c.index == component_uri_pair_index)?;
+        let key = mat
+            .captures
+            .iter()
+            .find(|c| c.index == component_uri_key_index)?;
+
+        let uri = mat
+            .captures
+            .iter()
+            .find(|c| c.index == component_uri_value_index)
+            .map(|c| ParserUtils::strip_quotes(&content[c.node.byte_range()]).to_string())
+            .unwrap_or_default();
+
+        let new_key = mat
+            .captures
+            .iter()
+            .find(|c| c.index == component_new_key_index);
+        if let Some(new_key) = new_key {
+            let mapping = pair.node.parent()?;
+            let mut cursor = mapping.walk();
+            let has_inputs = mapping.named_children(&mut cursor).any(|pair| {
+                pair.child_by_field_name("key")
+                    .is_some_and(|key| &content[key.byte_range()] == "inputs")
+            });
+
+            if new_key.node.start_position().row == position.line as usize && !has_inputs {
+                return Some(parser::PositionType::ComponentInputs(Component {
+                    uri,
+                    ..Default::default()
+                }));
+            }
+        }
+
+        if pair.node.start_position().row != position.line as usize
+            || key.node.end_position().column >= position.character as usize
+        {
+            return None;
+        }
+
+        Some(parser::PositionType::Include(IncludeInformation {
+            component: Some(Component {
+                uri,
+                uri_hovered: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }))
+    }
 }
 
 impl Treesitter for TreesitterImpl {
-    fn get_root_node(&self, uri: &str, content: &str, node_key: &str) -> Option<GitlabElement> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
+    // Parses document and keeps its tree as the latest version of `uri`.
+    fn update_tree(&self, uri: &str, content: &str) {
+        let hash = TreesitterImpl::content_hash(content);
+        if self
+            .trees
+            .lock()
+            .unwrap()
+            .get(uri)
+            .is_some_and(|(cached, _)| *cached == hash)
+        {
+            return;
+        }
 
-        let tree = parser.parse(content, None).unwrap();
+        let tree = self.parser.lock().unwrap().parse(content, None).unwrap();
+        self.trees
+            .lock()
+            .unwrap()
+            .insert(uri.to_string(), (hash, tree));
+    }
+
+    // Reparses edited document reusing tree of its previous version. Falls
+    // back to full parse when that version isn't cached.
+    fn edit_tree(&self, uri: &str, old_content: &str, edits: &[InputEdit], content: &str) {
+        let old_hash = TreesitterImpl::content_hash(old_content);
+        let Some(mut tree) = self
+            .trees
+            .lock()
+            .unwrap()
+            .get(uri)
+            .filter(|(cached, _)| *cached == old_hash)
+            .map(|(_, tree)| tree.clone())
+        else {
+            self.update_tree(uri, content);
+            return;
+        };
+
+        for edit in edits {
+            tree.edit(edit);
+        }
+
+        let tree = self
+            .parser
+            .lock()
+            .unwrap()
+            .parse(content, Some(&tree))
+            .unwrap();
+        self.trees.lock().unwrap().insert(
+            uri.to_string(),
+            (TreesitterImpl::content_hash(content), tree),
+        );
+    }
+
+    fn drop_tree(&self, uri: &str) {
+        self.trees.lock().unwrap().remove(uri);
+    }
+
+    fn get_root_node(&self, uri: &str, content: &str, node_key: &str) -> Option<GitlabElement> {
+        let tree = self.tree(uri, content);
         let root_node = tree.root_node();
 
-        let query = match Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_root_node(node_key),
-        ) {
+        let query = match self.query(&TreesitterQueries::get_root_node(node_key)) {
             Ok(q) => q,
             Err(err) => {
                 error!(
@@ -236,99 +706,578 @@ impl Treesitter for TreesitterImpl {
             }
         }
 
-        None
+        None
+    }
+
+    fn get_all_root_nodes(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        let tree = self.tree(uri, content);
+        let root_node = tree.root_node();
+
+        let query = self
+            .query(&TreesitterQueries::get_all_root_nodes())
+            .unwrap();
+
+        let mut cursor_qry = QueryCursor::new();
+        let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
+
+        let mut root_nodes = vec![];
+        while let Some(m) = matches.next() {
+            let mut node = GitlabElement {
+                uri: uri.to_string(),
+                ..Default::default()
+            };
+            for c in m.captures {
+                let text = content[c.node.byte_range()].to_string();
+                match c.index {
+                    0 => {
+                        node.key = ParserUtils::strip_quotes(&text).to_string();
+                    }
+                    1 => {
+                        node.content = Some(text);
+                        node.range = get_node_range(c.node);
+                    }
+                    _ => {}
+                }
+            }
+
+            root_nodes.push(node);
+        }
+
+        root_nodes
+    }
+
+    // Documents whose root is not a mapping. Empty and comment only documents
+    // are valid and syntax errors are left to the error nodes.
+    fn get_invalid_root_documents(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        let tree = self.tree(uri, content);
+        let root_node = tree.root_node();
+
+        let mut cursor = root_node.walk();
+        let documents: Vec<Node<'_>> = root_node
+            .named_children(&mut cursor)
+            .filter(|node| node.kind() == "document")
+            .collect();
+
+        documents
+            .into_iter()
+            .filter_map(|document| {
+                let mut cursor = document.walk();
+                let value = document
+                    .named_children(&mut cursor)
+                    .find(|node| node.kind() != "comment")?;
+
+                let is_mapping = value
+                    .named_child(0)
+                    .is_some_and(|node| ["block_mapping", "flow_mapping"].contains(&node.kind()));
+                if is_mapping || value.is_error() || value.has_error() {
+                    return None;
+                }
+
+                Some(GitlabElement {
+                    uri: uri.to_string(),
+                    key: value.kind().to_string(),
+                    content: Some(content[value.byte_range()].to_string()),
+                    range: get_node_range(value),
+                })
+            })
+            .collect()
+    }
+
+    fn get_root_variables(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        // TODO: this should be generic fn accepting treesitter query
+
+        let tree = self.tree(uri, content);
+        let root_node = tree.root_node();
+
+        let query = self
+            .query(&TreesitterQueries::get_root_variables())
+            .unwrap();
+        let mut cursor_qry = QueryCursor::new();
+        let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
+
+        let mut environments: Vec<GitlabElement> = vec![];
+        while let Some(mat) = matches.next() {
+            for c in mat.captures {
+                if c.index == 2 {
+                    // value follows its key so it belongs to last found variable;
+                    // indentation is kept so multiline values remain valid yaml
+                    if let Some(env) = environments.last_mut() {
+                        let text = &content[c.node.byte_range()];
+                        env.content = if c.node.start_position().row == c.node.end_position().row {
+                            Some(text.to_string())
+                        } else {
+                            Some(format!(
+                                "{}{text}",
+                                " ".repeat(c.node.start_position().column)
+                            ))
+                        };
+                    }
+                } else if c.index == 1 {
+                    let text = &content[c.node.byte_range()];
+                    if c.node.start_position().row != c.node.end_position().row {
+                        // sanity check
+                        error!(
+                            "environemnt spans over multiple rows: uri: {} text: {}",
+                            uri, text
+                        );
+
+                        continue;
+                    }
+
+                    environments.push(GitlabElement {
+                        key: ParserUtils::strip_quotes(text).to_string(),
+                        content: None,
+                        uri: uri.to_string(),
+                        range: Range {
+                            start: LSPPosition {
+                                line: u32::try_from(c.node.start_position().row).unwrap_or(0),
+                                character: u32::try_from(c.node.start_position().column)
+                                    .unwrap_or(0),
+                            },
+                            end: LSPPosition {
+                                line: u32::try_from(c.node.end_position().row).unwrap_or(0),
+                                character: u32::try_from(c.node.end_position().column).unwrap_or(0),
+                            },
+                        },
+                    });
+                }
+            }
+        }
+
+        environments
+    }
+
+    fn get_stage_definitions(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        let tree = self.tree(uri, content);
+        let root_node = tree.root_node();
+
+        let query = self
+            .query(&TreesitterQueries::get_stage_definitions())
+            .unwrap();
+        let mut cursor_qry = QueryCursor::new();
+        let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
+
+        let mut stages = vec![];
+        while let Some(mat) = matches.next() {
+            for c in mat.captures {
+                if c.index == 1 {
+                    let text = &content[c.node.byte_range()];
+                    if c.node.start_position().row != c.node.end_position().row {
+                        // sanity check
+                        error!(
+                            "STAGE: extends spans over multiple rows: uri: {} text: {}",
+                            uri, text
+                        );
+
+                        continue;
+                    }
+
+                    stages.push(GitlabElement {
+                        key: ParserUtils::strip_quotes(text).to_string(),
+                        content: None,
+                        uri: uri.to_string(),
+                        range: Range {
+                            start: LSPPosition {
+                                line: u32::try_from(c.node.start_position().row).unwrap_or(0),
+                                character: u32::try_from(c.node.start_position().column)
+                                    .unwrap_or(0),
+                            },
+                            end: LSPPosition {
+                                line: u32::try_from(c.node.end_position().row).unwrap_or(0),
+                                character: u32::try_from(c.node.end_position().column).unwrap_or(0),
+                            },
+                        },
+                    });
+                }
+            }
+        }
+
+        stages
+    }
+
+    // Content of element is the entry indented by its column so it can be
+    // deserialized on its own.
+    fn get_all_include_items(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.captures(
+            uri,
+            content,
+            &TreesitterQueries::get_all_include_items(),
+            "include_item",
+        )
+        .into_iter()
+        .map(|item| GitlabElement {
+            content: Some(format!(
+                "{}{}",
+                " ".repeat(item.range.start.character as usize),
+                item.key
+            )),
+            ..item
+        })
+        .collect()
+    }
+
+    fn get_all_include_targets(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.captures(
+            uri,
+            content,
+            &TreesitterQueries::get_all_include_targets(),
+            "include_target",
+        )
+    }
+
+    fn get_all_local_includes(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.captures(
+            uri,
+            content,
+            &TreesitterQueries::get_all_local_includes(),
+            "local_include",
+        )
+        .into_iter()
+        .filter_map(|include| {
+            let path = ParserUtils::strip_quotes(&include.key);
+            if path.starts_with("http://") || path.starts_with("https://") {
+                return None;
+            }
+
+            Some(GitlabElement {
+                key: path.to_string(),
+                content: Some(include.key.clone()),
+                ..include
+            })
+        })
+        .collect()
+    }
+
+    fn get_all_exit_codes(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.captures(
+            uri,
+            content,
+            &TreesitterQueries::get_all_exit_codes(),
+            "exit_code",
+        )
+    }
+
+    fn get_all_cache_key_files(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.captures(
+            uri,
+            content,
+            &TreesitterQueries::get_all_cache_key_files(),
+            "cache_key_file",
+        )
+        .into_iter()
+        .map(|file| GitlabElement {
+            key: ParserUtils::strip_quotes(&file.key).to_string(),
+            ..file
+        })
+        .collect()
+    }
+
+    fn get_all_docker_platforms(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.captures(
+            uri,
+            content,
+            &TreesitterQueries::get_all_docker_platforms(),
+            "docker_platform",
+        )
+    }
+
+    fn get_all_docker_users(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.captures(
+            uri,
+            content,
+            &TreesitterQueries::get_all_docker_users(),
+            "docker_user",
+        )
+    }
+
+    fn get_all_deployment_tiers(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.captures(
+            uri,
+            content,
+            &TreesitterQueries::get_all_deployment_tiers(),
+            "deployment_tier",
+        )
+    }
+
+    fn get_all_project_includes(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        let tree = self.tree(uri, content);
+        let root_node = tree.root_node();
+
+        let query = self
+            .query(&TreesitterQueries::get_all_project_includes())
+            .unwrap();
+        let mut cursor_qry = QueryCursor::new();
+        let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
+
+        let project_include_index = query.capture_index_for_name("project_include").unwrap();
+        let project_value_index = query.capture_index_for_name("project_value").unwrap();
+
+        let mut includes: Vec<GitlabElement> = vec![];
+        while let Some(mat) = matches.next() {
+            let Some(project) = mat.captures.iter().find(|c| c.index == project_value_index) else {
+                continue;
+            };
+
+            // content holds the ref if the include has one
+            let reference = mat
+                .captures
+                .iter()
+                .find(|c| c.index == project_include_index)
+                .and_then(|include| {
+                    let mut cursor = include.node.walk();
+                    let pair = include.node.named_children(&mut cursor).find(|pair| {
+                        pair.child_by_field_name("key")
+                            .is_some_and(|key| &content[key.byte_range()] == "ref")
+                    });
+
+                    pair.and_then(|pair| pair.child_by_field_name("value"))
+                        .map(|value| content[value.byte_range()].to_string())
+                });
+
+            let text = &content[project.node.byte_range()];
+            includes.push(GitlabElement {
+                key: text.to_string(),
+                content: reference,
+                uri: uri.to_string(),
+                range: get_range(project.node, text).unwrap_or_default(),
+            });
+        }
+
+        includes
+    }
+
+    fn get_all_job_definitions(
+        &self,
+        uri: &str,
+        content: &str,
+        keyword: &str,
+    ) -> Vec<GitlabElement> {
+        let tree = self.tree(uri, content);
+        let root_node = tree.root_node();
+
+        let query = self
+            .query(&TreesitterQueries::get_all_job_definitions(keyword))
+            .unwrap();
+        let mut cursor_qry = QueryCursor::new();
+        let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
+
+        let definition_index = query.capture_index_for_name("definition").unwrap();
+        let definition_key_index = query.capture_index_for_name("definition_key").unwrap();
+
+        let mut definitions: Vec<GitlabElement> = vec![];
+        while let Some(mat) = matches.next() {
+            let Some(key) = mat
+                .captures
+                .iter()
+                .find(|c| c.index == definition_key_index)
+            else {
+                continue;
+            };
+
+            // content holds definition without its name
+            let definition = mat
+                .captures
+                .iter()
+                .find(|c| c.index == definition_index)
+                .and_then(|definition| definition.node.child_by_field_name("value"))
+                .map(|value| content[value.byte_range()].to_string());
+
+            let text = &content[key.node.byte_range()];
+            definitions.push(GitlabElement {
+                key: text.to_string(),
+                content: definition,
+                uri: uri.to_string(),
+                range: get_range(key.node, text).unwrap_or_default(),
+            });
+        }
+
+        definitions
+    }
+
+    fn get_all_tags(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        let tree = self.tree(uri, content);
+        let root_node = tree.root_node();
+
+        let query = self.query(&TreesitterQueries::get_all_tags()).unwrap();
+        let mut cursor_qry = QueryCursor::new();
+        let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
+
+        let tags_index = query.capture_index_for_name("tags").unwrap();
+
+        let mut tags: Vec<GitlabElement> = vec![];
+        while let Some(mat) = matches.next() {
+            for c in mat.captures {
+                if c.index != tags_index {
+                    continue;
+                }
+
+                let Some(key) = c.node.child_by_field_name("key") else {
+                    continue;
+                };
+
+                // content is empty when tags have no value, e.g. `tags:`
+                let text = &content[key.byte_range()];
+                tags.push(GitlabElement {
+                    key: text.to_string(),
+                    content: c
+                        .node
+                        .child_by_field_name("value")
+                        .map(|value| content[value.byte_range()].to_string()),
+                    uri: uri.to_string(),
+                    range: get_range(key, text).unwrap_or_default(),
+                });
+            }
+        }
+
+        tags
     }
 
-    fn get_all_root_nodes(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
-
-        let tree = parser.parse(content, None).unwrap();
+    // Key of element is the nested keyword and content its parent keyword.
+    fn get_all_nested_keywords(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        let tree = self.tree(uri, content);
         let root_node = tree.root_node();
 
-        let query = Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_all_root_nodes(),
-        )
-        .unwrap();
-
+        let query = self
+            .query(&TreesitterQueries::get_all_nested_keywords())
+            .unwrap();
         let mut cursor_qry = QueryCursor::new();
         let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
 
-        let mut root_nodes = vec![];
-        while let Some(m) = matches.next() {
-            let mut node = GitlabElement {
+        let parent_index = query.capture_index_for_name("nested_parent_key").unwrap();
+        let keyword_index = query.capture_index_for_name("nested_keyword").unwrap();
+
+        let mut keywords: Vec<GitlabElement> = vec![];
+        while let Some(mat) = matches.next() {
+            let (Some(parent), Some(keyword)) = (
+                mat.captures.iter().find(|c| c.index == parent_index),
+                mat.captures.iter().find(|c| c.index == keyword_index),
+            ) else {
+                continue;
+            };
+
+            let text = &content[keyword.node.byte_range()];
+            keywords.push(GitlabElement {
+                key: text.to_string(),
+                content: Some(content[parent.node.byte_range()].to_string()),
                 uri: uri.to_string(),
-                ..Default::default()
+                range: get_range(keyword.node, text).unwrap_or_default(),
+            });
+        }
+
+        keywords
+    }
+
+    // Key of element is the keyword and content name of its job.
+    fn get_all_job_keywords(
+        &self,
+        uri: &str,
+        content: &str,
+        keywords: &[&'static str],
+    ) -> Vec<GitlabElement> {
+        let tree = self.tree(uri, content);
+        let root_node = tree.root_node();
+
+        let query = self
+            .query(&TreesitterQueries::get_all_job_keywords(keywords))
+            .unwrap();
+        let mut cursor_qry = QueryCursor::new();
+        let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
+
+        let job_index = query.capture_index_for_name("job_key").unwrap();
+        let keyword_index = query.capture_index_for_name("job_keyword").unwrap();
+
+        let mut keywords: Vec<GitlabElement> = vec![];
+        while let Some(mat) = matches.next() {
+            let (Some(job), Some(keyword)) = (
+                mat.captures.iter().find(|c| c.index == job_index),
+                mat.captures.iter().find(|c| c.index == keyword_index),
+            ) else {
+                continue;
             };
-            for c in m.captures {
-                let text = content[c.node.byte_range()].to_string();
-                match c.index {
-                    0 => {
-                        node.key = text;
-                    }
-                    1 => {
-                        node.content = Some(text);
-                    }
-                    _ => {}
-                }
-            }
 
-            root_nodes.push(node);
+            let text = &content[keyword.node.byte_range()];
+            keywords.push(GitlabElement {
+                key: text.to_string(),
+                content: Some(
+                    ParserUtils::strip_quotes(&content[job.node.byte_range()]).to_string(),
+                ),
+                uri: uri.to_string(),
+                range: get_range(keyword.node, text).unwrap_or_default(),
+            });
         }
 
-        root_nodes
+        keywords
     }
 
-    fn get_root_variables(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
+    fn get_all_variable_definitions(
+        &self,
+        uri: &str,
+        content: &str,
+        variable: Option<&str>,
+    ) -> Vec<GitlabElement> {
+        let mut definitions = self.captures(
+            uri,
+            content,
+            &TreesitterQueries::get_all_variable_definitions(),
+            "variable_definition",
+        );
+        definitions.retain(|definition| variable.is_none_or(|v| v == definition.key));
 
-        // TODO: this should be generic fn accepting treesitter query
+        definitions
+    }
 
-        let tree = parser.parse(content, None).unwrap();
+    // Usages of `$variable` and `${variable}` in scalar values. Range
+    // covers only the variable name.
+    fn get_all_variable_references(
+        &self,
+        uri: &str,
+        content: &str,
+        variable: &str,
+    ) -> Vec<GitlabElement> {
+        let tree = self.tree(uri, content);
         let root_node = tree.root_node();
 
-        let query = Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_root_variables(),
-        )
-        .unwrap();
+        let query = self
+            .query(&TreesitterQueries::get_all_scalar_values())
+            .unwrap();
         let mut cursor_qry = QueryCursor::new();
         let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
 
-        let mut environments = vec![];
+        let mut references: Vec<GitlabElement> = vec![];
         while let Some(mat) = matches.next() {
             for c in mat.captures {
-                if c.index == 1 {
-                    let text = &content[c.node.byte_range()];
-                    if c.node.start_position().row != c.node.end_position().row {
-                        // sanity check
-                        error!(
-                            "environemnt spans over multiple rows: uri: {} text: {}",
-                            uri, text
-                        );
+                // sequences and mappings are searched through their items
+                if c.node.kind() == "flow_node"
+                    && !c
+                        .node
+                        .named_child(0)
+                        .is_some_and(|child| child.kind().ends_with("_scalar"))
+                {
+                    continue;
+                }
 
-                        continue;
-                    }
+                let text = &content[c.node.byte_range()];
+                let start = c.node.start_position();
+                for offset in ParserUtils::variable_offsets(text, variable) {
+                    // block scalars span multiple rows
+                    let line = start.row + text[..offset].matches('\n').count();
+                    let character = match text[..offset].rfind('\n') {
+                        Some(newline) => offset - newline - 1,
+                        None => start.column + offset,
+                    };
 
-                    environments.push(GitlabElement {
-                        key: ParserUtils::strip_quotes(text).to_string(),
+                    references.push(GitlabElement {
+                        key: variable.to_string(),
                         content: None,
                         uri: uri.to_string(),
                         range: Range {
                             start: LSPPosition {
-                                line: u32::try_from(c.node.start_position().row).unwrap_or(0),
-                                character: u32::try_from(c.node.start_position().column)
-                                    .unwrap_or(0),
+                                line: u32::try_from(line).unwrap_or(0),
+                                character: u32::try_from(character).unwrap_or(0),
                             },
                             end: LSPPosition {
-                                line: u32::try_from(c.node.end_position().row).unwrap_or(0),
-                                character: u32::try_from(c.node.end_position().column).unwrap_or(0),
+                                line: u32::try_from(line).unwrap_or(0),
+                                character: u32::try_from(character + variable.len()).unwrap_or(0),
                             },
                         },
                     });
@@ -336,78 +1285,123 @@ impl Treesitter for TreesitterImpl {
             }
         }
 
-        environments
+        references
     }
 
-    fn get_stage_definitions(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
+    fn get_all_anchors(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.captures(
+            uri,
+            content,
+            &TreesitterQueries::get_all_anchors(),
+            "anchor_name",
+        )
+    }
+
+    fn get_all_spec_inputs(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.captures(
+            uri,
+            content,
+            &TreesitterQueries::get_all_spec_inputs(),
+            "spec_input",
+        )
+    }
 
-        let tree = parser.parse(content, None).unwrap();
+    fn get_all_default_keywords(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        let tree = self.tree(uri, content);
         let root_node = tree.root_node();
 
-        let query = Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_stage_definitions(),
-        )
-        .unwrap();
+        let query = self
+            .query(&TreesitterQueries::get_all_default_keywords())
+            .unwrap();
         let mut cursor_qry = QueryCursor::new();
         let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
 
-        let mut stages = vec![];
+        let keyword_index = query.capture_index_for_name("default_keyword").unwrap();
+
+        let mut keywords: Vec<GitlabElement> = vec![];
         while let Some(mat) = matches.next() {
             for c in mat.captures {
-                if c.index == 1 {
-                    let text = &content[c.node.byte_range()];
-                    if c.node.start_position().row != c.node.end_position().row {
-                        // sanity check
-                        error!(
-                            "STAGE: extends spans over multiple rows: uri: {} text: {}",
-                            uri, text
-                        );
+                if c.index != keyword_index {
+                    continue;
+                }
 
-                        continue;
-                    }
+                let Some(key) = c.node.child_by_field_name("key") else {
+                    continue;
+                };
 
-                    stages.push(GitlabElement {
-                        key: ParserUtils::strip_quotes(text).to_string(),
-                        content: None,
-                        uri: uri.to_string(),
-                        range: Range {
-                            start: LSPPosition {
-                                line: u32::try_from(c.node.start_position().row).unwrap_or(0),
-                                character: u32::try_from(c.node.start_position().column)
-                                    .unwrap_or(0),
-                            },
-                            end: LSPPosition {
-                                line: u32::try_from(c.node.end_position().row).unwrap_or(0),
-                                character: u32::try_from(c.node.end_position().column).unwrap_or(0),
-                            },
-                        },
-                    });
+                // range spans whole pair so value can be hovered as well
+                keywords.push(GitlabElement {
+                    key: content[key.byte_range()].to_string(),
+                    content: Some(content[c.node.byte_range()].to_string()),
+                    uri: uri.to_string(),
+                    range: get_node_range(c.node),
+                });
+            }
+        }
+
+        keywords
+    }
+
+    fn get_all_script_blocks(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        let tree = self.tree(uri, content);
+        let root_node = tree.root_node();
+
+        let query = self
+            .query(&TreesitterQueries::get_all_script_blocks())
+            .unwrap();
+        let mut cursor_qry = QueryCursor::new();
+        let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
+
+        let key_index = query.capture_index_for_name("script_key").unwrap();
+        let block_index = query.capture_index_for_name("script_block").unwrap();
+
+        let mut blocks: Vec<GitlabElement> = vec![];
+        while let Some(mat) = matches.next() {
+            let mut block = GitlabElement {
+                uri: uri.to_string(),
+                ..Default::default()
+            };
+
+            for c in mat.captures {
+                if c.index == key_index {
+                    block.key = content[c.node.byte_range()].to_string();
+                } else if c.index == block_index {
+                    block.range = get_node_range(c.node);
                 }
             }
+
+            blocks.push(block);
         }
 
-        stages
+        blocks
     }
 
-    fn get_all_stages(&self, uri: &str, content: &str, stage: Option<&str>) -> Vec<GitlabElement> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
+    fn get_all_images(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.captures(uri, content, &TreesitterQueries::get_all_images(), "image")
+            .into_iter()
+            .map(|image| GitlabElement {
+                key: ParserUtils::strip_quotes(&image.key).to_string(),
+                ..image
+            })
+            .collect()
+    }
 
-        let tree = parser.parse(content, None).unwrap();
+    fn get_all_rule_conditions(&self, uri: &str, content: &str) -> Vec<GitlabElement> {
+        self.captures(
+            uri,
+            content,
+            &TreesitterQueries::get_all_rule_conditions(),
+            "rule_condition",
+        )
+    }
+
+    fn get_all_stages(&self, uri: &str, content: &str, stage: Option<&str>) -> Vec<GitlabElement> {
+        let tree = self.tree(uri, content);
         let root_node = tree.root_node();
 
-        let query = Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_all_stages(stage),
-        )
-        .unwrap();
+        let query = self
+            .query(&TreesitterQueries::get_all_stages(stage))
+            .unwrap();
         let mut cursor_qry = QueryCursor::new();
         let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
 
@@ -457,19 +1451,10 @@ impl Treesitter for TreesitterImpl {
         content: &str,
         extend_name: Option<&str>,
     ) -> Vec<GitlabElement> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
-
-        let tree = parser.parse(content, None).unwrap();
+        let tree = self.tree(&uri, content);
         let root_node = tree.root_node();
 
-        let query = Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_all_extends(extend_name),
-        )
-        .unwrap();
+        let query = self.query(&TreesitterQueries::get_all_extends()).unwrap();
         let mut cursor_qry = QueryCursor::new();
         let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
 
@@ -479,21 +1464,27 @@ impl Treesitter for TreesitterImpl {
             for c in mat.captures {
                 if c.index == 1 {
                     let text = &content[c.node.byte_range()];
-                    if c.node.start_position().row != c.node.end_position().row {
-                        // sanity check
-                        error!(
-                            "ALL: extends spans over multiple rows: uri: {} text: {}",
-                            uri, text
-                        );
+                    let (key, range) = if c.node.start_position().row == c.node.end_position().row {
+                        (
+                            ParserUtils::strip_quotes(text).to_string(),
+                            get_range(c.node, text).unwrap_or_default(),
+                        )
+                    } else {
+                        (
+                            ParserUtils::multiline_scalar_value(text),
+                            get_multiline_range(c.node, text).unwrap_or_default(),
+                        )
+                    };
 
+                    if extend_name.is_some_and(|name| name != key) {
                         continue;
                     }
 
                     extends.push(GitlabElement {
-                        key: ParserUtils::strip_quotes(text).to_string(),
+                        key,
                         content: None,
                         uri: uri.clone(),
-                        range: get_range(c.node, text).unwrap_or_default(),
+                        range,
                     });
                 }
             }
@@ -504,19 +1495,10 @@ impl Treesitter for TreesitterImpl {
 
     #[allow(clippy::too_many_lines)]
     fn get_position_type(&self, content: &str, position: Position) -> parser::PositionType {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
-
-        let tree = parser.parse(content, None).unwrap();
+        let tree = self.tree("", content);
         let root_node = tree.root_node();
 
-        let query = Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_position_type(),
-        )
-        .unwrap();
+        let query = self.query(&TreesitterQueries::get_position_type()).unwrap();
         let mut cursor_qry = QueryCursor::new();
         let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
 
@@ -527,6 +1509,8 @@ impl Treesitter for TreesitterImpl {
         let extends_index = query.capture_index_for_name("extends").unwrap();
         let stage_index = query.capture_index_for_name("stage").unwrap();
         let variable_index = query.capture_index_for_name("variable").unwrap();
+        let script_key_index = query.capture_index_for_name("keyscript").unwrap();
+        let script_index = query.capture_index_for_name("script").unwrap();
         let root_node_index = query.capture_index_for_name("rootnode").unwrap();
         let local_include_index = query.capture_index_for_name("local_value").unwrap();
         let needs_index = query.capture_index_for_name("needs_job_value").unwrap();
@@ -539,6 +1523,8 @@ impl Treesitter for TreesitterImpl {
         let rule_reference_index = query
             .capture_index_for_name("rule_reference_value")
             .unwrap();
+        let rule_reference_path_index =
+            query.capture_index_for_name("rule_reference_path").unwrap();
         let component_uri_index = query.capture_index_for_name("component_uri").unwrap();
         let component_input_index = query.capture_index_for_name("component_input").unwrap();
         let component_input_error_index = query
@@ -551,7 +1537,29 @@ impl Treesitter for TreesitterImpl {
             .capture_index_for_name("component_input_value_block")
             .unwrap();
         let full_component_index = query.capture_index_for_name("full_component").unwrap();
+        let component_uri_pair_index = query.capture_index_for_name("component_uri_pair").unwrap();
+        let component_uri_key_index = query.capture_index_for_name("component_uri_key").unwrap();
+        let component_uri_value_index =
+            query.capture_index_for_name("component_uri_value").unwrap();
+        let component_new_key_index = query.capture_index_for_name("component_new_key").unwrap();
+        let empty_needs_index = query.capture_index_for_name("empty_needs").unwrap();
         let dependency_index = query.capture_index_for_name("dependency").unwrap();
+        let allow_failure_index = query.capture_index_for_name("allow_failure").unwrap();
+        let deployment_tier_index = query.capture_index_for_name("deployment_tier").unwrap();
+        let docker_platform_index = query.capture_index_for_name("docker_platform").unwrap();
+        let docker_user_index = query.capture_index_for_name("docker_user").unwrap();
+        let image_index = query.capture_index_for_name("image").unwrap();
+        let service_item_index = query.capture_index_for_name("service_item").unwrap();
+        let service_image_index = query.capture_index_for_name("service_image").unwrap();
+        let service_attribute_key_index = query
+            .capture_index_for_name("service_attribute_key")
+            .unwrap();
+        let service_attribute_value_index = query
+            .capture_index_for_name("service_attribute_value")
+            .unwrap();
+        let changes_path_index = query.capture_index_for_name("changes_path").unwrap();
+        let changes_compare_to_index = query.capture_index_for_name("changes_compare_to").unwrap();
+        let trigger_index = query.capture_index_for_name("trigger").unwrap();
 
         while let Some(mat) = matches.next() {
             // If this is a remote reference capture, I need to capture multiple values
@@ -578,6 +1586,38 @@ impl Treesitter for TreesitterImpl {
                 ) {
                     return position_type;
                 }
+            } else if mat
+                .captures
+                .iter()
+                .any(|c| c.index == component_uri_pair_index)
+            {
+                if let Some(position_type) = TreesitterImpl::get_position_type_component_uri(
+                    mat,
+                    position,
+                    content,
+                    component_uri_pair_index,
+                    component_uri_key_index,
+                    component_uri_value_index,
+                    component_new_key_index,
+                ) {
+                    return position_type;
+                }
+            } else if let Some(c) = mat.captures.iter().find(|c| c.index == empty_needs_index) {
+                if TreesitterImpl::is_in_empty_needs(c.node, position) {
+                    return parser::PositionType::EmptyNeeds;
+                }
+            } else if mat.captures.iter().any(|c| c.index == service_item_index) {
+                if let Some(position_type) = TreesitterImpl::get_position_type_service(
+                    mat,
+                    position,
+                    content,
+                    service_item_index,
+                    service_image_index,
+                    service_attribute_key_index,
+                    service_attribute_value_index,
+                ) {
+                    return position_type;
+                }
             } else if mat
                 .captures
                 .iter()
@@ -611,14 +1651,13 @@ impl Treesitter for TreesitterImpl {
                                     .to_string(),
                             );
                         }
-                        idx if idx == project_file_index => {
-                            if c.node.start_position().row == position.line as usize {
-                                remote_include.file =
-                                    Some(content[c.node.byte_range()].to_string());
-                            }
+                        idx if idx == project_file_index
+                            && c.node.start_position().row == position.line as usize =>
+                        {
+                            remote_include.file = Some(content[c.node.byte_range()].to_string());
                         }
-                        _ => continue,
-                    };
+                        _ => {}
+                    }
                 }
 
                 if remote_include.is_valid() {
@@ -629,10 +1668,18 @@ impl Treesitter for TreesitterImpl {
                 }
             } else {
                 for c in mat.captures {
+                    // block scalar extends and rule conditions start on the row
+                    // above their value
+                    let is_multiline_scalar = (c.index == extends_index
+                        || c.index == variable_index)
+                        && c.node.kind() == "block_scalar"
+                        && c.node.start_position().row < position.line as usize;
+
                     if c.node.start_position().row <= position.line as usize
                         && c.node.end_position().row >= position.line as usize
-                        && c.node.start_position().column <= position.character as usize
-                        && c.node.end_position().column >= position.character as usize
+                        && (is_multiline_scalar
+                            || (c.node.start_position().column <= position.character as usize
+                                && c.node.end_position().column >= position.character as usize))
                     {
                         match c.index {
                             idx if idx == extends_index => return parser::PositionType::Extend,
@@ -640,8 +1687,59 @@ impl Treesitter for TreesitterImpl {
                             idx if idx == dependency_index => {
                                 return parser::PositionType::Dependency
                             }
-                            idx if idx == variable_index => return parser::PositionType::Variable,
+                            idx if idx == variable_index => {
+                                // `!reference` values are resolved by rule reference capture
+                                if TreesitterImpl::is_reference_tag(c.node, content) {
+                                    continue;
+                                }
+
+                                // flow sequence of `changes` paths is resolved by changes capture
+                                if c.node
+                                    .parent()
+                                    .and_then(|pair| pair.child_by_field_name("key"))
+                                    .is_some_and(|key| &content[key.byte_range()] == "changes")
+                                {
+                                    continue;
+                                }
+
+                                return parser::PositionType::Variable;
+                            }
+                            idx if idx == script_index => {
+                                // `!reference` items are resolved by rule reference capture
+                                if TreesitterImpl::is_reference_tag(c.node, content) {
+                                    continue;
+                                }
+
+                                let key = mat
+                                    .captures
+                                    .iter()
+                                    .find(|c| c.index == script_key_index)
+                                    .map(|c| content[c.node.byte_range()].to_string())
+                                    .unwrap_or_default();
+
+                                return parser::PositionType::Script(ScriptDefinition { key });
+                            }
                             idx if idx == root_node_index => return parser::PositionType::RootNode,
+                            idx if idx == allow_failure_index => {
+                                return parser::PositionType::AllowFailure
+                            }
+                            idx if idx == deployment_tier_index => {
+                                return parser::PositionType::DeploymentTier
+                            }
+                            idx if idx == docker_platform_index => {
+                                return parser::PositionType::DockerPlatform
+                            }
+                            idx if idx == docker_user_index => {
+                                return parser::PositionType::DockerUser
+                            }
+                            idx if idx == image_index => return parser::PositionType::Image,
+                            idx if idx == changes_path_index => {
+                                return parser::PositionType::ChangesPath
+                            }
+                            idx if idx == changes_compare_to_index => {
+                                return parser::PositionType::ChangesCompareTo
+                            }
+                            idx if idx == trigger_index => return parser::PositionType::Trigger,
                             idx if idx == local_include_index => {
                                 return parser::PositionType::Include(IncludeInformation {
                                     local: Some(Include {
@@ -672,13 +1770,28 @@ impl Treesitter for TreesitterImpl {
                                 })
                             }
                             idx if idx == rule_reference_index => {
+                                let path = mat
+                                    .captures
+                                    .iter()
+                                    .find(|c| c.index == rule_reference_path_index)
+                                    .and_then(|c| {
+                                        serde_yaml::from_str::<Vec<String>>(
+                                            &content[c.node.byte_range()],
+                                        )
+                                        .ok()
+                                    })
+                                    .map(|path| path.into_iter().skip(1).collect())
+                                    .unwrap_or_default();
+
                                 return parser::PositionType::RuleReference(RuleReference {
                                     node: content[c.node.byte_range()]
                                         .trim_matches('\'')
                                         .trim_matches('"')
                                         .to_string(),
-                                })
+                                    path,
+                                });
                             }
+                            idx if idx == rule_reference_path_index => parser::PositionType::None,
                             _ => {
                                 error!("invalid index: {}", c.index);
                                 error!(
@@ -703,27 +1816,30 @@ impl Treesitter for TreesitterImpl {
         content: &str,
         needs_name: Option<&str>,
     ) -> Vec<GitlabElement> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
-
-        let tree = parser.parse(content, None).unwrap();
+        let tree = self.tree(&uri, content);
         let root_node = tree.root_node();
 
-        let query = Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_all_job_needs(needs_name),
-        )
-        .unwrap();
+        let query = self
+            .query(&TreesitterQueries::get_all_job_needs(needs_name))
+            .unwrap();
         let mut cursor_qry = QueryCursor::new();
         let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
 
+        let needs_job_value_index = query.capture_index_for_name("needs_job_value").unwrap();
+        let needs_job_index = query.capture_index_for_name("needs_job").unwrap();
+
         let mut needs: Vec<GitlabElement> = vec![];
 
         while let Some(mat) = matches.next() {
+            // mapping form can carry flags such as `optional`
+            let definition = mat
+                .captures
+                .iter()
+                .find(|c| c.index == needs_job_index)
+                .map(|c| content[c.node.byte_range()].trim_end().to_string());
+
             for c in mat.captures {
-                if c.index == 2 {
+                if c.index == needs_job_value_index {
                     let text = &content[c.node.byte_range()];
                     if c.node.start_position().row != c.node.end_position().row {
                         // sanity check
@@ -737,7 +1853,7 @@ impl Treesitter for TreesitterImpl {
 
                     needs.push(GitlabElement {
                         key: ParserUtils::strip_quotes(text).to_string(),
-                        content: None,
+                        content: definition.clone(),
                         uri: uri.clone(),
                         range: get_range(c.node, text).unwrap_or_default(),
                     });
@@ -753,19 +1869,12 @@ impl Treesitter for TreesitterImpl {
         content: &str,
         position: Position,
     ) -> Option<GitlabElement> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
-
-        let tree = parser.parse(content, None).unwrap();
+        let tree = self.tree("", content);
         let root_node = tree.root_node();
 
-        let query = Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_root_node_at_position(),
-        )
-        .unwrap();
+        let query = self
+            .query(&TreesitterQueries::get_root_node_at_position())
+            .unwrap();
 
         let mut cursor_qry = QueryCursor::new();
         let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
@@ -779,9 +1888,10 @@ impl Treesitter for TreesitterImpl {
                 {
                     // Extract the text and create the GitlabElement
                     let text = content[capture.node.byte_range()].to_string();
-                    let key = text.lines().collect::<Vec<&str>>()[0]
-                        .trim_end_matches(':')
-                        .to_string();
+                    let key = ParserUtils::strip_quotes(
+                        text.lines().collect::<Vec<&str>>()[0].trim_end_matches(':'),
+                    )
+                    .to_string();
 
                     return Some(GitlabElement {
                         key,
@@ -800,26 +1910,34 @@ impl Treesitter for TreesitterImpl {
         uri: &str,
         content: &str,
         variable_name: &str,
-        job_name: &str,
-    ) -> Option<GitlabElement> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
-
-        let tree = parser.parse(content, None).unwrap();
+        job_name: &str,
+    ) -> Option<GitlabElement> {
+        let tree = self.tree(uri, content);
         let root_node = tree.root_node();
 
-        let query = Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_job_variable_definition(job_name, variable_name),
-        )
-        .unwrap();
+        let query = self
+            .query(&TreesitterQueries::get_job_variable_definition(
+                job_name,
+                variable_name,
+            ))
+            .unwrap();
+
+        let value_index = query.capture_index_for_name("variable_value").unwrap();
 
         let mut cursor_qry = QueryCursor::new();
         let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
 
         while let Some(m) = matches.next() {
+            // multiline values keep their indentation so they remain valid yaml
+            let value = m.captures.iter().find(|c| c.index == value_index).map(|c| {
+                let text = &content[c.node.byte_range()];
+                if c.node.start_position().row == c.node.end_position().row {
+                    text.to_string()
+                } else {
+                    format!("{}{text}", " ".repeat(c.node.start_position().column))
+                }
+            });
+
             // Iterate over the captures for this match
             for capture in m.captures {
                 if capture.index == 2 {
@@ -828,7 +1946,7 @@ impl Treesitter for TreesitterImpl {
                         uri: uri.to_string(),
                         key: ParserUtils::strip_quotes(&content[capture.node.byte_range()])
                             .to_string(),
-                        content: None,
+                        content: value,
                         range: Range {
                             start: LSPPosition {
                                 line: u32::try_from(capture.node.start_position().row).unwrap_or(0),
@@ -849,20 +1967,155 @@ impl Treesitter for TreesitterImpl {
         None
     }
 
-    fn get_component_spec_inputs(&self, content: &str) -> Option<String> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
+    fn job_key_definition(
+        &self,
+        uri: &str,
+        content: &str,
+        job_name: &str,
+        path: &[String],
+    ) -> Option<GitlabElement> {
+        let tree = self.tree(uri, content);
+
+        let mut pair = TreesitterImpl::mapping_pair(tree.root_node(), job_name, content)?;
+        for key in path {
+            let value = pair.child_by_field_name("value")?;
+            pair = TreesitterImpl::mapping_pair(value, key, content)?;
+        }
+
+        let key = pair.child_by_field_name("key")?;
+        let text = &content[key.byte_range()];
+
+        Some(GitlabElement {
+            uri: uri.to_string(),
+            key: ParserUtils::strip_quotes(text).to_string(),
+            content: Some(content[pair.byte_range()].to_string()),
+            range: get_range(key, text).ok()?,
+        })
+    }
+
+    // Root key and keys of mappings leading to the key at `position`, e.g.
+    // `("build", ["variables", "URL"])`. Keys nested in sequences aren't
+    // resolved.
+    fn get_key_path(&self, content: &str, position: Position) -> Option<(String, Vec<String>)> {
+        let tree = self.tree("", content);
+        let point = Point {
+            row: position.line as usize,
+            column: position.character as usize,
+        };
+
+        let mut keys: Vec<String> = vec![];
+        let mut node = tree.root_node().descendant_for_point_range(point, point);
+        while let Some(current) = node {
+            match current.kind() {
+                "block_mapping_pair" | "flow_pair" => {
+                    let key = current.child_by_field_name("key")?;
+                    if keys.is_empty()
+                        && !(key.start_position()..=key.end_position()).contains(&point)
+                    {
+                        return None;
+                    }
+
+                    keys.push(ParserUtils::strip_quotes(&content[key.byte_range()]).to_string());
+                }
+                "block_sequence_item" | "flow_sequence" => return None,
+                _ => {}
+            }
+
+            node = current.parent();
+        }
+
+        let job = keys.pop()?;
+        if keys.is_empty() {
+            return None;
+        }
+        keys.reverse();
+
+        Some((job, keys))
+    }
+
+    fn job_parallel_matrix(
+        &self,
+        uri: &str,
+        content: &str,
+        job_name: &str,
+    ) -> Option<GitlabElement> {
+        let tree = self.tree(uri, content);
+        let key = self.parallel_matrix_key(&tree, content, job_name)?;
+
+        let text = &content[key.byte_range()];
+        Some(GitlabElement {
+            uri: uri.to_string(),
+            key: text.to_string(),
+            content: None,
+            range: get_range(key, text).unwrap_or_default(),
+        })
+    }
+
+    fn job_parallel_matrix_combinations(
+        &self,
+        uri: &str,
+        content: &str,
+        job_name: &str,
+    ) -> Vec<Vec<String>> {
+        let tree = self.tree(uri, content);
+        let Some(entries) = self
+            .parallel_matrix_key(&tree, content, job_name)
+            .and_then(|key| {
+                key.parent()?
+                    .parent()?
+                    .parent()?
+                    .child_by_field_name("value")
+            })
+        else {
+            return vec![];
+        };
+
+        let mut combinations = vec![];
+        for entry in sequence_items(entries) {
+            let Some(mapping) = entry.named_child(0) else {
+                continue;
+            };
+
+            let mut entry_combinations: Vec<Vec<String>> = vec![vec![]];
+            let mut cursor = mapping.walk();
+            for pair in mapping.named_children(&mut cursor) {
+                let Some(value) = pair.child_by_field_name("value") else {
+                    continue;
+                };
+
+                let items = sequence_items(value);
+                let values: Vec<String> = if items.is_empty() { vec![value] } else { items }
+                    .into_iter()
+                    .map(|value| {
+                        ParserUtils::strip_quotes(&content[value.byte_range()]).to_string()
+                    })
+                    .collect();
+
+                entry_combinations = entry_combinations
+                    .iter()
+                    .flat_map(|combination| {
+                        values.iter().map(move |value| {
+                            let mut combination = combination.clone();
+                            combination.push(value.clone());
+                            combination
+                        })
+                    })
+                    .collect();
+            }
+
+            combinations.append(&mut entry_combinations);
+        }
+
+        combinations
+    }
 
-        let tree = parser.parse(content, None).unwrap();
+    fn get_component_spec_inputs(&self, content: &str) -> Option<String> {
+        let tree = self.tree("", content);
         let root_node = tree.root_node();
 
-        let query = Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_component_spec_inputs(),
-        )
-        .unwrap();
+        let query = self
+            .query(&TreesitterQueries::get_component_spec_inputs())
+            .unwrap();
 
         let mut cursor_qry = QueryCursor::new();
         let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
@@ -881,19 +2134,12 @@ impl Treesitter for TreesitterImpl {
 
     #[allow(clippy::too_many_lines)]
     fn get_all_components(&self, uri: &str, content: &str) -> Vec<GitlabComponentElement> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
-
-        let tree = parser.parse(content, None).unwrap();
+        let tree = self.tree(uri, content);
         let root_node = tree.root_node();
 
-        let query = Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_all_components(),
-        )
-        .unwrap();
+        let query = self
+            .query(&TreesitterQueries::get_all_components())
+            .unwrap();
 
         let mut cursor_qry = QueryCursor::new();
         let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
@@ -1028,28 +2274,25 @@ impl Treesitter for TreesitterImpl {
         content: &str,
         rule: Option<&str>,
     ) -> Vec<GitlabElement> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
-
-        let tree = parser.parse(content, None).unwrap();
+        let tree = self.tree(uri, content);
         let root_node = tree.root_node();
 
-        let query = Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_all_rule_references(rule),
-        )
-        .unwrap();
+        let query = self
+            .query(&TreesitterQueries::get_all_rule_references(rule))
+            .unwrap();
 
         let mut cursor_qry = QueryCursor::new();
         let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
 
+        let rule_reference_index = query
+            .capture_index_for_name("rule_reference_value")
+            .unwrap();
+
         let mut extends: Vec<GitlabElement> = vec![];
 
         while let Some(mat) = matches.next() {
             for c in mat.captures {
-                if c.index == 2 {
+                if c.index == rule_reference_index {
                     let text = &content[c.node.byte_range()];
                     if c.node.start_position().row != c.node.end_position().row {
                         // sanity check
@@ -1075,18 +2318,10 @@ impl Treesitter for TreesitterImpl {
     }
 
     fn get_root_node_key(&self, uri: &str, content: &str, node_key: &str) -> Option<GitlabElement> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
-
-        let tree = parser.parse(content, None).unwrap();
+        let tree = self.tree(uri, content);
         let root_node = tree.root_node();
 
-        let query = match Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_root_node_key(node_key),
-        ) {
+        let query = match self.query(&TreesitterQueries::get_root_node_key(node_key)) {
             Ok(q) => q,
             Err(err) => {
                 error!(
@@ -1130,19 +2365,10 @@ impl Treesitter for TreesitterImpl {
     }
 
     fn get_all_multi_caches(&self, uri: &str, content: &str) -> Vec<GitlabCacheElement> {
-        let mut parser = tree_sitter::Parser::new();
-        parser
-            .set_language(&tree_sitter_yaml::LANGUAGE.into())
-            .expect("Error loading YAML grammar");
-
-        let tree = parser.parse(content, None).unwrap();
+        let tree = self.tree(uri, content);
         let root_node = tree.root_node();
 
-        let query = Query::new(
-            &tree_sitter_yaml::LANGUAGE.into(),
-            &TreesitterQueries::get_all_caches(),
-        )
-        .unwrap();
+        let query = self.query(&TreesitterQueries::get_all_caches()).unwrap();
 
         let mut cursor_qry = QueryCursor::new();
         let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
@@ -1205,6 +2431,63 @@ impl Treesitter for TreesitterImpl {
 
         components
     }
+    fn get_all_rules_blocks(&self, uri: &str, content: &str) -> Vec<GitlabRulesElement> {
+        let tree = self.tree(uri, content);
+        let root_node = tree.root_node();
+
+        let query = self
+            .query(&TreesitterQueries::get_all_rules_blocks())
+            .unwrap();
+
+        let mut cursor_qry = QueryCursor::new();
+        let mut matches = cursor_qry.matches(&query, root_node, content.as_bytes());
+
+        let job_key_index = query.capture_index_for_name("rules_job_key").unwrap();
+        let job_index = query.capture_index_for_name("rules_job").unwrap();
+        let rules_block_index = query.capture_index_for_name("rules_block").unwrap();
+        let rule_item_index = query.capture_index_for_name("rule_item").unwrap();
+
+        let mut rules_blocks = vec![];
+        while let Some(m) = matches.next() {
+            let mut block = GitlabRulesElement::default();
+
+            for c in m.captures {
+                let text = &content[c.node.byte_range()];
+                match c.index {
+                    idx if idx == job_key_index => {
+                        block.key = ParserUtils::strip_quotes(text).to_string();
+                    }
+                    idx if idx == job_index => block.range = get_node_range(c.node),
+                    idx if idx == rules_block_index => block.rules_range = get_node_range(c.node),
+                    idx if idx == rule_item_index => {
+                        if block.unconditional_rule.is_none()
+                            && TreesitterImpl::is_unconditional_rule(c.node, content)
+                        {
+                            block.unconditional_rule = Some(block.rules.len());
+                        }
+
+                        block.rules.push(GitlabElement {
+                            key: text
+                                .lines()
+                                .next()
+                                .unwrap_or_default()
+                                .trim_start_matches('-')
+                                .trim()
+                                .to_string(),
+                            content: Some(text.to_string()),
+                            uri: uri.to_string(),
+                            range: get_node_range(c.node),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+
+            rules_blocks.push(block);
+        }
+
+        rules_blocks
+    }
 }
 
 fn get_range(node: Node<'_>, text: &str) -> anyhow::Result<Range> {
@@ -1227,6 +2510,83 @@ fn get_range(node: Node<'_>, text: &str) -> anyhow::Result<Range> {
     })
 }
 
+// Values of block or flow sequence `node`, empty if it isn't a sequence.
+fn sequence_items(node: Node<'_>) -> Vec<Node<'_>> {
+    let Some(sequence) = node
+        .named_child(0)
+        .filter(|sequence| ["block_sequence", "flow_sequence"].contains(&sequence.kind()))
+    else {
+        return vec![];
+    };
+
+    let mut cursor = sequence.walk();
+    sequence
+        .named_children(&mut cursor)
+        .filter(|item| item.kind() != "comment")
+        .filter_map(|item| {
+            if item.kind() == "block_sequence_item" {
+                item.named_child(0)
+            } else {
+                Some(item)
+            }
+        })
+        .collect()
+}
+
+// Range of whole node, e.g. pair or block, including quotes of its key.
+fn get_node_range(node: Node<'_>) -> Range {
+    Range {
+        start: LSPPosition {
+            line: u32::try_from(node.start_position().row).unwrap_or(0),
+            character: u32::try_from(node.start_position().column).unwrap_or(0),
+        },
+        end: LSPPosition {
+            line: u32::try_from(node.end_position().row).unwrap_or(0),
+            character: u32::try_from(node.end_position().column).unwrap_or(0),
+        },
+    }
+}
+
+// Range of multi row scalar spans from its first to its last content
+// character, skipping block scalar header and quotes.
+fn get_multiline_range(node: Node<'_>, text: &str) -> anyhow::Result<Range> {
+    let start_row = u32::try_from(node.start_position().row)?;
+    let start_column = u32::try_from(node.start_position().column)?;
+    let is_block_scalar = node.kind() == "block_scalar";
+
+    let lines: Vec<(u32, u32, u32)> = text
+        .lines()
+        .enumerate()
+        .skip(usize::from(is_block_scalar))
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| -> anyhow::Result<(u32, u32, u32)> {
+            let row = start_row + u32::try_from(idx)?;
+            let offset = if idx == 0 { start_column } else { 0 };
+            let indent = u32::try_from(line.len() - line.trim_start().len())?;
+            let trimmed = u32::try_from(line.trim_end().len())?;
+
+            Ok((row, offset + indent, offset + trimmed))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    let (Some(first), Some(last)) = (lines.first(), lines.last()) else {
+        return get_range(node, text);
+    };
+
+    let quoted = u32::from(text.starts_with('\'') || text.starts_with('"'));
+
+    Ok(Range {
+        start: LSPPosition {
+            line: first.0,
+            character: first.1 + quoted,
+        },
+        end: LSPPosition {
+            line: last.0,
+            character: last.2 - quoted,
+        },
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1347,6 +2707,9 @@ forth: 5
 
         let cnts = [cnt_0, cnt_1, cnt_2];
 
+        // range spans whole definition
+        let ranges = [(1, 0, 7, 13), (8, 0, 10, 14), (11, 0, 11, 8)];
+
         for (idx, node) in root_nodes.iter().enumerate() {
             assert_eq!(node.key, keys[idx]);
             assert_eq!(node.uri, uri);
@@ -1355,10 +2718,19 @@ forth: 5
             let content = node.content.clone().unwrap();
 
             assert_eq!(content, cnts[idx]);
+
+            let (start_line, start_character, end_line, end_character) = ranges[idx];
             assert_eq!(
                 node.range,
                 Range {
-                    ..Default::default()
+                    start: LSPPosition {
+                        line: start_line,
+                        character: start_character,
+                    },
+                    end: LSPPosition {
+                        line: end_line,
+                        character: end_character,
+                    },
                 }
             );
         }
@@ -1385,6 +2757,7 @@ forth: 5
         assert_eq!(root_variables.len(), 2);
 
         let vars = ["first_var", "second_var"];
+        let values = ["3", "4"];
         let starts = [
             LSPPosition {
                 line: 7,
@@ -1407,7 +2780,7 @@ forth: 5
         ];
 
         for (idx, var) in root_variables.iter().enumerate() {
-            assert!(var.content.is_none());
+            assert_eq!(var.content.as_deref(), Some(values[idx]));
             assert_eq!(var.uri, uri);
             assert_eq!(var.key, vars[idx]);
             assert_eq!(var.key, vars[idx]);
@@ -1607,6 +2980,97 @@ job_two:
         }
     }
 
+    #[test]
+    fn test_get_all_extends_multiline() {
+        let cnt = r#"
+job_one:
+  extends: >-
+    .first
+  stage: one
+
+job_two:
+  extends: ".very
+    long"
+  stage: two
+
+job_three:
+  extends:
+    - .first
+    - .second
+"#;
+
+        let uri = "file://mocked";
+        let treesitter = TreesitterImpl::new();
+        let all_extends = treesitter.get_all_extends(uri.to_string(), cnt, None);
+
+        let found: Vec<(&str, Range)> = all_extends
+            .iter()
+            .map(|e| (e.key.as_str(), e.range.clone()))
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                (
+                    ".first",
+                    Range {
+                        start: LSPPosition {
+                            line: 3,
+                            character: 4
+                        },
+                        end: LSPPosition {
+                            line: 3,
+                            character: 10
+                        },
+                    }
+                ),
+                (
+                    ".very long",
+                    Range {
+                        start: LSPPosition {
+                            line: 7,
+                            character: 12
+                        },
+                        end: LSPPosition {
+                            line: 8,
+                            character: 8
+                        },
+                    }
+                ),
+                (
+                    ".first",
+                    Range {
+                        start: LSPPosition {
+                            line: 13,
+                            character: 6
+                        },
+                        end: LSPPosition {
+                            line: 13,
+                            character: 12
+                        },
+                    }
+                ),
+                (
+                    ".second",
+                    Range {
+                        start: LSPPosition {
+                            line: 14,
+                            character: 6
+                        },
+                        end: LSPPosition {
+                            line: 14,
+                            character: 13
+                        },
+                    }
+                ),
+            ]
+        );
+
+        let named = treesitter.get_all_extends(uri.to_string(), cnt, Some(".first"));
+        assert_eq!(named.len(), 2);
+        assert_eq!(named[0].range.start.line, 3);
+    }
+
     #[test]
     fn test_get_all_extends_no_results() {
         let cnt = r"
@@ -1675,7 +3139,7 @@ job_two:
         ];
 
         for (idx, need) in all_job_needs.iter().enumerate() {
-            assert!(need.content.is_none());
+            assert_eq!(need.content, Some(format!("job: {}", extends[idx])));
             assert_eq!(need.uri, uri);
             assert_eq!(need.key, extends[idx]);
             assert_eq!(need.key, extends[idx]);
@@ -1719,7 +3183,7 @@ job_two:
         }];
 
         for (idx, need) in all_job_needs.iter().enumerate() {
-            assert!(need.content.is_none());
+            assert_eq!(need.content, Some(format!("job: {}", extends[idx])));
             assert_eq!(need.uri, uri);
             assert_eq!(need.key, extends[idx]);
             assert_eq!(need.key, extends[idx]);
@@ -1824,6 +3288,101 @@ job_two:
         assert!(root_node.is_none());
     }
 
+    #[test]
+    fn test_job_key_definition() {
+        let cnt =
+            ".vars:\n  script:\n    - echo setup\n  variables: {URL: example.com,\n    PORT: 80}\n";
+        let path = |keys: &[&str]| keys.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        let treesitter = TreesitterImpl::new();
+        let key = treesitter
+            .job_key_definition(
+                "file:///a.yaml",
+                cnt,
+                ".vars",
+                &path(&["variables", "PORT"]),
+            )
+            .unwrap();
+
+        assert_eq!(key.key, "PORT");
+        assert_eq!(
+            key.range,
+            Range {
+                start: LSPPosition {
+                    line: 4,
+                    character: 4
+                },
+                end: LSPPosition {
+                    line: 4,
+                    character: 8
+                },
+            }
+        );
+        assert!(treesitter
+            .job_key_definition("file:///a.yaml", cnt, ".vars", &path(&["script", "URL"]))
+            .is_none());
+    }
+
+    #[test]
+    fn test_get_key_path() {
+        let cnt = "build:\n  variables:\n    # comment\n    URL: example.com\n  script:\n    - name: x\n      run: make\n  cache: {key: \"multi\n      line\", paths: [a]}\n";
+        let position = |line, character| Position { line, character };
+
+        let treesitter = TreesitterImpl::new();
+        assert_eq!(
+            treesitter.get_key_path(cnt, position(3, 5)),
+            Some((
+                "build".to_string(),
+                vec!["variables".to_string(), "URL".to_string()]
+            ))
+        );
+        assert_eq!(
+            treesitter.get_key_path(cnt, position(4, 2)),
+            Some(("build".to_string(), vec!["script".to_string()]))
+        );
+        assert_eq!(
+            treesitter.get_key_path(cnt, position(8, 14)),
+            Some((
+                "build".to_string(),
+                vec!["cache".to_string(), "paths".to_string()]
+            ))
+        );
+        assert_eq!(treesitter.get_key_path(cnt, position(3, 12)), None);
+        assert_eq!(treesitter.get_key_path(cnt, position(0, 2)), None);
+        assert_eq!(treesitter.get_key_path(cnt, position(6, 7)), None);
+    }
+
+    #[test]
+    fn test_job_parallel_matrix_combinations() {
+        let cnt = r#"build:
+  parallel:
+    matrix:
+      - PROVIDER: aws
+        STACK: [monitoring, "app1"]
+      # comment
+      - PROVIDER: gcp
+        VERSION:
+          - 2
+      - {PROVIDER: azure, STACK: data}
+test:
+  script: make
+"#;
+
+        let treesitter = TreesitterImpl::new();
+        assert_eq!(
+            treesitter.job_parallel_matrix_combinations("", cnt, "build"),
+            vec![
+                vec!["aws", "monitoring"],
+                vec!["aws", "app1"],
+                vec!["gcp", "2"],
+                vec!["azure", "data"],
+            ]
+        );
+        assert!(treesitter
+            .job_parallel_matrix_combinations("", cnt, "test")
+            .is_empty());
+    }
+
     #[test]
     fn test_job_variable_definition() {
         let cnt = r"
@@ -1847,7 +3406,7 @@ job_one:
 
         let variable_definition = variable_definition.unwrap();
         assert_eq!(variable_definition.key, "SEARCHED");
-        assert!(variable_definition.content.is_none());
+        assert_eq!(variable_definition.content.as_deref(), Some("no"));
         assert_eq!(
             variable_definition.range.start,
             LSPPosition {
@@ -1922,25 +3481,75 @@ job_one:
   image: alpine
   extends: .first
   stage: one
-  variables:
-    SEARCHED: no
-    OTHER: yes
-  needs:
-    - job: job_one
+  variables:
+    SEARCHED: no
+    OTHER: yes
+  needs:
+    - job: job_one
+"#;
+
+        let treesitter = TreesitterImpl::new();
+        let project_file = treesitter.get_position_type(
+            cnt,
+            Position {
+                line: 5,
+                character: 13,
+            },
+        );
+
+        let want_project = "myproject/name";
+        let want_reference = "1.5.0".to_string();
+        let want_file = "\"/resources/ci-templates/mytemplate.yml\"".to_string();
+        match project_file {
+            parser::PositionType::Include(IncludeInformation {
+                remote:
+                    Some(RemoteInclude {
+                        project: Some(project),
+                        reference: Some(reference),
+                        file: Some(file),
+                    }),
+                local: None,
+                remote_url: None,
+                basic: None,
+                component: None,
+            }) => {
+                assert_eq!(want_project, project);
+                assert_eq!(want_reference, reference);
+                assert_eq!(want_file, file);
+            }
+            _ => panic!("project file is invalid"),
+        }
+    }
+
+    #[test]
+    fn test_get_position_type_trigger_project() {
+        let cnt = r#"
+include:
+  - local: ".my-local.yml"
+
+job_one:
+  image: alpine
+  stage: one
+
+child_pipeline:
+  stage: deploy
+  trigger:
+    include:
+      - project: myproject/child
+        ref: main
+        file: "/ci/child.yml"
+    strategy: depend
 "#;
 
         let treesitter = TreesitterImpl::new();
         let project_file = treesitter.get_position_type(
             cnt,
             Position {
-                line: 5,
-                character: 13,
+                line: 14,
+                character: 18,
             },
         );
 
-        let want_project = "myproject/name";
-        let want_reference = "1.5.0".to_string();
-        let want_file = "\"/resources/ci-templates/mytemplate.yml\"".to_string();
         match project_file {
             parser::PositionType::Include(IncludeInformation {
                 remote:
@@ -1954,12 +3563,97 @@ job_one:
                 basic: None,
                 component: None,
             }) => {
-                assert_eq!(want_project, project);
-                assert_eq!(want_reference, reference);
-                assert_eq!(want_file, file);
+                assert_eq!(project, "myproject/child");
+                assert_eq!(reference, "main");
+                assert_eq!(file, "\"/ci/child.yml\"");
+            }
+            _ => panic!("trigger project file is invalid"),
+        }
+    }
+
+    #[test]
+    fn test_get_position_type_rules_changes() {
+        let cnt = r#"
+build:
+  rules:
+    - changes:
+        - src/main.rs
+    - changes: [docs/index.md]
+    - changes:
+        paths:
+          - Cargo.toml
+        compare_to: "refs/heads/main"
+"#;
+
+        let treesitter = TreesitterImpl::new();
+        for position in [
+            Position::new(4, 12),
+            Position::new(5, 19),
+            Position::new(8, 14),
+        ] {
+            assert!(
+                matches!(
+                    treesitter.get_position_type(cnt, position),
+                    parser::PositionType::ChangesPath
+                ),
+                "changes path is invalid at {position:?}"
+            );
+        }
+
+        assert!(matches!(
+            treesitter.get_position_type(cnt, Position::new(9, 25)),
+            parser::PositionType::ChangesCompareTo
+        ));
+    }
+
+    #[test]
+    fn test_get_position_type_trigger_local() {
+        let cnt = r#"
+child_pipeline:
+  stage: deploy
+  trigger:
+    include:
+      - local: "ci/child.yml"
+      - project: myproject/child
+        file: "/ci/child.yml"
+    strategy: depend
+
+single_child:
+  trigger:
+    include: ci/single.yml
+"#;
+
+        let treesitter = TreesitterImpl::new();
+        for (position, want) in [
+            (Position::new(5, 18), "\"ci/child.yml\""),
+            (Position::new(12, 16), "ci/single.yml"),
+        ] {
+            match treesitter.get_position_type(cnt, position) {
+                parser::PositionType::Include(IncludeInformation {
+                    local: Some(Include { path }),
+                    remote: None,
+                    remote_url: None,
+                    basic: None,
+                    component: None,
+                }) => assert_eq!(path, want),
+                _ => panic!("trigger local file is invalid at {position:?}"),
             }
-            _ => panic!("project file is invalid"),
         }
+
+        assert!(matches!(
+            treesitter.get_position_type(cnt, Position::new(3, 4)),
+            parser::PositionType::Trigger
+        ));
+
+        // project item is still resolved as project include
+        assert!(matches!(
+            treesitter.get_position_type(cnt, Position::new(7, 18)),
+            parser::PositionType::Include(IncludeInformation {
+                remote: Some(_),
+                local: None,
+                ..
+            })
+        ));
     }
 
     #[test]
@@ -2331,13 +4025,129 @@ job_one:
 
         let want_node = ".rules:job";
         match pos_type {
-            parser::PositionType::RuleReference(RuleReference { node }) => {
+            parser::PositionType::RuleReference(RuleReference { node, .. }) => {
                 assert_eq!(want_node, node);
             }
             _ => panic!("invalid type"),
         }
     }
 
+    #[test]
+    fn test_get_position_type_step_reference() {
+        let cnt = r"
+.setup:
+  script:
+    - echo setup
+job_one:
+  run:
+    - name: build
+      script: make $TARGET
+    - name: setup
+      script:
+        - !reference ['.setup', script]
+";
+
+        let treesitter = TreesitterImpl::new();
+        match treesitter.get_position_type(
+            cnt,
+            Position {
+                line: 10,
+                character: 24,
+            },
+        ) {
+            parser::PositionType::RuleReference(RuleReference { node, .. }) => {
+                assert_eq!(node, ".setup");
+            }
+            _ => panic!("invalid type"),
+        }
+
+        match treesitter.get_position_type(
+            cnt,
+            Position {
+                line: 7,
+                character: 22,
+            },
+        ) {
+            parser::PositionType::Script(ScriptDefinition { key }) => {
+                assert_eq!(key, "script");
+            }
+            _ => panic!("invalid type"),
+        }
+    }
+
+    #[test]
+    fn test_get_position_type_reference_path() {
+        let cnt = r"
+.setup:
+  script:
+    - echo setup
+job:
+  script: !reference [.setup, script]
+  variables:
+    URL: !reference ['.vars', variables, URL]
+  after_script:
+    - echo done # !reference
+";
+
+        let treesitter = TreesitterImpl::new();
+        for (line, character, want_node, want_path) in [
+            (5, 24, ".setup", vec!["script"]),
+            (7, 23, ".vars", vec!["variables", "URL"]),
+        ] {
+            match treesitter.get_position_type(cnt, Position { line, character }) {
+                parser::PositionType::RuleReference(RuleReference { node, path }) => {
+                    assert_eq!(node, want_node);
+                    assert_eq!(path, want_path);
+                }
+                other => panic!("invalid type: {other:?}"),
+            }
+        }
+
+        // text mentioning the tag isn't a reference
+        match treesitter.get_position_type(
+            cnt,
+            Position {
+                line: 9,
+                character: 10,
+            },
+        ) {
+            parser::PositionType::Script(ScriptDefinition { key }) => {
+                assert_eq!(key, "after_script");
+            }
+            other => panic!("invalid type: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_get_position_type_entrypoint_and_command() {
+        let cnt = r#"
+job:
+  image:
+    name: alpine
+    entrypoint: ["/bin/sh", "-c", "$ENTRY"]
+  services:
+    - name: postgres
+      command: ["postgres", "-c", "$PG_OPTS"]
+  script: make
+"#;
+
+        let treesitter = TreesitterImpl::new();
+        for (line, expected) in [(4, "entrypoint"), (7, "command")] {
+            match treesitter.get_position_type(
+                cnt,
+                Position {
+                    line,
+                    character: 38,
+                },
+            ) {
+                parser::PositionType::Script(ScriptDefinition { key }) => {
+                    assert_eq!(key, expected);
+                }
+                other => panic!("invalid type: {other:?}"),
+            }
+        }
+    }
+
     #[test]
     fn test_get_position_type_rule_reference_double_quote() {
         let cnt = r#"
@@ -2368,13 +4178,153 @@ job_one:
 
         let want_node = ".rules:job";
         match pos_type {
-            parser::PositionType::RuleReference(RuleReference { node }) => {
+            parser::PositionType::RuleReference(RuleReference { node, .. }) => {
                 assert_eq!(want_node, node);
             }
             _ => panic!("invalid type"),
         }
     }
 
+    #[test]
+    fn test_get_position_type_service_mapping() {
+        let cnt = r#"
+job_one:
+  image: alpine
+  services:
+    - docker:dind
+    - name: "registry.example.com/group/postgres:15"
+      alias: db
+"#;
+
+        let treesitter = TreesitterImpl::new();
+        let pos_type = treesitter.get_position_type(
+            cnt,
+            Position {
+                line: 5,
+                character: 20,
+            },
+        );
+
+        let parser::PositionType::Service(service) = pos_type else {
+            panic!("expected service position type, got {pos_type:?}");
+        };
+
+        assert_eq!(service.image, "registry.example.com/group/postgres:15");
+        assert_eq!(service.alias, Some("db".to_string()));
+    }
+
+    #[test]
+    fn test_get_all_local_includes() {
+        let cnt = r#"
+include:
+  - local: "/templates/build.yml"
+  - "templates/test.yml"
+  - remote: "https://myremote.com/template.yml"
+  - "https://myremote.com/other.yml"
+  - project: myproject/name
+    file: "/templates/build.yml"
+
+job_one:
+  image: alpine
+"#;
+
+        let treesitter = TreesitterImpl::new();
+        let includes = treesitter.get_all_local_includes("file:///.gitlab-ci.yml", cnt);
+
+        let keys: Vec<&str> = includes.iter().map(|i| i.key.as_str()).collect();
+        assert_eq!(keys, vec!["/templates/build.yml", "templates/test.yml"]);
+        assert_eq!(includes[0].range.start.line, 2);
+        assert_eq!(includes[0].range.start.character, 12);
+    }
+
+    #[test]
+    fn test_get_all_exit_codes() {
+        let cnt = r"
+job_one:
+  allow_failure:
+    exit_codes: 137
+job_two:
+  allow_failure:
+    exit_codes: [1, 2]
+job_three:
+  allow_failure:
+    exit_codes:
+      - 3
+      - four
+";
+
+        let treesitter = TreesitterImpl::new();
+        let exit_codes = treesitter.get_all_exit_codes("file://mocked", cnt);
+
+        let keys: Vec<&str> = exit_codes.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, vec!["137", "1", "2", "3", "four"]);
+    }
+
+    #[test]
+    fn test_get_all_deployment_tiers() {
+        let cnt = r"
+job_one:
+  environment: production
+job_two:
+  environment:
+    name: staging
+    deployment_tier: staging
+";
+
+        let treesitter = TreesitterImpl::new();
+        let tiers = treesitter.get_all_deployment_tiers("file://mocked", cnt);
+
+        assert_eq!(tiers.len(), 1);
+        assert_eq!(tiers[0].key, "staging");
+        assert_eq!(tiers[0].range.start.line, 6);
+        assert_eq!(tiers[0].range.start.character, 21);
+    }
+
+    #[test]
+    fn test_get_all_cache_key_files() {
+        let cnt = r#"
+job_one:
+  cache:
+    key:
+      files:
+        - Gemfile.lock
+        - "package.json"
+job_two:
+  cache:
+    - key:
+        files: [yarn.lock]
+      paths:
+        - node_modules
+"#;
+
+        let treesitter = TreesitterImpl::new();
+        let files = treesitter.get_all_cache_key_files("file://mocked", cnt);
+
+        let keys: Vec<&str> = files.iter().map(|f| f.key.as_str()).collect();
+        assert_eq!(keys, vec!["Gemfile.lock", "package.json", "yarn.lock"]);
+    }
+
+    #[test]
+    fn test_get_position_type_allow_failure() {
+        let cnt = r"
+job_one:
+  image: alpine
+  allow_failure:
+    exit
+";
+
+        let treesitter = TreesitterImpl::new();
+        let pos_type = treesitter.get_position_type(
+            cnt,
+            Position {
+                line: 4,
+                character: 8,
+            },
+        );
+
+        assert!(matches!(pos_type, parser::PositionType::AllowFailure));
+    }
+
     #[test]
     fn test_get_all_multi_caches() {
         let cnt = r"
@@ -2405,4 +4355,218 @@ job_one:
         assert_eq!(1, all_multi_caches.len());
         assert_eq!(2, all_multi_caches[0].cache_items.len());
     }
+
+    #[test]
+    fn test_get_position_type_script() {
+        let cnt = r"
+job_one:
+  before_script:
+    - echo $BEFORE
+  script:
+    - echo $SCRIPT
+  after_script:
+    - echo $AFTER
+";
+
+        let treesitter = TreesitterImpl::new();
+
+        for (line, expected) in [(3, "before_script"), (5, "script"), (7, "after_script")] {
+            let pos_type = treesitter.get_position_type(cnt, Position::new(line, 12));
+
+            let parser::PositionType::Script(script) = pos_type else {
+                panic!("expected script position type, got {pos_type:?}");
+            };
+
+            assert_eq!(script.key, expected);
+        }
+    }
+
+    #[test]
+    fn test_get_all_docker_platforms() {
+        let cnt = r"
+job_one:
+  image: alpine
+job_two:
+  image:
+    name: alpine
+    docker:
+      platform: linux/arm64
+      user: dave
+";
+
+        let treesitter = TreesitterImpl::new();
+        let platforms = treesitter.get_all_docker_platforms("file://mocked", cnt);
+
+        assert_eq!(platforms.len(), 1);
+        assert_eq!(platforms[0].key, "linux/arm64");
+        assert_eq!(platforms[0].range.start.line, 7);
+        assert_eq!(platforms[0].range.start.character, 16);
+
+        let users = treesitter.get_all_docker_users("file://mocked", cnt);
+
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].key, "dave");
+        assert_eq!(users[0].range.start.line, 8);
+        assert_eq!(users[0].range.start.character, 12);
+    }
+
+    #[test]
+    fn test_get_all_rules_blocks() {
+        let cnt = r#"
+workflow:
+  rules:
+    - if: $CI_PIPELINE_SOURCE == "push"
+"job one":
+  script: echo
+  rules:
+    - if: $CI_COMMIT_TAG
+      when: never
+    - when: always
+job_two:
+  script: echo
+"#;
+
+        let treesitter = TreesitterImpl::new();
+        let blocks = treesitter.get_all_rules_blocks("file://mocked", cnt);
+
+        let keys: Vec<&str> = blocks.iter().map(|b| b.key.as_str()).collect();
+        assert_eq!(keys, vec!["workflow", "job one"]);
+
+        let job = &blocks[1];
+        assert_eq!(job.range.start.line, 4);
+        assert_eq!(job.rules_range.start.line, 6);
+        assert_eq!(job.rules.len(), 2);
+        assert_eq!(job.rules[0].key, "if: $CI_COMMIT_TAG");
+        assert_eq!(job.rules[0].range.end.line, 8);
+    }
+
+    #[test]
+    fn test_queries_are_compiled_once() {
+        let treesitter = TreesitterImpl::new();
+        let content = r"
+.base:
+  script: echo
+
+job:
+  extends: .base
+  stage: build
+";
+
+        treesitter.get_all_extends("file:///.gitlab-ci.yml".to_string(), content, None);
+        let query = treesitter
+            .query(&TreesitterQueries::get_all_extends())
+            .unwrap();
+
+        for _ in 0..10 {
+            treesitter.get_all_extends("file:///.gitlab-ci.yml".to_string(), content, None);
+            treesitter.get_all_stages("file:///.gitlab-ci.yml", content, None);
+        }
+
+        let cached = treesitter
+            .query(&TreesitterQueries::get_all_extends())
+            .unwrap();
+        assert!(Arc::ptr_eq(&query, &cached));
+        assert_eq!(treesitter.queries.lock().unwrap().queries.len(), 2);
+
+        // dynamic queries are cached per argument
+        treesitter.get_all_stages("file:///.gitlab-ci.yml", content, Some("build"));
+        treesitter.get_all_stages("file:///.gitlab-ci.yml", content, Some("build"));
+        assert_eq!(treesitter.queries.lock().unwrap().queries.len(), 3);
+    }
+
+    #[test]
+    fn test_query_cache_is_bounded() {
+        let treesitter = TreesitterImpl::new();
+        let content = "job:\n  stage: build\n";
+
+        let extends = treesitter
+            .query(&TreesitterQueries::get_all_extends())
+            .unwrap();
+        for i in 0..MAX_CACHED_QUERIES + 10 {
+            treesitter.get_all_stages("file:///.gitlab-ci.yml", content, Some(&format!("s{i}")));
+            // recently used queries are kept
+            treesitter
+                .query(&TreesitterQueries::get_all_extends())
+                .unwrap();
+        }
+
+        let cache = treesitter.queries.lock().unwrap();
+        assert_eq!(cache.queries.len(), MAX_CACHED_QUERIES);
+        assert!(Arc::ptr_eq(
+            &extends,
+            &cache.queries[&TreesitterQueries::get_all_extends()].0
+        ));
+        assert!(!cache
+            .queries
+            .contains_key(&TreesitterQueries::get_all_stages(Some("s0"))));
+    }
+
+    #[test]
+    fn test_edit_tree_reparses_incrementally() {
+        let treesitter = TreesitterImpl::new();
+        let uri = "file:///.gitlab-ci.yml";
+        let content = "job:\n  stage: build\n  script: make\n";
+        treesitter.update_tree(uri, content);
+
+        let mut edited = content.to_string();
+        let edit = ParserUtils::input_edit(&edited, 14, 19, "test");
+        edited.replace_range(14..19, "test");
+        treesitter.edit_tree(uri, content, &[edit], &edited);
+
+        let (hash, tree) = treesitter.trees.lock().unwrap()[uri].clone();
+        assert_eq!(hash, TreesitterImpl::content_hash(&edited));
+        assert_eq!(
+            tree.root_node().to_sexp(),
+            treesitter
+                .parser
+                .lock()
+                .unwrap()
+                .parse(&edited, None)
+                .unwrap()
+                .root_node()
+                .to_sexp()
+        );
+        assert_eq!(treesitter.get_all_stages(uri, &edited, None)[0].key, "test");
+    }
+
+    #[test]
+    fn test_trees_are_reused_per_content() {
+        let treesitter = TreesitterImpl::new();
+        let uri = "file:///.gitlab-ci.yml";
+        let content = "job:\n  stage: build\n";
+        let cached_hash = |treesitter: &TreesitterImpl| {
+            treesitter
+                .trees
+                .lock()
+                .unwrap()
+                .get(uri)
+                .map(|(hash, _)| *hash)
+        };
+
+        treesitter.update_tree(uri, content);
+        treesitter.get_all_stages(uri, content, None);
+        assert_eq!(
+            cached_hash(&treesitter),
+            Some(TreesitterImpl::content_hash(content))
+        );
+
+        // job definitions parsed under document URI don't replace its tree
+        treesitter.get_all_stages(uri, "stage: test\n", None);
+        assert_eq!(
+            cached_hash(&treesitter),
+            Some(TreesitterImpl::content_hash(content))
+        );
+
+        // only latest version is kept
+        let changed = "job:\n  stage: test\n";
+        treesitter.update_tree(uri, changed);
+        assert_eq!(treesitter.trees.lock().unwrap().len(), 1);
+        assert_eq!(
+            cached_hash(&treesitter),
+            Some(TreesitterImpl::content_hash(changed))
+        );
+
+        treesitter.drop_tree(uri);
+        assert_eq!(cached_hash(&treesitter), None);
+    }
 }
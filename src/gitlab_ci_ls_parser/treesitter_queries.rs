@@ -1,31 +1,26 @@
 pub struct TreesitterQueries {}
 
 impl TreesitterQueries {
-    pub fn get_all_extends(extend_name: Option<&str>) -> String {
-        let mut search = String::new();
-        if extend_name.is_some() {
-            let extend_name = extend_name.unwrap();
-            search = format!(
-                r#"(#any-of? @value "{extend_name}" "'{extend_name}'" "\"{extend_name}\"")"#,
-            );
-        }
-
-        format!(
-            r#"
+    // Values can span multiple rows (block scalars, folded quoted strings)
+    // so they are filtered by name after their logical value is extracted.
+    pub fn get_all_extends() -> String {
+        r#"
         (
             block_mapping_pair
             key: (flow_node) @key
             value: [
-                (flow_node(plain_scalar(string_scalar))) @value
+                (flow_node[(plain_scalar(string_scalar)) (double_quote_scalar) (single_quote_scalar)]) @value
+                (block_node(block_scalar) @value)
                 (block_node(block_sequence(block_sequence_item(flow_node)@value)))
             ]
             (#eq? @key "extends")
-            {search}
         )
         "#
-        )
+        .to_string()
     }
 
+    // Root keys can be quoted when they contain spaces or other special
+    // characters, e.g. ".my template":
     pub fn get_root_node(node_key: &str) -> String {
         format!(
             r#"
@@ -35,12 +30,12 @@ impl TreesitterQueries {
                     block_node(
                         block_mapping(
                             block_mapping_pair
-                                key: (flow_node(plain_scalar(string_scalar)@key))
+                                key: (flow_node[(plain_scalar(string_scalar)) (double_quote_scalar) (single_quote_scalar)]@key)
                         )@value
                     )
                 )
             )
-            (#eq? @key "{node_key}")
+            (#any-of? @key "{node_key}" "'{node_key}'" "\"{node_key}\"")
         )
         "#
         )
@@ -55,12 +50,12 @@ impl TreesitterQueries {
                     block_node(
                         block_mapping(
                             block_mapping_pair
-                                key: (flow_node(plain_scalar(string_scalar)@key))
+                                key: (flow_node[(plain_scalar(string_scalar)) (double_quote_scalar) (single_quote_scalar)]@key)
                         )
                     )
                 )
             )
-            (#eq? @key "{node_key}")
+            (#any-of? @key "{node_key}" "'{node_key}'" "\"{node_key}\"")
         )
         "#
         )
@@ -74,7 +69,7 @@ impl TreesitterQueries {
                 block_node(
                     block_mapping(
                     block_mapping_pair
-                        key: (flow_node(plain_scalar(string_scalar)@key))
+                        key: (flow_node[(plain_scalar(string_scalar)) (double_quote_scalar) (single_quote_scalar)]@key)
                     )@value
                 )
                 )
@@ -97,6 +92,7 @@ impl TreesitterQueries {
                                     block_mapping(
                                         block_mapping_pair
                                             key: (flow_node(plain_scalar(string_scalar)@env_key))
+                                            value: (_)? @env_value
                                     )
                                 )
                             )
@@ -125,8 +121,8 @@ impl TreesitterQueries {
 
     pub fn get_all_stages(stage: Option<&str>) -> String {
         let mut search = String::new();
-        if stage.is_some() {
-            search = format!("(#eq? @value \"{}\")", stage.unwrap());
+        if let Some(stage) = stage {
+            search = format!("(#eq? @value \"{stage}\")");
         }
 
         format!(
@@ -162,36 +158,21 @@ impl TreesitterQueries {
 
     pub fn get_all_rule_references(rule: Option<&str>) -> String {
         let mut search = String::new();
-        if rule.is_some() {
-            let rule = rule.unwrap();
+        if let Some(rule) = rule {
             search =
-                format!(r#"(#any-of? @rule_reference_value "{rule}" "'{rule}'" "\"{rule}\"")"#,);
+                format!(r#"(#any-of? @rule_reference_value "{rule}" "'{rule}'" "\"{rule}\"")"#);
         }
 
         format!(
             r#"
         (
-            block_mapping_pair
-            key: (flow_node) @rule_reference_key
-            value: (
-                block_node(
-                    block_sequence(
-                        block_sequence_item(
-                            flow_node
-                            (
-                              (tag)@rule_reference_tag
-                              (
-                                flow_sequence(
-                                    (flow_node[(single_quote_scalar)(double_quote_scalar)])@rule_reference_value
-                                    (_)?
-                                )
-                               )
-                            )
-                        )
-                    )
-                )
-            )
-            (#eq? @rule_reference_key "rules")
+            flow_node
+            (tag)@rule_reference_tag
+            (
+                flow_sequence
+                .
+                (flow_node)@rule_reference_value
+            )@rule_reference_path
             (#eq? @rule_reference_tag "!reference")
             {search}
         )
@@ -206,7 +187,8 @@ impl TreesitterQueries {
                 block_mapping_pair
                 key: (flow_node) @keyextends
                 value: [
-                    (flow_node(plain_scalar(string_scalar))) @extends
+                    (flow_node[(plain_scalar(string_scalar)) (double_quote_scalar) (single_quote_scalar)]) @extends
+                    (block_node(block_scalar) @extends)
                     (block_node(block_sequence(block_sequence_item) @extends))
                 ]
                 (#eq? @keyextends "extends")
@@ -251,6 +233,23 @@ impl TreesitterQueries {
             )
         "#;
 
+        let search_scripts = r#"
+            (
+                block_mapping_pair
+                key: (
+                    flow_node(
+                        plain_scalar(string_scalar)  @keyscript
+                    )
+                )
+                value: [
+                    (block_node(block_sequence(block_sequence_item) @script))
+                    (block_node(block_scalar) @script)
+                    (flow_node) @script
+                ]
+                (#any-of? @keyscript "before_script" "script" "after_script" "entrypoint" "command")
+            )
+        "#;
+
         let search_variables = r#"
             (
                 block_mapping_pair
@@ -263,6 +262,7 @@ impl TreesitterQueries {
                 (
                     block_node(
                         block_mapping(block_mapping_pair
+                            key: (flow_node) @image_attribute_key
                             value:
                                 [
                                     (flow_node(flow_sequence(flow_node) ))
@@ -272,6 +272,7 @@ impl TreesitterQueries {
                     )
                 )
                 (#eq? @keyvariable "image")
+                (#not-eq? @image_attribute_key "entrypoint")
             )
             (
                 block_mapping_pair
@@ -304,40 +305,20 @@ impl TreesitterQueries {
                 value:
                 (
                     block_node(
-                        block_sequence(block_sequence_item) @variable
-                    )
-                )
-                (#eq? @keyvariable "before_script")
-            )
-            (
-                block_mapping_pair
-                key: (
-                    flow_node(
-                        plain_scalar(string_scalar)  @keyvariable
-                    )
-                )
-                value:
-                (
-                    block_node(
-                        block_sequence(block_sequence_item) @variable
-                    )
-                )
-                (#eq? @keyvariable "script")
-            )
-            (
-                block_mapping_pair
-                key: (
-                    flow_node(
-                        plain_scalar(string_scalar)  @keyvariable
-                    )
-                )
-                value:
-                (
-                    block_node(
-                        block_sequence(block_sequence_item) @variable
+                        block_mapping(block_mapping_pair
+                            value: (
+                                block_node(
+                                    block_mapping(block_mapping_pair
+                                        key: (flow_node(plain_scalar(string_scalar)@keyvariablevalue))
+                                        value: (flow_node)@variable
+                                    )
+                                )
+                            )
+                        )
                     )
                 )
-                (#eq? @keyvariable "after_script")
+                (#eq? @keyvariable "variables")
+                (#eq? @keyvariablevalue "value")
             )
             (
                 block_mapping_pair
@@ -355,7 +336,7 @@ impl TreesitterQueries {
                                     block_mapping(
                                         block_mapping_pair
                                             key: (flow_node(plain_scalar))
-                                            value: (flow_node)@variable
+                                            value: [(flow_node)@variable (block_node(block_scalar)@variable)]
                                     )
                                 )
                             )
@@ -391,7 +372,7 @@ impl TreesitterQueries {
                         block_node(
                             block_mapping(
                                 block_mapping_pair
-                                    key: (flow_node(plain_scalar(string_scalar)@rootnode))
+                                    key: (flow_node[(plain_scalar(string_scalar)) (double_quote_scalar) (single_quote_scalar)]@rootnode)
                             )
                         )
                     )
@@ -507,6 +488,98 @@ impl TreesitterQueries {
             )
         "#;
 
+        // child pipelines reuse the same captures as project includes so they are
+        // resolved the same way
+        let search_trigger_project_includes = r#"
+            (
+                block_mapping_pair
+                    key: (flow_node(plain_scalar(string_scalar)@trigger_key))
+                    value: (
+                        block_node(
+                            block_mapping(
+                                block_mapping_pair
+                                    key: (flow_node(plain_scalar(string_scalar)@trigger_include_key))
+                                    value: (
+                                        block_node(
+                                            block_sequence(
+                                                block_sequence_item(
+                                                    block_node
+                                                    [
+                                                        (
+                                                            block_mapping(
+                                                                block_mapping_pair
+                                                                    key: (flow_node(plain_scalar(string_scalar)@project_key))
+                                                                    value: (flow_node)@project_value
+                                                            )
+                                                        )
+                                                        (
+                                                            block_mapping(
+                                                                block_mapping_pair
+                                                                    key: (flow_node(plain_scalar(string_scalar)@ref_key))
+                                                                    value: (flow_node)@ref_value
+                                                            )
+                                                        )
+                                                        (
+                                                            block_mapping(
+                                                            block_mapping_pair
+                                                                key: (flow_node(plain_scalar(string_scalar)@file_key))
+                                                                value: [(block_node(block_sequence(block_sequence_item(flow_node)@file_value)))(flow_node)@file_value]
+                                                            )
+                                                        )
+                                                    ]
+                                                )
+                                            )@remote_include_item
+                                        )
+                                    )
+                            )
+                        )
+                    )
+                (#eq? @trigger_key "trigger")
+                (#eq? @trigger_include_key "include")
+                (#eq? @ref_key "ref")
+                (#eq? @project_key "project")
+                (#eq? @file_key "file")
+            )
+        "#;
+
+        // local child pipeline files, either as `local:` entries or a single
+        // path, reuse local include captures
+        let search_trigger_local_includes = r#"
+            (
+                block_mapping_pair
+                    key: (flow_node(plain_scalar(string_scalar)@trigger_local_key))
+                    value: (
+                        block_node(
+                            block_mapping(
+                                block_mapping_pair
+                                    key: (flow_node(plain_scalar(string_scalar)@trigger_local_include_key))
+                                    value: [
+                                        (flow_node)@local_value
+                                        (
+                                            block_node(
+                                                block_sequence(
+                                                    block_sequence_item(
+                                                        block_node(
+                                                            block_mapping(
+                                                                block_mapping_pair
+                                                                    key: (flow_node(plain_scalar(string_scalar)@local_key))
+                                                                    value: (flow_node)@local_value
+                                                            )
+                                                        )
+                                                    )
+                                                )
+                                            )
+                                        )
+                                    ]
+                            )
+                        )
+                    )
+                (#eq? @trigger_local_key "trigger")
+                (#eq? @trigger_local_include_key "include")
+                (#eq? @local_key "local")
+            )
+        "#;
+
         // component_input_error is when user start typing new key and its an error because it
         // needs to be a map
         let search_component_include = r#"
@@ -543,6 +616,26 @@ impl TreesitterQueries {
         )
         "#;
 
+        // matches component uri even without inputs or a value so uri can be
+        // autocompleted while its being typed; component_new_key is a key of
+        // the include item being typed, e.g. `inputs`
+        let search_component_uri = r#"
+        (
+            block_sequence_item(
+                block_node(
+                    block_mapping(
+                        (block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@component_uri_key))
+                            value: (flow_node)? @component_uri_value
+                        ) @component_uri_pair
+                        (ERROR(flow_node(plain_scalar(string_scalar)@component_new_key)))?
+                    )
+                )
+            )
+            (#eq? @component_uri_key "component")
+        )
+        "#;
+
         let search_job_needs = r#"
             (
                 block_mapping_pair
@@ -567,6 +660,15 @@ impl TreesitterQueries {
             )
         "#;
 
+        // value is checked in code as it's either missing or a word being typed
+        let search_empty_needs = r#"
+            (
+                block_mapping_pair
+                    key: (flow_node(plain_scalar(string_scalar)@empty_needs_key))
+                (#eq? @empty_needs_key "needs")
+            )@empty_needs
+        "#;
+
         let search_remote_urls = r#"
             (
                 stream(
@@ -599,206 +701,1161 @@ impl TreesitterQueries {
             )
         "#;
 
-        // (_)? means optional any node
+        // `!reference` can be used under any key; first element is the node
+        // and the rest is path to referenced key
         let search_rule_references = r#"
+        (
+            flow_node
+            (tag)@rule_reference_tag
+            (
+                flow_sequence
+                .
+                (flow_node)@rule_reference_value
+            )@rule_reference_path
+            (#eq? @rule_reference_tag "!reference")
+        )
+        "#;
+
+        let search_services = r#"
         (
             block_mapping_pair
-            key: (flow_node) @rule_reference_key
+            key: (flow_node) @services_key
             value: (
                 block_node(
                     block_sequence(
-                        block_sequence_item(
-                            flow_node
-                            (
-                              (tag)@rule_reference_tag
-                              (
-                                flow_sequence(
-                                    (flow_node[(single_quote_scalar)(double_quote_scalar)])@rule_reference_value
-                                    (_)?
-                                )
-                               )
-                            )
-                        )
+                        (block_sequence_item
+                            [
+                                (flow_node)@service_image
+                                (block_node(
+                                    block_mapping(
+                                        (block_mapping_pair
+                                            key: (flow_node)@service_attribute_key
+                                            value: (_)?@service_attribute_value
+                                        )*
+                                    )
+                                ))
+                            ]
+                        )@service_item
                     )
                 )
             )
-            (#eq? @rule_reference_key "rules")
-            (#eq? @rule_reference_tag "!reference")
+            (#eq? @services_key "services")
         )
         "#;
 
-        format!(
-            r#"
-            {search_rule_references}
-            {search_extends}
-            {search_stages}
-            {search_variables}
-            {search_root_node}
-            {search_local_include}
-            {search_project_includes}
-            {search_component_include}
-            {search_job_needs}
-            {search_remote_urls}
-            {search_basic_include}
-            {search_dependencies}
-        "#
+        let search_allow_failure = r#"
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@allow_failure_key))
+            value: [
+                (flow_node(plain_scalar))@allow_failure
+                (block_node(block_mapping(block_mapping_pair key: (flow_node)@allow_failure)))
+            ]
+            (#eq? @allow_failure_key "allow_failure")
         )
-    }
-
-    pub fn get_all_job_needs(needs_name: Option<&str>) -> String {
-        let mut search = String::new();
-        if needs_name.is_some() {
-            let needs_name = needs_name.unwrap();
-            search = format!(
-                r#"(#any-of? @needs_job_value "{needs_name}" "'{needs_name}'" "\"{needs_name}\"")"#,
-            );
-        }
+        "#;
 
-        format!(
-            r#"
-            (
-                block_mapping_pair
-                    key: (flow_node)@needs_key
-                    value: (
-                    block_node(
-                        block_sequence(
-                        block_sequence_item(
-                            block_node(
-                            block_mapping(
-                                block_mapping_pair
-                                key: (flow_node)@needs_job_key
-                                value: (flow_node)@needs_job_value
-                            )
-                            )
-                        )
-                        )
+        let search_deployment_tier = r#"
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@environment_key))
+            value: (
+                block_node(
+                    block_mapping(
+                        block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@deployment_tier_key))
+                            value: (flow_node)@deployment_tier
                     )
                 )
-                (#eq? @needs_key "needs")
-                (#eq? @needs_job_key "job")
-                {search}
             )
-        "#
+            (#eq? @environment_key "environment")
+            (#eq? @deployment_tier_key "deployment_tier")
         )
-    }
+        "#;
 
-    pub fn get_root_node_at_position() -> String {
-        r"
+        let search_image = r#"
         (
-            stream(
-                document(
-                    block_node(
-                        block_mapping(
-                            block_mapping_pair
-                                key: (flow_node(plain_scalar(string_scalar)@key))
-                        )@full
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@image_key))
+            value: (flow_node)@image
+            (#eq? @image_key "image")
+        )
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@image_key))
+            value: (
+                block_node(
+                    block_mapping(
+                        block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@image_name_key))
+                            value: (flow_node)@image
                     )
                 )
             )
+            (#eq? @image_key "image")
+            (#eq? @image_name_key "name")
         )
-        "
-        .to_string()
-    }
+        "#;
 
-    pub fn get_job_variable_definition(job_name: &str, variable_name: &str) -> String {
-        format!(
-            r#"
+        let search_docker_platform = r#"
         (
-            stream(
-                document(
-                    block_node(
-                        block_mapping(
-                            block_mapping_pair
-                                key: (flow_node(plain_scalar(string_scalar)@key))
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@docker_image_key))
+            value: (
+                block_node(
+                    block_mapping(
+                        block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@docker_key))
+                            value: (
+                                block_node(
+                                    block_mapping(
+                                        block_mapping_pair
+                                            key: (flow_node(plain_scalar(string_scalar)@docker_platform_key))
+                                            value: (flow_node)@docker_platform
+                                    )
+                                )
+                            )
+                    )
+                )
+            )
+            (#eq? @docker_image_key "image")
+            (#eq? @docker_key "docker")
+            (#eq? @docker_platform_key "platform")
+        )
+        "#;
+
+        let search_docker_user = r#"
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@docker_image_key))
+            value: (
+                block_node(
+                    block_mapping(
+                        block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@docker_key))
+                            value: (
+                                block_node(
+                                    block_mapping(
+                                        block_mapping_pair
+                                            key: (flow_node(plain_scalar(string_scalar)@docker_user_key))
+                                            value: (flow_node)@docker_user
+                                    )
+                                )
+                            )
+                    )
+                )
+            )
+            (#eq? @docker_image_key "image")
+            (#eq? @docker_key "docker")
+            (#eq? @docker_user_key "user")
+        )
+        "#;
+
+        // `trigger` key of a job
+        let search_trigger = r#"
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                value: (
+                                    block_node(
+                                        block_mapping(
+                                            block_mapping_pair
+                                                key: (flow_node(plain_scalar(string_scalar)@trigger))
+                                        )
+                                    )
+                                )
+                        )
+                    )
+                )
+            )
+            (#eq? @trigger "trigger")
+        )
+        "#;
+
+        // `changes` accepts list of paths or object with `paths` and `compare_to`
+        let search_changes = r#"
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@changes_key))
+            value: [
+                (block_node(block_sequence(block_sequence_item(flow_node)@changes_path)))
+                (flow_node(flow_sequence(flow_node)@changes_path))
+            ]
+            (#eq? @changes_key "changes")
+        )
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@changes_key))
+            value: (
+                block_node(
+                    block_mapping(
+                        block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@changes_paths_key))
+                            value: [
+                                (block_node(block_sequence(block_sequence_item(flow_node)@changes_path)))
+                                (flow_node(flow_sequence(flow_node)@changes_path))
+                            ]
+                    )
+                )
+            )
+            (#eq? @changes_key "changes")
+            (#eq? @changes_paths_key "paths")
+        )
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@changes_key))
+            value: (
+                block_node(
+                    block_mapping(
+                        block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@changes_compare_to_key))
+                            value: (flow_node)@changes_compare_to
+                    )
+                )
+            )
+            (#eq? @changes_key "changes")
+            (#eq? @changes_compare_to_key "compare_to")
+        )
+        "#;
+
+        format!(
+            r"
+            {search_rule_references}
+            {search_extends}
+            {search_stages}
+            {search_variables}
+            {search_scripts}
+            {search_root_node}
+            {search_local_include}
+            {search_project_includes}
+            {search_trigger_project_includes}
+            {search_trigger_local_includes}
+            {search_component_include}
+            {search_component_uri}
+            {search_job_needs}
+            {search_empty_needs}
+            {search_remote_urls}
+            {search_basic_include}
+            {search_dependencies}
+            {search_services}
+            {search_allow_failure}
+            {search_deployment_tier}
+            {search_docker_platform}
+            {search_docker_user}
+            {search_image}
+            {search_changes}
+            {search_trigger}
+        "
+        )
+    }
+
+    // Entries of root `include` list.
+    pub fn get_all_include_items() -> String {
+        r#"
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                key: (flow_node(plain_scalar(string_scalar)@include_key))
+                                value: [
+                                    (block_node(block_sequence(block_sequence_item(_)@include_item)))
+                                    (flow_node(flow_sequence(flow_node)@include_item))
+                                ]
+                        )
+                    )
+                )
+            )
+            (#eq? @include_key "include")
+        )
+        "#
+        .to_string()
+    }
+
+    // Captures values of root includes pointing to included files, written
+    // as plain strings or as `local`, `remote`, `file` and `component` keys.
+    pub fn get_all_include_targets() -> String {
+        r#"
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                key: (flow_node(plain_scalar(string_scalar)@include_key))
+                                value: [
+                                    (flow_node)@include_target
+                                    (block_node(block_sequence(block_sequence_item(flow_node)@include_target)))
+                                ]
+                        )
+                    )
+                )
+            )
+            (#eq? @include_key "include")
+        )
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                key: (flow_node(plain_scalar(string_scalar)@include_key))
+                                value: [
+                                    (block_node(
+                                        block_mapping(
+                                            block_mapping_pair
+                                                key: (flow_node(plain_scalar(string_scalar)@include_target_key))
+                                                value: [
+                                                    (flow_node)@include_target
+                                                    (block_node(block_sequence(block_sequence_item(flow_node)@include_target)))
+                                                ]
+                                        )
+                                    ))
+                                    (block_node(
+                                        block_sequence(
+                                            block_sequence_item(
+                                                block_node(
+                                                    block_mapping(
+                                                        block_mapping_pair
+                                                            key: (flow_node(plain_scalar(string_scalar)@include_target_key))
+                                                            value: [
+                                                                (flow_node)@include_target
+                                                                (block_node(block_sequence(block_sequence_item(flow_node)@include_target)))
+                                                            ]
+                                                    )
+                                                )
+                                            )
+                                        )
+                                    ))
+                                ]
+                        )
+                    )
+                )
+            )
+            (#eq? @include_key "include")
+            (#any-of? @include_target_key "local" "remote" "file" "component")
+        )
+        "#
+        .to_string()
+    }
+
+    // Captures local include paths written either as `local:` entries or
+    // as plain strings directly under `include`.
+    pub fn get_all_local_includes() -> String {
+        r#"
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                key: (flow_node(plain_scalar(string_scalar)@include_key))
+                                value: [
+                                    (flow_node)@local_include
+                                    (block_node(
+                                        block_mapping(
+                                            block_mapping_pair
+                                                key: (flow_node(plain_scalar(string_scalar)@local_key))
+                                                value: (flow_node)@local_include
+                                        )
+                                    ))
+                                    (block_node(
+                                        block_sequence(
+                                            block_sequence_item[
+                                                (flow_node)@local_include
+                                                (block_node(
+                                                    block_mapping(
+                                                        block_mapping_pair
+                                                            key: (flow_node(plain_scalar(string_scalar)@local_key))
+                                                            value: (flow_node)@local_include
+                                                    )
+                                                ))
+                                            ]
+                                        )
+                                    ))
+                                ]
+                        )
+                    )
+                )
+            )
+            (#eq? @include_key "include")
+            (#eq? @local_key "local")
+        )
+        "#
+        .to_string()
+    }
+
+    pub fn get_all_job_needs(needs_name: Option<&str>) -> String {
+        let mut search = String::new();
+        if let Some(needs_name) = needs_name {
+            search = format!(
+                r#"(#any-of? @needs_job_value "{needs_name}" "'{needs_name}'" "\"{needs_name}\"")"#,
+            );
+        }
+
+        format!(
+            r#"
+            (
+                block_mapping_pair
+                    key: (flow_node)@needs_key
+                    value: (
+                    block_node(
+                        block_sequence(
+                        block_sequence_item(
+                            block_node(
+                            block_mapping(
+                                block_mapping_pair
+                                key: (flow_node)@needs_job_key
+                                value: (flow_node)@needs_job_value
+                            )
+                            )@needs_job
+                        )
+                        )
+                    )
+                )
+                (#eq? @needs_key "needs")
+                (#eq? @needs_job_key "job")
+                {search}
+            )
+            (
+                block_mapping_pair
+                    key: (flow_node)@needs_key
+                    value: (
+                    block_node(
+                        block_sequence(
+                        block_sequence_item(
+                            (flow_node)@needs_job_value
+                        )
+                        )
+                    )
+                )
+                (#eq? @needs_key "needs")
+                {search}
+            )
+        "#
+        )
+    }
+
+    pub fn get_root_node_at_position() -> String {
+        r"
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                key: (flow_node[(plain_scalar(string_scalar)) (double_quote_scalar) (single_quote_scalar)]@key)
+                        )@full
+                    )
+                )
+            )
+        )
+        "
+        .to_string()
+    }
+
+    pub fn get_job_variable_definition(job_name: &str, variable_name: &str) -> String {
+        format!(
+            r#"
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                key: (flow_node(plain_scalar(string_scalar)@key))
+                                value: (
+                                    block_node(
+                                        block_mapping(
+                                            block_mapping_pair
+                                                key: (flow_node(plain_scalar(string_scalar)@property_key))
+                                                value: (
+                                                    block_node(
+                                                        block_mapping(
+                                                            block_mapping_pair
+                                                            key: (flow_node(plain_scalar(string_scalar)@variable_key))
+                                                            value: (_)? @variable_value
+                                                        )
+                                                    )
+                                                )
+                                            (#any-of? @property_key "variables" "secrets" "id_tokens")
+                                        )
+                                    )
+                                )
+                            )
+                        )
+                    )
+                )
+            (#eq? @key "{job_name}")
+            (#eq? @variable_key "{variable_name}")
+        )
+        "#
+        )
+    }
+
+    pub fn get_job_parallel_matrix(job_name: &str) -> String {
+        format!(
+            r#"
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                key: (flow_node(plain_scalar(string_scalar)@key))
+                                value: (
+                                    block_node(
+                                        block_mapping(
+                                            block_mapping_pair
+                                                key: (flow_node(plain_scalar(string_scalar)@parallel_key))
+                                                value: (
+                                                    block_node(
+                                                        block_mapping(
+                                                            block_mapping_pair
+                                                            key: (flow_node(plain_scalar(string_scalar)@matrix_key))
+                                                        )
+                                                    )
+                                                )
+                                            (#eq? @parallel_key "parallel")
+                                        )
+                                    )
+                                )
+                            )
+                        )
+                    )
+                )
+            (#eq? @key "{job_name}")
+            (#eq? @matrix_key "matrix")
+        )
+        "#
+        )
+    }
+
+    pub fn get_component_spec_inputs() -> String {
+        r#"
+           (
+               stream(
+                   document(
+                       block_node(
+                           block_mapping(
+                               block_mapping_pair
+                               key: (flow_node(plain_scalar(string_scalar)@key))
+                           )
+                       )
+                   ) @spec
+               )
+               (#eq? @key "spec")
+           )
+           "#
+        .to_string()
+    }
+
+    pub fn get_all_components() -> String {
+        r#"
+        (
+            block_sequence_item(
+                block_node(
+                    block_mapping(
+                        (block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@component_include_key))
+                            value: (flow_node) @component_uri
+                        )
+                        (block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@component_inputs_key))
+                            value: (block_node(block_mapping(
+                              block_mapping_pair
+                                key: (flow_node(plain_scalar(string_scalar)@component_input))
+                                value:
+                                [
+                                    (flow_node)@component_input_value_plain
+                                    (block_node)@component_input_value_block
+                                ]?
+                            )*))
+                        )
+                    )
+                ) @full_component
+            )
+            (#eq? @component_include_key "component")
+            (#eq? @component_inputs_key "inputs")
+        )
+        "#
+        .to_string()
+    }
+
+    pub fn get_all_caches() -> String {
+        r#"
+        (
+            block_mapping (
+            block_mapping_pair
+                key: (flow_node (plain_scalar(string_scalar)@cache_key))
+                value: (block_node(block_sequence(block_sequence_item)+@cache_item))
+
+            (#eq? @cache_key "cache")
+            ) @cache_node
+        )
+        "#
+        .to_string()
+    }
+
+    // Cache can be a single mapping or a list of mappings, files are captured
+    // for both forms.
+    pub fn get_all_cache_key_files() -> String {
+        let key_files = r"
+            block_mapping(
+                block_mapping_pair
+                    key: (flow_node(plain_scalar(string_scalar)@key_key))
+                    value: (
+                        block_node(
+                            block_mapping(
+                                block_mapping_pair
+                                    key: (flow_node(plain_scalar(string_scalar)@files_key))
+                                    value: [
+                                        (block_node(block_sequence(block_sequence_item(flow_node)@cache_key_file)))
+                                        (flow_node(flow_sequence(flow_node)@cache_key_file))
+                                    ]
+                            )
+                        )
+                    )
+            )
+        ";
+
+        format!(
+            r#"
+        (
+            block_mapping_pair
+                key: (flow_node(plain_scalar(string_scalar)@cache_key))
+                value: [
+                    (block_node({key_files}))
+                    (block_node(block_sequence(block_sequence_item(block_node({key_files})))))
+                ]
+            (#eq? @cache_key "cache")
+            (#eq? @key_key "key")
+            (#eq? @files_key "files")
+        )
+        "#
+        )
+    }
+
+    pub fn get_all_exit_codes() -> String {
+        r#"
+        (
+            block_mapping_pair
+                key: (flow_node(plain_scalar(string_scalar)@allow_failure_key))
+                value: (
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                key: (flow_node(plain_scalar(string_scalar)@exit_codes_key))
+                                value: [
+                                    (flow_node[(plain_scalar)(single_quote_scalar)(double_quote_scalar)])@exit_code
+                                    (flow_node(flow_sequence(flow_node)@exit_code))
+                                    (block_node(block_sequence(block_sequence_item(flow_node)@exit_code)))
+                                ]
+                        )
+                    )
+                )
+            (#eq? @allow_failure_key "allow_failure")
+            (#eq? @exit_codes_key "exit_codes")
+        )
+        "#
+        .to_string()
+    }
+
+    pub fn get_all_rule_conditions() -> String {
+        r#"
+        (
+            block_mapping_pair
+                key: (flow_node(plain_scalar(string_scalar)@rules_key))
+                value: (
+                    block_node(
+                        block_sequence(
+                            block_sequence_item(
+                                block_node(
+                                    block_mapping(
+                                        block_mapping_pair
+                                            key: (flow_node(plain_scalar(string_scalar)@if_key))
+                                            value: (flow_node)@rule_condition
+                                    )
+                                )
+                            )
+                        )
+                    )
+                )
+            (#eq? @rules_key "rules")
+            (#eq? @if_key "if")
+        )
+        "#
+        .to_string()
+    }
+
+    pub fn get_all_deployment_tiers() -> String {
+        r#"
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@environment_key))
+            value: (
+                block_node(
+                    block_mapping(
+                        block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@deployment_tier_key))
+                            value: (flow_node)@deployment_tier
+                    )
+                )
+            )
+            (#eq? @environment_key "environment")
+            (#eq? @deployment_tier_key "deployment_tier")
+        )
+        "#
+        .to_string()
+    }
+
+    pub fn get_all_docker_platforms() -> String {
+        r#"
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@docker_image_key))
+            value: (
+                block_node(
+                    block_mapping(
+                        block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@docker_key))
+                            value: (
+                                block_node(
+                                    block_mapping(
+                                        block_mapping_pair
+                                            key: (flow_node(plain_scalar(string_scalar)@docker_platform_key))
+                                            value: (flow_node)@docker_platform
+                                    )
+                                )
+                            )
+                    )
+                )
+            )
+            (#eq? @docker_image_key "image")
+            (#eq? @docker_key "docker")
+            (#eq? @docker_platform_key "platform")
+        )
+        "#
+        .to_string()
+    }
+
+    pub fn get_all_docker_users() -> String {
+        r#"
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@docker_image_key))
+            value: (
+                block_node(
+                    block_mapping(
+                        block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@docker_key))
+                            value: (
+                                block_node(
+                                    block_mapping(
+                                        block_mapping_pair
+                                            key: (flow_node(plain_scalar(string_scalar)@docker_user_key))
+                                            value: (flow_node)@docker_user
+                                    )
+                                )
+                            )
+                    )
+                )
+            )
+            (#eq? @docker_image_key "image")
+            (#eq? @docker_key "docker")
+            (#eq? @docker_user_key "user")
+        )
+        "#
+        .to_string()
+    }
+
+    pub fn get_all_rules_blocks() -> String {
+        r#"
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            (block_mapping_pair
+                                key: (flow_node[(plain_scalar(string_scalar)) (double_quote_scalar) (single_quote_scalar)]@rules_job_key)
+                                value: (
+                                    block_node(
+                                        block_mapping(
+                                            (block_mapping_pair
+                                                key: (flow_node(plain_scalar(string_scalar)@rules_key))
+                                                value: (block_node(block_sequence(block_sequence_item)+@rule_item))
+                                            )@rules_block
+                                        )
+                                    )
+                                )
+                            )@rules_job
+                        )
+                    )
+                )
+            )
+            (#eq? @rules_key "rules")
+        )
+        "#
+        .to_string()
+    }
+
+    // Captures `include: project` entries. Whole mapping is captured so the
+    // caller can check whether `ref` is set.
+    pub fn get_all_project_includes() -> String {
+        r#"
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                key: (flow_node(plain_scalar(string_scalar)@include_key))
+                                value: (block_node(
+                                    block_sequence(
+                                        block_sequence_item(
+                                            block_node(
+                                                (block_mapping
+                                                    (block_mapping_pair
+                                                        key: (flow_node(plain_scalar(string_scalar)@project_key))
+                                                        value: (flow_node)@project_value
+                                                    )
+                                                )@project_include
+                                            )
+                                        )
+                                    )
+                                ))
+                        )
+                    )
+                )
+            )
+            (#eq? @include_key "include")
+            (#eq? @project_key "project")
+        )
+        "#
+        .to_string()
+    }
+
+    // Captures named entries of job keywords which define variables, e.g.
+    // `secrets: NAME: { vault: ... }` or `id_tokens: NAME: { aud: ... }`.
+    pub fn get_all_job_definitions(keyword: &str) -> String {
+        format!(
+            r#"
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@keyword))
+            value: (
+                block_node(
+                    block_mapping(
+                        (block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@definition_key))
+                        )@definition
+                    )
+                )
+            )
+            (#eq? @keyword "{keyword}")
+        )
+        "#
+        )
+    }
+
+    pub fn get_all_tags() -> String {
+        r#"
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                value: (
+                                    block_node(
+                                        block_mapping(
+                                            (block_mapping_pair
+                                                key: (flow_node(plain_scalar(string_scalar)@tags_key))
+                                            )@tags
+                                        )
+                                    )
+                                )
+                        )
+                    )
+                )
+            )
+            (#eq? @tags_key "tags")
+        )
+        "#
+        .to_string()
+    }
+
+    // Keys nested inside job keywords, e.g. `stage` under `variables`. Root
+    // keywords whose children aren't job keywords are skipped.
+    pub fn get_all_nested_keywords() -> String {
+        r#"
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                key: (flow_node)@nested_job_key
                                 value: (
                                     block_node(
                                         block_mapping(
                                             block_mapping_pair
-                                                key: (flow_node(plain_scalar(string_scalar)@property_key))
+                                                key: (flow_node(plain_scalar(string_scalar)@nested_parent_key))
                                                 value: (
                                                     block_node(
                                                         block_mapping(
                                                             block_mapping_pair
-                                                            key: (flow_node(plain_scalar(string_scalar)@variable_key))
+                                                                key: (flow_node(plain_scalar(string_scalar)@nested_keyword))
                                                         )
                                                     )
                                                 )
-                                            (#eq? @property_key "variables")
                                         )
                                     )
                                 )
-                            )
                         )
                     )
                 )
-            (#eq? @key "{job_name}")
-            (#eq? @variable_key "{variable_name}")
+            )
+            (#not-any-of? @nested_job_key "variables" "include" "spec" "workflow" "stages")
+        )
+        "#
+        .to_string()
+    }
+
+    // Legacy `only` and `except` keywords of jobs and templates.
+    // Direct keys of jobs which are one of given keywords.
+    pub fn get_all_job_keywords(keywords: &[&str]) -> String {
+        let keywords = keywords
+            .iter()
+            .map(|keyword| format!("\"{keyword}\""))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        format!(
+            r#"
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                key: (flow_node)@job_key
+                                value: (
+                                    block_node(
+                                        block_mapping(
+                                            block_mapping_pair
+                                                key: (flow_node(plain_scalar(string_scalar)@job_keyword))
+                                        )
+                                    )
+                                )
+                        )
+                    )
+                )
+            )
+            (#not-any-of? @job_key "variables" "include" "spec" "workflow" "stages" "default")
+            (#any-of? @job_keyword {keywords})
         )
         "#
         )
     }
 
-    pub fn get_component_spec_inputs() -> String {
+    // Keys of `variables` blocks, both root and job level ones.
+    pub fn get_all_variable_definitions() -> String {
         r#"
-           (
-               stream(
-                   document(
-                       block_node(
-                           block_mapping(
-                               block_mapping_pair
-                               key: (flow_node(plain_scalar(string_scalar)@key))
-                           )
-                       )
-                   ) @spec
-               )
-               (#eq? @key "spec")
-           )
-           "#
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@variables_key))
+            value: (
+                block_node(
+                    block_mapping(
+                        block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@variable_definition))
+                    )
+                )
+            )
+            (#eq? @variables_key "variables")
+        )
+        "#
         .to_string()
     }
 
-    pub fn get_all_components() -> String {
+    // Scalar values which can reference variables. Keys aren't captured.
+    pub fn get_all_scalar_values() -> String {
+        r"
+        [
+            (block_mapping_pair value: (flow_node)@value)
+            (block_mapping_pair value: (block_node(block_scalar)@value))
+            (block_sequence_item (flow_node)@value)
+            (block_sequence_item (block_node(block_scalar)@value))
+            (flow_sequence (flow_node)@value)
+            (flow_pair value: (flow_node)@value)
+        ]
+        "
+        .to_string()
+    }
+
+    pub fn get_all_anchors() -> String {
+        r"
+        (anchor(anchor_name)@anchor_name)
+        "
+        .to_string()
+    }
+
+    pub fn get_all_spec_inputs() -> String {
         r#"
         (
-            block_sequence_item(
-                block_node(
-                    block_mapping(
-                        (block_mapping_pair
-                            key: (flow_node(plain_scalar(string_scalar)@component_include_key))
-                            value: (flow_node) @component_uri
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                key: (flow_node(plain_scalar(string_scalar)@spec_key))
+                                value: (
+                                    block_node(
+                                        block_mapping(
+                                            block_mapping_pair
+                                                key: (flow_node(plain_scalar(string_scalar)@inputs_key))
+                                                value: (
+                                                    block_node(
+                                                        block_mapping(
+                                                            block_mapping_pair
+                                                                key: (flow_node(plain_scalar(string_scalar)@spec_input))
+                                                        )
+                                                    )
+                                                )
+                                        )
+                                    )
+                                )
                         )
-                        (block_mapping_pair
-                            key: (flow_node(plain_scalar(string_scalar)@component_inputs_key))
-                            value: (block_node(block_mapping(
-                              block_mapping_pair
-                                key: (flow_node(plain_scalar(string_scalar)@component_input))
-                                value:
-                                [
-                                    (flow_node)@component_input_value_plain
-                                    (block_node)@component_input_value_block
-                                ]?
-                            )*))
+                    )
+                )
+            )
+            (#eq? @spec_key "spec")
+            (#eq? @inputs_key "inputs")
+        )
+        "#
+        .to_string()
+    }
+
+    pub fn get_all_default_keywords() -> String {
+        r#"
+        (
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                key: (flow_node(plain_scalar(string_scalar)@default_key))
+                                value: (
+                                    block_node(
+                                        block_mapping(
+                                            (block_mapping_pair
+                                                key: (flow_node)
+                                            )@default_keyword
+                                        )
+                                    )
+                                )
                         )
                     )
-                ) @full_component
+                )
             )
-            (#eq? @component_include_key "component")
-            (#eq? @component_inputs_key "inputs")
+            (#eq? @default_key "default")
         )
         "#
         .to_string()
     }
 
-    pub fn get_all_caches() -> String {
+    pub fn get_all_script_blocks() -> String {
         r#"
         (
-            block_mapping (
-            block_mapping_pair
-                key: (flow_node (plain_scalar(string_scalar)@cache_key))
-                value: (block_node(block_sequence(block_sequence_item)+@cache_item))
+            stream(
+                document(
+                    block_node(
+                        block_mapping(
+                            block_mapping_pair
+                                value: (
+                                    block_node(
+                                        block_mapping(
+                                            (block_mapping_pair
+                                                key: (flow_node(plain_scalar(string_scalar)@script_key))
+                                                value: (block_node(block_sequence))
+                                            )@script_block
+                                        )
+                                    )
+                                )
+                        )
+                    )
+                )
+            )
+            (#any-of? @script_key "script" "before_script" "after_script")
+        )
+        "#
+        .to_string()
+    }
 
-            (#eq? @cache_key "cache")
-            ) @cache_node
+    // Images used by `image:` and `services:`, written either as plain value
+    // or under `name:` key.
+    pub fn get_all_images() -> String {
+        r#"
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@image_key))
+            value: (flow_node)@image
+            (#eq? @image_key "image")
+        )
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@image_key))
+            value: (
+                block_node(
+                    block_mapping(
+                        block_mapping_pair
+                            key: (flow_node(plain_scalar(string_scalar)@image_name_key))
+                            value: (flow_node)@image
+                    )
+                )
+            )
+            (#eq? @image_key "image")
+            (#eq? @image_name_key "name")
+        )
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@image_key))
+            value: (
+                block_node(
+                    block_sequence(
+                        block_sequence_item(
+                            (flow_node)@image
+                        )
+                    )
+                )
+            )
+            (#eq? @image_key "services")
+        )
+        (
+            block_mapping_pair
+            key: (flow_node(plain_scalar(string_scalar)@image_key))
+            value: (
+                block_node(
+                    block_sequence(
+                        block_sequence_item(
+                            block_node(
+                                block_mapping(
+                                    block_mapping_pair
+                                        key: (flow_node(plain_scalar(string_scalar)@image_name_key))
+                                        value: (flow_node)@image
+                                )
+                            )
+                        )
+                    )
+                )
+            )
+            (#eq? @image_key "services")
+            (#eq? @image_name_key "name")
         )
         "#
         .to_string()
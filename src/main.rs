@@ -1,7 +1,7 @@
 use anyhow::anyhow;
 use clap::Parser;
-use gitlab_ci_ls_parser::LSPExperimental;
-use log::{error, info, warn, LevelFilter};
+use gitlab_ci_ls_parser::{LSPExperimental, LSPFileConfiguration, GITLAB_PREDEFINED_VARIABLES};
+use log::{error, info, LevelFilter};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
@@ -25,7 +25,10 @@ struct Args {}
 
 #[derive(Serialize, Deserialize, Debug)]
 struct InitializationOptions {
-    #[serde(default = "default_package_map")]
+    #[serde(
+        default = "default_package_map",
+        deserialize_with = "deserialize_lenient"
+    )]
     package_map: HashMap<String, String>,
 
     #[serde(default = "default_log_path")]
@@ -34,14 +37,42 @@ struct InitializationOptions {
     #[serde(rename = "cache", default = "default_cache_path")]
     cache_path: String,
 
-    #[serde(default = "default_options")]
+    #[serde(default, deserialize_with = "deserialize_lenient")]
     options: Options,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 struct Options {
-    #[serde(default = "default_dependencies_autocomplete_stage_filtering")]
+    #[serde(
+        default = "default_dependencies_autocomplete_stage_filtering",
+        deserialize_with = "deserialize_lenient"
+    )]
     dependencies_autocomplete_stage_filtering: bool,
+
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    completion_limit: Option<usize>,
+
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    cache_key_files_diagnostics: bool,
+
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    store_idle_timeout: Option<u64>,
+
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    default_include_ref: Option<String>,
+
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    include_ref_diagnostics: bool,
+
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    shadowed_keys_diagnostics: bool,
+
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    needs_job_scaffold_completion: bool,
+
+    #[serde(default, deserialize_with = "deserialize_lenient")]
+    deprecated_keywords_diagnostics: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -53,12 +84,55 @@ struct InitializationParams {
     root_path: String,
 }
 
-fn default_options() -> Options {
-    Options {
-        dependencies_autocomplete_stage_filtering: false,
+impl InitializationParams {
+    // Falls back field by field so a single malformed option doesn't reset
+    // the rest of configuration, e.g. workspace root.
+    fn from_value(value: &serde_json::Value) -> InitializationParams {
+        match serde_json::from_value::<InitializationParams>(value.clone()) {
+            Ok(p) => return p,
+            Err(err) => error!("error deserializing init params; got err {}", err),
+        }
+
+        let options = value
+            .get("initializationOptions")
+            .unwrap_or(&serde_json::Value::Null);
+
+        InitializationParams {
+            root_path: init_field(value, "rootPath").unwrap_or_default(),
+            initialization_options: InitializationOptions {
+                package_map: init_field(options, "package_map").unwrap_or_else(default_package_map),
+                log_path: init_field(options, "log_path").unwrap_or_else(default_log_path),
+                cache_path: init_field(options, "cache").unwrap_or_else(default_cache_path),
+                options: init_field(options, "options").unwrap_or_default(),
+            },
+        }
     }
 }
 
+// Falls back to default value when field's value is invalid, so a single
+// malformed option doesn't reset the rest of them.
+fn deserialize_lenient<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned + Default,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+
+    Ok(serde_json::from_value(value).unwrap_or_else(|err| {
+        error!("error deserializing init param; got err {err}");
+        T::default()
+    }))
+}
+
+// Deserializes single init param field; missing or malformed fields are None.
+fn init_field<T: serde::de::DeserializeOwned>(value: &serde_json::Value, key: &str) -> Option<T> {
+    let field = value.get(key)?;
+
+    serde_json::from_value(field.clone())
+        .map_err(|err| error!("error deserializing init param {key}; got err {err}"))
+        .ok()
+}
+
 fn default_dependencies_autocomplete_stage_filtering() -> bool {
     false
 }
@@ -85,7 +159,7 @@ fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
 
     let server_capabilities = serde_json::to_value(ServerCapabilities {
         text_document_sync: Some(lsp_types::TextDocumentSyncCapability::Kind(
-            TextDocumentSyncKind::FULL,
+            TextDocumentSyncKind::INCREMENTAL,
         )),
         hover_provider: Some(lsp_types::HoverProviderCapability::Simple(true)),
         definition_provider: Some(lsp_types::OneOf::Left(true)),
@@ -110,30 +184,25 @@ fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
                 ..Default::default()
             },
         })),
+        folding_range_provider: Some(lsp_types::FoldingRangeProviderCapability::Simple(true)),
+        document_symbol_provider: Some(lsp_types::OneOf::Left(true)),
+        document_link_provider: Some(lsp_types::DocumentLinkOptions {
+            resolve_provider: Some(false),
+            work_done_progress_options: lsp_types::WorkDoneProgressOptions::default(),
+        }),
+        code_action_provider: Some(lsp_types::CodeActionProviderCapability::Simple(true)),
+        call_hierarchy_provider: Some(lsp_types::CallHierarchyServerCapability::Simple(true)),
+        execute_command_provider: Some(lsp_types::ExecuteCommandOptions {
+            commands: vec![gitlab_ci_ls_parser::REVEAL_CACHED_FILE_COMMAND.to_string()],
+            work_done_progress_options: WorkDoneProgressOptions {
+                work_done_progress: None,
+            },
+        }),
         ..Default::default()
     })?;
 
     let initialization_params = connection.initialize(server_capabilities)?;
-    let init_params =
-        match serde_json::from_value::<InitializationParams>(initialization_params.clone()) {
-            Ok(p) => p,
-            Err(err) => {
-                error!("error deserializing init params; got err {}", err);
-
-                InitializationParams {
-                    root_path: String::new(),
-                    initialization_options: InitializationOptions {
-                        log_path: default_log_path(),
-                        package_map: HashMap::new(),
-                        cache_path: default_cache_path(),
-                        options: Options {
-                            dependencies_autocomplete_stage_filtering:
-                                default_dependencies_autocomplete_stage_filtering(),
-                        },
-                    },
-                }
-            }
-        };
+    let init_params = InitializationParams::from_value(&initialization_params);
 
     let home_path = std::env::var("HOME")?;
     let fs_utils = FSUtilsImpl::new(home_path);
@@ -166,13 +235,44 @@ fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
                 .to_string(),
             package_map: init_params.initialization_options.package_map,
             remote_urls,
+            configuration: LSPFileConfiguration::load(&init_params.root_path),
             root_dir: init_params.root_path,
-            experimental: LSPExperimental {
-                dependencies_autocomplete_stage_filtering: init_params
-                    .initialization_options
-                    .options
-                    .dependencies_autocomplete_stage_filtering,
-            },
+            completion_limit: init_params.initialization_options.options.completion_limit,
+            store_idle_timeout: init_params
+                .initialization_options
+                .options
+                .store_idle_timeout
+                .map(std::time::Duration::from_secs),
+            default_include_ref: init_params
+                .initialization_options
+                .options
+                .default_include_ref,
+        },
+        LSPExperimental {
+            dependencies_autocomplete_stage_filtering: init_params
+                .initialization_options
+                .options
+                .dependencies_autocomplete_stage_filtering,
+            cache_key_files_diagnostics: init_params
+                .initialization_options
+                .options
+                .cache_key_files_diagnostics,
+            include_ref_diagnostics: init_params
+                .initialization_options
+                .options
+                .include_ref_diagnostics,
+            shadowed_keys_diagnostics: init_params
+                .initialization_options
+                .options
+                .shadowed_keys_diagnostics,
+            needs_job_scaffold_completion: init_params
+                .initialization_options
+                .options
+                .needs_job_scaffold_completion,
+            deprecated_keywords_diagnostics: init_params
+                .initialization_options
+                .options
+                .deprecated_keywords_diagnostics,
         },
         Box::new(fs_utils),
     );
@@ -222,13 +322,12 @@ fn save_base_files(
     );
     let _ = fs_utils.create_dir_all(&base_path);
 
-    let gitlab_predefined = include_str!("./resources/gitlab_predefined_vars.yaml");
     let gitlab_predefined_path = format!("{base_path}/gitlab_predefined_vars.yaml");
     info!("predefined path: {}", gitlab_predefined_path);
 
     let mut file = File::create(&gitlab_predefined_path)
         .map_err(|e| anyhow!("error creating file: {gitlab_predefined_path}; got err: {e}"))?;
-    file.write_all(gitlab_predefined.as_bytes())?;
+    file.write_all(GITLAB_PREDEFINED_VARIABLES.as_bytes())?;
 
     Ok(())
 }
@@ -246,6 +345,60 @@ fn get_remote_hosts(remote: &str) -> Option<String> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_init_params_partial_fallback() {
+        let value = serde_json::json!({
+            "rootPath": "/home/user/project",
+            "initializationOptions": {
+                "package_map": "malformed",
+                "log_path": "/tmp/gitlab-ci-ls.log",
+                "options": {
+                    "completion_limit": "many",
+                    "include_ref_diagnostics": true
+                }
+            }
+        });
+
+        let params = InitializationParams::from_value(&value);
+        assert_eq!(params.root_path, "/home/user/project");
+        assert!(params.initialization_options.package_map.is_empty());
+        assert_eq!(
+            params.initialization_options.log_path,
+            "/tmp/gitlab-ci-ls.log"
+        );
+        assert_eq!(params.initialization_options.options.completion_limit, None);
+        assert!(
+            params
+                .initialization_options
+                .options
+                .include_ref_diagnostics
+        );
+
+        let params = InitializationParams::from_value(&serde_json::json!({
+            "rootPath": "/home/user/project",
+            "initializationOptions": {
+                "log_path": "/tmp/gitlab-ci-ls.log",
+                "options": "malformed"
+            }
+        }));
+        assert_eq!(
+            params.initialization_options.log_path,
+            "/tmp/gitlab-ci-ls.log"
+        );
+        assert!(
+            !params
+                .initialization_options
+                .options
+                .include_ref_diagnostics
+        );
+
+        let params = InitializationParams::from_value(&serde_json::json!({
+            "rootPath": "/home/user/project"
+        }));
+        assert_eq!(params.root_path, "/home/user/project");
+        assert_eq!(params.initialization_options.log_path, default_log_path());
+    }
+
     #[test]
     fn test_get_remote_urls_full_scheme() {
         assert_eq!(